@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    // let the CTCP VERSION reply say exactly which commit we were built from
+    let output = Command::new("git")
+        .args(&["rev-parse", "--short", "HEAD"])
+        .output();
+
+    if let Ok(output) = output {
+        if output.status.success() {
+            let hash = String::from_utf8_lossy(&output.stdout);
+            println!("cargo:rustc-env=IRC_BOT_GIT_HASH={}", hash.trim());
+        }
+    }
+
+    // and which version of the irc crate, for debugging protocol issues
+    if let Ok(lockfile) = fs::read_to_string("Cargo.lock") {
+        if let Some(version) = locked_version(&lockfile, "irc") {
+            println!("cargo:rustc-env=IRC_BOT_IRC_VERSION={}", version);
+        }
+    }
+
+    println!("cargo:rerun-if-changed=build.rs");
+    watch_git_head();
+    // cargo would rebuild every time if we watched a file which isn't there
+    if Path::new("Cargo.lock").exists() {
+        println!("cargo:rerun-if-changed=Cargo.lock");
+    }
+}
+
+/// Rebuild whenever `HEAD` moves, so the commit hash doesn't go stale.
+fn watch_git_head() {
+    let git_dir = Path::new(".git");
+    let head = match fs::read_to_string(git_dir.join("HEAD")) {
+        Ok(head) => head,
+        // not built from a git checkout
+        Err(_) => return,
+    };
+    println!("cargo:rerun-if-changed=.git/HEAD");
+
+    // on a branch, HEAD only names the ref which changes when we commit
+    let head = head.trim();
+    if head.starts_with("ref: ") {
+        let reference = git_dir.join(&head["ref: ".len()..]);
+
+        if reference.exists() {
+            println!("cargo:rerun-if-changed={}", reference.display());
+        } else if let Some(parent) = reference.parent() {
+            // the ref is packed, so watch for it being written out again
+            println!("cargo:rerun-if-changed={}", parent.display());
+        }
+    }
+
+    if git_dir.join("packed-refs").exists() {
+        println!("cargo:rerun-if-changed=.git/packed-refs");
+    }
+}
+
+/// Find the version of a package in `Cargo.lock`.
+fn locked_version<'a>(lockfile: &'a str, package: &str) -> Option<&'a str> {
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lockfile.lines().map(str::trim);
+
+    lines.find(|line| *line == name)?;
+    let version = lines.next()?;
+
+    Some(version.trim_start_matches("version = ").trim_matches('"'))
+}
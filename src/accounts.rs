@@ -0,0 +1,157 @@
+use crate::utils;
+use lru::LruCache;
+use std::fmt::{self, Debug, Formatter};
+use std::time::{Duration, Instant};
+
+/// A bounded cache of which account each nick is logged in as.
+///
+/// Entries are evicted when the cache is full (least recently used first) or
+/// once they're older than the configured time-to-live.
+pub struct AccountCache {
+    entries: LruCache<String, Entry>,
+    ttl: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    account: Option<String>,
+    inserted: Instant,
+}
+
+impl AccountCache {
+    pub fn new(capacity: usize, ttl: Duration) -> AccountCache {
+        AccountCache {
+            entries: LruCache::new(capacity),
+            ttl,
+        }
+    }
+
+    /// Look up the account a nick is logged in as.
+    ///
+    /// The outer `Option` is `None` when we don't know (or the entry has
+    /// expired), while `Some(None)` means the user isn't logged in.
+    pub fn get(&mut self, nick: &str) -> Option<Option<String>> {
+        self.get_at(nick, Instant::now())
+    }
+
+    fn get_at(&mut self, nick: &str, now: Instant) -> Option<Option<String>> {
+        let key = utils::irc_lowercase(nick);
+
+        let expired = match self.entries.get(&key) {
+            Some(entry) if now.duration_since(entry.inserted) < self.ttl => {
+                return Some(entry.account.clone());
+            }
+            Some(_) => true,
+            None => false,
+        };
+
+        if expired {
+            self.entries.pop(&key);
+        }
+
+        None
+    }
+
+    /// Record the account a nick is logged in as (`None` if they aren't).
+    pub fn insert(&mut self, nick: &str, account: Option<String>) {
+        self.insert_at(nick, account, Instant::now());
+    }
+
+    fn insert_at(&mut self, nick: &str, account: Option<String>, now: Instant) {
+        let entry = Entry {
+            account,
+            inserted: now,
+        };
+        self.entries.put(utils::irc_lowercase(nick), entry);
+    }
+
+    /// Forget everything we know about a nick (e.g. because they quit).
+    pub fn remove(&mut self, nick: &str) {
+        self.entries.pop(&utils::irc_lowercase(nick));
+    }
+
+    /// A user changed their nick, so move their entry across.
+    pub fn rename(&mut self, old: &str, new: &str) {
+        self.remove(new);
+
+        if let Some(entry) = self.entries.pop(&utils::irc_lowercase(old)) {
+            self.entries.put(utils::irc_lowercase(new), entry);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Debug for AccountCache {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("AccountCache")
+            .field("len", &self.entries.len())
+            .field("capacity", &self.entries.cap())
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lookups_are_case_insensitive() {
+        let mut cache = AccountCache::new(8, Duration::from_secs(60));
+
+        cache.insert("Michael", Some(String::from("michael")));
+        cache.insert("Anonymous", None);
+
+        assert_eq!(cache.get("MICHAEL"), Some(Some(String::from("michael"))));
+        assert_eq!(cache.get("anonymous"), Some(None));
+        assert_eq!(cache.get("somebody-else"), None);
+    }
+
+    #[test]
+    fn the_least_recently_used_entry_is_evicted() {
+        let mut cache = AccountCache::new(2, Duration::from_secs(60));
+
+        cache.insert("first", None);
+        cache.insert("second", None);
+        cache.get("first");
+        cache.insert("third", None);
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("second"), None);
+        assert_eq!(cache.get("first"), Some(None));
+    }
+
+    #[test]
+    fn entries_expire() {
+        let ttl = Duration::from_secs(60);
+        let mut cache = AccountCache::new(8, ttl);
+        let start = Instant::now();
+
+        cache.insert_at("Michael", Some(String::from("michael")), start);
+
+        assert!(cache.get_at("Michael", start + ttl / 2).is_some());
+        assert!(cache.get_at("Michael", start + ttl).is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn nick_changes_carry_the_account_across() {
+        let mut cache = AccountCache::new(8, Duration::from_secs(60));
+
+        cache.insert("Michael", Some(String::from("michael")));
+        cache.rename("Michael", "Michael_away");
+
+        assert_eq!(cache.get("Michael"), None);
+        assert_eq!(
+            cache.get("Michael_away"),
+            Some(Some(String::from("michael")))
+        );
+    }
+}
@@ -0,0 +1,1186 @@
+use actix::fut::WrapFuture;
+use actix::{Actor, ActorFuture, Addr, AsyncContext, Context, Handler};
+use crate::bot::lift_err;
+use crate::channel::Channel;
+use crate::commands::{CommandAccess, CommandContext, CommandInfo};
+use crate::logging::LogLevel;
+use crate::messages::{
+    AnnounceAll, CancelSchedule, ChangeNick, Channels, CommandCompleted,
+    CommandReceived, DescribeWiring, GetStats, Join, LeaveAndStop,
+    ListCommands, ListPlugins, ListSchedules, LookupAccount, MeasureLatency,
+    ModAnnounce, Notice, Part, PluginSummary, PrivateMessage, Quit,
+    RegisterCommand, Registration, ReplyLines, ScheduleSummary,
+    SetCommandEnabled, ShutdownReason, Stats, Summarize, Wiring,
+};
+use crate::settings::ReplyTemplates;
+use crate::utils;
+use crate::World;
+use failure::Error;
+use futures::future::{self, Either, Future};
+use irc::client::data::Config as IrcConfig;
+use irc::client::Client;
+use slog::{Level, Logger};
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// The commands anyone can run.
+const ANYONE_COMMANDS: &[&str] = &["help", "version"];
+/// The commands anyone listed as an admin can run.
+const ADMIN_COMMANDS: &[&str] = &[
+    "nick",
+    "announce",
+    "modannounce",
+    "plugins",
+    "selftest",
+    "stats",
+    "ping",
+    "enable",
+    "disable",
+    "schedules",
+    "unschedule",
+    "wiring",
+];
+/// The commands only the owner can run, and only via direct message.
+const OWNER_COMMANDS: &[&str] = &["join", "part", "quit", "reload", "loglevel"];
+/// How long each line of a reply can be, leaving plenty of room in the
+/// server's 512 byte limit for the `PRIVMSG` and its target.
+const MAX_LINE_LENGTH: usize = 400;
+/// The throwaway channel `!selftest` joins and leaves.
+const SELF_TEST_CHANNEL: &str = "#irc_bot-selftest";
+/// How often `!selftest` checks whether the server has confirmed its `JOIN`.
+const SELF_TEST_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How many times `!selftest` checks for the `JOIN` before giving up.
+const SELF_TEST_POLLS: usize = 10;
+/// How many lines of `!wiring` to send at a time, leaving room for the line
+/// saying which page it is.
+const WIRING_LINES_PER_PAGE: usize = 3;
+
+/// Commands for the people running the bot, authenticated by the account
+/// they're logged in as.
+///
+/// Admins can use:
+///
+/// - `!nick <new-nick>` changes the bot's nick
+/// - `!announce <message>` sends a notice to every channel the bot is in
+/// - `!modannounce <message>` sends a notice to every channel the bot is an op
+///   in
+/// - `!plugins` lists the plugins and the messages they're subscribed to
+/// - `!selftest` sends a notice, then joins and leaves a throwaway channel,
+///   reporting how each step went
+///
+/// - `!stats` shows our uptime, how many messages we've received, how many
+///   channels we're in, and how many messages are waiting to be handled
+/// - `!ping` measures the round trip to the server
+/// - `!enable <channel> <command>` and `!disable <channel> <command>` turn a
+///   command on or off in a particular channel
+/// - `!schedules` lists the timers the bot is waiting on, and
+///   `!unschedule <id>` cancels one
+/// - `!wiring [page]` shows which messages have subscribers and which plugins
+///   they belong to
+///
+/// While the owner can also send these as a direct message:
+///
+/// - `!join <channel> [key]`
+/// - `!part <channel> [--force] [reason]`
+/// - `!quit [message]`
+/// - `!reload` re-reads the config file and joins any new channels
+/// - `!loglevel <level>`
+///
+/// Anyone can use `!help [command]` to list the commands they're allowed to
+/// run, or get the details for one of them, and `!version` to see which
+/// build of the bot is running.
+pub struct Admin<C: Client + 'static> {
+    logger: Logger,
+    world: Addr<World<C>>,
+    /// The accounts allowed to run admin commands.
+    admins: Vec<String>,
+    /// The account allowed to run owner commands.
+    owner: Option<String>,
+    log_level: Option<LogLevel>,
+    config: Option<PathBuf>,
+    templates: ReplyTemplates,
+    /// The character commands start with, for writing out their usage.
+    prefix: char,
+}
+
+impl<C: Client + 'static> Admin<C> {
+    pub fn new(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        admins: Vec<String>,
+    ) -> Admin<C> {
+        Admin {
+            logger,
+            world: world.clone(),
+            admins,
+            owner: None,
+            log_level: None,
+            config: None,
+            templates: ReplyTemplates::default(),
+            prefix: '!',
+        }
+    }
+
+    /// Accept owner commands from a particular account.
+    pub fn with_owner(mut self, account: String) -> Admin<C> {
+        self.owner = Some(account);
+        self
+    }
+
+    /// Let the owner change the log level with `!loglevel`.
+    pub fn with_log_level(mut self, log_level: LogLevel) -> Admin<C> {
+        self.log_level = Some(log_level);
+        self
+    }
+
+    /// The config file to re-read on `!reload`.
+    pub fn with_config(mut self, path: PathBuf) -> Admin<C> {
+        self.config = Some(path);
+        self
+    }
+
+    /// Customise the wording of our replies.
+    pub fn with_templates(mut self, templates: ReplyTemplates) -> Admin<C> {
+        self.templates = templates;
+        self
+    }
+
+    /// Tell people to start commands with something other than `!`.
+    pub fn with_command_prefix(mut self, prefix: char) -> Admin<C> {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Start the [`Admin`] actor in the background.
+    pub fn spawn(self) -> Addr<Admin<C>> {
+        let world = self.world.clone();
+        let prefix = self.prefix;
+        let admin = utils::start_isolated("admin", self);
+
+        world.do_send(
+            Registration::<CommandReceived>::register(
+                admin.clone().recipient(),
+            )
+            .from_plugin("admin"),
+        );
+
+        let commands = ADMIN_COMMANDS
+            .iter()
+            .map(|name| (name, CommandAccess::Admins))
+            .chain(
+                OWNER_COMMANDS
+                    .iter()
+                    .map(|name| (name, CommandAccess::Owner)),
+            )
+            .chain(
+                ANYONE_COMMANDS
+                    .iter()
+                    .map(|name| (name, CommandAccess::Anyone)),
+            );
+
+        for (name, access) in commands {
+            let mut register = RegisterCommand::new(*name)
+                .from_plugin("admin")
+                .restricted_to(access);
+
+            // owner commands are only accepted in private, so say so up front
+            if access == CommandAccess::Owner {
+                register = register.only_in(CommandContext::DmOnly);
+            }
+
+            if let Some((usage, description)) = command_help(name) {
+                register = register
+                    .with_usage(format!("{}{}", prefix, usage))
+                    .with_description(description);
+            }
+
+            world.do_send(register);
+        }
+
+        admin
+    }
+
+    fn is_owner(&self, account: Option<&str>) -> bool {
+        match (account, self.owner.as_ref()) {
+            (Some(account), Some(owner)) => account == owner,
+            _ => false,
+        }
+    }
+
+    fn is_admin(&self, account: Option<&str>) -> bool {
+        self.is_owner(account)
+            || account.map_or(false, |account| {
+                self.admins.iter().any(|admin| admin == account)
+            })
+    }
+
+    /// The commands someone logged in as this account can use.
+    fn access(&self, account: Option<&str>) -> CommandAccess {
+        if self.is_owner(account) {
+            CommandAccess::Owner
+        } else if self.is_admin(account) {
+            CommandAccess::Admins
+        } else {
+            CommandAccess::Anyone
+        }
+    }
+
+    fn reply(&self, to: &str, content: String) {
+        self.world.do_send(PrivateMessage {
+            to: to.to_string(),
+            content,
+        });
+    }
+
+    /// Tell someone how a command should be used.
+    fn usage(&self, cmd: &CommandReceived, usage: &str) {
+        let content = self.templates.render(
+            "usage",
+            "Usage: {usage}",
+            &[
+                ("nick", cmd.sender.as_ref().map_or("", String::as_str)),
+                ("command", &cmd.name),
+                ("usage", &format!("{}{}", self.prefix, usage)),
+            ],
+        );
+        self.reply(&cmd.reply_to, content);
+    }
+
+    /// Run a command we know the sender is allowed to use.
+    fn run(
+        &mut self,
+        cmd: CommandReceived,
+        access: CommandAccess,
+        ctx: &mut Context<Self>,
+    ) {
+        let name = cmd.name.clone();
+
+        match name.as_str() {
+            "help" => self.help(cmd, access, ctx),
+            "version" => self.reply(&cmd.reply_to, crate::describe_version()),
+            "nick" => self.change_nick(cmd, ctx),
+            "announce" => self.announce(cmd, ctx),
+            "modannounce" => self.mod_announce(cmd, ctx),
+            "plugins" => self.list_plugins(cmd, ctx),
+            "selftest" => self.self_test(cmd, ctx),
+            "stats" => self.stats(cmd, ctx),
+            "ping" => self.ping(cmd, ctx),
+            "enable" => self.set_command_enabled(&cmd, true),
+            "disable" => self.set_command_enabled(&cmd, false),
+            "schedules" => self.list_schedules(cmd, ctx),
+            "unschedule" => self.cancel_schedule(cmd, ctx),
+            "wiring" => self.describe_wiring(cmd, ctx),
+            "join" => self.join(&cmd),
+            "part" => self.part(cmd, ctx),
+            "quit" => self.quit(&cmd),
+            "reload" => self.reload(&cmd),
+            "loglevel" => self.set_log_level(&cmd),
+            other => unreachable!("Unknown admin command, {}", other),
+        }
+    }
+
+    fn change_nick(&mut self, cmd: CommandReceived, ctx: &mut Context<Self>) {
+        let nick = match cmd.args.as_slice() {
+            [nick] => nick.clone(),
+            _ => {
+                self.usage(&cmd, "nick <new-nick>");
+                return;
+            }
+        };
+
+        let fut = lift_err(self.world.send(ChangeNick { nick: nick.clone() }))
+            .into_actor(self)
+            .then(move |outcome: Result<(), Error>, admin, _ctx| {
+                let content = match outcome {
+                    Ok(_) => admin.templates.render(
+                        "nick-changed",
+                        "I am now known as {nick}",
+                        &[("nick", &nick)],
+                    ),
+                    Err(e) => {
+                        warn!(admin.logger, "Unable to change nick";
+                            "nick" => &nick,
+                            "error" => e.to_string());
+                        e.to_string()
+                    }
+                };
+
+                admin.reply(&cmd.reply_to, content);
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+
+    fn announce(&mut self, cmd: CommandReceived, ctx: &mut Context<Self>) {
+        if cmd.args.is_empty() {
+            self.usage(&cmd, "announce <message>");
+            return;
+        }
+
+        let content = cmd.args.join(" ");
+        let fut = lift_err(self.world.send(AnnounceAll { content }))
+            .into_actor(self)
+            .then(move |outcome: Result<usize, Error>, admin, _ctx| {
+                let content = match outcome {
+                    Ok(count) => format!("Announced to {} channels", count),
+                    Err(e) => {
+                        warn!(admin.logger, "Unable to make an announcement";
+                            "error" => e.to_string());
+                        e.to_string()
+                    }
+                };
+
+                admin.reply(&cmd.reply_to, content);
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+
+    fn mod_announce(&mut self, cmd: CommandReceived, ctx: &mut Context<Self>) {
+        if cmd.args.is_empty() {
+            self.usage(&cmd, "modannounce <message>");
+            return;
+        }
+
+        let content = cmd.args.join(" ");
+        let fut = lift_err(self.world.send(ModAnnounce { content }))
+            .into_actor(self)
+            .then(move |outcome: Result<usize, Error>, admin, _ctx| {
+                let content = match outcome {
+                    Ok(count) => {
+                        format!("Announced to {} moderated channels", count)
+                    }
+                    Err(e) => {
+                        warn!(admin.logger, "Unable to make an announcement";
+                            "error" => e.to_string());
+                        e.to_string()
+                    }
+                };
+
+                admin.reply(&cmd.reply_to, content);
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+
+    fn list_plugins(&mut self, cmd: CommandReceived, ctx: &mut Context<Self>) {
+        let fut = self.world.send(ListPlugins).into_actor(self).then(
+            move |plugins, admin, _ctx| {
+                match plugins {
+                    Ok(plugins) => admin.world.do_send(ReplyLines {
+                        to: cmd.reply_to,
+                        lines: describe_plugins(&plugins, admin.prefix),
+                        requester: cmd.sender,
+                    }),
+                    Err(e) => warn!(admin.logger, "Unable to list the plugins";
+                        "error" => e.to_string()),
+                }
+
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    fn list_schedules(
+        &mut self,
+        cmd: CommandReceived,
+        ctx: &mut Context<Self>,
+    ) {
+        let fut = self.world.send(ListSchedules).into_actor(self).then(
+            move |schedules, admin, _ctx| {
+                match schedules {
+                    Ok(schedules) => admin.world.do_send(ReplyLines {
+                        to: cmd.reply_to,
+                        lines: describe_schedules(&schedules),
+                        requester: cmd.sender,
+                    }),
+                    Err(e) => {
+                        warn!(admin.logger, "Unable to list the schedules";
+                        "error" => e.to_string())
+                    }
+                }
+
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    fn cancel_schedule(
+        &mut self,
+        cmd: CommandReceived,
+        ctx: &mut Context<Self>,
+    ) {
+        let id = match cmd.args.as_slice() {
+            [id] => id.parse().ok(),
+            _ => None,
+        };
+        let id = match id {
+            Some(id) => id,
+            None => {
+                self.usage(&cmd, "unschedule <id>");
+                return;
+            }
+        };
+
+        let fut = self
+            .world
+            .send(CancelSchedule { id })
+            .into_actor(self)
+            .then(move |cancelled, admin, _ctx| {
+                match cancelled {
+                    Ok(true) => admin.reply(
+                        &cmd.reply_to,
+                        format!("Cancelled schedule {}", id),
+                    ),
+                    Ok(false) => admin.reply(
+                        &cmd.reply_to,
+                        format!("There's no schedule {}", id),
+                    ),
+                    Err(e) => {
+                        warn!(admin.logger, "Unable to cancel a schedule";
+                        "id" => id,
+                        "error" => e.to_string())
+                    }
+                }
+
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+
+    fn describe_wiring(
+        &mut self,
+        cmd: CommandReceived,
+        ctx: &mut Context<Self>,
+    ) {
+        let page = match cmd.args.as_slice() {
+            [] => Some(1),
+            [page] => page.parse().ok().filter(|&page| page > 0),
+            _ => None,
+        };
+        let page = match page {
+            Some(page) => page,
+            None => {
+                self.usage(&cmd, "wiring [page]");
+                return;
+            }
+        };
+
+        let fut = self.world.send(DescribeWiring).into_actor(self).then(
+            move |wiring, admin, _ctx| {
+                match wiring {
+                    Ok(wiring) => admin.world.do_send(ReplyLines {
+                        to: cmd.reply_to,
+                        lines: wiring_page(&wiring, page, admin.prefix),
+                        requester: cmd.sender,
+                    }),
+                    Err(e) => {
+                        warn!(admin.logger, "Unable to describe the wiring";
+                        "error" => e.to_string())
+                    }
+                }
+
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    fn ping(&mut self, cmd: CommandReceived, ctx: &mut Context<Self>) {
+        let fut = lift_err(self.world.send(MeasureLatency))
+            .into_actor(self)
+            .then(move |rtt: Result<Duration, Error>, admin, _ctx| {
+                let (content, outcome) = match rtt {
+                    Ok(rtt) => {
+                        let millis = rtt.as_secs() * 1000
+                            + u64::from(rtt.subsec_millis());
+                        (
+                            format!("Round trip to the server: {}ms", millis),
+                            CommandCompleted::succeeded("ping"),
+                        )
+                    }
+                    Err(e) => {
+                        warn!(admin.logger, "Unable to measure the latency";
+                            "error" => e.to_string());
+                        (
+                            String::from("The server never answered our ping"),
+                            CommandCompleted::failed("ping", e),
+                        )
+                    }
+                };
+
+                admin.world.do_send(outcome);
+
+                admin.reply(&cmd.reply_to, content);
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+
+    /// Send the sender the commands they can use, or the details for one of
+    /// them, as a direct message so long lists don't spam the channel.
+    fn help(
+        &mut self,
+        cmd: CommandReceived,
+        access: CommandAccess,
+        ctx: &mut Context<Self>,
+    ) {
+        let sender = match cmd.sender {
+            Some(ref sender) => sender.clone(),
+            None => return,
+        };
+
+        let fut = self.world.send(ListCommands).into_actor(self).then(
+            move |commands, admin, _ctx| {
+                match commands {
+                    Ok(commands) => {
+                        let visible: Vec<CommandInfo> = commands
+                            .into_iter()
+                            .filter(|command| command.access <= access)
+                            .collect();
+                        let lines = match cmd.args.first() {
+                            Some(name) => describe_command(
+                                &visible,
+                                name,
+                                &admin.templates,
+                                admin.prefix,
+                            ),
+                            None => describe_commands(&visible, admin.prefix),
+                        };
+
+                        admin.world.do_send(ReplyLines {
+                            to: sender.clone(),
+                            lines,
+                            requester: Some(sender),
+                        });
+                    }
+                    Err(e) => {
+                        warn!(admin.logger, "Unable to list the commands";
+                        "error" => e.to_string())
+                    }
+                }
+
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    fn stats(&mut self, cmd: CommandReceived, ctx: &mut Context<Self>) {
+        let fut = self.world.send(GetStats).into_actor(self).then(
+            move |stats, admin, _ctx| {
+                match stats {
+                    Ok(stats) => admin.world.do_send(ReplyLines {
+                        to: cmd.reply_to,
+                        lines: describe_stats(&stats),
+                        requester: cmd.sender,
+                    }),
+                    Err(e) => warn!(admin.logger, "Unable to get the stats";
+                        "error" => e.to_string()),
+                }
+
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    /// Check the connection and send path are healthy by sending a notice,
+    /// then joining and leaving a throwaway channel.
+    fn self_test(&mut self, cmd: CommandReceived, ctx: &mut Context<Self>) {
+        let requester = match cmd.sender {
+            Some(sender) => sender,
+            None => return,
+        };
+        let notice = Notice {
+            to: requester.clone(),
+            content: String::from("Self-test: notices are getting through"),
+        };
+        let test = SelfTest {
+            reply_to: cmd.reply_to,
+            requester,
+            report: Vec::new(),
+        };
+
+        let fut = lift_err(self.world.send(notice)).into_actor(self).then(
+            move |outcome: Result<(), Error>, admin, ctx| {
+                let mut test = test;
+                test.record("notice", outcome);
+                admin.self_test_join(test, ctx);
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    fn self_test_join(&mut self, test: SelfTest, ctx: &mut Context<Self>) {
+        let fut = lift_err(self.world.send(Join::new(SELF_TEST_CHANNEL)))
+            .into_actor(self)
+            .then(move |outcome: Result<(), Error>, admin, ctx| {
+                let mut test = test;
+
+                match outcome {
+                    Ok(_) => admin.self_test_confirm_join(test, 0, ctx),
+                    Err(e) => {
+                        test.record("join", Err(e));
+                        test.skip("part");
+                        admin.finish_self_test(test);
+                    }
+                }
+
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+
+    /// Keep checking until the server confirms we've joined the channel.
+    fn self_test_confirm_join(
+        &mut self,
+        test: SelfTest,
+        polls: usize,
+        ctx: &mut Context<Self>,
+    ) {
+        let fut = self
+            .world
+            .send(Channels)
+            .and_then(|channels| {
+                let channel = channels
+                    .into_iter()
+                    .find(|(name, _)| utils::same_nick(name, SELF_TEST_CHANNEL))
+                    .map(|(_, channel)| channel);
+
+                match channel {
+                    Some(channel) => Either::A(
+                        channel
+                            .send(Summarize)
+                            .map(|summary| Some((channel, summary.joined))),
+                    ),
+                    None => Either::B(future::ok(None)),
+                }
+            })
+            .into_actor(self)
+            .then(move |outcome, admin, ctx| {
+                let mut test = test;
+
+                match outcome {
+                    Ok(Some((channel, true))) => {
+                        test.record::<Error>("join", Ok(()));
+                        admin.self_test_part(test, channel, ctx);
+                    }
+                    Ok(Some((channel, false))) if polls >= SELF_TEST_POLLS => {
+                        test.record("join", Err("never confirmed"));
+                        // leave anyway so we don't leave a stale channel behind
+                        admin.self_test_part(test, channel, ctx);
+                    }
+                    Ok(Some(_)) => {
+                        ctx.run_later(
+                            SELF_TEST_POLL_INTERVAL,
+                            move |admin, ctx| {
+                                admin.self_test_confirm_join(
+                                    test,
+                                    polls + 1,
+                                    ctx,
+                                )
+                            },
+                        );
+                    }
+                    Ok(None) => {
+                        test.record("join", Err("the server refused"));
+                        test.skip("part");
+                        admin.finish_self_test(test);
+                    }
+                    Err(e) => {
+                        test.record("join", Err(e));
+                        test.skip("part");
+                        admin.finish_self_test(test);
+                    }
+                }
+
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+
+    fn self_test_part(
+        &mut self,
+        test: SelfTest,
+        channel: Addr<Channel>,
+        ctx: &mut Context<Self>,
+    ) {
+        let leave = LeaveAndStop {
+            reason: Some(String::from("Self-test complete")),
+        };
+
+        let fut = channel.send(leave).into_actor(self).then(
+            move |outcome, admin, _ctx| {
+                let mut test = test;
+                test.record("part", outcome);
+                admin.finish_self_test(test);
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    fn finish_self_test(&self, test: SelfTest) {
+        let report = test.report.join(", ");
+        info!(self.logger, "Finished the self-test"; "report" => &report);
+
+        self.world.do_send(ReplyLines {
+            to: test.reply_to,
+            lines: vec![format!("Self-test: {}", report)],
+            requester: Some(test.requester),
+        });
+    }
+
+    fn set_command_enabled(&self, cmd: &CommandReceived, enabled: bool) {
+        let (channel, command) = match cmd.args.as_slice() {
+            [channel, command] => (channel.clone(), command.clone()),
+            _ => {
+                let usage = format!("{} <channel> <command>", cmd.name);
+                self.usage(cmd, &usage);
+                return;
+            }
+        };
+        let command = command.trim_start_matches(self.prefix).to_string();
+
+        let content = format!(
+            "{}{} is now {} in {}",
+            self.prefix,
+            command,
+            if enabled { "enabled" } else { "disabled" },
+            channel
+        );
+        self.world.do_send(SetCommandEnabled {
+            channel,
+            command,
+            enabled,
+        });
+        self.reply(&cmd.reply_to, content);
+    }
+
+    fn join(&self, cmd: &CommandReceived) {
+        match cmd.args.as_slice() {
+            [channel] => self.world.do_send(Join::new(channel.clone())),
+            [channel, key] => self.world.do_send(Join {
+                channels: channel.clone(),
+                keys: Some(key.clone()),
+            }),
+            _ => self.usage(cmd, "join <channel> [key]"),
+        }
+    }
+
+    fn part(&mut self, cmd: CommandReceived, ctx: &mut Context<Self>) {
+        let force = cmd.args.iter().any(|arg| arg == "--force");
+        let mut args = cmd.args.iter().filter(|arg| *arg != "--force");
+
+        let channels = match args.next() {
+            Some(channels) => channels.clone(),
+            None => {
+                self.usage(&cmd, "part <channel> [--force] [reason]");
+                return;
+            }
+        };
+        let reason: Vec<&str> = args.map(String::as_str).collect();
+        let part = Part {
+            channels,
+            reason: if reason.is_empty() {
+                None
+            } else {
+                Some(reason.join(" "))
+            },
+            force,
+        };
+
+        let fut = lift_err(self.world.send(part)).into_actor(self).then(
+            move |outcome: Result<(), Error>, admin, _ctx| {
+                if let Err(e) = outcome {
+                    warn!(admin.logger, "Unable to leave a channel";
+                        "error" => e.to_string());
+                    admin.reply(&cmd.reply_to, e.to_string());
+                }
+
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    fn quit(&self, cmd: &CommandReceived) {
+        let quit = if cmd.args.is_empty() {
+            Quit::default()
+        } else {
+            Quit::new(cmd.args.join(" "))
+        };
+        let sender = cmd.sender.clone().unwrap_or_default();
+
+        self.world
+            .do_send(quit.because(ShutdownReason::AdminCommand(sender)));
+    }
+
+    fn reload(&self, cmd: &CommandReceived) {
+        let path = match self.config {
+            Some(ref path) => path,
+            None => {
+                self.reply(
+                    &cmd.reply_to,
+                    String::from("I wasn't started with a config file"),
+                );
+                return;
+            }
+        };
+
+        match IrcConfig::load(path) {
+            Ok(config) => {
+                let channels = config.channels();
+                info!(self.logger, "Reloaded the config file";
+                    "channels" => format_args!("{:?}", channels));
+
+                for channel in &channels {
+                    self.world.do_send(Join {
+                        channels: channel.to_string(),
+                        keys: config.channel_key(channel).map(String::from),
+                    });
+                }
+
+                self.reply(
+                    &cmd.reply_to,
+                    format!("Reloaded, {} channels", channels.len()),
+                );
+            }
+            Err(e) => {
+                warn!(self.logger, "Unable to reload the config file";
+                    "error" => e.to_string());
+                self.reply(&cmd.reply_to, e.to_string());
+            }
+        }
+    }
+
+    fn set_log_level(&self, cmd: &CommandReceived) {
+        let log_level = match self.log_level {
+            Some(ref log_level) => log_level,
+            None => {
+                self.reply(
+                    &cmd.reply_to,
+                    String::from("The log level can't be changed"),
+                );
+                return;
+            }
+        };
+
+        let level = match cmd.args.as_slice() {
+            [level] => level.parse::<Level>().ok(),
+            _ => None,
+        };
+
+        match level {
+            Some(level) => {
+                info!(self.logger, "Changing the log level";
+                    "level" => level.as_str());
+                log_level.set(level);
+                self.reply(
+                    &cmd.reply_to,
+                    format!("Log level set to {}", level.as_str()),
+                );
+            }
+            None => self.usage(cmd, "loglevel <trace|debug|info|warn|error>"),
+        }
+    }
+}
+
+impl<C: Client + 'static> Actor for Admin<C> {
+    type Context = Context<Admin<C>>;
+}
+
+impl<C: Client + 'static> Handler<CommandReceived> for Admin<C> {
+    type Result = ();
+
+    fn handle(&mut self, cmd: CommandReceived, ctx: &mut Self::Context) {
+        let name = cmd.name.as_str();
+        let required = if ANYONE_COMMANDS.contains(&name) {
+            CommandAccess::Anyone
+        } else if ADMIN_COMMANDS.contains(&name) {
+            CommandAccess::Admins
+        } else if OWNER_COMMANDS.contains(&name) {
+            CommandAccess::Owner
+        } else {
+            return;
+        };
+
+        let sender = match cmd.sender {
+            Some(ref sender) => sender.clone(),
+            None => return,
+        };
+
+        if required == CommandAccess::Owner && utils::is_channel(&cmd.reply_to)
+        {
+            debug!(self.logger, "Ignoring an owner command sent to a channel";
+                "command" => &cmd.name,
+                "channel" => &cmd.reply_to);
+            return;
+        }
+
+        // nicks are trivial to spoof, so check who they're logged in as
+        let fut = lift_err(self.world.send(LookupAccount { nick: sender }))
+            .into_actor(self)
+            .then(move |account: Result<Option<String>, Error>, admin, ctx| {
+                let account = account.unwrap_or_else(|e| {
+                    warn!(admin.logger, "Unable to look up an account";
+                        "error" => e.to_string());
+                    None
+                });
+                let account = account.as_ref().map(String::as_str);
+                let access = admin.access(account);
+
+                if access >= required {
+                    info!(admin.logger, "Running an admin command";
+                        "command" => &cmd.name,
+                        "account" => account);
+                    admin.run(cmd, access, ctx);
+                } else {
+                    warn!(admin.logger, "Refusing to run an admin command";
+                        "command" => &cmd.name,
+                        "sender" => cmd.sender.as_ref(),
+                        "account" => account);
+                }
+
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+}
+
+/// Summarize each plugin (e.g. `topics (TopicReply, UserJoined)`), packing
+/// as many as we can onto each line.
+fn describe_plugins(plugins: &[PluginSummary], prefix: char) -> Vec<String> {
+    if plugins.is_empty() {
+        return vec![String::from("No plugins are registered")];
+    }
+
+    let descriptions = plugins.iter().map(|plugin| {
+        let mut description =
+            format!("{} ({})", plugin.name, plugin.subscriptions.join(", "));
+
+        if !plugin.commands.is_empty() {
+            let commands: Vec<String> = plugin
+                .commands
+                .iter()
+                .map(|name| format!("{}{}", prefix, name))
+                .collect();
+            description.push_str(&format!(" [{}]", commands.join(", ")));
+        }
+
+        description
+    });
+
+    utils::pack_lines(descriptions, "; ", MAX_LINE_LENGTH)
+}
+
+/// The usage (without the command prefix) and a description of each
+/// command, for `!help`.
+fn command_help(name: &str) -> Option<(&'static str, &'static str)> {
+    let help = match name {
+        "help" => ("help [command]", "List the commands you can use"),
+        "version" => ("version", "Show which build of the bot is running"),
+        "nick" => ("nick <new-nick>", "Change the bot's nick"),
+        "announce" => ("announce <message>", "Notify every channel"),
+        "modannounce" => {
+            ("modannounce <message>", "Notify the channels we moderate")
+        }
+        "plugins" => ("plugins", "List the plugins and what they handle"),
+        "selftest" => ("selftest", "Check the bot can send, join, and part"),
+        "stats" => (
+            "stats",
+            "Show the uptime, message count, channels, and backlog",
+        ),
+        "ping" => ("ping", "Measure the round trip to the server"),
+        "enable" => (
+            "enable <channel> <command>",
+            "Turn a command on in a channel",
+        ),
+        "disable" => (
+            "disable <channel> <command>",
+            "Turn a command off in a channel",
+        ),
+        "schedules" => ("schedules", "List the timers the bot is waiting on"),
+        "wiring" => (
+            "wiring [page]",
+            "Show which messages have subscribers, and who they are",
+        ),
+        "unschedule" => ("unschedule <id>", "Cancel one of the schedules"),
+        "join" => ("join <channel> [key]", "Join a channel"),
+        "part" => ("part <channel> [--force] [reason]", "Leave a channel"),
+        "quit" => ("quit [message]", "Shut the bot down"),
+        "reload" => ("reload", "Re-read the config file"),
+        "loglevel" => ("loglevel <level>", "Change the log level"),
+        _ => return None,
+    };
+
+    Some(help)
+}
+
+fn describe_commands(commands: &[CommandInfo], prefix: char) -> Vec<String> {
+    if commands.is_empty() {
+        return vec![String::from("There are no commands you can use")];
+    }
+
+    let names = commands.iter().map(|command| match command.description {
+        Some(ref description) => {
+            format!("{}{} ({})", prefix, command.name, description)
+        }
+        None => format!("{}{}", prefix, command.name),
+    });
+    let mut lines = utils::pack_lines(names, "; ", MAX_LINE_LENGTH);
+    lines.push(format!("Use {}help <command> for the details", prefix));
+
+    lines
+}
+
+fn describe_command(
+    commands: &[CommandInfo],
+    name: &str,
+    templates: &ReplyTemplates,
+    prefix: char,
+) -> Vec<String> {
+    let name = name.trim_start_matches(prefix);
+    let prefix = prefix.to_string();
+
+    let command = match commands.iter().find(|command| command.name == name) {
+        Some(command) => command,
+        None => {
+            return vec![templates.render(
+                "no-such-command",
+                "There's no {prefix}{command} command",
+                &[("command", name), ("prefix", &prefix)],
+            )]
+        }
+    };
+
+    let who = match command.access {
+        CommandAccess::Anyone => "anyone",
+        CommandAccess::Admins => "admins",
+        CommandAccess::Owner => "the owner",
+    };
+    let plugin = command.plugin.as_ref().map_or("?", String::as_str);
+
+    let mut lines = vec![format!(
+        "{}{} ({} plugin), usable by {}",
+        prefix, name, plugin, who
+    )];
+    lines.extend(command.description.clone());
+    if let Some(ref usage) = command.usage {
+        lines.push(format!("Usage: {}", usage));
+    }
+
+    lines
+}
+
+fn describe_stats(stats: &Stats) -> Vec<String> {
+    let mut items = vec![
+        format!("nick: {}", stats.nick.as_ref().map_or("?", String::as_str)),
+        format!("up {}", utils::human_duration(stats.uptime)),
+        format!("{} messages received", stats.messages_received),
+        format!("{} channels", stats.channels),
+        format!("{} messages waiting", stats.mailbox.backlog),
+    ];
+
+    if let Some(delay) = stats.mailbox.last_delay {
+        items.push(format!("mailbox delay {}ms", delay.as_millis()));
+    }
+
+    utils::pack_lines(items, ", ", MAX_LINE_LENGTH)
+}
+
+/// One page of the `!wiring` output, numbered from 1.
+fn wiring_page(wiring: &Wiring, page: usize, prefix: char) -> Vec<String> {
+    let lines = utils::pack_lines(wiring.lines(), "; ", MAX_LINE_LENGTH);
+    if lines.is_empty() {
+        return vec![String::from("Nothing is subscribed to anything")];
+    }
+
+    let pages =
+        (lines.len() + WIRING_LINES_PER_PAGE - 1) / WIRING_LINES_PER_PAGE;
+    if page > pages {
+        return vec![format!("There are only {} pages", pages)];
+    }
+
+    let mut got: Vec<String> = lines
+        .into_iter()
+        .skip((page - 1) * WIRING_LINES_PER_PAGE)
+        .take(WIRING_LINES_PER_PAGE)
+        .collect();
+    if pages > 1 {
+        got.push(format!(
+            "Page {} of {}, use {}wiring <page> for the rest",
+            page, pages, prefix
+        ));
+    }
+
+    got
+}
+
+fn describe_schedules(schedules: &[ScheduleSummary]) -> Vec<String> {
+    if schedules.is_empty() {
+        return vec![String::from("Nothing is scheduled")];
+    }
+
+    let items = schedules.iter().map(|s| {
+        let mut item = format!(
+            "{}: {} in {}",
+            s.id,
+            s.name,
+            utils::human_duration(s.due_in)
+        );
+        if let Some(interval) = s.interval {
+            item.push_str(&format!(
+                " (every {})",
+                utils::human_duration(interval)
+            ));
+        }
+        item
+    });
+
+    utils::pack_lines(items, "; ", MAX_LINE_LENGTH)
+}
+
+/// The progress of a `!selftest`.
+struct SelfTest {
+    reply_to: String,
+    requester: String,
+    /// How each step went (e.g. `"join: ok"`).
+    report: Vec<String>,
+}
+
+impl SelfTest {
+    fn record<E: Display>(&mut self, step: &str, outcome: Result<(), E>) {
+        let line = match outcome {
+            Ok(_) => format!("{}: ok", step),
+            Err(e) => format!("{}: failed ({})", step, e),
+        };
+
+        self.report.push(line);
+    }
+
+    fn skip(&mut self, step: &str) {
+        self.report.push(format!("{}: skipped", step));
+    }
+}
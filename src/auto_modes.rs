@@ -0,0 +1,403 @@
+//! Giving trusted users ops or voice when they join a channel.
+
+use actix::fut::WrapFuture;
+use actix::{Actor, ActorFuture, Addr, AsyncContext, Context, Handler};
+use crate::bot::lift_err;
+use crate::channel::MemberStatus;
+use crate::messages::{
+    ChangeModes, LookupAccount, OurStatusIn, Registration, UserJoined,
+};
+use crate::modes::ModeChange;
+use crate::utils;
+use crate::World;
+use failure::Error;
+use irc::client::Client;
+use slog::Logger;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How many modes we'll set in a single `MODE` command, the most RFC 2812
+/// servers accept.
+const MODES_PER_COMMAND: usize = 3;
+
+/// Automatically ops or voices trusted users when they join a channel we
+/// have ops in.
+///
+/// Joins are collected for a short delay before the modes go out together,
+/// so a netjoin or a join flood doesn't turn into a flood of `MODE`s.
+pub struct AutoModes<C: Client + 'static> {
+    logger: Logger,
+    world: Addr<World<C>>,
+    rules: Vec<AutoMode>,
+    delay: Duration,
+    /// The modes waiting to be set, keyed by the lowercased channel name.
+    pending: HashMap<String, PendingModes>,
+}
+
+impl<C: Client + 'static> AutoModes<C> {
+    /// Spawn an [`AutoModes`] actor in the background.
+    pub fn spawn(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        rules: Vec<AutoMode>,
+        delay: Duration,
+    ) -> Addr<AutoModes<C>> {
+        let auto_modes = AutoModes {
+            logger,
+            world: world.clone(),
+            rules,
+            delay,
+            pending: HashMap::new(),
+        };
+        let auto_modes = utils::start_isolated("auto-modes", auto_modes);
+
+        world.do_send(
+            Registration::<UserJoined>::register(
+                auto_modes.clone().recipient(),
+            )
+            .from_plugin("auto-modes"),
+        );
+
+        auto_modes
+    }
+
+    /// Remember to give someone a mode, setting everything waiting for a
+    /// channel once the delay is up.
+    fn queue(
+        &mut self,
+        channel: &str,
+        nick: &str,
+        mode: char,
+        ctx: &mut Context<Self>,
+    ) {
+        let key = utils::irc_lowercase(channel);
+        let first = !self.pending.contains_key(&key);
+
+        self.pending
+            .entry(key.clone())
+            .or_insert_with(|| PendingModes::new(channel))
+            .add(nick, mode);
+
+        if first {
+            ctx.run_later(self.delay, move |auto_modes, ctx| {
+                auto_modes.flush(&key, ctx)
+            });
+        }
+    }
+
+    /// Set the pending modes for a channel, as long as we're an op there.
+    fn flush(&mut self, key: &str, ctx: &mut Context<Self>) {
+        let pending = match self.pending.remove(key) {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        let fut = self
+            .world
+            .send(OurStatusIn {
+                channel: pending.channel.clone(),
+            })
+            .into_actor(self)
+            .then(move |status, auto_modes, _ctx| {
+                match status {
+                    Ok(Ok(Some(status))) if status >= MemberStatus::Op => {
+                        for change in pending.mode_changes() {
+                            auto_modes.world.do_send(ChangeModes(change));
+                        }
+                    }
+                    Ok(_) => {
+                        debug!(auto_modes.logger, "We aren't an op, so can't set anyone's modes";
+                            "channel" => &pending.channel,
+                            "skipped" => pending.modes.len());
+                    }
+                    Err(e) => {
+                        warn!(auto_modes.logger, "Unable to check our status in a channel";
+                            "channel" => &pending.channel,
+                            "error" => e.to_string());
+                    }
+                }
+
+                actix::fut::ok(())
+            });
+
+        ctx.spawn(fut);
+    }
+}
+
+impl<C: Client + 'static> Actor for AutoModes<C> {
+    type Context = Context<AutoModes<C>>;
+}
+
+impl<C: Client + 'static> Handler<UserJoined> for AutoModes<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: UserJoined, ctx: &mut Self::Context) {
+        if msg.is_us {
+            return;
+        }
+
+        let rules: Vec<AutoMode> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.applies_to(&msg.channel))
+            .cloned()
+            .collect();
+
+        if rules.is_empty() {
+            return;
+        }
+
+        let hostmask = msg.hostmask.as_ref().map(String::as_str);
+        let by_hostmask = mode_for(&rules, hostmask, None);
+        let wants_account = rules.iter().any(|rule| match rule.who {
+            Trusted::Account(_) => true,
+            Trusted::Hostmask(_) => false,
+        });
+
+        // only look up their account if it could get them something better
+        if by_hostmask == Some('o') || !wants_account {
+            if let Some(mode) = by_hostmask {
+                self.queue(&msg.channel, &msg.nick, mode, ctx);
+            }
+            return;
+        }
+
+        let fut = lift_err(self.world.send(LookupAccount {
+            nick: msg.nick.clone(),
+        }))
+        .into_actor(self)
+        .then(
+            move |account: Result<Option<String>, Error>, auto_modes, ctx| {
+                let account = account.unwrap_or_else(|e| {
+                    warn!(auto_modes.logger, "Unable to look up an account";
+                        "nick" => &msg.nick,
+                        "error" => e.to_string());
+                    None
+                });
+                let hostmask = msg.hostmask.as_ref().map(String::as_str);
+                let account = account.as_ref().map(String::as_str);
+
+                if let Some(mode) = mode_for(&rules, hostmask, account) {
+                    auto_modes.queue(&msg.channel, &msg.nick, mode, ctx);
+                }
+
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+}
+
+/// The best mode someone is trusted with (`o` beats `v`), if any.
+fn mode_for(
+    rules: &[AutoMode],
+    hostmask: Option<&str>,
+    account: Option<&str>,
+) -> Option<char> {
+    rules
+        .iter()
+        .filter(|rule| rule.who.matches(hostmask, account))
+        .map(|rule| rule.mode)
+        .max_by_key(|&mode| mode == 'o')
+}
+
+/// The modes waiting to be set in a channel.
+#[derive(Debug, Clone, PartialEq)]
+struct PendingModes {
+    channel: String,
+    modes: Vec<(char, String)>,
+}
+
+impl PendingModes {
+    fn new(channel: &str) -> PendingModes {
+        PendingModes {
+            channel: channel.to_string(),
+            modes: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, nick: &str, mode: char) {
+        let already_pending = self
+            .modes
+            .iter()
+            .any(|&(m, ref n)| m == mode && utils::same_nick(n, nick));
+
+        if !already_pending {
+            self.modes.push((mode, nick.to_string()));
+        }
+    }
+
+    /// Group the modes into as few `MODE` commands as we can.
+    fn mode_changes(&self) -> Vec<ModeChange> {
+        self.modes
+            .chunks(MODES_PER_COMMAND)
+            .map(|chunk| {
+                chunk.iter().fold(
+                    ModeChange::new(self.channel.clone()),
+                    |change, &(mode, ref nick)| {
+                        change.add_with(mode, nick.clone())
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+/// Someone to op or voice in a channel, written as `channel:mode:who` where
+/// `mode` is `o` or `v` and `who` is either an account or a hostmask (e.g.
+/// `#rust:o:Michael` or `*:v:*!*@rust/contributor/*`).
+///
+/// A channel of `*` applies to every channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoMode {
+    pub channel: String,
+    pub mode: char,
+    pub who: Trusted,
+}
+
+impl AutoMode {
+    fn applies_to(&self, channel: &str) -> bool {
+        self.channel == "*" || utils::same_nick(&self.channel, channel)
+    }
+}
+
+impl FromStr for AutoMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<AutoMode, Error> {
+        // hostmasks can contain a colon (e.g. IPv6 addresses)
+        let mut parts = s.splitn(3, ':');
+
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(channel), Some(mode), Some(who))
+                if (channel == "*" || utils::is_channel(channel))
+                    && (mode == "o" || mode == "v")
+                    && !who.is_empty() =>
+            {
+                let who = if who.contains('!') || who.contains('@') {
+                    Trusted::Hostmask(who.to_string())
+                } else {
+                    Trusted::Account(who.to_string())
+                };
+
+                Ok(AutoMode {
+                    channel: channel.to_string(),
+                    mode: if mode == "o" { 'o' } else { 'v' },
+                    who,
+                })
+            }
+            _ => Err(failure::format_err!(
+                "Expected \"channel:o:who\" or \"channel:v:who\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// How we recognise someone we trust.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trusted {
+    /// The account they're logged in as, which can't be spoofed.
+    Account(String),
+    /// A mask their `nick!user@host` has to match.
+    Hostmask(String),
+}
+
+impl Trusted {
+    fn matches(&self, hostmask: Option<&str>, account: Option<&str>) -> bool {
+        match (self, hostmask, account) {
+            (Trusted::Account(trusted), _, Some(account)) => {
+                utils::same_nick(trusted, account)
+            }
+            (Trusted::Hostmask(mask), Some(hostmask), _) => {
+                utils::matches_mask(mask, hostmask)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> Vec<AutoMode> {
+        rules.iter().map(|rule| rule.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parse_auto_modes() {
+        let got: AutoMode = "#rust:o:Michael".parse().unwrap();
+        assert_eq!(
+            got,
+            AutoMode {
+                channel: String::from("#rust"),
+                mode: 'o',
+                who: Trusted::Account(String::from("Michael")),
+            }
+        );
+
+        let got: AutoMode = "*:v:*!*@2001:db8::1".parse().unwrap();
+        assert_eq!(got.who, Trusted::Hostmask(String::from("*!*@2001:db8::1")));
+
+        assert!("#rust:b:Michael".parse::<AutoMode>().is_err());
+        assert!("rust:o:Michael".parse::<AutoMode>().is_err());
+        assert!("#rust:o:".parse::<AutoMode>().is_err());
+    }
+
+    #[test]
+    fn trusted_users_get_their_modes() {
+        let rules = rules(&["#rust:o:Michael", "#rust:v:*!*@rust/*"]);
+        let hostmask = Some("Michael!mike@example.com");
+
+        assert_eq!(mode_for(&rules, hostmask, Some("michael")), Some('o'));
+        assert_eq!(
+            mode_for(&rules, Some("Bob!bob@rust/contributor/bob"), None),
+            Some('v')
+        );
+        // ops beats voice when someone matches both
+        assert_eq!(
+            mode_for(&rules, Some("Michael!mike@rust/mod"), Some("Michael")),
+            Some('o')
+        );
+    }
+
+    #[test]
+    fn untrusted_users_get_nothing() {
+        let rules = rules(&["#rust:o:Michael", "#rust:v:*!*@rust/*"]);
+
+        // the same nick isn't enough without the account
+        assert_eq!(
+            mode_for(&rules, Some("Michael!mike@example.com"), None),
+            None
+        );
+        assert_eq!(
+            mode_for(&rules, Some("Eve!eve@example.com"), Some("eve")),
+            None
+        );
+    }
+
+    #[test]
+    fn batch_pending_modes() {
+        let mut pending = PendingModes::new("#rust");
+        for nick in &["a", "b", "c", "d"] {
+            pending.add(nick, 'v');
+        }
+        pending.add("A", 'v');
+
+        let got = pending.mode_changes();
+
+        assert_eq!(
+            got,
+            vec![
+                ModeChange::new("#rust")
+                    .add_with('v', "a")
+                    .add_with('v', "b")
+                    .add_with('v', "c"),
+                ModeChange::new("#rust").add_with('v', "d"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,295 @@
+use actix::{Actor, Addr, Arbiter, AsyncContext, Context, Handler};
+use crate::bot::lift_err;
+use crate::messages::{Join, RawMessage, Registration};
+use crate::utils;
+use crate::World;
+use failure::Error;
+use futures::future::Future;
+use irc::client::Client;
+use irc::proto::{Command, Response};
+use slog::Logger;
+use std::time::Duration;
+
+/// How many channels to put in each `JOIN` when the server doesn't tell us.
+const DEFAULT_JOIN_TARGETS: usize = 4;
+
+/// Joins the channels we were configured with once we've connected, spacing
+/// the `JOIN`s out so a long list of channels doesn't trip the server's flood
+/// protection.
+pub struct Autojoin<C: Client + 'static> {
+    logger: Logger,
+    world: Addr<World<C>>,
+    /// Each channel, plus its key (if it has one).
+    channels: Vec<(String, Option<String>)>,
+    /// How long to wait between each `JOIN`.
+    interval: Duration,
+    limits: JoinLimits,
+}
+
+impl<C: Client + 'static> Autojoin<C> {
+    /// Spawn an [`Autojoin`] actor in the background.
+    pub fn spawn(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        channels: Vec<(String, Option<String>)>,
+        interval: Duration,
+    ) -> Addr<Autojoin<C>> {
+        let autojoin = Autojoin {
+            logger,
+            world: world.clone(),
+            channels,
+            interval,
+            limits: JoinLimits::default(),
+        };
+        let autojoin = utils::start_isolated("autojoin", autojoin);
+
+        world.do_send(
+            Registration::<RawMessage>::register(autojoin.clone().recipient())
+                .from_plugin("autojoin"),
+        );
+
+        autojoin
+    }
+
+    fn join_all(&mut self, ctx: &mut Context<Self>) {
+        let (batches, skipped) = self.limits.plan(&self.channels);
+
+        for name in &skipped {
+            warn!(self.logger, "Not joining a channel because we'd go over the server's limit";
+                "channel" => name);
+        }
+
+        info!(self.logger, "Joining channels";
+            "channels" => self.channels.len() - skipped.len(),
+            "batches" => batches.len(),
+            "interval" => format_args!("{:?}", self.interval));
+
+        for (i, batch) in batches.into_iter().enumerate() {
+            ctx.run_later(self.interval * i as u32, move |autojoin, _ctx| {
+                autojoin.join(batch);
+            });
+        }
+    }
+
+    fn join(&self, batch: Join) {
+        debug!(self.logger, "Joining channels"; "channels" => &batch.channels);
+
+        let logger = self.logger.clone();
+        let channels = batch.channels.clone();
+        let fut = lift_err(self.world.send(batch));
+
+        Arbiter::spawn(fut.map_err(move |e: Error| {
+            error!(logger, "Unable to join a channel";
+                "channels" => channels,
+                "error" => e.to_string());
+        }));
+    }
+}
+
+/// What the server will let us join, from the `CHANLIMIT`, `TARGMAX` and
+/// `MAXTARGETS` tokens in its `RPL_ISUPPORT`.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct JoinLimits {
+    /// Groups of channel prefixes (e.g. `#&`), and how many of those
+    /// channels we can be in at once (`None` for no limit).
+    channel_limits: Vec<(String, Option<usize>)>,
+    /// How many channels a `JOIN` can have, from `TARGMAX`.
+    join_targets: Option<usize>,
+    /// How many targets any command can have, from `MAXTARGETS`.
+    max_targets: Option<usize>,
+}
+
+impl JoinLimits {
+    /// Update the limits from the tokens in a `RPL_ISUPPORT` (e.g.
+    /// `["CHANLIMIT=#&:20,+:", "TARGMAX=JOIN:5,PRIVMSG:4"]`).
+    fn update<'a, I>(&mut self, tokens: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        for token in tokens {
+            let mut parts = token.splitn(2, '=');
+            let name = parts.next().unwrap_or_default();
+            let value = parts.next().unwrap_or_default();
+
+            match name {
+                "CHANLIMIT" => {
+                    self.channel_limits = limits(value)
+                        .map(|(prefixes, limit)| (prefixes.to_string(), limit))
+                        .collect();
+                }
+                "TARGMAX" => {
+                    self.join_targets = limits(value)
+                        .find(|&(command, _)| command == "JOIN")
+                        .and_then(|(_, limit)| limit);
+                }
+                "MAXTARGETS" => self.max_targets = value.parse().ok(),
+                _ => {}
+            }
+        }
+    }
+
+    /// How many channels we can put in a single `JOIN`.
+    fn targets(&self) -> usize {
+        self.join_targets
+            .or(self.max_targets)
+            .unwrap_or(DEFAULT_JOIN_TARGETS)
+            .max(1)
+    }
+
+    /// Split the channels into `JOIN`s the server will accept, returning the
+    /// channels which would take us over its `CHANLIMIT`.
+    fn plan(
+        &self,
+        channels: &[(String, Option<String>)],
+    ) -> (Vec<Join>, Vec<String>) {
+        let mut joined = vec![0; self.channel_limits.len()];
+        let mut accepted = Vec::new();
+        let mut skipped = Vec::new();
+
+        for (name, key) in channels {
+            let prefix = name.chars().next().unwrap_or_default();
+            let group = self
+                .channel_limits
+                .iter()
+                .position(|(prefixes, _)| prefixes.contains(prefix));
+
+            if let Some(group) = group {
+                if let Some(limit) = self.channel_limits[group].1 {
+                    if joined[group] >= limit {
+                        skipped.push(name.clone());
+                        continue;
+                    }
+                }
+                joined[group] += 1;
+            }
+
+            accepted.push((name.clone(), key.clone()));
+        }
+
+        let batches = accepted.chunks(self.targets()).map(batch).collect();
+
+        (batches, skipped)
+    }
+}
+
+/// Parse a list like `#&:20,+:` into `[("#&", Some(20)), ("+", None)]`.
+fn limits(value: &str) -> impl Iterator<Item = (&str, Option<usize>)> {
+    value
+        .split(',')
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let mut parts = item.splitn(2, ':');
+            let name = parts.next().unwrap_or_default();
+            let limit = parts.next().and_then(|n| n.parse().ok());
+            (name, limit)
+        })
+}
+
+/// Combine several channels into one `JOIN`, putting the channels with keys
+/// first so the keys line up.
+fn batch(channels: &[(String, Option<String>)]) -> Join {
+    let (with_keys, without_keys): (Vec<_>, Vec<_>) =
+        channels.iter().partition(|(_, key)| key.is_some());
+
+    let names: Vec<&str> = with_keys
+        .iter()
+        .chain(&without_keys)
+        .map(|(name, _)| name.as_str())
+        .collect();
+    let keys: Vec<&str> = with_keys
+        .iter()
+        .filter_map(|(_, key)| key.as_ref().map(String::as_str))
+        .collect();
+
+    Join {
+        channels: names.join(","),
+        keys: if keys.is_empty() {
+            None
+        } else {
+            Some(keys.join(","))
+        },
+    }
+}
+
+impl<C: Client + 'static> Actor for Autojoin<C> {
+    type Context = Context<Autojoin<C>>;
+}
+
+impl<C: Client + 'static> Handler<RawMessage> for Autojoin<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: RawMessage, ctx: &mut Self::Context) {
+        match msg.0.command {
+            Command::Response(Response::RPL_ISUPPORT, ref args, _) => {
+                // the first argument is our nick
+                self.limits.update(args.iter().skip(1).map(String::as_str));
+            }
+            // the server sends its MOTD once we've finished registering
+            Command::Response(Response::RPL_ENDOFMOTD, ..)
+            | Command::Response(Response::ERR_NOMOTD, ..) => self.join_all(ctx),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels(names: &[&str]) -> Vec<(String, Option<String>)> {
+        names.iter().map(|name| (name.to_string(), None)).collect()
+    }
+
+    #[test]
+    fn parse_the_isupport_tokens() {
+        let mut limits = JoinLimits::default();
+        limits.update(vec![
+            "CHANLIMIT=#&:2,+:",
+            "TARGMAX=PRIVMSG:4,JOIN:3,NOTICE:4",
+            "MAXTARGETS=1",
+        ]);
+
+        assert_eq!(
+            limits.channel_limits,
+            vec![(String::from("#&"), Some(2)), (String::from("+"), None)]
+        );
+        assert_eq!(limits.targets(), 3);
+    }
+
+    #[test]
+    fn conservative_defaults() {
+        let limits = JoinLimits::default();
+
+        let (batches, skipped) =
+            limits.plan(&channels(&["#a", "#b", "#c", "#d", "#e"]));
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].channels, "#a,#b,#c,#d");
+        assert!(skipped.is_empty());
+    }
+
+    #[test]
+    fn stay_within_the_channel_limit() {
+        let mut limits = JoinLimits::default();
+        limits.update(vec!["CHANLIMIT=#:2,&:1", "MAXTARGETS=2"]);
+
+        let (batches, skipped) =
+            limits.plan(&channels(&["#a", "&b", "#c", "&d", "#e", "+f"]));
+        let got: Vec<&str> =
+            batches.iter().map(|join| join.channels.as_str()).collect();
+
+        assert_eq!(got, vec!["#a,&b", "#c,+f"]);
+        assert_eq!(skipped, vec!["&d", "#e"]);
+    }
+
+    #[test]
+    fn keys_line_up_with_their_channels() {
+        let got = batch(&[
+            (String::from("#open"), None),
+            (String::from("#secret"), Some(String::from("hunter2"))),
+        ]);
+
+        assert_eq!(got.channels, "#secret,#open");
+        assert_eq!(got.keys, Some(String::from("hunter2")));
+    }
+}
@@ -1,17 +1,34 @@
-use actix::msgs::StopArbiter;
-use actix::{Actor, Addr, Arbiter, Context, Handler};
-use crate::messages::{Connected, Identify, PrivateMessage, Registration};
+use actix::fut::WrapFuture;
+use actix::{Actor, ActorFuture, Addr, Arbiter, AsyncContext, Context, Handler};
+use crate::messages::{
+    Connected, Identified, Identify, PrivateMessage, Quit, RawMessage,
+    Registration, ShutdownReason, WhenReady,
+};
 use crate::World;
 use failure::Error;
-use futures::future::{self, Future};
+use futures::future::Future;
 use irc::client::Client;
+use irc::proto::Command;
 use slog::Logger;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long to wait for a service to respond before moving on to the next
+/// [`IdentifyAttempt`].
+pub const DEFAULT_IDENTIFY_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Clone)]
 pub struct Bot<C: Client + 'static> {
     logger: Logger,
     world: Addr<World<C>>,
     identify_password: String,
+    attempts: Vec<IdentifyAttempt>,
+    identify_timeout: Duration,
+    /// How long to wait after connecting before we start identifying, for
+    /// servers which ignore anything sent too soon.
+    identify_delay: Duration,
+    /// The index of the [`IdentifyAttempt`] we're waiting to hear back about.
+    pending: Option<usize>,
 }
 
 impl<C: Client + 'static> Bot<C> {
@@ -19,11 +36,17 @@ impl<C: Client + 'static> Bot<C> {
         logger: Logger,
         world: Addr<World<C>>,
         identify_password: String,
+        attempts: Vec<IdentifyAttempt>,
+        identify_delay: Duration,
     ) -> Bot<C> {
         Bot {
             logger,
             world,
             identify_password,
+            attempts,
+            identify_timeout: DEFAULT_IDENTIFY_TIMEOUT,
+            identify_delay,
+            pending: None,
         }
     }
 
@@ -33,15 +56,106 @@ impl<C: Client + 'static> Bot<C> {
         world: &Addr<World<C>>,
         identify_password: String,
     ) -> Addr<Bot<C>> {
-        let bot = Bot::new(logger, world.clone(), identify_password);
+        Bot::spawn_with_attempts(
+            logger,
+            world,
+            identify_password,
+            vec![IdentifyAttempt::nickserv()],
+            Duration::from_secs(0),
+        )
+    }
+
+    /// Spawn a [`Bot`] which will try each [`IdentifyAttempt`] in turn until
+    /// one of the services tells us we've been identified, waiting
+    /// `identify_delay` after connecting before it starts.
+    pub fn spawn_with_attempts(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        identify_password: String,
+        attempts: Vec<IdentifyAttempt>,
+        identify_delay: Duration,
+    ) -> Addr<Bot<C>> {
+        let bot = Bot::new(
+            logger,
+            world.clone(),
+            identify_password,
+            attempts,
+            identify_delay,
+        );
         let bot = bot.start();
 
-        world.do_send(Registration::<Connected>::register(
-            bot.clone().recipient(),
-        ));
+        world.do_send(
+            Registration::<Connected>::register(bot.clone().recipient())
+                .from_plugin("bot"),
+        );
+        world.do_send(
+            Registration::<RawMessage>::register(bot.clone().recipient())
+                .from_plugin("bot"),
+        );
 
         bot
     }
+
+    /// Tell the [`World`] our password, then start working through the
+    /// [`IdentifyAttempt`]s once we've registered.
+    fn identify(&mut self, ctx: &mut Context<Self>) {
+        let fut = lift_err(self.world.send(Identify))
+            .into_actor(self)
+            .map(|_, bot, ctx| {
+                // services ignore us until we've finished registering
+                let addr = ctx.address();
+                bot.world.do_send(WhenReady::new(move || {
+                    addr.do_send(StartIdentifying)
+                }));
+            })
+            .map_err(|e: Error, bot, _ctx| {
+                error!(bot.logger, "Unable to identify";
+                    "error" => e.to_string());
+                bot.world.do_send(fatal(e));
+            });
+
+        ctx.spawn(fut);
+    }
+
+    /// Send the identification message for a particular attempt, moving on to
+    /// the next one if the service doesn't reply in time.
+    fn try_identify(&mut self, index: usize, ctx: &mut Context<Self>) {
+        let attempt = match self.attempts.get(index) {
+            Some(attempt) => attempt.clone(),
+            None => {
+                error!(self.logger, "Unable to identify with any services";
+                    "attempts" => self.attempts.len());
+                self.pending = None;
+                return;
+            }
+        };
+
+        info!(self.logger, "Identifying";
+            "service" => &attempt.service,
+            "attempt" => index + 1,
+            "total-attempts" => self.attempts.len());
+        self.pending = Some(index);
+
+        let logger = self.logger.clone();
+        let world = self.world.clone();
+        let fut = lift_err(self.world.send(PrivateMessage {
+            to: attempt.service.clone(),
+            content: attempt.message(&self.identify_password),
+        }));
+
+        Arbiter::spawn(fut.map_err(move |e: Error| {
+            error!(logger, "Unable to identify"; "error" => e.to_string());
+            world.do_send(fatal(e));
+        }));
+
+        ctx.run_later(self.identify_timeout, move |bot, ctx| {
+            if bot.pending == Some(index) {
+                warn!(bot.logger, "Timed out waiting for a response";
+                    "service" => &attempt.service);
+                bot.try_identify(index + 1, ctx);
+            }
+        });
+    }
 }
 
 impl<C: Client + 'static> Actor for Bot<C> {
@@ -51,36 +165,309 @@ impl<C: Client + 'static> Actor for Bot<C> {
 impl<C: Client + 'static> Handler<Connected> for Bot<C> {
     type Result = ();
 
-    fn handle(&mut self, _msg: Connected, _ctx: &mut Self::Context) {
+    fn handle(&mut self, _msg: Connected, ctx: &mut Self::Context) {
         info!(self.logger, "Connected to server");
 
-        let world = self.world.clone();
-        let logger = self.logger.clone();
-        let identify_password = self.identify_password.clone();
-
-        let fut = lift_err(self.world.send(Identify));
-        let fut = lift_err(fut.and_then(move |_| {
-            world
-                .send(PrivateMessage {
-                    to: String::from("NickServ"),
-                    content: format!("IDENTIFY {}", identify_password),
-                })
-                .map_err(Error::from)
-        }));
+        if self.identify_delay > Duration::from_secs(0) {
+            debug!(self.logger, "Waiting before identifying";
+                "delay" => format_args!("{:?}", self.identify_delay));
+            ctx.run_later(self.identify_delay, |bot, ctx| bot.identify(ctx));
+        } else {
+            self.identify(ctx);
+        }
+    }
+}
 
-        Arbiter::spawn(fut.map_err(move |e: Error| {
-            error!(logger, "Unable to identify"; "error" => e.to_string());
-            Arbiter::current().do_send(StopArbiter(1));
-        }));
+/// Start working through the [`IdentifyAttempt`]s.
+#[derive(Debug, Copy, Clone, Message)]
+struct StartIdentifying;
+
+impl<C: Client + 'static> Handler<StartIdentifying> for Bot<C> {
+    type Result = ();
+
+    fn handle(&mut self, _msg: StartIdentifying, ctx: &mut Self::Context) {
+        self.try_identify(0, ctx);
+    }
+}
+
+impl<C: Client + 'static> Handler<RawMessage> for Bot<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: RawMessage, ctx: &mut Self::Context) {
+        let index = match self.pending {
+            Some(ix) => ix,
+            None => return,
+        };
+
+        let content = match msg.0.command {
+            Command::NOTICE(_, ref content) => content,
+            _ => return,
+        };
+
+        let attempt = &self.attempts[index];
+        let from_service = msg
+            .0
+            .source_nickname()
+            .map(|nick| nick.eq_ignore_ascii_case(attempt.service_nick()))
+            .unwrap_or(false);
+
+        if !from_service {
+            return;
+        }
+
+        match attempt.outcome(content) {
+            Some(true) => {
+                info!(self.logger, "Successfully identified";
+                    "service" => &attempt.service);
+                self.pending = None;
+                self.world.do_send(Identified {
+                    service: attempt.service.clone(),
+                });
+            }
+            Some(false) => {
+                warn!(self.logger, "Identification was rejected";
+                    "service" => &attempt.service,
+                    "response" => content);
+                self.try_identify(index + 1, ctx);
+            }
+            None => {}
+        }
+    }
+}
+
+/// One way of identifying with a network's services (e.g. `NickServ` or
+/// QuakeNet's `Q`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdentifyAttempt {
+    /// Who the identification message is sent to.
+    pub service: String,
+    /// The message to send, with `{password}` substituted for the password.
+    pub template: String,
+    /// Fragments of a `NOTICE` from the service which mean we're identified.
+    pub success: Vec<String>,
+    /// Fragments of a `NOTICE` from the service which mean we were rejected.
+    pub failure: Vec<String>,
+}
+
+impl IdentifyAttempt {
+    /// Create a new [`IdentifyAttempt`], using the responses we know about
+    /// for the well-known services.
+    pub fn new<S, T>(service: S, template: T) -> IdentifyAttempt
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        let service = service.into();
+        let (success, failure) = known_responses(&service);
+
+        IdentifyAttempt {
+            service,
+            template: template.into(),
+            success: success.iter().map(|s| s.to_string()).collect(),
+            failure: failure.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    /// The traditional `/msg NickServ IDENTIFY <password>`.
+    pub fn nickserv() -> IdentifyAttempt {
+        IdentifyAttempt::new("NickServ", "IDENTIFY {password}")
+    }
+
+    /// QuakeNet's `/msg Q@CServe.quakenet.org AUTH <username> <password>`.
+    pub fn quakenet(username: &str) -> IdentifyAttempt {
+        IdentifyAttempt::new(
+            "Q@CServe.quakenet.org",
+            format!("AUTH {} {{password}}", username),
+        )
+    }
+
+    /// The nick the service will send its responses from.
+    pub fn service_nick(&self) -> &str {
+        self.service.split('@').next().unwrap_or(&self.service)
+    }
+
+    /// The message to send to the service.
+    pub fn message(&self, password: &str) -> String {
+        self.template.replace("{password}", password)
+    }
+
+    /// Check a notice from the service, returning `Some(true)` if it means
+    /// we've identified successfully, `Some(false)` if we were rejected, and
+    /// `None` if it's unrelated.
+    pub fn outcome(&self, notice: &str) -> Option<bool> {
+        let notice = notice.to_lowercase();
+        let matches = |fragments: &[String]| {
+            fragments
+                .iter()
+                .any(|fragment| notice.contains(&fragment.to_lowercase()))
+        };
+
+        if matches(&self.failure) {
+            Some(false)
+        } else if matches(&self.success) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+/// Parses attempts written as `service:template` (e.g.
+/// `NickServ:LOGIN {password}`).
+impl FromStr for IdentifyAttempt {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<IdentifyAttempt, Error> {
+        let mut parts = s.splitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some(service), Some(template))
+                if !service.trim().is_empty()
+                    && !template.trim().is_empty() =>
+            {
+                Ok(IdentifyAttempt::new(service.trim(), template.trim()))
+            }
+            _ => Err(failure::format_err!(
+                "Expected \"service:template\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+fn known_responses(
+    service: &str,
+) -> (&'static [&'static str], &'static [&'static str]) {
+    const NICKSERV_SUCCESS: &[&str] = &[
+        "You are now identified",
+        "You are now logged in",
+        "Password accepted",
+    ];
+    const NICKSERV_FAILURE: &[&str] = &[
+        "Invalid password",
+        "Password incorrect",
+        "is not a registered nickname",
+        "isn't registered",
+    ];
+    const Q_SUCCESS: &[&str] = &["You are now logged in"];
+    const Q_FAILURE: &[&str] = &["Username or password incorrect"];
+
+    let nick = service.split('@').next().unwrap_or(service);
+
+    if nick.eq_ignore_ascii_case("Q") {
+        (Q_SUCCESS, Q_FAILURE)
+    } else {
+        (NICKSERV_SUCCESS, NICKSERV_FAILURE)
     }
 }
 
 /// Convert a future which returns a result into a future which will error when
 /// the inner result errors.
-fn lift_err<T, E>(
+pub(crate) fn lift_err<T, E>(
     fut: impl Future<Item = Result<T, impl Into<E>>, Error = impl Into<E>>,
 ) -> impl Future<Item = T, Error = E> {
     fut.map_err(Into::into)
         .then(|item| item.map(|inner| inner.map_err(Into::into)))
         .flatten()
 }
+
+/// Quit because we couldn't identify with services.
+fn fatal(e: Error) -> Quit {
+    let reason = format!("Unable to identify, {}", e);
+    Quit::default().because(ShutdownReason::Fatal(reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::MailboxError;
+    use futures::future;
+    use irc::error::IrcError;
+    use std::io;
+
+    fn io_error(msg: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, msg)
+    }
+
+    #[test]
+    fn lift_a_successful_result() {
+        let fut = future::ok::<Result<u32, io::Error>, io::Error>(Ok(42));
+
+        let got: Result<u32, Error> = lift_err(fut).wait();
+
+        assert_eq!(got.unwrap(), 42);
+    }
+
+    #[test]
+    fn lift_the_inner_error() {
+        let fut = future::ok::<Result<u32, io::Error>, io::Error>(Err(
+            io_error("inner"),
+        ));
+
+        let got: Result<u32, Error> = lift_err(fut).wait();
+
+        assert_eq!(got.unwrap_err().to_string(), "inner");
+    }
+
+    #[test]
+    fn lift_the_outer_error() {
+        let fut =
+            future::err::<Result<u32, io::Error>, io::Error>(io_error("outer"));
+
+        let got: Result<u32, Error> = lift_err(fut).wait();
+
+        assert_eq!(got.unwrap_err().to_string(), "outer");
+    }
+
+    #[test]
+    fn both_error_types_are_converted() {
+        // the same shape as sending a message to the World
+        let closed = future::err::<Result<(), IrcError>, MailboxError>(
+            MailboxError::Closed,
+        );
+        let got: Result<(), Error> = lift_err(closed).wait();
+        assert!(got.unwrap_err().downcast_ref::<MailboxError>().is_some());
+
+        let rejected = future::ok::<Result<(), IrcError>, MailboxError>(Err(
+            IrcError::NoUsableNick,
+        ));
+        let got: Result<(), Error> = lift_err(rejected).wait();
+        let err = got.unwrap_err();
+        match err.downcast_ref::<IrcError>() {
+            Some(IrcError::NoUsableNick) => {}
+            other => panic!("Expected NoUsableNick, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn the_password_is_substituted_into_the_template() {
+        let attempt = IdentifyAttempt::quakenet("michael");
+
+        assert_eq!(attempt.message("hunter2"), "AUTH michael hunter2");
+        assert_eq!(attempt.service_nick(), "Q");
+    }
+
+    #[test]
+    fn recognise_nickserv_responses() {
+        let attempt = IdentifyAttempt::nickserv();
+
+        assert_eq!(
+            attempt.outcome("You are now identified for \u{2}michael\u{2}."),
+            Some(true)
+        );
+        assert_eq!(
+            attempt.outcome("Invalid password for \u{2}michael\u{2}."),
+            Some(false)
+        );
+        assert_eq!(attempt.outcome("This nickname is registered."), None);
+    }
+
+    #[test]
+    fn parse_an_attempt_from_the_command_line() {
+        let got: IdentifyAttempt = "NickServ:LOGIN {password}".parse().unwrap();
+
+        assert_eq!(got, IdentifyAttempt::new("NickServ", "LOGIN {password}"));
+        assert!("NickServ".parse::<IdentifyAttempt>().is_err());
+        assert!(":IDENTIFY {password}".parse::<IdentifyAttempt>().is_err());
+    }
+}
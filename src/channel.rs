@@ -1,10 +1,483 @@
-use actix::{Actor, Context};
+use actix::{Actor, ActorContext, Context, Handler, MessageResult, Recipient};
+use crate::messages::{
+    ChannelLeft, ChannelSummary, EndOfNames, GetTopic, HostChanged, HostmaskOf,
+    Joined, LeaveAndStop, MemberCount, MemberJoined, MemberLeft,
+    MemberStatusOf, Members, NamesReply, NickChanged, Summarize, TopicReply,
+    WhoReply,
+};
+use crate::utils;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Clone)]
 pub struct Channel {
     pub name: String,
+    pub topic: Option<String>,
+    pub modes: Option<String>,
+    pub state: ChannelState,
+    /// Everyone in the channel, keyed by their lowercased nick.
+    members: HashMap<String, Member>,
+    /// Each member's `nick!user@host`, keyed by their lowercased nick, for
+    /// the members we know it for.
+    hostmasks: HashMap<String, String>,
+    /// The (lowercased) nicks mentioned in the `RPL_NAMREPLY`s we've seen
+    /// since the last `RPL_ENDOFNAMES`.
+    names_seen: HashSet<String>,
+    /// The `World` to notify when we leave.
+    world: Option<Recipient<ChannelLeft>>,
+}
+
+impl Channel {
+    pub fn new<S: Into<String>>(name: S) -> Channel {
+        Channel {
+            name: name.into(),
+            topic: None,
+            modes: None,
+            state: ChannelState::Joining,
+            members: HashMap::new(),
+            hostmasks: HashMap::new(),
+            names_seen: HashSet::new(),
+            world: None,
+        }
+    }
+
+    pub(crate) fn with_world(
+        mut self,
+        world: Recipient<ChannelLeft>,
+    ) -> Channel {
+        self.world = Some(world);
+        self
+    }
+}
+
+impl Debug for Channel {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Channel {
+            ref name,
+            ref topic,
+            ref modes,
+            state,
+            ref members,
+            ref hostmasks,
+            ref names_seen,
+            ref world,
+        } = *self;
+
+        f.debug_struct("Channel")
+            .field("name", name)
+            .field("topic", topic)
+            .field("modes", modes)
+            .field("state", &state)
+            .field("members", members)
+            .field("hostmasks", hostmasks)
+            .field("names_seen", names_seen)
+            .field("world", &world.is_some())
+            .finish()
+    }
 }
 
 impl Actor for Channel {
     type Context = Context<Channel>;
 }
+
+impl Handler<NamesReply> for Channel {
+    type Result = ();
+
+    fn handle(&mut self, msg: NamesReply, _ctx: &mut Self::Context) {
+        for name in &msg.names {
+            let (status, nick) = MemberStatus::parse(name);
+
+            if !nick.is_empty() {
+                let key = utils::irc_lowercase(nick);
+                self.names_seen.insert(key.clone());
+                self.members.insert(key, Member::new(nick, status));
+            }
+        }
+    }
+}
+
+impl Handler<EndOfNames> for Channel {
+    type Result = ();
+
+    fn handle(&mut self, _msg: EndOfNames, _ctx: &mut Self::Context) {
+        let seen = mem::replace(&mut self.names_seen, HashSet::new());
+        self.members.retain(|nick, _| seen.contains(nick));
+        self.hostmasks.retain(|nick, _| seen.contains(nick));
+    }
+}
+
+impl Handler<Joined> for Channel {
+    type Result = ();
+
+    fn handle(&mut self, _msg: Joined, _ctx: &mut Self::Context) {
+        self.state = ChannelState::Joined;
+    }
+}
+
+impl Handler<WhoReply> for Channel {
+    type Result = ();
+
+    fn handle(&mut self, msg: WhoReply, _ctx: &mut Self::Context) {
+        let status = MemberStatus::from_who_flags(&msg.flags);
+        let nick = utils::irc_lowercase(&msg.nick);
+
+        if let Some(hostmask) = msg.hostmask {
+            self.hostmasks.insert(nick.clone(), hostmask);
+        }
+        self.members.insert(nick, Member::new(msg.nick, status));
+    }
+}
+
+impl Handler<MemberJoined> for Channel {
+    type Result = ();
+
+    fn handle(&mut self, msg: MemberJoined, _ctx: &mut Self::Context) {
+        let nick = utils::irc_lowercase(&msg.nick);
+
+        match msg.hostmask {
+            Some(hostmask) => self.hostmasks.insert(nick.clone(), hostmask),
+            None => self.hostmasks.remove(&nick),
+        };
+        self.members
+            .insert(nick, Member::new(msg.nick, MemberStatus::Regular));
+    }
+}
+
+impl Handler<HostChanged> for Channel {
+    type Result = ();
+
+    fn handle(&mut self, msg: HostChanged, _ctx: &mut Self::Context) {
+        let nick = utils::irc_lowercase(&msg.nick);
+
+        if self.members.contains_key(&nick) {
+            self.hostmasks.insert(nick, msg.hostmask());
+        }
+    }
+}
+
+impl Handler<NickChanged> for Channel {
+    type Result = ();
+
+    fn handle(&mut self, msg: NickChanged, _ctx: &mut Self::Context) {
+        let old = utils::irc_lowercase(&msg.old);
+        let new = utils::irc_lowercase(&msg.new);
+
+        if let Some(member) = self.members.remove(&old) {
+            self.members.insert(
+                new.clone(),
+                Member::new(msg.new.clone(), member.status),
+            );
+        }
+
+        if let Some(hostmask) = self.hostmasks.remove(&old) {
+            // the nick is part of their hostmask
+            let user_and_host =
+                hostmask.splitn(2, '!').nth(1).unwrap_or_default();
+            self.hostmasks
+                .insert(new, format!("{}!{}", msg.new, user_and_host));
+        }
+    }
+}
+
+impl Handler<HostmaskOf> for Channel {
+    type Result = Option<String>;
+
+    fn handle(
+        &mut self,
+        msg: HostmaskOf,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        self.hostmasks
+            .get(&utils::irc_lowercase(&msg.nick))
+            .cloned()
+    }
+}
+
+impl Handler<MemberLeft> for Channel {
+    type Result = Option<usize>;
+
+    fn handle(
+        &mut self,
+        msg: MemberLeft,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let nick = utils::irc_lowercase(&msg.nick);
+        self.hostmasks.remove(&nick);
+        self.members.remove(&nick).map(|_| self.members.len())
+    }
+}
+
+impl Handler<TopicReply> for Channel {
+    type Result = ();
+
+    fn handle(&mut self, msg: TopicReply, _ctx: &mut Self::Context) {
+        self.topic = msg.topic;
+    }
+}
+
+impl Handler<GetTopic> for Channel {
+    type Result = Result<Option<String>, ()>;
+
+    fn handle(
+        &mut self,
+        _msg: GetTopic,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        Ok(self.topic.clone())
+    }
+}
+
+impl Handler<LeaveAndStop> for Channel {
+    type Result = ();
+
+    fn handle(&mut self, msg: LeaveAndStop, ctx: &mut Self::Context) {
+        if let Some(ref world) = self.world {
+            let _ = world.do_send(ChannelLeft {
+                channel: self.name.clone(),
+                reason: msg.reason,
+            });
+        }
+
+        self.topic = None;
+        self.modes = None;
+        self.members.clear();
+        self.hostmasks.clear();
+        ctx.stop();
+    }
+}
+
+impl Handler<MemberStatusOf> for Channel {
+    type Result = MessageResult<MemberStatusOf>;
+
+    fn handle(
+        &mut self,
+        msg: MemberStatusOf,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        MessageResult(
+            self.members
+                .get(&utils::irc_lowercase(&msg.nick))
+                .map(|member| member.status),
+        )
+    }
+}
+
+impl Handler<Members> for Channel {
+    type Result = Result<HashSet<String>, ()>;
+
+    fn handle(
+        &mut self,
+        _msg: Members,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        Ok(self
+            .members
+            .values()
+            .map(|member| member.nick.clone())
+            .collect())
+    }
+}
+
+impl Handler<MemberCount> for Channel {
+    type Result = Result<usize, ()>;
+
+    fn handle(
+        &mut self,
+        _msg: MemberCount,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        Ok(self.members.len())
+    }
+}
+
+impl Handler<Summarize> for Channel {
+    type Result = MessageResult<Summarize>;
+
+    fn handle(
+        &mut self,
+        _msg: Summarize,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        MessageResult(ChannelSummary {
+            name: self.name.clone(),
+            topic: self.topic.clone(),
+            member_count: self.members.len(),
+            modes: self.modes.clone(),
+            joined: self.state == ChannelState::Joined,
+        })
+    }
+}
+
+/// Someone in a channel.
+#[derive(Debug, Clone, PartialEq)]
+struct Member {
+    /// Their nick, as the server last spelled it.
+    nick: String,
+    status: MemberStatus,
+}
+
+impl Member {
+    fn new<S: Into<String>>(nick: S, status: MemberStatus) -> Member {
+        Member {
+            nick: nick.into(),
+            status,
+        }
+    }
+}
+
+/// Whether the server has confirmed we're in a channel yet.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChannelState {
+    /// We've sent a `JOIN` and are waiting to hear back.
+    Joining,
+    /// The server echoed our `JOIN` back to us.
+    Joined,
+}
+
+/// A user's privileges within a channel, from least to most privileged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MemberStatus {
+    Regular,
+    Voice,
+    HalfOp,
+    Op,
+    Admin,
+    Owner,
+}
+
+impl MemberStatus {
+    /// The status corresponding to a prefix in a `RPL_NAMREPLY` (e.g. the
+    /// `@` in `@Michael`).
+    pub fn from_prefix(prefix: char) -> Option<MemberStatus> {
+        match prefix {
+            '+' => Some(MemberStatus::Voice),
+            '%' => Some(MemberStatus::HalfOp),
+            '@' => Some(MemberStatus::Op),
+            '&' => Some(MemberStatus::Admin),
+            '~' => Some(MemberStatus::Owner),
+            _ => None,
+        }
+    }
+
+    /// Split a name from a `RPL_NAMREPLY` into the user's highest status and
+    /// their bare nick.
+    pub fn parse(name: &str) -> (MemberStatus, &str) {
+        let nick =
+            name.trim_start_matches(|c| MemberStatus::from_prefix(c).is_some());
+        let prefixes = &name[..name.len() - nick.len()];
+
+        let status = prefixes
+            .chars()
+            .filter_map(MemberStatus::from_prefix)
+            .max()
+            .unwrap_or(MemberStatus::Regular);
+
+        (status, nick)
+    }
+
+    /// The status from the flags in a `RPL_WHOREPLY` (e.g. `"H*@"` for an
+    /// oper who is here and has ops).
+    pub fn from_who_flags(flags: &str) -> MemberStatus {
+        flags
+            .chars()
+            .filter_map(MemberStatus::from_prefix)
+            .max()
+            .unwrap_or(MemberStatus::Regular)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::System;
+
+    #[test]
+    fn parse_names_with_prefixes() {
+        let inputs = vec![
+            ("Michael", MemberStatus::Regular, "Michael"),
+            ("+Michael", MemberStatus::Voice, "Michael"),
+            ("@Michael", MemberStatus::Op, "Michael"),
+            ("@+Michael", MemberStatus::Op, "Michael"),
+            ("~&@%+Michael", MemberStatus::Owner, "Michael"),
+        ];
+
+        for (src, status, nick) in inputs {
+            assert_eq!(MemberStatus::parse(src), (status, nick));
+        }
+    }
+
+    #[test]
+    fn parse_who_flags() {
+        let inputs = vec![
+            ("H", MemberStatus::Regular),
+            ("G+", MemberStatus::Voice),
+            ("H*@", MemberStatus::Op),
+            ("H@+", MemberStatus::Op),
+        ];
+
+        for (src, status) in inputs {
+            assert_eq!(MemberStatus::from_who_flags(src), status);
+        }
+    }
+
+    #[test]
+    fn forget_members_missing_from_a_fresh_names_list() {
+        let mut sys = System::new("test");
+        let channel = Channel::new("#rust").start();
+        let names = |names: &[&str]| NamesReply {
+            names: names.iter().map(|n| n.to_string()).collect(),
+        };
+
+        channel.do_send(names(&["bot", "@Michael", "Bob"]));
+        channel.do_send(EndOfNames);
+        channel.do_send(names(&["bot", "Michael"]));
+        channel.do_send(EndOfNames);
+
+        let status_of = |nick: &str| MemberStatusOf {
+            nick: nick.to_string(),
+        };
+        let michael = sys.block_on(channel.send(status_of("Michael")));
+        let bob = sys.block_on(channel.send(status_of("Bob")));
+
+        assert_eq!(michael.unwrap(), Some(MemberStatus::Regular));
+        assert_eq!(bob.unwrap(), None);
+    }
+
+    #[test]
+    fn list_members_without_their_prefixes() {
+        let mut sys = System::new("test");
+        let channel = Channel::new("#rust").start();
+
+        channel.do_send(NamesReply {
+            names: vec![
+                String::from("bot"),
+                String::from("@+Michael"),
+                String::from("+Bob"),
+            ],
+        });
+        channel.do_send(MemberJoined {
+            nick: String::from("Alice"),
+            hostmask: None,
+        });
+        channel.do_send(MemberLeft {
+            nick: String::from("bob"),
+        });
+
+        let members = Members {
+            channel: String::from("#rust"),
+        };
+        let got = sys.block_on(channel.send(members)).unwrap().unwrap();
+        let count = MemberCount {
+            channel: String::from("#rust"),
+        };
+        let count = sys.block_on(channel.send(count)).unwrap();
+
+        let should_be: HashSet<String> = vec!["bot", "Michael", "Alice"]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        assert_eq!(got, should_be);
+        assert_eq!(count, Ok(3));
+    }
+}
@@ -0,0 +1,334 @@
+//! Archiving what happens in each channel to a log file which reads like a
+//! normal IRC client's logs.
+
+use actix::{Actor, Addr, Context, Handler};
+use crate::messages::{
+    ChatEvent, Registration, UserJoined, UserKicked, UserParted, UserQuit,
+};
+use crate::utils;
+use crate::World;
+use irc::client::Client;
+use slog::Logger;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Which kinds of channel activity a [`ChannelLogger`] should write down.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChannelLogEvents {
+    /// What people say (`PRIVMSG`s and actions).
+    pub messages: bool,
+    /// People joining, leaving, being kicked, and quitting.
+    pub membership: bool,
+}
+
+impl Default for ChannelLogEvents {
+    fn default() -> ChannelLogEvents {
+        ChannelLogEvents {
+            messages: true,
+            membership: true,
+        }
+    }
+}
+
+/// Writes each channel's activity to its own file (e.g. `#rust.log`) in a
+/// directory.
+///
+/// The server doesn't say which channels someone was in when they quit, so a
+/// quit is only logged in the channels we've seen them in since we started.
+pub struct ChannelLogger {
+    logger: Logger,
+    dir: PathBuf,
+    events: ChannelLogEvents,
+    /// The open log files, keyed by the lowercased channel name.
+    files: HashMap<String, File>,
+    /// Who we've seen in each channel, keyed by the lowercased channel name.
+    seen: HashMap<String, HashSet<String>>,
+}
+
+impl ChannelLogger {
+    /// Spawn a [`ChannelLogger`] actor in the background.
+    pub fn spawn<C: Client + 'static>(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        dir: PathBuf,
+        events: ChannelLogEvents,
+    ) -> io::Result<Addr<ChannelLogger>> {
+        fs::create_dir_all(&dir)?;
+
+        let channel_logger = ChannelLogger {
+            logger,
+            dir,
+            events,
+            files: HashMap::new(),
+            seen: HashMap::new(),
+        };
+        let channel_logger =
+            utils::start_isolated("channel-logs", channel_logger);
+
+        // we need to hear about messages to know who's in each channel
+        world.do_send(
+            Registration::<ChatEvent>::register(
+                channel_logger.clone().recipient(),
+            )
+            .from_plugin("channel-logs"),
+        );
+
+        if events.membership {
+            world.do_send(
+                Registration::<UserJoined>::register(
+                    channel_logger.clone().recipient(),
+                )
+                .from_plugin("channel-logs"),
+            );
+            world.do_send(
+                Registration::<UserParted>::register(
+                    channel_logger.clone().recipient(),
+                )
+                .from_plugin("channel-logs"),
+            );
+            world.do_send(
+                Registration::<UserKicked>::register(
+                    channel_logger.clone().recipient(),
+                )
+                .from_plugin("channel-logs"),
+            );
+            world.do_send(
+                Registration::<UserQuit>::register(
+                    channel_logger.clone().recipient(),
+                )
+                .from_plugin("channel-logs"),
+            );
+        }
+
+        Ok(channel_logger)
+    }
+
+    fn write(&mut self, channel: &str, line: &str) {
+        let key = utils::irc_lowercase(channel);
+
+        if !self.files.contains_key(&key) {
+            // channel names can contain slashes
+            let filename = key.replace(|c: char| c == '/' || c == '\\', "_");
+            let path = self.dir.join(format!("{}.log", filename));
+
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(f) => {
+                    self.files.insert(key.clone(), f);
+                }
+                Err(e) => {
+                    warn!(self.logger, "Unable to open a channel log";
+                        "channel" => channel,
+                        "path" => format_args!("{}", path.display()),
+                        "error" => e.to_string());
+                    return;
+                }
+            }
+        }
+
+        let line = format!("[{}] {}\n", timestamp(SystemTime::now()), line);
+        let got = match self.files.get_mut(&key) {
+            Some(f) => f.write_all(line.as_bytes()),
+            None => return,
+        };
+
+        if let Err(e) = got {
+            warn!(self.logger, "Unable to write to a channel log";
+                "channel" => channel,
+                "error" => e.to_string());
+            // try opening it again next time
+            self.files.remove(&key);
+        }
+    }
+
+    fn saw(&mut self, channel: &str, nick: &str) {
+        self.seen
+            .entry(utils::irc_lowercase(channel))
+            .or_insert_with(HashSet::new)
+            .insert(utils::irc_lowercase(nick));
+    }
+
+    fn gone(&mut self, channel: &str, nick: &str) {
+        if let Some(seen) = self.seen.get_mut(&utils::irc_lowercase(channel)) {
+            seen.remove(&utils::irc_lowercase(nick));
+        }
+    }
+}
+
+impl Actor for ChannelLogger {
+    type Context = Context<ChannelLogger>;
+}
+
+/// The time of day (UTC) a line was written, e.g. `13:37:00`.
+fn timestamp(now: SystemTime) -> String {
+    let secs = now
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    format!(
+        "{:02}:{:02}:{:02}",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+fn with_reason(line: String, reason: Option<&String>) -> String {
+    match reason {
+        Some(reason) if !reason.is_empty() => format!("{} ({})", line, reason),
+        _ => line,
+    }
+}
+
+fn message_line(nick: &str, content: &str, is_action: bool) -> String {
+    if is_action {
+        format!("* {} {}", nick, content)
+    } else {
+        format!("<{}> {}", nick, content)
+    }
+}
+
+fn joined_line(msg: &UserJoined) -> String {
+    format!("*** {} has joined {}", msg.nick, msg.channel)
+}
+
+fn parted_line(msg: &UserParted) -> String {
+    let line = format!("*** {} has left {}", msg.nick, msg.channel);
+    with_reason(line, msg.reason.as_ref())
+}
+
+fn kicked_line(msg: &UserKicked) -> String {
+    let line = match msg.by {
+        Some(ref by) => format!("*** {} was kicked by {}", msg.nick, by),
+        None => format!("*** {} was kicked", msg.nick),
+    };
+    with_reason(line, msg.reason.as_ref())
+}
+
+fn quit_line(msg: &UserQuit) -> String {
+    let line = format!("*** {} has quit", msg.nick);
+    with_reason(line, msg.reason.as_ref())
+}
+
+impl Handler<ChatEvent> for ChannelLogger {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChatEvent, _ctx: &mut Self::Context) {
+        let (channel, sender) = match (msg.channel, msg.sender) {
+            (Some(channel), Some(sender)) => (channel, sender),
+            _ => return,
+        };
+
+        self.saw(&channel, &sender);
+
+        if self.events.messages {
+            let line = message_line(&sender, &msg.content, msg.is_action);
+            self.write(&channel, &line);
+        }
+    }
+}
+
+impl Handler<UserJoined> for ChannelLogger {
+    type Result = ();
+
+    fn handle(&mut self, msg: UserJoined, _ctx: &mut Self::Context) {
+        self.saw(&msg.channel, &msg.nick);
+        self.write(&msg.channel, &joined_line(&msg));
+    }
+}
+
+impl Handler<UserParted> for ChannelLogger {
+    type Result = ();
+
+    fn handle(&mut self, msg: UserParted, _ctx: &mut Self::Context) {
+        self.gone(&msg.channel, &msg.nick);
+        self.write(&msg.channel, &parted_line(&msg));
+    }
+}
+
+impl Handler<UserKicked> for ChannelLogger {
+    type Result = ();
+
+    fn handle(&mut self, msg: UserKicked, _ctx: &mut Self::Context) {
+        self.gone(&msg.channel, &msg.nick);
+        self.write(&msg.channel, &kicked_line(&msg));
+    }
+}
+
+impl Handler<UserQuit> for ChannelLogger {
+    type Result = ();
+
+    fn handle(&mut self, msg: UserQuit, _ctx: &mut Self::Context) {
+        let nick = utils::irc_lowercase(&msg.nick);
+        let channels: Vec<String> = self
+            .seen
+            .iter_mut()
+            .filter_map(|(channel, seen)| {
+                if seen.remove(&nick) {
+                    Some(channel.clone())
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        let line = quit_line(&msg);
+        for channel in channels {
+            self.write(&channel, &line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn format_channel_activity() {
+        let joined = UserJoined {
+            channel: String::from("#rust"),
+            nick: String::from("Michael"),
+            hostmask: None,
+            is_us: false,
+        };
+        let parted = UserParted {
+            channel: String::from("#rust"),
+            nick: String::from("Michael"),
+            reason: None,
+            is_us: false,
+        };
+        let kicked = UserKicked {
+            channel: String::from("#rust"),
+            nick: String::from("spammer"),
+            by: Some(String::from("Michael")),
+            reason: Some(String::from("No spam")),
+            is_us: false,
+        };
+        let quit = UserQuit {
+            nick: String::from("Michael"),
+            reason: Some(String::from("Ping timeout")),
+        };
+
+        assert_eq!(joined_line(&joined), "*** Michael has joined #rust");
+        assert_eq!(parted_line(&parted), "*** Michael has left #rust");
+        assert_eq!(
+            kicked_line(&kicked),
+            "*** spammer was kicked by Michael (No spam)"
+        );
+        assert_eq!(quit_line(&quit), "*** Michael has quit (Ping timeout)");
+        assert_eq!(message_line("Michael", "hello", false), "<Michael> hello");
+        assert_eq!(message_line("Michael", "waves", true), "* Michael waves");
+    }
+
+    #[test]
+    fn timestamps_are_the_time_of_day() {
+        let at = UNIX_EPOCH
+            + Duration::from_secs(3 * 86400 + 13 * 3600 + 37 * 60 + 5);
+
+        assert_eq!(timestamp(at), "13:37:05");
+    }
+}
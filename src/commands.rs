@@ -0,0 +1,369 @@
+//! Keeping track of which bot commands (e.g. `!nick`) the plugins handle.
+
+use crate::utils;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+/// The most typos we'll put up with when suggesting a command.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// Every command a plugin has said it handles, keyed by name.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, CommandInfo>,
+    /// Which commands each channel has turned off, keyed by the lowercased
+    /// channel name.
+    policies: BTreeMap<String, CommandPolicy>,
+    /// How each command's handler has been doing lately, keyed by name.
+    circuits: BTreeMap<String, CircuitBreaker>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> CommandRegistry {
+        CommandRegistry::default()
+    }
+
+    /// Add a command, replacing anything already registered with the same
+    /// name.
+    pub fn register(&mut self, info: CommandInfo) {
+        self.commands.insert(info.name.clone(), info);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CommandInfo> {
+        self.commands.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.commands.contains_key(name)
+    }
+
+    /// Every registered command, sorted by name.
+    pub fn iter(&self) -> impl Iterator<Item = &CommandInfo> {
+        self.commands.values()
+    }
+
+    /// The names of the commands a particular plugin handles.
+    pub fn handled_by(&self, plugin: &str) -> Vec<String> {
+        self.iter()
+            .filter(|info| {
+                info.plugin.as_ref().map(String::as_str) == Some(plugin)
+            })
+            .map(|info| info.name.clone())
+            .collect()
+    }
+
+    /// Can this command be used in a particular channel?
+    pub fn is_enabled_in(&self, channel: &str, command: &str) -> bool {
+        self.policy(channel)
+            .map_or(true, |policy| policy.is_enabled(command))
+    }
+
+    /// Turn a command on or off in a particular channel.
+    pub fn set_enabled_in(
+        &mut self,
+        channel: &str,
+        command: &str,
+        enabled: bool,
+    ) {
+        self.policies
+            .entry(utils::irc_lowercase(channel))
+            .or_insert_with(CommandPolicy::default)
+            .set_enabled(command, enabled);
+    }
+
+    pub fn policy(&self, channel: &str) -> Option<&CommandPolicy> {
+        self.policies.get(&utils::irc_lowercase(channel))
+    }
+
+    /// Should this command be passed on to its plugin right now, or has it
+    /// been failing too much?
+    pub fn is_available(
+        &mut self,
+        command: &str,
+        now: Instant,
+        cooldown: Duration,
+    ) -> bool {
+        match self.circuits.get_mut(command) {
+            Some(circuit) => circuit.allow(now, cooldown),
+            None => true,
+        }
+    }
+
+    /// Remember that a command's handler failed, returning `true` if it has
+    /// now failed `threshold` times in a row and should be switched off.
+    pub fn record_failure(
+        &mut self,
+        command: &str,
+        now: Instant,
+        threshold: usize,
+        cooldown: Duration,
+    ) -> bool {
+        self.circuits
+            .entry(command.to_string())
+            .or_insert_with(CircuitBreaker::default)
+            .failed(now, threshold, cooldown)
+    }
+
+    /// Remember that a command's handler succeeded, returning `true` if it
+    /// had been switched off and can now be used again.
+    pub fn record_success(&mut self, command: &str) -> bool {
+        self.circuits
+            .remove(command)
+            .map_or(false, |circuit| circuit.state != CircuitState::Closed)
+    }
+
+    /// The registered command whose name is closest to what someone typed,
+    /// as long as it's close enough to be a typo.
+    pub fn closest(&self, name: &str) -> Option<&str> {
+        self.commands
+            .keys()
+            .map(|candidate| (utils::levenshtein(name, candidate), candidate))
+            .filter(|&(distance, candidate)| {
+                distance <= MAX_SUGGESTION_DISTANCE
+                    && distance < candidate.len()
+            })
+            .min()
+            .map(|(_, candidate)| candidate.as_str())
+    }
+}
+
+/// What we know about a registered command.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandInfo {
+    /// The command's name, without the leading `!`.
+    pub name: String,
+    /// The plugin which handles the command.
+    pub plugin: Option<String>,
+    /// Who is allowed to use the command.
+    pub access: CommandAccess,
+    /// Where the command can be used.
+    pub context: CommandContext,
+    /// A one-line summary of what the command does.
+    pub description: Option<String>,
+    /// How to call the command (e.g. `!nick <new-nick>`).
+    pub usage: Option<String>,
+}
+
+/// Which commands are turned off in a channel (e.g. `!8ball` in a serious
+/// channel).
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CommandPolicy {
+    disabled: BTreeSet<String>,
+}
+
+impl CommandPolicy {
+    pub fn is_enabled(&self, command: &str) -> bool {
+        !self.disabled.contains(command)
+    }
+
+    pub fn set_enabled(&mut self, command: &str, enabled: bool) {
+        if enabled {
+            self.disabled.remove(command);
+        } else {
+            self.disabled.insert(command.to_string());
+        }
+    }
+
+    /// The commands which are turned off, sorted by name.
+    pub fn disabled(&self) -> impl Iterator<Item = &str> {
+        self.disabled.iter().map(String::as_str)
+    }
+}
+
+/// Keeps track of a command's recent failures, switching it off for a while
+/// once its plugin keeps failing (e.g. because an external service is down).
+#[derive(Debug, Default, Clone, PartialEq)]
+struct CircuitBreaker {
+    /// How many times in a row the command has failed.
+    failures: usize,
+    state: CircuitState,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum CircuitState {
+    /// The command is working normally.
+    Closed,
+    /// The command is switched off until `retry_at`.
+    Open { retry_at: Instant },
+    /// We've let a single use through to see whether the command has
+    /// recovered, and are waiting to hear how it went.
+    HalfOpen { retry_at: Instant },
+}
+
+impl Default for CircuitState {
+    fn default() -> CircuitState {
+        CircuitState::Closed
+    }
+}
+
+impl CircuitBreaker {
+    fn allow(&mut self, now: Instant, cooldown: Duration) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open { retry_at }
+            | CircuitState::HalfOpen { retry_at }
+                if now >= retry_at =>
+            {
+                // we'll try again later if this never reports back
+                self.state = CircuitState::HalfOpen {
+                    retry_at: now + cooldown,
+                };
+                true
+            }
+            CircuitState::Open { .. } | CircuitState::HalfOpen { .. } => false,
+        }
+    }
+
+    fn failed(
+        &mut self,
+        now: Instant,
+        threshold: usize,
+        cooldown: Duration,
+    ) -> bool {
+        self.failures += 1;
+
+        match self.state {
+            CircuitState::Closed
+                if threshold > 0 && self.failures >= threshold =>
+            {
+                self.state = CircuitState::Open {
+                    retry_at: now + cooldown,
+                };
+                true
+            }
+            CircuitState::Closed => false,
+            // the trial failed, so stay switched off
+            CircuitState::Open { .. } | CircuitState::HalfOpen { .. } => {
+                self.state = CircuitState::Open {
+                    retry_at: now + cooldown,
+                };
+                false
+            }
+        }
+    }
+}
+
+/// Who can use a command, from least to most privileged.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CommandAccess {
+    Anyone,
+    Admins,
+    Owner,
+}
+
+impl Default for CommandAccess {
+    fn default() -> CommandAccess {
+        CommandAccess::Anyone
+    }
+}
+
+/// Where a command can be used (e.g. keeping `!identify` out of channels).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CommandContext {
+    Anywhere,
+    /// Only in a channel.
+    ChannelOnly,
+    /// Only in a private message to the bot.
+    DmOnly,
+}
+
+impl CommandContext {
+    /// Can the command be used somewhere, given whether it was sent to a
+    /// channel?
+    pub fn allows(self, in_channel: bool) -> bool {
+        match self {
+            CommandContext::Anywhere => true,
+            CommandContext::ChannelOnly => in_channel,
+            CommandContext::DmOnly => !in_channel,
+        }
+    }
+}
+
+impl Default for CommandContext {
+    fn default() -> CommandContext {
+        CommandContext::Anywhere
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(names: &[&str]) -> CommandRegistry {
+        let mut registry = CommandRegistry::new();
+
+        for name in names {
+            registry.register(CommandInfo {
+                name: name.to_string(),
+                plugin: None,
+                access: CommandAccess::Anyone,
+                context: CommandContext::Anywhere,
+                description: None,
+                usage: None,
+            });
+        }
+
+        registry
+    }
+
+    #[test]
+    fn suggest_the_closest_command() {
+        let registry = registry(&["nick", "join", "part", "quit", "stats"]);
+
+        assert_eq!(registry.closest("nikc"), Some("nick"));
+        assert_eq!(registry.closest("stat"), Some("stats"));
+        assert_eq!(registry.closest("jion"), Some("join"));
+        assert_eq!(registry.closest("announce"), None);
+    }
+
+    #[test]
+    fn disable_commands_per_channel() {
+        let mut registry = registry(&["8ball", "nick"]);
+
+        registry.set_enabled_in("#Serious", "8ball", false);
+
+        assert!(!registry.is_enabled_in("#serious", "8ball"));
+        assert!(registry.is_enabled_in("#serious", "nick"));
+        assert!(registry.is_enabled_in("#rust", "8ball"));
+
+        registry.set_enabled_in("#serious", "8ball", true);
+        assert!(registry.is_enabled_in("#serious", "8ball"));
+    }
+
+    #[test]
+    fn restrict_where_commands_can_be_used() {
+        assert!(CommandContext::Anywhere.allows(true));
+        assert!(CommandContext::Anywhere.allows(false));
+        assert!(CommandContext::ChannelOnly.allows(true));
+        assert!(!CommandContext::ChannelOnly.allows(false));
+        assert!(!CommandContext::DmOnly.allows(true));
+        assert!(CommandContext::DmOnly.allows(false));
+    }
+
+    #[test]
+    fn switch_off_commands_which_keep_failing() {
+        let mut registry = registry(&["weather"]);
+        let cooldown = Duration::from_secs(60);
+        let start = Instant::now();
+
+        assert!(!registry.record_failure("weather", start, 2, cooldown));
+        assert!(registry.is_available("weather", start, cooldown));
+        assert!(registry.record_failure("weather", start, 2, cooldown));
+        assert!(!registry.is_available("weather", start, cooldown));
+
+        // once the cooldown is up a single trial gets through
+        let later = start + cooldown;
+        assert!(registry.is_available("weather", later, cooldown));
+        assert!(!registry.is_available("weather", later, cooldown));
+
+        // the trial failed, so we wait another cooldown
+        assert!(!registry.record_failure("weather", later, 2, cooldown));
+        assert!(!registry.is_available("weather", later, cooldown));
+
+        let even_later = later + cooldown;
+        assert!(registry.is_available("weather", even_later, cooldown));
+        assert!(registry.record_success("weather"));
+        assert!(registry.is_available("weather", even_later, cooldown));
+        assert!(!registry.record_success("weather"));
+    }
+}
@@ -0,0 +1,274 @@
+//! Streaming bot events to other processes on the same machine over a Unix
+//! domain socket.
+//!
+//! Each event is written as a line of JSON, the same objects a
+//! [`crate::WebhookSink`] sends (e.g. `{"event": "join", "channel": "#rust",
+//! "nick": "Michael"}`). Anyone who can open the socket can connect and start
+//! reading, and events are only sent to whoever is connected at the time.
+
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
+use crate::utils;
+use crate::webhook::{self, JsonEvent, WebhookEvent};
+use crate::World;
+use irc::client::Client;
+use slog::Logger;
+use std::fs;
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How often to check for new connections and retry blocked writes.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How far a client can fall behind before we disconnect it, so a consumer
+/// which stops reading can't make us buffer events forever.
+const MAX_PENDING_BYTES: usize = 64 * 1024;
+
+/// Subscribes to some of the bot's events and writes them to everyone
+/// connected to a Unix socket as newline-delimited JSON.
+pub struct EventSocket {
+    logger: Logger,
+    path: PathBuf,
+    listener: UnixListener,
+    subscribers: Vec<Subscriber>,
+}
+
+impl EventSocket {
+    /// Listen on a Unix socket, replacing one left behind by a previous run.
+    pub fn bind<P: Into<PathBuf>>(
+        path: P,
+        logger: Logger,
+    ) -> io::Result<EventSocket> {
+        let path = path.into();
+        remove_stale_socket(&path)?;
+
+        let listener = UnixListener::bind(&path)?;
+        // a slow or absent consumer must never hold up the bot
+        listener.set_nonblocking(true)?;
+
+        Ok(EventSocket {
+            logger,
+            path,
+            listener,
+            subscribers: Vec::new(),
+        })
+    }
+
+    /// Spawn an [`EventSocket`] listening at `path` and subscribe it to the
+    /// requested events.
+    pub fn spawn<C, P>(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        path: P,
+        events: &[WebhookEvent],
+    ) -> io::Result<Addr<EventSocket>>
+    where
+        C: Client + 'static,
+        P: Into<PathBuf>,
+    {
+        let socket = EventSocket::bind(path, logger)?;
+        info!(socket.logger, "Sending events to a Unix socket";
+            "path" => format_args!("{}", socket.path.display()));
+
+        let socket = utils::start_isolated("event-socket", socket);
+        webhook::subscribe_to_events(world, &socket, "event-socket", events);
+
+        Ok(socket)
+    }
+
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(_) => {
+                        debug!(self.logger, "Someone connected to the event socket";
+                            "connections" => self.subscribers.len() + 1);
+                        self.subscribers.push(Subscriber::new(stream));
+                    }
+                    Err(e) => {
+                        warn!(self.logger, "Unable to set up an event socket connection";
+                            "error" => e.to_string());
+                    }
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!(self.logger, "Unable to accept an event socket connection";
+                        "error" => e.to_string());
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Queue an event for everyone who's connected.
+    fn broadcast(&mut self, event: &str) {
+        for subscriber in &mut self.subscribers {
+            subscriber.pending.extend_from_slice(event.as_bytes());
+            subscriber.pending.push(b'\n');
+        }
+
+        self.flush();
+    }
+
+    /// Write as much as each connection will take without blocking,
+    /// dropping the ones which have gone away or fallen too far behind.
+    fn flush(&mut self) {
+        let subscribers = mem::replace(&mut self.subscribers, Vec::new());
+
+        for mut subscriber in subscribers {
+            match subscriber.flush() {
+                Ok(_) if subscriber.pending.len() > MAX_PENDING_BYTES => {
+                    warn!(self.logger, "Disconnecting an event socket client which isn't keeping up";
+                        "pending-bytes" => subscriber.pending.len());
+                }
+                Ok(_) => self.subscribers.push(subscriber),
+                Err(e) => {
+                    debug!(self.logger, "An event socket client disconnected";
+                        "error" => e.to_string());
+                }
+            }
+        }
+    }
+}
+
+impl Actor for EventSocket {
+    type Context = Context<EventSocket>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(POLL_INTERVAL, |socket, _ctx| {
+            socket.accept();
+            socket.flush();
+        });
+    }
+}
+
+impl Drop for EventSocket {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(self.logger, "Unable to remove the event socket";
+                "path" => format_args!("{}", self.path.display()),
+                "error" => e.to_string());
+        }
+    }
+}
+
+impl<M> Handler<M> for EventSocket
+where
+    M: JsonEvent + Message<Result = ()>,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: M, _ctx: &mut Self::Context) {
+        if !self.subscribers.is_empty() {
+            self.broadcast(&msg.to_json().to_string());
+        }
+    }
+}
+
+/// Someone connected to the [`EventSocket`].
+struct Subscriber {
+    stream: UnixStream,
+    /// Events which haven't been written yet.
+    pending: Vec<u8>,
+}
+
+impl Subscriber {
+    fn new(stream: UnixStream) -> Subscriber {
+        Subscriber {
+            stream,
+            pending: Vec::new(),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(())
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Clean up a socket left behind by a previous run which didn't shut down
+/// cleanly, without touching anything which isn't a socket.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(ref meta) if meta.file_type().is_socket() => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::Discard;
+    use std::io::{BufRead, BufReader};
+    use std::process;
+
+    fn event_socket(name: &str) -> EventSocket {
+        let path = std::env::temp_dir().join(format!(
+            "irc-bot-{}-{}.sock",
+            name,
+            process::id()
+        ));
+        EventSocket::bind(path, Logger::root(Discard, o!())).unwrap()
+    }
+
+    #[test]
+    fn events_are_written_to_each_client() {
+        let mut socket = event_socket("events");
+        let first = UnixStream::connect(&socket.path).unwrap();
+        let second = UnixStream::connect(&socket.path).unwrap();
+        socket.accept();
+
+        socket.broadcast(r#"{"event":"join"}"#);
+        socket.broadcast(r#"{"event":"part"}"#);
+
+        for client in vec![first, second] {
+            let lines: Vec<String> = BufReader::new(client)
+                .lines()
+                .take(2)
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(
+                lines,
+                vec![r#"{"event":"join"}"#, r#"{"event":"part"}"#]
+            );
+        }
+    }
+
+    #[test]
+    fn clients_which_go_away_are_dropped() {
+        let mut socket = event_socket("disconnect");
+        let client = UnixStream::connect(&socket.path).unwrap();
+        socket.accept();
+        assert_eq!(socket.subscribers.len(), 1);
+
+        drop(client);
+        socket.broadcast(r#"{"event":"quit"}"#);
+
+        assert!(socket.subscribers.is_empty());
+    }
+
+    #[test]
+    fn the_socket_is_removed_on_shutdown() {
+        let socket = event_socket("cleanup");
+        let path = socket.path.clone();
+        assert!(path.exists());
+
+        drop(socket);
+
+        assert!(!path.exists());
+    }
+}
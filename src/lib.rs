@@ -3,12 +3,56 @@ extern crate slog;
 #[macro_use]
 extern crate actix;
 
+mod accounts;
+mod admin;
+mod auto_modes;
+mod autojoin;
 mod bot;
 mod channel;
+mod channel_log;
+mod commands;
+#[cfg(unix)]
+mod event_socket;
+pub mod logging;
+mod mailbox;
 pub mod messages;
+pub mod middleware;
+mod modes;
+mod proxy;
+mod raw_log;
+mod relay;
+mod replay;
+mod settings;
+mod topics;
 mod utils;
+mod version;
+mod webhook;
 mod world;
 
-pub use crate::bot::Bot;
-pub use crate::utils::PanicHook;
-pub use crate::world::World;
+pub use crate::admin::Admin;
+pub use crate::auto_modes::{AutoMode, AutoModes, Trusted};
+pub use crate::autojoin::Autojoin;
+pub use crate::bot::{Bot, IdentifyAttempt};
+pub use crate::channel::MemberStatus;
+pub use crate::channel_log::{ChannelLogEvents, ChannelLogger};
+pub use crate::commands::{
+    CommandAccess, CommandContext, CommandInfo, CommandPolicy, CommandRegistry,
+};
+#[cfg(unix)]
+pub use crate::event_socket::EventSocket;
+pub use crate::mailbox::MailboxMonitor;
+pub use crate::modes::{ModeChange, ServerModes};
+pub use crate::proxy::{Proxy, ProxyError};
+pub use crate::raw_log::RawLog;
+pub use crate::relay::{Relay, RelayLink};
+pub use crate::replay::{Recording, Replay, ReplayClient, ReplayTiming};
+pub use crate::settings::{
+    ChannelRateLimit, DisabledCommand, GhostCommand, LongReplyPolicy,
+    PanicPolicy, ReconnectPolicy, RegistrationOrder, ReplyTemplate,
+    ReplyTemplates, Settings, UnjoinedChannelPolicy, UnknownCommandPolicy,
+};
+pub use crate::topics::{TopicAnnouncer, TopicTarget};
+pub use crate::utils::{start_isolated, PanicHook};
+pub use crate::version::{describe_version, git_hash, irc_version, version};
+pub use crate::webhook::{Webhook, WebhookError, WebhookEvent, WebhookSink};
+pub use crate::world::{ConnectionState, World};
@@ -0,0 +1,442 @@
+use failure::Error;
+use slog::{
+    BorrowedKV, Drain, Key, Level, Logger, Never, OwnedKVList, Record,
+    Serializer, KV,
+};
+use slog_term::{
+    CompactFormat, Decorator, FullFormat, PlainDecorator, PlainSyncDecorator,
+};
+use std::collections::VecDeque;
+use std::fmt::{self, Debug, Formatter, Write};
+use std::fs::OpenOptions;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// How the bot's logs should be written.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The log level, keep a copy around if you want to change it later.
+    pub level: LogLevel,
+    pub format: Format,
+    /// Write to a file instead of the terminal.
+    pub file: Option<PathBuf>,
+    /// How many recent messages to keep, whatever their level, so they can
+    /// be written out when something fatal happens (`0` keeps none).
+    pub flight_recorder: usize,
+}
+
+impl Options {
+    /// The usual mapping from the number of `-v` flags to a log level.
+    pub fn from_verbosity(verbosity: usize) -> Options {
+        let level = match verbosity {
+            0 => Level::Info,
+            1 => Level::Debug,
+            _ => Level::Trace,
+        };
+
+        Options {
+            level: LogLevel::new(level),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            level: LogLevel::new(Level::Info),
+            format: Format::Full,
+            file: None,
+            flight_recorder: 100,
+        }
+    }
+}
+
+/// The layout used for each log line.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    /// Every key-value pair on the same line as the message.
+    Full,
+    /// Group messages by their shared context.
+    Compact,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Format, Error> {
+        match s {
+            "full" => Ok(Format::Full),
+            "compact" => Ok(Format::Compact),
+            _ => Err(failure::format_err!(
+                "Expected one of \"full\" or \"compact\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Create the [`Logger`] used by the rest of the bot.
+pub fn init(opts: &Options) -> io::Result<Logger> {
+    let drain = match opts.file {
+        Some(ref path) => {
+            let f = OpenOptions::new().create(true).append(true).open(path)?;
+            formatted(PlainDecorator::new(f), opts.format)
+        }
+        None => formatted(slog_term::TermDecorator::new().build(), opts.format),
+    };
+
+    let drain = Arc::new(OrStderr::new(slog_async::Async::new(drain).build()));
+    let filtered = DynamicFilter::new(Arc::clone(&drain), opts.level.clone());
+    let drain =
+        FlightRecorder::new(filtered, drain, opts.flight_recorder).fuse();
+
+    Ok(Logger::root(drain, o!()))
+}
+
+fn formatted<D>(
+    decorator: D,
+    format: Format,
+) -> Box<dyn Drain<Ok = (), Err = Never> + Send>
+where
+    D: Decorator + Send + 'static,
+{
+    match format {
+        Format::Full => Box::new(FullFormat::new(decorator).build().fuse()),
+        Format::Compact => {
+            Box::new(CompactFormat::new(decorator).build().fuse())
+        }
+    }
+}
+
+/// A handle which can be used to change the log level while the bot is
+/// running.
+#[derive(Debug, Clone)]
+pub struct LogLevel(Arc<AtomicUsize>);
+
+impl LogLevel {
+    pub fn new(level: Level) -> LogLevel {
+        LogLevel(Arc::new(AtomicUsize::new(level.as_usize())))
+    }
+
+    pub fn get(&self) -> Level {
+        Level::from_usize(self.0.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+    }
+
+    pub fn set(&self, level: Level) {
+        self.0.store(level.as_usize(), Ordering::Relaxed);
+    }
+}
+
+/// A [`Drain`] which ignores anything less important than the current
+/// [`LogLevel`].
+#[derive(Debug)]
+pub struct DynamicFilter<D> {
+    drain: D,
+    level: LogLevel,
+}
+
+impl<D> DynamicFilter<D> {
+    pub fn new(drain: D, level: LogLevel) -> DynamicFilter<D> {
+        DynamicFilter { drain, level }
+    }
+}
+
+impl<D: Drain> Drain for DynamicFilter<D> {
+    type Ok = Option<D::Ok>;
+    type Err = D::Err;
+
+    fn log(
+        &self,
+        record: &Record,
+        values: &OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        if record.level().is_at_least(self.level.get()) {
+            self.drain.log(record, values).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// A [`Drain`] which remembers the last few messages regardless of the log
+/// level and, when a critical message comes through, writes them out in
+/// full before it.
+///
+/// This gives some context for post-mortems without needing to run at
+/// `debug` level all the time.
+pub struct FlightRecorder<D, U> {
+    /// Where messages normally go.
+    drain: D,
+    /// Where recorded messages go when they're written out, bypassing
+    /// whatever filtering `drain` does.
+    unfiltered: U,
+    capacity: usize,
+    recent: Mutex<VecDeque<Recorded>>,
+}
+
+struct Recorded {
+    level: Level,
+    /// The message, followed by its key-value pairs.
+    text: String,
+}
+
+impl<D, U> FlightRecorder<D, U> {
+    pub fn new(
+        drain: D,
+        unfiltered: U,
+        capacity: usize,
+    ) -> FlightRecorder<D, U> {
+        FlightRecorder {
+            drain,
+            unfiltered,
+            capacity,
+            recent: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// How many messages are waiting to be written out.
+    pub fn len(&self) -> usize {
+        self.recent
+            .lock()
+            .map(|recent| recent.len())
+            .unwrap_or_default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn record(&self, record: &Record, values: &OwnedKVList) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut text = KeyValues(record.msg().to_string());
+        let _ = record.kv().serialize(record, &mut text);
+        let _ = values.serialize(record, &mut text);
+
+        if let Ok(mut recent) = self.recent.lock() {
+            if recent.len() >= self.capacity {
+                recent.pop_front();
+            }
+            recent.push_back(Recorded {
+                level: record.level(),
+                text: text.0,
+            });
+        }
+    }
+
+    fn dump(&self)
+    where
+        U: Drain,
+    {
+        let recent: Vec<Recorded> = match self.recent.lock() {
+            Ok(mut recent) => recent.drain(..).collect(),
+            Err(_) => return,
+        };
+        if recent.is_empty() {
+            return;
+        }
+
+        let values = OwnedKVList::from(o!());
+        let header = format!("The last {} log messages were", recent.len());
+        let entries = recent.iter().map(|r| (r.level, r.text.as_str()));
+
+        for (level, text) in Some((Level::Critical, header.as_str()))
+            .into_iter()
+            .chain(entries)
+        {
+            let rs = record_static!(level, "flight-recorder");
+            let _ = self.unfiltered.log(
+                &Record::new(&rs, &format_args!("{}", text), BorrowedKV(&())),
+                &values,
+            );
+        }
+    }
+}
+
+impl<D: Drain, U: Drain> Drain for FlightRecorder<D, U> {
+    type Ok = D::Ok;
+    type Err = D::Err;
+
+    fn log(
+        &self,
+        record: &Record,
+        values: &OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        if record.level() == Level::Critical {
+            self.dump();
+        } else {
+            self.record(record, values);
+        }
+
+        self.drain.log(record, values)
+    }
+}
+
+impl<D: Debug, U> Debug for FlightRecorder<D, U> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("FlightRecorder")
+            .field("drain", &self.drain)
+            .field("capacity", &self.capacity)
+            .field("recorded", &self.len())
+            .finish()
+    }
+}
+
+/// Appends each key-value pair to a message (e.g. `"Joined, channel: #rust"`).
+struct KeyValues(String);
+
+impl Serializer for KeyValues {
+    fn emit_arguments(
+        &mut self,
+        key: Key,
+        val: &fmt::Arguments,
+    ) -> slog::Result {
+        let _ = write!(self.0, ", {}: {}", key, val);
+        Ok(())
+    }
+}
+
+/// A [`Drain`] which writes to stderr whenever the wrapped drain fails (e.g.
+/// because the background logging thread has died during shutdown), so
+/// errors never go missing without a trace.
+pub struct OrStderr<D> {
+    drain: D,
+    stderr: FullFormat<PlainSyncDecorator<io::Stderr>>,
+    failed: AtomicBool,
+}
+
+impl<D> OrStderr<D> {
+    pub fn new(drain: D) -> OrStderr<D> {
+        OrStderr {
+            drain,
+            stderr: FullFormat::new(PlainSyncDecorator::new(io::stderr()))
+                .build(),
+            failed: AtomicBool::new(false),
+        }
+    }
+
+    /// Has the wrapped drain ever failed?
+    pub fn has_failed(&self) -> bool {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+impl<D: Drain> Drain for OrStderr<D>
+where
+    D::Err: Debug,
+{
+    type Ok = ();
+    type Err = Never;
+
+    fn log(
+        &self,
+        record: &Record,
+        values: &OwnedKVList,
+    ) -> Result<Self::Ok, Self::Err> {
+        if let Err(e) = self.drain.log(record, values) {
+            if !self.failed.swap(true, Ordering::Relaxed) {
+                eprintln!("Logging failed ({:?}), falling back to stderr", e);
+            }
+
+            // there's nowhere left to report this error
+            let _ = self.stderr.log(record, values);
+        }
+
+        Ok(())
+    }
+}
+
+impl<D: Debug> Debug for OrStderr<D> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("OrStderr")
+            .field("drain", &self.drain)
+            .field("failed", &self.has_failed())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A drain whose backing thread has already gone away.
+    #[derive(Debug)]
+    struct Broken;
+
+    impl Drain for Broken {
+        type Ok = ();
+        type Err = io::Error;
+
+        fn log(&self, _: &Record, _: &OwnedKVList) -> io::Result<()> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "logger stopped"))
+        }
+    }
+
+    #[test]
+    fn fall_back_to_stderr_when_the_drain_fails() {
+        let drain = Arc::new(OrStderr::new(Broken));
+        let logger = Logger::root(Arc::clone(&drain), o!());
+
+        error!(logger, "Something went wrong"; "answer" => 42);
+
+        assert!(drain.has_failed());
+    }
+
+    /// Keeps a copy of everything logged to it.
+    #[derive(Debug, Default)]
+    struct Captured(Mutex<Vec<(Level, String)>>);
+
+    impl Drain for Captured {
+        type Ok = ();
+        type Err = Never;
+
+        fn log(&self, record: &Record, _: &OwnedKVList) -> Result<(), Never> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((record.level(), record.msg().to_string()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_out_recent_messages_when_something_critical_happens() {
+        let captured = Arc::new(Captured::default());
+        let filtered = DynamicFilter::new(
+            Arc::clone(&captured),
+            LogLevel::new(Level::Info),
+        );
+        let drain =
+            Arc::new(FlightRecorder::new(filtered, Arc::clone(&captured), 2));
+        let logger =
+            Logger::root(Arc::clone(&drain).fuse(), o!("plugin" => "admin"));
+
+        debug!(logger, "Too old to be remembered");
+        debug!(logger, "Looking up an account"; "nick" => "Michael");
+        info!(logger, "Running an admin command");
+        assert_eq!(captured.0.lock().unwrap().len(), 1);
+
+        crit!(logger, "Shutting down after a fatal error");
+
+        let got = captured.0.lock().unwrap();
+        let messages: Vec<_> =
+            got.iter().map(|(_, msg)| msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Running an admin command",
+                "The last 2 log messages were",
+                "Looking up an account, nick: Michael, plugin: admin",
+                "Running an admin command, plugin: admin",
+                "Shutting down after a fatal error",
+            ]
+        );
+        assert!(drain.is_empty());
+    }
+}
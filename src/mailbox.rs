@@ -0,0 +1,89 @@
+//! Keeping an eye on how far behind the [`World`] is.
+//!
+//! actix doesn't tell us how many messages are waiting in an actor's
+//! mailbox, so the [`World`] counts the messages it reads from the server
+//! and how many it has handled, and every so often we send it a probe to see
+//! how long a message waits before it gets looked at. A growing backlog (or
+//! a probe which sat in the mailbox for a long time) means something,
+//! usually a slow plugin, is stopping the bot from keeping up.
+
+use actix::fut::WrapFuture;
+use actix::{Actor, ActorFuture, Addr, AsyncContext, Context};
+use crate::messages::{MailboxProbe, MailboxStats};
+use crate::utils;
+use crate::World;
+use irc::client::Client;
+use slog::Logger;
+use std::time::{Duration, Instant};
+
+/// How long a probe can wait in the mailbox before we start complaining.
+const SLOW_MAILBOX: Duration = Duration::from_secs(1);
+
+/// Periodically probes the [`World`]'s mailbox and logs how far behind it
+/// is.
+pub struct MailboxMonitor<C: Client + 'static> {
+    logger: Logger,
+    world: Addr<World<C>>,
+    interval: Duration,
+}
+
+impl<C: Client + 'static> MailboxMonitor<C> {
+    /// Spawn a [`MailboxMonitor`] on its own thread, so it keeps probing
+    /// even when everything else is bogged down.
+    pub fn spawn(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        interval: Duration,
+    ) -> Addr<MailboxMonitor<C>> {
+        let monitor = MailboxMonitor {
+            logger,
+            world: world.clone(),
+            interval,
+        };
+
+        utils::start_isolated("mailbox-monitor", monitor)
+    }
+
+    fn probe(&mut self, ctx: &mut Context<Self>) {
+        let probe = MailboxProbe {
+            sent: Instant::now(),
+        };
+        let fut = self.world.send(probe).into_actor(self).then(
+            |outcome, monitor, _ctx| {
+                match outcome {
+                    Ok(stats) => monitor.report(&stats),
+                    Err(e) => {
+                        warn!(monitor.logger, "Unable to probe the World's mailbox";
+                            "error" => e.to_string());
+                    }
+                }
+
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    fn report(&self, stats: &MailboxStats) {
+        let delay = stats.last_delay.unwrap_or_default();
+
+        if delay >= SLOW_MAILBOX {
+            warn!(self.logger, "The World is falling behind";
+                "delay" => format_args!("{:?}", delay),
+                "backlog" => stats.backlog);
+        } else {
+            debug!(self.logger, "Probed the World's mailbox";
+                "delay" => format_args!("{:?}", delay),
+                "backlog" => stats.backlog);
+        }
+    }
+}
+
+impl<C: Client + 'static> Actor for MailboxMonitor<C> {
+    type Context = Context<MailboxMonitor<C>>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |monitor, ctx| monitor.probe(ctx));
+    }
+}
@@ -1,34 +1,154 @@
+use actix::actors::signal::SignalType;
 use actix::dev::ToEnvelope;
 use actix::{Actor, Addr, Handler, Message, Recipient};
-use crate::channel::Channel;
+use crate::channel::{Channel, MemberStatus};
+use crate::commands::{CommandAccess, CommandContext, CommandInfo};
+use crate::middleware::{Interceptor, Middleware};
+use crate::modes::ModeChange;
 use crate::utils::MessageBox;
 use failure::Backtrace;
 use irc::error::IrcError;
 use irc::proto::message::Message as IrcMessage;
+use irc::proto::Response;
+use serde::Serialize;
 use std::any::Any;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Debug, Display, Formatter};
 use std::panic::PanicInfo;
+use std::str::FromStr;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// A raw, unprocessed IRC message.
+///
+/// Sending one of these to the [`crate::World`] is processed exactly as if
+/// it had just arrived from the server, which makes it handy for driving the
+/// bot from tests or scripts. Keep in mind this bypasses the network
+/// entirely, so the server never knows about it.
+///
+/// ```rust,ignore
+/// let msg: RawMessage = ":Michael!mike@example.com PRIVMSG #rust :!nick new-bot"
+///     .parse()?;
+/// world.do_send(msg);
+/// ```
 #[derive(Debug, Clone, PartialEq, Message)]
 pub struct RawMessage(pub IrcMessage);
 
+impl FromStr for RawMessage {
+    type Err = IrcError;
+
+    fn from_str(s: &str) -> Result<RawMessage, IrcError> {
+        s.parse().map(RawMessage)
+    }
+}
+
 /// Tell the IRC client to disconnect from the server and halt the actor system.
-#[derive(Debug, Message)]
+///
+/// The `{uptime}` and `{version}` placeholders in the quit message are
+/// filled in when we quit.
+#[derive(Debug, Default, Message)]
 pub struct Quit {
-    pub msg: String,
+    /// What to tell everyone as we leave, or `None` to use
+    /// [`crate::Settings::quit_message`].
+    pub msg: Option<String>,
+    pub reason: ShutdownReason,
 }
 
 impl Quit {
     pub fn new<S: Into<String>>(msg: S) -> Quit {
-        Quit { msg: msg.into() }
+        Quit {
+            msg: Some(msg.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Say why we're quitting.
+    pub fn because(mut self, reason: ShutdownReason) -> Quit {
+        self.reason = reason;
+        self
+    }
+}
+
+/// Why the bot is shutting down.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShutdownReason {
+    /// Someone asked us to quit by sending a [`Quit`] message.
+    Requested,
+    /// The process received a signal (e.g. `SIGTERM`).
+    Signal(SignalType),
+    /// An admin used the `!quit` command.
+    AdminCommand(String),
+    /// Something went wrong and we can't carry on.
+    Fatal(String),
+}
+
+impl ShutdownReason {
+    /// Did someone deliberately shut us down, as opposed to something going
+    /// wrong? There's no point reconnecting after an intentional quit.
+    pub fn is_intentional(&self) -> bool {
+        match *self {
+            ShutdownReason::Fatal(_) => false,
+            _ => true,
+        }
+    }
+
+    /// The code the process should exit with.
+    ///
+    /// We can't recover from a fatal error by ourselves, so exit with a
+    /// non-zero code and let whoever is supervising the bot restart it.
+    pub fn exit_code(&self) -> i32 {
+        if self.is_intentional() {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+impl Default for ShutdownReason {
+    fn default() -> ShutdownReason {
+        ShutdownReason::Requested
+    }
+}
+
+impl Display for ShutdownReason {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            ShutdownReason::Requested => write!(f, "Asked to quit"),
+            ShutdownReason::Signal(signal) => {
+                write!(f, "Received a {:?} signal", signal)
+            }
+            ShutdownReason::AdminCommand(ref nick) => {
+                write!(f, "{} used the quit command", nick)
+            }
+            ShutdownReason::Fatal(ref error) => write!(f, "{}", error),
+        }
     }
 }
 
-impl Default for Quit {
-    fn default() -> Quit {
-        Quit::new("Leaving...")
+/// Run something once we've finished registering with the server, or
+/// straight away if we already have.
+///
+/// This is handy for plugins which are started before the connection is up
+/// but can't do anything useful until the server has welcomed us.
+pub struct WhenReady(pub Box<dyn FnOnce() + Send>);
+
+impl WhenReady {
+    pub fn new<F>(f: F) -> WhenReady
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        WhenReady(Box::new(f))
+    }
+}
+
+impl Message for WhenReady {
+    type Result = ();
+}
+
+impl Debug for WhenReady {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("WhenReady").field(&"...").finish()
     }
 }
 
@@ -36,6 +156,26 @@ impl Default for Quit {
 #[derive(Debug, Clone, Message)]
 pub struct Connected;
 
+/// The server closed the connection unexpectedly and we've opened a new one.
+///
+/// Anything a plugin knew about the old connection (e.g. who is in which
+/// channel) is stale. We'll register again and rejoin the channels we were
+/// in.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct Reconnected {
+    /// How many attempts it took.
+    pub attempts: usize,
+}
+
+/// We've registered and joined the channels in
+/// [`crate::Settings::wait_for_channels`] (or given up waiting for them), so
+/// we're taking commands.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct Ready {
+    /// The channels we didn't manage to join in time.
+    pub missing: Vec<String>,
+}
+
 /// Send a private message.
 #[derive(Debug, Clone)]
 pub struct PrivateMessage {
@@ -47,16 +187,305 @@ impl Message for PrivateMessage {
     type Result = Result<(), IrcError>;
 }
 
+impl PrivateMessage {
+    /// Wait for the server to confirm it has delivered this message.
+    pub fn confirmed(self) -> ConfirmedPrivateMessage {
+        ConfirmedPrivateMessage(self)
+    }
+}
+
+/// Send a [`PrivateMessage`], resolving once the server echoes it back.
+///
+/// This relies on the `echo-message` and `labeled-response` capabilities,
+/// when the server doesn't support them we resolve as soon as the message has
+/// been sent.
+#[derive(Debug, Clone)]
+pub struct ConfirmedPrivateMessage(pub PrivateMessage);
+
+impl Message for ConfirmedPrivateMessage {
+    type Result = Result<(), IrcError>;
+}
+
+/// Change our nick, resolving once the server has confirmed the change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeNick {
+    pub nick: String,
+}
+
+impl Message for ChangeNick {
+    type Result = Result<(), IrcError>;
+}
+
+/// Reply with several lines, sent in order as separate messages.
+///
+/// To avoid accidentally flooding a channel, replies to a channel which are
+/// longer than [`crate::Settings::max_reply_lines`] are either refused or
+/// sent to the `requester` as a direct message instead. Empty lines are
+/// skipped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplyLines {
+    pub to: String,
+    pub lines: Vec<String>,
+    /// Who asked for the reply, if anyone.
+    pub requester: Option<String>,
+}
+
+impl Message for ReplyLines {
+    type Result = Result<(), IrcError>;
+}
+
+/// Send a `NOTICE` to a user or channel.
+///
+/// Notices go through the same middleware and checks as a
+/// [`PrivateMessage`], but bots are never supposed to reply to them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notice {
+    pub to: String,
+    pub content: String,
+}
+
+impl Message for Notice {
+    type Result = Result<(), IrcError>;
+}
+
+/// Send a `NOTICE` to every channel we're in (e.g. "Restarting in 5
+/// minutes"), resolving with the number of channels which were notified.
+///
+/// Announcements must be a single line and only go to the first few hundred
+/// channels, so a compromised admin account can't do too much damage.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnnounceAll {
+    pub content: String,
+}
+
+impl Message for AnnounceAll {
+    type Result = Result<usize, IrcError>;
+}
+
+/// Like [`AnnounceAll`], except the `NOTICE` only goes to the channels where
+/// we're an op (e.g. for moderation announcements), resolving with the number
+/// of channels which were notified.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModAnnounce {
+    pub content: String,
+}
+
+impl Message for ModAnnounce {
+    type Result = Result<usize, IrcError>;
+}
+
+/// Measure the round trip to the server by sending a `PING` and waiting for
+/// the matching `PONG`.
+///
+/// Everyone registered for [`Latency`] hears about the result too.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MeasureLatency;
+
+impl Message for MeasureLatency {
+    type Result = Result<Duration, IrcError>;
+}
+
+/// Ask the server which nick we actually have, in case we missed a forced
+/// nick change (e.g. during a netsplit) and our idea of it has drifted.
+///
+/// This sends a `WHOIS` for the nick we think we have and takes the nick the
+/// server addresses its reply to as the truth, resolving with it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ResyncNick;
+
+impl Message for ResyncNick {
+    type Result = Result<String, IrcError>;
+}
+
+/// The server answered one of our [`MeasureLatency`] pings.
+#[derive(Debug, Copy, Clone, PartialEq, Message)]
+pub struct Latency {
+    pub rtt: Duration,
+}
+
+/// Send a CTCP query to someone, resolving with their reply.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CtcpRequest {
+    pub to: String,
+    /// The CTCP command (e.g. `VERSION`).
+    pub command: String,
+    pub args: Option<String>,
+}
+
+impl CtcpRequest {
+    pub fn new<S, T>(to: S, command: T) -> CtcpRequest
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        CtcpRequest {
+            to: to.into(),
+            command: command.into(),
+            args: None,
+        }
+    }
+
+    /// Ask which client someone is using.
+    pub fn version<S: Into<String>>(to: S) -> CtcpRequest {
+        CtcpRequest::new(to, "VERSION")
+    }
+
+    /// Ping someone, the reply will contain the current time (in
+    /// milliseconds since the unix epoch) so you can work out the round
+    /// trip.
+    pub fn ping<S: Into<String>>(to: S) -> CtcpRequest {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let millis = now.as_secs() * 1000 + u64::from(now.subsec_millis());
+
+        CtcpRequest {
+            args: Some(millis.to_string()),
+            ..CtcpRequest::new(to, "PING")
+        }
+    }
+
+    /// Ask someone what time it is for them.
+    pub fn time<S: Into<String>>(to: S) -> CtcpRequest {
+        CtcpRequest::new(to, "TIME")
+    }
+}
+
+impl Message for CtcpRequest {
+    type Result = Result<Option<String>, IrcError>;
+}
+
+/// Add a [`Middleware`] to the end of the chain every outgoing message is
+/// passed through.
+#[derive(Message)]
+pub struct AddMiddleware(pub Middleware);
+
+/// Add an [`Interceptor`] to the end of the chain every message from the
+/// server is passed through.
+#[derive(Message)]
+pub struct AddInterceptor(pub Interceptor);
+
 /// Attempt to join a channel.
 #[derive(Debug, Clone)]
 pub struct Join {
+    /// A comma-separated list of channels.
     pub channels: String,
+    /// The comma-separated keys for any channels which need them.
+    pub keys: Option<String>,
+}
+
+impl Join {
+    pub fn new<S: Into<String>>(channels: S) -> Join {
+        Join {
+            channels: channels.into(),
+            keys: None,
+        }
+    }
 }
 
 impl Message for Join {
     type Result = Result<(), IrcError>;
 }
 
+/// Leave one or more channels.
+///
+/// Sticky channels (see [`crate::Settings::sticky_channels`]) are only left
+/// when the part is forced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    /// A comma-separated list of channels.
+    pub channels: String,
+    pub reason: Option<String>,
+    /// Leave even if one of the channels is sticky.
+    pub force: bool,
+}
+
+impl Part {
+    pub fn new<S: Into<String>>(channels: S) -> Part {
+        Part {
+            channels: channels.into(),
+            reason: None,
+            force: false,
+        }
+    }
+}
+
+impl Message for Part {
+    type Result = Result<(), IrcError>;
+}
+
+/// The server refused to let us join a channel.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct JoinFailed {
+    pub channel: String,
+    pub reason: JoinFailure,
+    /// The human-readable explanation sent by the server.
+    pub message: Option<String>,
+}
+
+/// We were turned away from a registered-only (`+r`) channel before we'd
+/// identified, so we'll try again once we have.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct JoinDeferred {
+    pub channel: String,
+    /// The human-readable explanation sent by the server.
+    pub reason: Option<String>,
+}
+
+/// Let the [`crate::World`] know we've identified with services, so it can
+/// retry any [`JoinDeferred`] channels.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct Identified {
+    /// The service we identified with (e.g. `NickServ`).
+    pub service: String,
+}
+
+/// Why a [`JoinFailed`].
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum JoinFailure {
+    /// The channel has hit its user limit (`+l`).
+    ChannelIsFull,
+    /// The channel is invite-only (`+i`).
+    InviteOnly,
+    /// We're banned from the channel (`+b`).
+    Banned,
+    /// We didn't provide the right channel key (`+k`).
+    BadChannelKey,
+    /// Only people identified with services can join (`+r`).
+    RegisteredOnly,
+}
+
+impl JoinFailure {
+    /// Check whether a numeric is one of the ways a `JOIN` can fail.
+    pub fn from_response(response: Response) -> Option<JoinFailure> {
+        match response {
+            Response::ERR_CHANNELISFULL => Some(JoinFailure::ChannelIsFull),
+            Response::ERR_INVITEONLYCHAN => Some(JoinFailure::InviteOnly),
+            Response::ERR_BANNEDFROMCHAN => Some(JoinFailure::Banned),
+            Response::ERR_BADCHANNELKEY => Some(JoinFailure::BadChannelKey),
+            // the irc crate knows 477 by its RFC 2812 name, but most
+            // networks use it for "you need to be identified"
+            Response::ERR_NOCHANMODES => Some(JoinFailure::RegisteredOnly),
+            _ => None,
+        }
+    }
+}
+
+/// Ask the server which channels exist (`LIST`), optionally only those
+/// matching a mask like `"#rust*"`.
+///
+/// Each channel comes back as its name, user count, and topic. Big networks
+/// have a *lot* of channels, so the server may throttle or even refuse the
+/// request and only the first few thousand channels are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListChannels {
+    pub filter: Option<String>,
+}
+
+impl Message for ListChannels {
+    type Result = Result<Vec<(String, usize, String)>, IrcError>;
+}
+
 /// Identify the IRC client with the server, typically by sending a nick and
 /// username.
 #[derive(Debug, Clone)]
@@ -66,6 +495,28 @@ impl Message for Identify {
     type Result = Result<(), IrcError>;
 }
 
+/// The server refused a nick because it contains characters which aren't
+/// allowed (`ERR_ERRONEOUSNICKNAME`).
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct NickInvalid {
+    pub nick: String,
+    /// The human-readable explanation sent by the server.
+    pub reason: Option<String>,
+}
+
+/// Someone else is already using a nick (`ERR_NICKNAMEINUSE`).
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct NickInUse {
+    pub nick: String,
+}
+
+/// The server killed a nick because it collided with one on another server
+/// (`ERR_NICKCOLLISION`).
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct NickCollision {
+    pub nick: String,
+}
+
 /// The server sent a *NOT REGISTERED* message.
 #[derive(Debug, Clone, PartialEq, Message)]
 pub struct NotRegistered {
@@ -82,6 +533,9 @@ where
 {
     register: bool,
     recipient: Recipient<M>,
+    /// The plugin doing the registering, if it wants to show up in
+    /// [`ListPlugins`].
+    plugin: Option<String>,
 }
 
 impl<M> Registration<M>
@@ -93,9 +547,16 @@ where
         Registration {
             recipient,
             register,
+            plugin: None,
         }
     }
 
+    /// Say which plugin this registration is for.
+    pub fn from_plugin<S: Into<String>>(mut self, name: S) -> Registration<M> {
+        self.plugin = Some(name.into());
+        self
+    }
+
     pub fn register(recipient: Recipient<M>) -> Registration<M> {
         Registration::new(recipient, true)
     }
@@ -116,16 +577,41 @@ where
         let Registration {
             register,
             recipient,
+            plugin,
         } = self;
 
         if register {
-            message_box.register(recipient);
+            message_box.register(recipient.clone());
         } else {
             message_box.unregister(&recipient);
         }
+
+        if let Some(plugin) = plugin {
+            if register {
+                message_box.track(&plugin, recipient);
+                message_box.subscribe::<M>(&plugin);
+            } else {
+                message_box.untrack(&plugin, &recipient);
+                message_box.unsubscribe::<M>(&plugin);
+            }
+        }
     }
 }
 
+/// Unsubscribe a plugin from every message it registered for, whatever
+/// their types, resolving with how many registrations were removed.
+///
+/// Only registrations made with [`Registration::from_plugin()`] are known
+/// to belong to a plugin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnregisterAll {
+    pub plugin: String,
+}
+
+impl Message for UnregisterAll {
+    type Result = usize;
+}
+
 /// A panic has occurred.
 #[derive(Debug, Default, Message)]
 pub struct Panic {
@@ -190,9 +676,818 @@ impl Message for Channels {
     type Result = HashMap<String, Addr<Channel>>;
 }
 
+/// Ask for a summary of every channel we're in, sorted by name.
+///
+/// Channels which take too long to respond are left out.
+#[derive(Debug, Copy, Clone)]
+pub struct ChannelSummaries;
+
+impl Message for ChannelSummaries {
+    type Result = Result<Vec<ChannelSummary>, ()>;
+}
+
+/// Let the [`crate::World`] know a plugin handles a particular command, so
+/// it shows up in `!help` and isn't treated as unknown.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct RegisterCommand {
+    /// The command's name, without the leading `!`.
+    pub name: String,
+    pub plugin: Option<String>,
+    /// Who can use the command, so `!help` only shows people what they're
+    /// allowed to run.
+    pub access: CommandAccess,
+    /// Whether the command only makes sense in a channel or in a private
+    /// message.
+    pub context: CommandContext,
+    /// A one-line summary of what the command does.
+    pub description: Option<String>,
+    /// How to call the command (e.g. `!nick <new-nick>`).
+    pub usage: Option<String>,
+}
+
+impl RegisterCommand {
+    pub fn new<S: Into<String>>(name: S) -> RegisterCommand {
+        RegisterCommand {
+            name: name.into(),
+            plugin: None,
+            access: CommandAccess::Anyone,
+            context: CommandContext::Anywhere,
+            description: None,
+            usage: None,
+        }
+    }
+
+    pub fn with_description<S: Into<String>>(
+        mut self,
+        description: S,
+    ) -> RegisterCommand {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn with_usage<S: Into<String>>(mut self, usage: S) -> RegisterCommand {
+        self.usage = Some(usage.into());
+        self
+    }
+
+    pub fn restricted_to(mut self, access: CommandAccess) -> RegisterCommand {
+        self.access = access;
+        self
+    }
+
+    /// Only accept the command in channels or in private messages.
+    pub fn only_in(mut self, context: CommandContext) -> RegisterCommand {
+        self.context = context;
+        self
+    }
+
+    /// Say which plugin handles the command.
+    pub fn from_plugin<S: Into<String>>(mut self, name: S) -> RegisterCommand {
+        self.plugin = Some(name.into());
+        self
+    }
+}
+
+/// Turn a command on or off in a particular channel.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct SetCommandEnabled {
+    pub channel: String,
+    /// The command's name, without the leading `!`.
+    pub command: String,
+    pub enabled: bool,
+}
+
+/// Let the [`crate::World`] know how handling a [`CommandReceived`] went.
+///
+/// A command which keeps failing (e.g. because the service behind it is
+/// down) is switched off for a while, with anyone trying to use it told it's
+/// temporarily unavailable.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct CommandCompleted {
+    /// The command's name, without the leading `!`.
+    pub command: String,
+    /// What went wrong, if anything.
+    pub error: Option<String>,
+}
+
+impl CommandCompleted {
+    pub fn succeeded<S: Into<String>>(command: S) -> CommandCompleted {
+        CommandCompleted {
+            command: command.into(),
+            error: None,
+        }
+    }
+
+    pub fn failed<S, E>(command: S, error: E) -> CommandCompleted
+    where
+        S: Into<String>,
+        E: Display,
+    {
+        CommandCompleted {
+            command: command.into(),
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// A command failed too many times in a row and has been switched off for a
+/// while.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct CircuitOpened {
+    pub command: String,
+    /// The most recent failure.
+    pub error: Option<String>,
+}
+
+/// A command which was switched off by a [`CircuitOpened`] is working again.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct CircuitClosed {
+    pub command: String,
+}
+
+/// Get every command registered with [`RegisterCommand`], sorted by name.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ListCommands;
+
+impl Message for ListCommands {
+    type Result = Vec<CommandInfo>;
+}
+
+/// Get every plugin which registered itself with
+/// [`Registration::from_plugin()`], and the messages and commands they
+/// handle.
+#[derive(Debug, Copy, Clone)]
+pub struct ListPlugins;
+
+impl Message for ListPlugins {
+    type Result = Vec<PluginSummary>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PluginSummary {
+    pub name: String,
+    /// The names of the message types the plugin is subscribed to.
+    pub subscriptions: Vec<&'static str>,
+    /// The commands the plugin registered with [`RegisterCommand`].
+    pub commands: Vec<String>,
+}
+
+/// Get the timers the [`crate::World`] is waiting on (e.g. re-syncing
+/// channels), soonest first.
+#[derive(Debug, Copy, Clone)]
+pub struct ListSchedules;
+
+impl Message for ListSchedules {
+    type Result = Vec<ScheduleSummary>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduleSummary {
+    /// The number to pass to [`CancelSchedule`].
+    pub id: usize,
+    /// What the timer does (e.g. `"resync-channels"`).
+    pub name: String,
+    /// How often it repeats (`None` if it only fires once).
+    pub interval: Option<Duration>,
+    /// How long until it next fires.
+    pub due_in: Duration,
+}
+
+/// Stop a timer from [`ListSchedules`] before it fires, resolving to `false`
+/// if there was no such timer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CancelSchedule {
+    pub id: usize,
+}
+
+impl Message for CancelSchedule {
+    type Result = bool;
+}
+
+/// How far the server's clock is from ours, worked out from the
+/// `server-time` tags on the messages it sends us.
+///
+/// This resolves to `None` until we've heard enough timestamped messages to
+/// say.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GetClockSkew;
+
+impl Message for GetClockSkew {
+    type Result = Option<ClockSkew>;
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ClockSkew {
+    /// How many milliseconds the server's clock is ahead of ours (negative
+    /// if it's behind), including however long messages take to reach us.
+    pub millis: i64,
+    /// How many messages the estimate is based on.
+    pub samples: usize,
+}
+
+/// The name of the IRC network we're connected to (e.g. `"Libera.Chat"`), as
+/// advertised by the server's `NETWORK` token.
+///
+/// This falls back to the server's address when the server doesn't say, and
+/// is only `None` if we don't know that either.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GetNetwork;
+
+impl Message for GetNetwork {
+    type Result = Option<String>;
+}
+
+/// Describe how events are routed: which message types have subscribers,
+/// how many, and which plugins they belong to.
+///
+/// This is the place to start when a handler never seems to fire.
+#[derive(Debug, Copy, Clone)]
+pub struct DescribeWiring;
+
+impl Message for DescribeWiring {
+    type Result = Wiring;
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Wiring {
+    /// Every message type with at least one subscriber, sorted by name.
+    pub messages: Vec<MessageWiring>,
+}
+
+impl Wiring {
+    /// A line describing each message type (e.g.
+    /// `"UserJoined: 2 subscribers (channel-logs, 1 unnamed)"`).
+    pub fn lines(&self) -> Vec<String> {
+        self.messages.iter().map(ToString::to_string).collect()
+    }
+}
+
+impl Display for Wiring {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.lines().join("\n"))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MessageWiring {
+    /// The message type's name (e.g. `"UserJoined"`).
+    pub message: String,
+    /// How many recipients are registered for it.
+    pub subscribers: usize,
+    /// The plugins which registered with
+    /// [`Registration::from_plugin()`] for it.
+    pub plugins: Vec<String>,
+}
+
+impl Display for MessageWiring {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let plural = if self.subscribers == 1 { "" } else { "s" };
+        write!(
+            f,
+            "{}: {} subscriber{}",
+            self.message, self.subscribers, plural
+        )?;
+
+        let mut names = self.plugins.clone();
+        let unnamed = self.subscribers.saturating_sub(self.plugins.len());
+        if unnamed > 0 && !names.is_empty() {
+            names.push(format!("{} unnamed", unnamed));
+        }
+
+        if !names.is_empty() {
+            write!(f, " ({})", names.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Get a quick overview of how the bot is doing.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GetStats;
+
+impl Message for GetStats {
+    type Result = Stats;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// How long the [`crate::World`] has been running.
+    pub uptime: Duration,
+    /// The number of messages received from the server.
+    pub messages_received: usize,
+    /// The number of channels we're in (or trying to join).
+    pub channels: usize,
+    pub nick: Option<String>,
+    /// How far behind the [`crate::World`] is.
+    pub mailbox: MailboxStats,
+}
+
+/// Ask a [`Channel`] to summarize itself.
+#[derive(Debug, Copy, Clone)]
+pub struct Summarize;
+
+impl Message for Summarize {
+    type Result = ChannelSummary;
+}
+
+/// A snapshot of a channel's state.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChannelSummary {
+    pub name: String,
+    pub topic: Option<String>,
+    pub member_count: usize,
+    pub modes: Option<String>,
+    /// Has the server confirmed we're in the channel?
+    pub joined: bool,
+}
+
 #[derive(Debug, Clone, PartialEq, Message)]
 pub struct PrivateMessageReceived {
     pub msg_target: String,
     pub content: String,
     pub raw: IrcMessage,
 }
+
+/// Another user sent a `NOTICE` to us or to a channel we're in.
+///
+/// Notices from the server itself are published as a [`ServerNotice`]
+/// instead.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct NoticeReceived {
+    pub msg_target: String,
+    pub content: String,
+    pub raw: IrcMessage,
+}
+
+/// Someone sent a bot command (e.g. `!nick new-nick`).
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct CommandReceived {
+    /// The [`crate::Settings::command_prefix`] the command started with.
+    pub prefix: char,
+    /// The command's name, without the prefix.
+    pub name: String,
+    /// The command's arguments, where anything in double quotes counts as a
+    /// single argument.
+    pub args: Vec<String>,
+    /// Where replies should go, the channel the command was sent to or the
+    /// sender for direct messages.
+    pub reply_to: String,
+    pub sender: Option<String>,
+    pub raw: IrcMessage,
+}
+
+/// Someone used `/me` (a CTCP `ACTION`), e.g. `* Michael waves`.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct ActionReceived {
+    /// What they did, without their nick (e.g. `"waves"`).
+    pub action: String,
+    /// The channel it was sent to, or `None` for direct messages.
+    pub channel: Option<String>,
+    /// Where replies should go, the channel the action was sent to or the
+    /// sender for direct messages.
+    pub reply_to: String,
+    pub sender: Option<String>,
+    pub raw: IrcMessage,
+}
+
+/// A chat message (`PRIVMSG`) along with everything we know about who sent
+/// it and where.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct ChatEvent {
+    /// The sender's nick.
+    pub sender: Option<String>,
+    /// The sender's full `nick!user@host` hostmask.
+    pub hostmask: Option<String>,
+    /// The account the sender is logged in as, if the server told us (via
+    /// the `account` tag).
+    pub account: Option<String>,
+    /// The channel the message was sent to, or `None` for direct messages.
+    pub channel: Option<String>,
+    /// Did the message mention our nick?
+    pub highlighted: bool,
+    /// The sender's status within the channel, if we know it.
+    pub status: Option<MemberStatus>,
+    /// When the server received the message (from the `time` tag).
+    pub timestamp: Option<String>,
+    /// Was this a `/me` action? If so, `content` is what they did (e.g.
+    /// `"waves"`).
+    pub is_action: bool,
+    pub content: String,
+    pub raw: IrcMessage,
+}
+
+impl ChatEvent {
+    /// Was this a direct message to us?
+    pub fn is_private(&self) -> bool {
+        self.channel.is_none()
+    }
+}
+
+/// The list of names (with status prefixes) sent by the server when we join
+/// a channel.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct NamesReply {
+    pub names: Vec<String>,
+}
+
+/// The server has finished sending a channel's names, so anyone who wasn't
+/// mentioned since the last [`EndOfNames`] has left.
+#[derive(Debug, Copy, Clone, PartialEq, Message)]
+pub struct EndOfNames;
+
+/// The server confirmed we've joined a [`Channel`].
+#[derive(Debug, Copy, Clone, PartialEq, Message)]
+pub struct Joined;
+
+/// Leave a [`Channel`], forgetting everything we knew about it and stopping
+/// its actor.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct LeaveAndStop {
+    pub reason: Option<String>,
+}
+
+/// A [`Channel`] is shutting down, so its `World` should send a `PART` and
+/// stop tracking it.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub(crate) struct ChannelLeft {
+    pub channel: String,
+    pub reason: Option<String>,
+}
+
+/// Change a channel's modes, after checking the server supports them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeModes(pub ModeChange);
+
+impl Message for ChangeModes {
+    type Result = Result<(), IrcError>;
+}
+
+/// Send a message to every oper on the network (`WALLOPS`).
+///
+/// Servers only accept these from opers, so this fails unless the server has
+/// told us we're one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wallops {
+    pub content: String,
+}
+
+impl Message for Wallops {
+    type Result = Result<(), IrcError>;
+}
+
+/// Start (or stop) watching for some nicks to come online, using the IRCv3
+/// `MONITOR` extension.
+///
+/// This fails if the server doesn't support `MONITOR`, or if we'd be watching
+/// more nicks than it allows.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Monitor {
+    pub add: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+impl Message for Monitor {
+    type Result = Result<(), IrcError>;
+}
+
+/// Someone we're monitoring came online.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct NickOnline {
+    pub nick: String,
+}
+
+/// Someone we're monitoring went offline.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct NickOffline {
+    pub nick: String,
+}
+
+/// Change our realname without reconnecting, using the IRCv3 `setname`
+/// capability.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetRealName {
+    pub name: String,
+}
+
+impl Message for SetRealName {
+    type Result = Result<(), IrcError>;
+}
+
+/// The server says someone (possibly us) changed their realname.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct RealNameChanged {
+    pub nick: String,
+    pub name: String,
+}
+
+/// Everything the server told us in answer to a `WHOIS`, published once it
+/// says it's finished (`RPL_ENDOFWHOIS`).
+///
+/// Not every server sends every numeric, so anything we weren't told is left
+/// as `None`.
+#[derive(Debug, Clone, Default, PartialEq, Message)]
+pub struct WhoIsReply {
+    pub nick: String,
+    /// The username and host from `RPL_WHOISUSER`.
+    pub user: Option<String>,
+    pub host: Option<String>,
+    pub real_name: Option<String>,
+    /// The account they're logged in as, from `RPL_WHOISACCOUNT` (330).
+    pub account: Option<String>,
+    /// Are they connected over TLS (`RPL_WHOISSECURE`, 671)?
+    ///
+    /// This is `None` when the server didn't say, which is how most servers
+    /// answer for insecure connections.
+    pub secure_connection: Option<bool>,
+    /// The host they're really connecting from, from `RPL_WHOISHOST` (378).
+    /// We usually only get told this about ourselves or as an oper.
+    pub actual_host: Option<String>,
+}
+
+/// A `NOTICE` or `WALLOPS` sent by the server itself rather than by another
+/// user (e.g. `"*** Looking up your hostname"`).
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct ServerNotice {
+    /// The server's name.
+    pub from: String,
+    pub content: String,
+}
+
+/// The server sent us an `ERROR`, usually just before closing the connection
+/// (e.g. `"Closing Link: bot[example.com] (Ping timeout)"`).
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct ServerError {
+    pub message: String,
+}
+
+/// Someone (possibly us) joined a channel.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct UserJoined {
+    pub channel: String,
+    pub nick: String,
+    /// Their full `nick!user@host` hostmask.
+    pub hostmask: Option<String>,
+    pub is_us: bool,
+}
+
+/// Someone (possibly us) left a channel.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct UserParted {
+    pub channel: String,
+    pub nick: String,
+    pub reason: Option<String>,
+    pub is_us: bool,
+}
+
+/// Someone (possibly us) was kicked out of a channel.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct UserKicked {
+    pub channel: String,
+    pub nick: String,
+    /// Whoever did the kicking.
+    pub by: Option<String>,
+    pub reason: Option<String>,
+    pub is_us: bool,
+}
+
+/// Someone disconnected from the server, leaving every channel they were in.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct UserQuit {
+    pub nick: String,
+    pub reason: Option<String>,
+}
+
+/// Someone else joined a [`Channel`].
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct MemberJoined {
+    pub nick: String,
+    /// Their full `nick!user@host` hostmask.
+    pub hostmask: Option<String>,
+}
+
+/// Someone (possibly us) changed their nick.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct NickChanged {
+    pub old: String,
+    pub new: String,
+}
+
+/// Someone left a [`Channel`], resolving with how many members are left if
+/// they were in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberLeft {
+    pub nick: String,
+}
+
+impl Message for MemberLeft {
+    type Result = Option<usize>;
+}
+
+/// The server told us a channel's topic (`RPL_TOPIC`), or that it doesn't
+/// have one (`RPL_NOTOPIC`).
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct TopicReply {
+    pub channel: String,
+    pub topic: Option<String>,
+}
+
+/// A channel's topic was set, either by someone sending a `TOPIC` or by the
+/// server telling us what it is (`RPL_TOPIC`).
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct TopicChanged {
+    pub channel: String,
+    /// The new topic, empty if it was cleared.
+    pub topic: String,
+    /// Who set the topic, when we know.
+    pub set_by: Option<String>,
+}
+
+/// Change a channel's topic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetTopic {
+    pub channel: String,
+    pub topic: String,
+}
+
+impl Message for SetTopic {
+    type Result = Result<(), IrcError>;
+}
+
+/// Get the nicks of everyone in a channel.
+///
+/// This only checks what the [`Channel`] already knows, resolving to an
+/// empty set if we aren't in the channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Members {
+    pub channel: String,
+}
+
+impl Message for Members {
+    type Result = Result<HashSet<String>, ()>;
+}
+
+/// Find out how many people are in a channel, like [`Members`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberCount {
+    pub channel: String,
+}
+
+impl Message for MemberCount {
+    type Result = Result<usize, ()>;
+}
+
+/// Find out a channel's current topic.
+///
+/// This only checks what the [`Channel`] already knows, resolving to `None`
+/// if the channel has no topic or we aren't in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GetTopic {
+    pub channel: String,
+}
+
+impl Message for GetTopic {
+    type Result = Result<Option<String>, ()>;
+}
+
+/// One line of the server's reply to a `WHO`.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct WhoReply {
+    pub nick: String,
+    /// Whether they're here or gone (`H`/`G`), an oper (`*`), and their
+    /// channel status (e.g. `@`).
+    pub flags: String,
+    /// Their full `nick!user@host` hostmask.
+    pub hostmask: Option<String>,
+}
+
+/// The server says someone's username or host changed (`CHGHOST`), e.g.
+/// because services gave them a cloak after they identified.
+#[derive(Debug, Clone, PartialEq, Message)]
+pub struct HostChanged {
+    pub nick: String,
+    pub new_user: String,
+    pub new_host: String,
+}
+
+impl HostChanged {
+    /// Their new `nick!user@host` hostmask.
+    pub fn hostmask(&self) -> String {
+        format!("{}!{}@{}", self.nick, self.new_user, self.new_host)
+    }
+}
+
+/// Ask a [`Channel`] for a member's hostmask, if it knows it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostmaskOf {
+    pub nick: String,
+}
+
+impl Message for HostmaskOf {
+    type Result = Option<String>;
+}
+
+/// Find someone's `nick!user@host` hostmask from the channels we share with
+/// them.
+///
+/// Like [`SharedChannels`], this never touches the network, so it resolves
+/// to `None` for anyone we don't share a channel with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupHostmask {
+    pub nick: String,
+}
+
+impl Message for LookupHostmask {
+    type Result = Result<Option<String>, ()>;
+}
+
+/// Find the channels we share with someone, sorted by name.
+///
+/// This only checks the members each [`Channel`] already knows about, so it
+/// never touches the network. Channels which take too long to respond are
+/// left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedChannels {
+    pub nick: String,
+}
+
+impl Message for SharedChannels {
+    type Result = Result<Vec<String>, ()>;
+}
+
+/// Is someone in a particular channel?
+///
+/// Like [`SharedChannels`], this only checks the members the [`Channel`]
+/// already knows about. Channels we aren't tracking have no members.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsMember {
+    pub channel: String,
+    pub nick: String,
+}
+
+impl Message for IsMember {
+    type Result = Result<bool, ()>;
+}
+
+/// Check how long it takes the [`crate::World`] to get around to handling a
+/// message, resolving with how far behind it is.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MailboxProbe {
+    pub sent: Instant,
+}
+
+impl Message for MailboxProbe {
+    type Result = MailboxStats;
+}
+
+/// A snapshot of how well the [`crate::World`] is keeping up with the
+/// server.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MailboxStats {
+    /// How many messages have been read from the server.
+    pub received: usize,
+    /// How many messages have been handled, including ones which didn't
+    /// come from the server (e.g. when replaying a log).
+    pub handled: usize,
+    /// The messages from the server still waiting in the mailbox.
+    pub backlog: usize,
+    /// How long the most recent [`MailboxProbe`] waited in the mailbox.
+    pub last_delay: Option<Duration>,
+}
+
+/// Ask a [`Channel`] for a user's status.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberStatusOf {
+    pub nick: String,
+}
+
+impl Message for MemberStatusOf {
+    type Result = Option<MemberStatus>;
+}
+
+/// Find out what status we have in a channel (e.g. whether we're an op).
+///
+/// Like [`IsMember`], this only checks what the [`Channel`] already knows,
+/// resolving to `None` if we aren't in the channel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OurStatusIn {
+    pub channel: String,
+}
+
+impl Message for OurStatusIn {
+    type Result = Result<Option<MemberStatus>, ()>;
+}
+
+/// Find out which account a user is logged in as (`None` if they aren't).
+///
+/// Recent answers are cached, so this only sends a `WHOIS` to the server when
+/// we don't already know. This is what permission checks should use instead
+/// of trusting a user's nick.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupAccount {
+    pub nick: String,
+}
+
+impl Message for LookupAccount {
+    type Result = Result<Option<String>, IrcError>;
+}
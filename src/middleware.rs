@@ -0,0 +1,201 @@
+//! Hooks for inspecting and rewriting messages before they are sent to the
+//! server, and for filtering the ones it sends us.
+
+use irc::error::IrcError;
+use irc::proto::message::Tag;
+use irc::proto::Message as IrcMessage;
+
+/// A message which is about to be sent to the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingMessage {
+    pub to: String,
+    pub content: String,
+}
+
+/// What an outgoing [`Middleware`] decided to do with a message.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Decision {
+    /// Let the message through untouched.
+    Allow,
+    /// The message was rewritten in place and should still be sent.
+    Modify,
+    /// Drop the message, nothing will be sent.
+    Block,
+}
+
+/// A function which gets to look at (and possibly rewrite) every outgoing
+/// message.
+pub type Middleware =
+    Box<dyn Fn(&mut OutgoingMessage) -> Decision + Send + 'static>;
+
+/// Run a message through each [`Middleware`] in order, stopping as soon as
+/// one of them blocks it.
+///
+/// The returned [`Decision`] is [`Decision::Block`] if any middleware blocked
+/// the message, [`Decision::Modify`] if at least one rewrote it, otherwise
+/// [`Decision::Allow`].
+pub fn apply(chain: &[Middleware], msg: &mut OutgoingMessage) -> Decision {
+    let mut outcome = Decision::Allow;
+
+    for middleware in chain {
+        match middleware(msg) {
+            Decision::Allow => {}
+            Decision::Modify => outcome = Decision::Modify,
+            Decision::Block => return Decision::Block,
+        }
+    }
+
+    outcome
+}
+
+/// The error returned when a message is vetoed by a [`Middleware`].
+pub(crate) fn blocked(msg: &OutgoingMessage) -> IrcError {
+    IrcError::Custom {
+        inner: failure::err_msg(format!(
+            "The message to \"{}\" was blocked by middleware",
+            msg.to
+        )),
+    }
+}
+
+/// What an [`Interceptor`] decided to do with a message from the server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InboundDecision {
+    /// Handle the message as normal.
+    Process,
+    /// Ignore the message completely, as if the server never sent it.
+    Drop,
+    /// Handle the message, but add some tags to it first so plugins can
+    /// tell it apart (e.g. messages replayed from a `chathistory` batch).
+    ProcessTagged(Vec<Tag>),
+}
+
+/// A function which gets to look at every message from the server before
+/// the [`crate::World`] acts on it.
+pub type Interceptor =
+    Box<dyn Fn(&IrcMessage) -> InboundDecision + Send + 'static>;
+
+/// Run an incoming message through each [`Interceptor`] in order, stopping
+/// as soon as one of them drops it.
+///
+/// The tags from every [`InboundDecision::ProcessTagged`] are collected
+/// together, so the result is only [`InboundDecision::Process`] if nobody
+/// tagged the message.
+pub fn intercept(chain: &[Interceptor], msg: &IrcMessage) -> InboundDecision {
+    let mut tags = Vec::new();
+
+    for interceptor in chain {
+        match interceptor(msg) {
+            InboundDecision::Process => {}
+            InboundDecision::ProcessTagged(more) => tags.extend(more),
+            InboundDecision::Drop => return InboundDecision::Drop,
+        }
+    }
+
+    if tags.is_empty() {
+        InboundDecision::Process
+    } else {
+        InboundDecision::ProcessTagged(tags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(content: &str) -> OutgoingMessage {
+        OutgoingMessage {
+            to: String::from("#rust"),
+            content: String::from(content),
+        }
+    }
+
+    #[test]
+    fn an_empty_chain_allows_everything() {
+        let mut m = msg("Hello, World!");
+
+        assert_eq!(apply(&[], &mut m), Decision::Allow);
+        assert_eq!(m, msg("Hello, World!"));
+    }
+
+    #[test]
+    fn middleware_can_rewrite_messages() {
+        let chain: Vec<Middleware> = vec![
+            Box::new(|_| Decision::Allow),
+            Box::new(|m| {
+                m.content.push_str(" -- bot");
+                Decision::Modify
+            }),
+        ];
+        let mut m = msg("Hello, World!");
+
+        assert_eq!(apply(&chain, &mut m), Decision::Modify);
+        assert_eq!(m, msg("Hello, World! -- bot"));
+    }
+
+    #[test]
+    fn blocking_short_circuits_the_chain() {
+        let chain: Vec<Middleware> = vec![
+            Box::new(|m| {
+                if m.content.contains("secret") {
+                    Decision::Block
+                } else {
+                    Decision::Allow
+                }
+            }),
+            Box::new(|_| panic!("Should never be called")),
+        ];
+        let mut m = msg("the secret password");
+
+        assert_eq!(apply(&chain, &mut m), Decision::Block);
+    }
+
+    #[test]
+    fn interceptors_can_drop_messages() {
+        let chain: Vec<Interceptor> = vec![
+            Box::new(|m| match m.source_nickname() {
+                Some("spammer") => InboundDecision::Drop,
+                _ => InboundDecision::Process,
+            }),
+            Box::new(|_| panic!("Should never be called")),
+        ];
+        let msg: IrcMessage =
+            ":spammer!spam@example.com PRIVMSG #rust :buy now"
+                .parse()
+                .unwrap();
+
+        assert_eq!(intercept(&chain, &msg), InboundDecision::Drop);
+    }
+
+    #[test]
+    fn tags_from_each_interceptor_are_combined() {
+        let msg: IrcMessage = ":Michael!mike@example.com PRIVMSG #rust :hi"
+            .parse()
+            .unwrap();
+        assert_eq!(intercept(&[], &msg), InboundDecision::Process);
+
+        let chain: Vec<Interceptor> = vec![
+            Box::new(|_| {
+                InboundDecision::ProcessTagged(vec![Tag(
+                    String::from("historical"),
+                    None,
+                )])
+            }),
+            Box::new(|_| InboundDecision::Process),
+            Box::new(|_| {
+                InboundDecision::ProcessTagged(vec![Tag(
+                    String::from("seen-by"),
+                    Some(String::from("bot")),
+                )])
+            }),
+        ];
+
+        assert_eq!(
+            intercept(&chain, &msg),
+            InboundDecision::ProcessTagged(vec![
+                Tag(String::from("historical"), None),
+                Tag(String::from("seen-by"), Some(String::from("bot"))),
+            ])
+        );
+    }
+}
@@ -0,0 +1,280 @@
+//! Building `MODE` commands which the server will actually accept.
+
+use failure::Error;
+use irc::proto::Command;
+
+/// The channel modes a server supports, as advertised by the `CHANMODES` and
+/// `PREFIX` tokens in its `RPL_ISUPPORT`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerModes {
+    /// Modes which add or remove an entry from a list (e.g. `b` for bans).
+    pub lists: String,
+    /// Modes which always take an argument (e.g. `k` for the channel key).
+    pub always_args: String,
+    /// Modes which only take an argument when being set (e.g. `l`).
+    pub set_args: String,
+    /// Modes which never take an argument (e.g. `m`).
+    pub flags: String,
+    /// Modes which give someone a status in the channel (e.g. `o`), paired
+    /// with their prefix (e.g. `@`).
+    pub prefixes: Vec<(char, char)>,
+}
+
+impl ServerModes {
+    /// Update our knowledge of the server from the tokens in a
+    /// `RPL_ISUPPORT` (e.g. `["CHANMODES=beI,k,l,imnpst", "PREFIX=(ov)@+"]`).
+    ///
+    /// Tokens we don't understand are ignored.
+    pub fn update<'a, I>(&mut self, tokens: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        for token in tokens {
+            if token.starts_with("CHANMODES=") {
+                let mut groups = token["CHANMODES=".len()..].split(',');
+                self.lists = groups.next().unwrap_or_default().to_string();
+                self.always_args =
+                    groups.next().unwrap_or_default().to_string();
+                self.set_args = groups.next().unwrap_or_default().to_string();
+                self.flags = groups.next().unwrap_or_default().to_string();
+            } else if token.starts_with("PREFIX=") {
+                let value = &token["PREFIX=".len()..];
+
+                if let Some(prefixes) = parse_prefix(value) {
+                    self.prefixes = prefixes;
+                }
+            }
+        }
+    }
+
+    /// Does this mode take an argument when being set (or unset)?
+    fn takes_argument(&self, mode: char, adding: bool) -> Option<bool> {
+        if self.lists.contains(mode)
+            || self.always_args.contains(mode)
+            || self.prefixes.iter().any(|&(m, _)| m == mode)
+        {
+            Some(true)
+        } else if self.set_args.contains(mode) {
+            Some(adding)
+        } else if self.flags.contains(mode) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for ServerModes {
+    /// What RFC 2812 servers support, for when the server doesn't say.
+    fn default() -> ServerModes {
+        ServerModes {
+            lists: String::from("beI"),
+            always_args: String::from("k"),
+            set_args: String::from("l"),
+            flags: String::from("imnpst"),
+            prefixes: vec![('o', '@'), ('v', '+')],
+        }
+    }
+}
+
+/// Parse a `PREFIX` value like `(ov)@+` into `[('o', '@'), ('v', '+')]`.
+fn parse_prefix(value: &str) -> Option<Vec<(char, char)>> {
+    if !value.starts_with('(') {
+        return None;
+    }
+
+    let close = value.find(')')?;
+    let modes = value[1..close].chars();
+    let prefixes = value[close + 1..].chars();
+
+    if modes.clone().count() != prefixes.clone().count() {
+        return None;
+    }
+
+    Some(modes.zip(prefixes).collect())
+}
+
+/// A set of changes to a channel's modes, checked against what the server
+/// supports before being turned into a `MODE` command.
+///
+/// # Examples
+///
+/// ```rust
+/// use irc_bot::{ModeChange, ServerModes};
+///
+/// let cmd = ModeChange::new("#rust")
+///     .add_with('o', "Michael")
+///     .remove('m')
+///     .build(&ServerModes::default())
+///     .unwrap();
+///
+/// assert_eq!(String::from(&cmd), "MODE #rust +o-m Michael");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeChange {
+    pub channel: String,
+    changes: Vec<(bool, char, Option<String>)>,
+}
+
+impl ModeChange {
+    pub fn new<S: Into<String>>(channel: S) -> ModeChange {
+        ModeChange {
+            channel: channel.into(),
+            changes: Vec::new(),
+        }
+    }
+
+    /// Set a mode which doesn't take an argument.
+    pub fn add(self, mode: char) -> ModeChange {
+        self.change(true, mode, None)
+    }
+
+    /// Set a mode with an argument (e.g. `+o Michael`).
+    pub fn add_with<S: Into<String>>(self, mode: char, arg: S) -> ModeChange {
+        self.change(true, mode, Some(arg.into()))
+    }
+
+    /// Unset a mode which doesn't take an argument.
+    pub fn remove(self, mode: char) -> ModeChange {
+        self.change(false, mode, None)
+    }
+
+    /// Unset a mode with an argument (e.g. `-b *!*@example.com`).
+    pub fn remove_with<S: Into<String>>(
+        self,
+        mode: char,
+        arg: S,
+    ) -> ModeChange {
+        self.change(false, mode, Some(arg.into()))
+    }
+
+    fn change(
+        mut self,
+        adding: bool,
+        mode: char,
+        arg: Option<String>,
+    ) -> ModeChange {
+        self.changes.push((adding, mode, arg));
+        self
+    }
+
+    /// Check each change against the modes the server supports, and turn
+    /// them into a `MODE` command.
+    pub fn build(&self, modes: &ServerModes) -> Result<Command, Error> {
+        if self.changes.is_empty() {
+            return Err(failure::err_msg("No modes were changed"));
+        }
+
+        let mut mode_string = String::new();
+        let mut args = Vec::new();
+        let mut last_sign = None;
+
+        for &(adding, mode, ref arg) in &self.changes {
+            let sign = if adding { '+' } else { '-' };
+            let wants_arg = match modes.takes_argument(mode, adding) {
+                Some(wants_arg) => wants_arg,
+                None => {
+                    return Err(failure::format_err!(
+                        "The server doesn't support the \"{}\" mode",
+                        mode
+                    ))
+                }
+            };
+
+            match (wants_arg, arg) {
+                (true, Some(arg)) => args.push(arg.clone()),
+                (false, None) => {}
+                (true, None) => {
+                    return Err(bad_mode(sign, mode, "needs an argument"))
+                }
+                (false, Some(_)) => {
+                    return Err(bad_mode(
+                        sign,
+                        mode,
+                        "doesn't take an argument",
+                    ))
+                }
+            }
+
+            if last_sign != Some(sign) {
+                mode_string.push(sign);
+                last_sign = Some(sign);
+            }
+            mode_string.push(mode);
+        }
+
+        let mut params = vec![self.channel.clone(), mode_string];
+        params.extend(args);
+
+        Ok(Command::Raw(String::from("MODE"), params, None))
+    }
+}
+
+fn bad_mode(sign: char, mode: char, problem: &str) -> Error {
+    failure::format_err!("The \"{}{}\" mode {}", sign, mode, problem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a typical (InspIRCd-ish) network advertises.
+    fn realistic_modes() -> ServerModes {
+        let mut modes = ServerModes::default();
+        modes.update(vec![
+            "CHANMODES=IXbeg,k,FHJLfjl,ACKMNOPQRSTcimnprstz",
+            "PREFIX=(qaohv)~&@%+",
+            "NETWORK=Example",
+        ]);
+        modes
+    }
+
+    #[test]
+    fn parse_the_isupport_tokens() {
+        let got = realistic_modes();
+
+        assert_eq!(got.lists, "IXbeg");
+        assert_eq!(got.always_args, "k");
+        assert_eq!(got.set_args, "FHJLfjl");
+        assert_eq!(got.flags, "ACKMNOPQRSTcimnprstz");
+        assert_eq!(
+            got.prefixes,
+            vec![('q', '~'), ('a', '&'), ('o', '@'), ('h', '%'), ('v', '+')]
+        );
+    }
+
+    #[test]
+    fn build_a_valid_mode_change() {
+        let got = ModeChange::new("#rust")
+            .add_with('h', "Michael")
+            .add_with('l', "50")
+            .remove('l')
+            .remove_with('b', "*!*@example.com")
+            .add('m')
+            .build(&realistic_modes())
+            .unwrap();
+
+        assert_eq!(
+            String::from(&got),
+            "MODE #rust +hl-lb+m Michael 50 *!*@example.com"
+        );
+    }
+
+    #[test]
+    fn reject_malformed_mode_changes() {
+        let modes = realistic_modes();
+        let inputs = vec![
+            ModeChange::new("#rust"),
+            ModeChange::new("#rust").add('W'),
+            ModeChange::new("#rust").add('o'),
+            ModeChange::new("#rust").add('k'),
+            ModeChange::new("#rust").remove('b'),
+            ModeChange::new("#rust").add_with('m', "Michael"),
+            ModeChange::new("#rust").remove_with('l', "50"),
+        ];
+
+        for change in inputs {
+            assert!(change.build(&modes).is_err(), "{:?}", change);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use irc::client::data::{Config, User};
+use irc::client::{Client, ClientStream};
+use irc::error::Result as IrcResult;
+use irc::proto::message::Message as IrcMessage;
+use slog::Logger;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// A trace of the exact lines sent to (`>>`) and received from (`<<`) the
+/// server, the IRC equivalent of a packet capture.
+///
+/// Inbound lines are re-serialized from the parsed message, so (for example)
+/// tags may not be in the same order the server sent them.
+///
+/// A raw log can be fed back through the bot with a [`crate::Replay`].
+pub struct RawLog {
+    writer: RefCell<Box<dyn Write>>,
+    logger: Logger,
+    /// When to measure timestamps from, if each line should have one.
+    started: Option<Instant>,
+}
+
+impl RawLog {
+    pub fn new<W: Write + 'static>(writer: W, logger: Logger) -> RawLog {
+        RawLog {
+            writer: RefCell::new(Box::new(writer)),
+            logger,
+            started: None,
+        }
+    }
+
+    /// Start each line with the number of milliseconds since the log was
+    /// created (e.g. `1500 << PING :irc.example.com`), so a replay can keep
+    /// the original timing.
+    pub fn with_timestamps(mut self) -> RawLog {
+        self.started = Some(Instant::now());
+        self
+    }
+
+    /// Append to the raw log at a particular path, creating it if necessary.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        logger: Logger,
+    ) -> io::Result<RawLog> {
+        let f: File =
+            OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(RawLog::new(LineWriter::new(f), logger))
+    }
+
+    pub fn inbound(&self, msg: &IrcMessage) {
+        self.record("<<", msg);
+    }
+
+    pub fn outbound(&self, msg: &IrcMessage) {
+        self.record(">>", msg);
+    }
+
+    fn record(&self, direction: &str, msg: &IrcMessage) {
+        let line = msg.to_string();
+        let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+
+        let mut writer = self.writer.borrow_mut();
+        let got = match self.started {
+            Some(started) => {
+                let elapsed = started.elapsed();
+                let millis = elapsed.as_secs() * 1000
+                    + u64::from(elapsed.subsec_millis());
+                writeln!(writer, "{} {} {}", millis, direction, line)
+            }
+            None => writeln!(writer, "{} {}", direction, line),
+        };
+
+        if let Err(e) = got {
+            warn!(self.logger, "Unable to write to the raw log";
+                "error" => e.to_string());
+        }
+    }
+}
+
+/// A [`Client`] which records every message it sends in the [`RawLog`].
+pub(crate) struct Logged<'a, C: 'a> {
+    client: &'a C,
+    raw_log: Option<&'a RawLog>,
+}
+
+impl<'a, C: 'a> Logged<'a, C> {
+    pub fn new(client: &'a C, raw_log: Option<&'a RawLog>) -> Logged<'a, C> {
+        Logged { client, raw_log }
+    }
+}
+
+impl<'a, C: Client + 'a> Client for Logged<'a, C> {
+    fn config(&self) -> &Config {
+        self.client.config()
+    }
+
+    fn send<M: Into<IrcMessage>>(&self, message: M) -> IrcResult<()>
+    where
+        Self: Sized,
+    {
+        let message = message.into();
+
+        if let Some(raw_log) = self.raw_log {
+            raw_log.outbound(&message);
+        }
+
+        self.client.send(message)
+    }
+
+    fn stream(&self) -> ClientStream {
+        self.client.stream()
+    }
+
+    fn list_channels(&self) -> Option<Vec<String>> {
+        self.client.list_channels()
+    }
+
+    fn list_users(&self, channel: &str) -> Option<Vec<User>> {
+        self.client.list_users(channel)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use irc::proto::Command;
+    use slog::Discard;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn record_the_wire_format_with_direction_markers() {
+        let buffer = SharedBuffer::default();
+        let raw_log = RawLog::new(buffer.clone(), Logger::root(Discard, o!()));
+
+        let inbound: IrcMessage =
+            ":Michael!mike@example.com PRIVMSG #rust :Hello, World!"
+                .parse()
+                .unwrap();
+        raw_log.inbound(&inbound);
+        raw_log.outbound(&IrcMessage::from(Command::PRIVMSG(
+            String::from("#rust"),
+            String::from("hi"),
+        )));
+
+        let got = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        assert_eq!(
+            got,
+            "<< :Michael!mike@example.com PRIVMSG #rust :Hello, World!\n\
+             >> PRIVMSG #rust :hi\n"
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Bridging channels by repeating what's said in one of them in another.
+
+use actix::{Actor, Addr, Arbiter, Context, Handler};
+use crate::bot::lift_err;
+use crate::messages::{PrivateMessage, PrivateMessageReceived, Registration};
+use crate::utils;
+use crate::World;
+use failure::Error;
+use futures::future::Future;
+use irc::client::Client;
+use slog::Logger;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// The most messages we'll relay into a single channel per
+/// [`RATE_LIMIT_WINDOW`], so a flood in one channel doesn't get copied into
+/// the other.
+const MAX_RELAYED_PER_WINDOW: usize = 5;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+
+/// A pair of channels to relay between, written as `#from>#to` to only
+/// relay one way or `#left<>#right` to relay in both directions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayLink {
+    pub from: String,
+    pub to: String,
+    pub bidirectional: bool,
+}
+
+impl RelayLink {
+    /// Where a message sent to `channel` should be repeated, if anywhere.
+    fn destination(&self, channel: &str) -> Option<&str> {
+        if utils::same_nick(channel, &self.from) {
+            Some(self.to.as_str())
+        } else if self.bidirectional && utils::same_nick(channel, &self.to) {
+            Some(self.from.as_str())
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for RelayLink {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RelayLink, Error> {
+        let (from, to, bidirectional) = if let Some(ix) = s.find("<>") {
+            (&s[..ix], &s[ix + 2..], true)
+        } else if let Some(ix) = s.find('>') {
+            (&s[..ix], &s[ix + 1..], false)
+        } else {
+            (s, "", false)
+        };
+
+        if !utils::is_channel(from)
+            || !utils::is_channel(to)
+            || utils::same_nick(from, to)
+        {
+            return Err(failure::format_err!(
+                "Expected \"#from>#to\" or \"#left<>#right\", found \"{}\"",
+                s
+            ));
+        }
+
+        Ok(RelayLink {
+            from: from.to_string(),
+            to: to.to_string(),
+            bidirectional,
+        })
+    }
+}
+
+/// Repeats messages from one channel in another, prefixed with the nick of
+/// whoever originally sent them (e.g. `<Michael> hello`).
+///
+/// We never see our own messages (the [`World`] filters out anything the
+/// server echoes back to us), so relayed messages can't bounce back and
+/// forth between linked channels.
+pub struct Relay<C: Client + 'static> {
+    logger: Logger,
+    world: Addr<World<C>>,
+    links: Vec<RelayLink>,
+    /// When we recently relayed into each channel, keyed by the lowercased
+    /// channel name.
+    recent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl<C: Client + 'static> Relay<C> {
+    /// Spawn a [`Relay`] actor in the background.
+    pub fn spawn(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        links: Vec<RelayLink>,
+    ) -> Addr<Relay<C>> {
+        let relay = Relay {
+            logger,
+            world: world.clone(),
+            links,
+            recent: HashMap::new(),
+        };
+        let relay = utils::start_isolated("relay", relay);
+
+        world.do_send(
+            Registration::<PrivateMessageReceived>::register(
+                relay.clone().recipient(),
+            )
+            .from_plugin("relay"),
+        );
+
+        relay
+    }
+
+    /// Check whether we're allowed to relay another message into a channel
+    /// right now, and if so, remember that we did.
+    fn allow(&mut self, channel: &str, now: Instant) -> bool {
+        let recent = self
+            .recent
+            .entry(utils::irc_lowercase(channel))
+            .or_insert_with(VecDeque::new);
+
+        while recent
+            .front()
+            .map_or(false, |&at| now.duration_since(at) >= RATE_LIMIT_WINDOW)
+        {
+            recent.pop_front();
+        }
+
+        if recent.len() < MAX_RELAYED_PER_WINDOW {
+            recent.push_back(now);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn relay(&self, to: &str, content: String) {
+        let logger = self.logger.clone();
+        let fut = lift_err(self.world.send(PrivateMessage {
+            to: to.to_string(),
+            content,
+        }));
+
+        Arbiter::spawn(fut.map_err(move |e: Error| {
+            warn!(logger, "Unable to relay a message"; "error" => e.to_string());
+        }));
+    }
+}
+
+/// How a message should look once it's been relayed, or `None` if it
+/// shouldn't be (e.g. a CTCP query).
+fn relayed_content(sender: &str, content: &str) -> Option<String> {
+    match utils::parse_ctcp(content) {
+        Some(("ACTION", action)) => {
+            Some(format!("* {} {}", sender, action.unwrap_or_default()))
+        }
+        Some(_) => None,
+        None => Some(format!("<{}> {}", sender, content)),
+    }
+}
+
+impl<C: Client + 'static> Actor for Relay<C> {
+    type Context = Context<Relay<C>>;
+}
+
+impl<C: Client + 'static> Handler<PrivateMessageReceived> for Relay<C> {
+    type Result = ();
+
+    fn handle(
+        &mut self,
+        msg: PrivateMessageReceived,
+        _ctx: &mut Self::Context,
+    ) {
+        let sender = match msg.raw.source_nickname() {
+            Some(sender) => sender,
+            None => return,
+        };
+        let content = match relayed_content(sender, &msg.content) {
+            Some(content) => content,
+            None => return,
+        };
+
+        let destinations: Vec<String> = self
+            .links
+            .iter()
+            .filter_map(|link| link.destination(&msg.msg_target))
+            .map(String::from)
+            .collect();
+
+        let now = Instant::now();
+
+        for to in destinations {
+            if self.allow(&to, now) {
+                self.relay(&to, content.clone());
+            } else {
+                warn!(self.logger, "Relaying too quickly, dropping a message";
+                    "from" => &msg.msg_target,
+                    "to" => &to);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::MockClient;
+    use slog::Discard;
+
+    #[test]
+    fn parse_relay_links() {
+        let got: RelayLink = "#rust<>#rust-offtopic".parse().unwrap();
+        assert_eq!(
+            got,
+            RelayLink {
+                from: String::from("#rust"),
+                to: String::from("#rust-offtopic"),
+                bidirectional: true,
+            }
+        );
+        assert_eq!(got.destination("#Rust-Offtopic"), Some("#rust"));
+
+        let got: RelayLink = "#announcements>#rust".parse().unwrap();
+        assert!(!got.bidirectional);
+        assert_eq!(got.destination("#announcements"), Some("#rust"));
+        assert_eq!(got.destination("#rust"), None);
+
+        assert!("#rust".parse::<RelayLink>().is_err());
+        assert!("#rust>Michael".parse::<RelayLink>().is_err());
+        assert!("#rust<>#rust".parse::<RelayLink>().is_err());
+    }
+
+    #[test]
+    fn format_relayed_messages() {
+        assert_eq!(
+            relayed_content("Michael", "hello"),
+            Some(String::from("<Michael> hello"))
+        );
+        assert_eq!(
+            relayed_content("Michael", "\u{1}ACTION waves\u{1}"),
+            Some(String::from("* Michael waves"))
+        );
+        assert_eq!(relayed_content("Michael", "\u{1}VERSION\u{1}"), None);
+    }
+
+    #[test]
+    fn only_relay_a_few_messages_per_window() {
+        let sys = actix::System::new("test");
+        let world = World::new(MockClient::default()).start();
+        let mut relay = Relay {
+            logger: Logger::root(Discard, o!()),
+            world,
+            links: Vec::new(),
+            recent: HashMap::new(),
+        };
+        let start = Instant::now();
+
+        for _ in 0..MAX_RELAYED_PER_WINDOW {
+            assert!(relay.allow("#rust", start));
+        }
+        assert!(!relay.allow("#Rust", start));
+        assert!(relay.allow("#rust-offtopic", start));
+        assert!(relay.allow("#rust", start + RATE_LIMIT_WINDOW));
+
+        drop(sys);
+    }
+}
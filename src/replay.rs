@@ -0,0 +1,307 @@
+//! Feeding a recorded [`RawLog`] back through the [`World`] without
+//! connecting to a server, so a bug can be reproduced from a capture.
+//!
+//! [`RawLog`]: crate::RawLog
+
+use actix::fut::WrapFuture;
+use actix::{Actor, ActorFuture, Addr, AsyncContext, Context, System};
+use crate::messages::{GetStats, RawMessage};
+use crate::World;
+use failure::{Error, ResultExt};
+use irc::client::data::{Config, User};
+use irc::client::{Client, ClientStream};
+use irc::error::Result as IrcResult;
+use irc::proto::message::Message as IrcMessage;
+use slog::Logger;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The inbound messages from a [`crate::RawLog`], in the order they were
+/// received.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    messages: VecDeque<Recorded>,
+}
+
+impl Recording {
+    /// Parse a recording, one message per line.
+    ///
+    /// Each line is either a raw log entry (e.g. `<< PING :irc.example.com`,
+    /// optionally starting with a timestamp) or a bare message in wire
+    /// format. Anything we sent (`>>`) is skipped, the bot will send it again
+    /// while replaying.
+    pub fn parse(text: &str) -> Result<Recording, Error> {
+        let mut messages = VecDeque::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let recorded = parse_line(line)
+                .with_context(|_| format!("Unable to parse line {}", i + 1))?;
+            messages.extend(recorded);
+        }
+
+        Ok(Recording { messages })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Recording, Error> {
+        let text = fs::read_to_string(path)?;
+        Recording::parse(&text)
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
+/// A single message from a [`Recording`].
+#[derive(Debug, Clone, PartialEq)]
+struct Recorded {
+    /// When the message was received, relative to the start of the
+    /// recording.
+    at: Option<Duration>,
+    message: IrcMessage,
+}
+
+fn parse_line(line: &str) -> Result<Option<Recorded>, Error> {
+    let line = line.trim_end();
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    let (at, rest) = match line.find(' ') {
+        Some(i) if line[..i].chars().all(|c| c.is_ascii_digit()) => (
+            Some(Duration::from_millis(line[..i].parse()?)),
+            &line[i + 1..],
+        ),
+        _ => (None, line),
+    };
+
+    if rest.starts_with(">> ") {
+        return Ok(None);
+    }
+
+    let wire = rest.trim_start_matches("<< ");
+    let message = wire.parse::<IrcMessage>()?;
+
+    Ok(Some(Recorded { at, message }))
+}
+
+/// How quickly to replay a [`Recording`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ReplayTiming {
+    /// Send each message as soon as the last one has been sent.
+    AsFastAsPossible,
+    /// Wait as long between messages as the server originally did, using the
+    /// timestamps in the recording.
+    Recorded,
+}
+
+impl FromStr for ReplayTiming {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ReplayTiming, Error> {
+        match s {
+            "fast" => Ok(ReplayTiming::AsFastAsPossible),
+            "recorded" => Ok(ReplayTiming::Recorded),
+            _ => Err(failure::format_err!(
+                "Expected one of \"fast\" or \"recorded\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Sends each message in a [`Recording`] to the [`World`] as if it had just
+/// come from the server, stopping the [`System`] once they've all been
+/// handled.
+pub struct Replay<C: Client + 'static> {
+    logger: Logger,
+    world: Addr<World<C>>,
+    recording: Recording,
+    timing: ReplayTiming,
+    /// When the previous message was originally received.
+    previous: Option<Duration>,
+    replayed: usize,
+}
+
+impl<C: Client + 'static> Replay<C> {
+    pub fn spawn(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        recording: Recording,
+        timing: ReplayTiming,
+    ) -> Addr<Replay<C>> {
+        let replay = Replay {
+            logger,
+            world: world.clone(),
+            recording,
+            timing,
+            previous: None,
+            replayed: 0,
+        };
+
+        replay.start()
+    }
+
+    fn replay_next(&mut self, ctx: &mut Context<Self>) {
+        let next = match self.recording.messages.pop_front() {
+            Some(next) => next,
+            None => return self.finish(ctx),
+        };
+
+        let delay = match (self.timing, next.at, self.previous) {
+            (ReplayTiming::Recorded, Some(at), Some(previous)) => {
+                at.checked_sub(previous).unwrap_or_default()
+            }
+            _ => Duration::default(),
+        };
+        self.previous = next.at.or(self.previous);
+
+        ctx.run_later(delay, move |replay, ctx| {
+            replay.world.do_send(RawMessage(next.message));
+            replay.replayed += 1;
+            replay.replay_next(ctx);
+        });
+    }
+
+    fn finish(&mut self, ctx: &mut Context<Self>) {
+        info!(self.logger, "Finished the replay"; "messages" => self.replayed);
+
+        // wait for the World to get through everything we sent it
+        let fut = self.world.send(GetStats).into_actor(self).then(
+            |_, _replay, _ctx| {
+                System::current().stop();
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+}
+
+impl<C: Client + 'static> Actor for Replay<C> {
+    type Context = Context<Replay<C>>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        info!(self.logger, "Replaying a recording";
+            "messages" => self.recording.len(),
+            "timing" => format_args!("{:?}", self.timing));
+
+        self.replay_next(ctx);
+    }
+}
+
+/// A [`Client`] which logs everything the bot would have sent to the server
+/// instead of sending it.
+///
+/// There's no connection to receive messages from, so never send the
+/// [`World`] a [`crate::messages::StartListening`] during a replay.
+#[derive(Debug)]
+pub struct ReplayClient {
+    config: Config,
+    logger: Logger,
+}
+
+impl ReplayClient {
+    pub fn new(config: Config, logger: Logger) -> ReplayClient {
+        ReplayClient { config, logger }
+    }
+}
+
+impl Client for ReplayClient {
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn send<M: Into<IrcMessage>>(&self, message: M) -> IrcResult<()>
+    where
+        Self: Sized,
+    {
+        let line = message.into().to_string();
+        info!(self.logger, "Would have sent a message";
+            "message" => line.trim_end());
+        Ok(())
+    }
+
+    fn stream(&self) -> ClientStream {
+        panic!("A replay has no connection to receive messages from")
+    }
+
+    fn list_channels(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    fn list_users(&self, _channel: &str) -> Option<Vec<User>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::MockClient;
+    use irc::proto::Command;
+    use slog::Discard;
+
+    #[test]
+    fn parse_raw_logs_and_bare_messages() {
+        let src = "1500 << :irc.example.com 001 bot :Welcome\n\
+                   1510 >> JOIN #rust\n\
+                   \n\
+                   PING :irc.example.com\n";
+
+        let got = Recording::parse(src).unwrap();
+
+        let messages: Vec<_> = got.messages.into_iter().collect();
+        assert_eq!(
+            messages,
+            vec![
+                Recorded {
+                    at: Some(Duration::from_millis(1500)),
+                    message: ":irc.example.com 001 bot :Welcome"
+                        .parse()
+                        .unwrap(),
+                },
+                Recorded {
+                    at: None,
+                    message: IrcMessage::from(Command::PING(
+                        String::from("irc.example.com"),
+                        None
+                    )),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn replay_a_recording_through_the_world() {
+        let sys = System::new("test");
+        let client = MockClient::default();
+        let world = World::new(client.clone()).start();
+        let recording = Recording::parse(
+            "0 << :irc.example.com 001 bot :Welcome\n\
+             5 << :bot!bot@example.com JOIN #rust\n",
+        )
+        .unwrap();
+
+        let _replay = Replay::spawn(
+            Logger::root(Discard, o!()),
+            &world,
+            recording,
+            ReplayTiming::Recorded,
+        );
+        sys.run();
+
+        let sent = client.sent.lock().unwrap();
+        assert!(sent.contains(&IrcMessage::from(Command::WHO(
+            Some(String::from("#rust")),
+            None
+        ))));
+    }
+}
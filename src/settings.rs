@@ -0,0 +1,568 @@
+use crate::utils;
+use failure::Error;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// The longest we'll wait between attempts to reconnect.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Knobs for tweaking how the [`crate::World`] behaves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// What to do when asked to send a message to a channel we aren't in.
+    pub unjoined_channels: UnjoinedChannelPolicy,
+    /// The maximum number of nick-to-account mappings to remember.
+    pub account_cache_size: usize,
+    /// How long a nick-to-account mapping can be trusted for.
+    pub account_cache_ttl: Duration,
+    /// The longest reply we'll send to a channel.
+    pub max_reply_lines: usize,
+    /// What to do when a reply to a channel is longer than
+    /// `max_reply_lines`.
+    pub long_replies: LongReplyPolicy,
+    /// How long to wait for the server to finish registering us before
+    /// giving up on the connection (`None` waits forever).
+    pub registration_timeout: Option<Duration>,
+    /// The message to quit with when we aren't given one, where `{uptime}`
+    /// and `{version}` are replaced with how long we've been running and our
+    /// version number.
+    pub quit_message: String,
+    /// The channels to leave once everyone else has gone, where `"*"` means
+    /// every channel.
+    pub leave_when_empty: Vec<String>,
+    /// What to do when a plugin panics.
+    pub plugin_panics: PanicPolicy,
+    /// How often to re-request the names in each channel, in case our
+    /// idea of who's there has drifted (`None` never does).
+    pub resync_interval: Option<Duration>,
+    /// Also re-request each channel's topic when re-syncing.
+    pub resync_topics: bool,
+    /// Channels we should never leave unless explicitly forced to (e.g. the
+    /// bot's home channel).
+    pub sticky_channels: Vec<String>,
+    /// What to do when someone uses a command no plugin has registered.
+    pub unknown_commands: UnknownCommandPolicy,
+    /// When to negotiate capabilities while registering with the server.
+    pub registration_order: RegistrationOrder,
+    /// How long to wait for the server to close the connection after we
+    /// send a `QUIT`, before stopping anyway.
+    pub shutdown_timeout: Duration,
+    /// Commands which can't be used in particular channels.
+    pub disabled_commands: Vec<DisabledCommand>,
+    /// When a registered-only (`+r`) channel turns us away before we've
+    /// identified, try again once we have instead of giving up.
+    pub defer_joins_until_identified: bool,
+    /// Overrides for the wording of the bot's replies.
+    pub reply_templates: ReplyTemplates,
+    /// How many times in a row a command can fail before it's switched off
+    /// (`0` never switches it off).
+    pub command_failure_threshold: usize,
+    /// How long to leave a failing command switched off before letting
+    /// someone try it again.
+    pub command_cooldown: Duration,
+    /// What a message has to start with to be treated as a command.
+    pub command_prefix: char,
+    /// The most channels we'll be in at once, not counting sticky channels
+    /// (`None` has no limit).
+    pub max_channels: Option<usize>,
+    /// How many times the server can say we aren't registered
+    /// (`ERR_NOTREGISTERED`) within `not_registered_window` while we're
+    /// still registering, before we give up on the connection.
+    pub not_registered_limit: usize,
+    pub not_registered_window: Duration,
+    /// How far the server's clock can drift from ours before we warn about
+    /// it.
+    pub clock_skew_threshold: Duration,
+    /// Channels which need a stricter limit on how quickly we send messages
+    /// than the connection-wide flood protection.
+    pub channel_rate_limits: Vec<ChannelRateLimit>,
+    /// Try to get our configured nick back once we've identified, if we had
+    /// to register with a fallback (e.g. because a ghost of our previous
+    /// connection was still holding it).
+    pub reclaim_nick: bool,
+    /// What to send to services to disconnect whoever is holding our nick
+    /// (`None` just waits for them to leave).
+    pub ghost_command: Option<GhostCommand>,
+    /// How long to wait for the ghost to go before asking for our nick
+    /// anyway.
+    pub reclaim_timeout: Duration,
+    /// Channels we need to have joined before we're ready and start taking
+    /// commands (empty means we're ready as soon as we've registered).
+    pub wait_for_channels: Vec<String>,
+    /// How long to wait for `wait_for_channels` before giving up on the
+    /// ones we haven't joined.
+    pub ready_timeout: Duration,
+}
+
+impl Settings {
+    /// Should we leave a channel once we're the only one left in it?
+    pub fn leaves_when_empty(&self, channel: &str) -> bool {
+        self.leave_when_empty
+            .iter()
+            .any(|name| name == "*" || utils::same_nick(name, channel))
+    }
+
+    /// The rate limit for messages sent to a particular channel, if it has
+    /// its own.
+    pub fn rate_limit(&self, channel: &str) -> Option<&ChannelRateLimit> {
+        self.channel_rate_limits
+            .iter()
+            .find(|limit| utils::same_nick(&limit.channel, channel))
+    }
+
+    /// Is this a channel we should refuse to leave?
+    pub fn is_sticky(&self, channel: &str) -> bool {
+        self.sticky_channels
+            .iter()
+            .any(|name| utils::same_nick(name, channel))
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            unjoined_channels: UnjoinedChannelPolicy::Warn,
+            account_cache_size: 512,
+            account_cache_ttl: Duration::from_secs(10 * 60),
+            max_reply_lines: 4,
+            long_replies: LongReplyPolicy::Refuse,
+            registration_timeout: Some(Duration::from_secs(120)),
+            quit_message: String::from("Leaving..."),
+            leave_when_empty: Vec::new(),
+            plugin_panics: PanicPolicy::Shutdown,
+            resync_interval: None,
+            resync_topics: false,
+            sticky_channels: Vec::new(),
+            unknown_commands: UnknownCommandPolicy::Ignore,
+            registration_order: RegistrationOrder::CapFirst,
+            shutdown_timeout: Duration::from_secs(10),
+            disabled_commands: Vec::new(),
+            defer_joins_until_identified: true,
+            reply_templates: ReplyTemplates::default(),
+            command_failure_threshold: 5,
+            command_cooldown: Duration::from_secs(60),
+            command_prefix: '!',
+            max_channels: Some(250),
+            not_registered_limit: 3,
+            not_registered_window: Duration::from_secs(30),
+            clock_skew_threshold: Duration::from_secs(10),
+            channel_rate_limits: Vec::new(),
+            reclaim_nick: true,
+            ghost_command: Some(GhostCommand::new("NickServ", "GHOST {nick}")),
+            reclaim_timeout: Duration::from_secs(10),
+            wait_for_channels: Vec::new(),
+            ready_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// How to handle sending a message to a channel we haven't joined.
+///
+/// Some servers will happily accept these messages while others (e.g. for
+/// channels with the `+n` mode) reject them, but either way it's usually a
+/// sign that a plugin is replying to the wrong channel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UnjoinedChannelPolicy {
+    /// Send the message without complaint.
+    Ignore,
+    /// Log a warning, then send the message anyway.
+    Warn,
+    /// Refuse to send the message.
+    Error,
+}
+
+impl FromStr for UnjoinedChannelPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<UnjoinedChannelPolicy, Error> {
+        match s {
+            "ignore" => Ok(UnjoinedChannelPolicy::Ignore),
+            "warn" => Ok(UnjoinedChannelPolicy::Warn),
+            "error" => Ok(UnjoinedChannelPolicy::Error),
+            _ => Err(failure::format_err!(
+                "Expected one of \"ignore\", \"warn\", or \"error\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// How to handle a multi-line reply which is too long to send to a channel.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LongReplyPolicy {
+    /// Don't send anything.
+    Refuse,
+    /// Send the reply to whoever asked for it instead.
+    DirectMessage,
+}
+
+impl FromStr for LongReplyPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LongReplyPolicy, Error> {
+        match s {
+            "refuse" => Ok(LongReplyPolicy::Refuse),
+            "dm" => Ok(LongReplyPolicy::DirectMessage),
+            _ => Err(failure::format_err!(
+                "Expected one of \"refuse\" or \"dm\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// How to handle a panic in one of the plugins.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PanicPolicy {
+    /// Shut the entire bot down.
+    Shutdown,
+    /// Log the panic and carry on without the plugin.
+    ///
+    /// This only works for plugins started with
+    /// [`crate::utils::start_isolated()`], anything running on the main
+    /// thread will still take the bot down with it.
+    Isolate,
+}
+
+impl FromStr for PanicPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PanicPolicy, Error> {
+        match s {
+            "shutdown" => Ok(PanicPolicy::Shutdown),
+            "isolate" => Ok(PanicPolicy::Isolate),
+            _ => Err(failure::format_err!(
+                "Expected one of \"shutdown\" or \"isolate\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// How to respond to a command (e.g. `!nikc`) no plugin has registered.
+///
+/// Unless they're ignored, unknown commands aren't passed on to plugins as a
+/// [`crate::messages::CommandReceived`], so a plugin needs to register its
+/// commands to see them.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum UnknownCommandPolicy {
+    /// Say nothing, and pass the command on to plugins anyway.
+    Ignore,
+    /// Send the sender a notice pointing them at `!help`.
+    Reply,
+    /// Send the sender a notice suggesting the closest registered command.
+    Suggest,
+}
+
+impl FromStr for UnknownCommandPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<UnknownCommandPolicy, Error> {
+        match s {
+            "ignore" => Ok(UnknownCommandPolicy::Ignore),
+            "reply" => Ok(UnknownCommandPolicy::Reply),
+            "suggest" => Ok(UnknownCommandPolicy::Suggest),
+            _ => Err(failure::format_err!(
+                "Expected one of \"ignore\", \"reply\", or \"suggest\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// The order we send things in when registering with the server.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RegistrationOrder {
+    /// Send `CAP LS 302`, `PASS`, `NICK`, and `USER`, then request whichever
+    /// capabilities the server offers and only send `CAP END` once it has
+    /// answered every request.
+    ///
+    /// The server holds off on registering us until `CAP END`, which leaves
+    /// room for things like SASL.
+    CapFirst,
+    /// Send `CAP REQ` for everything we want, followed by `CAP END`, `PASS`,
+    /// `NICK`, and `USER`, without waiting to hear what the server supports.
+    RequestBlindly,
+}
+
+impl FromStr for RegistrationOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<RegistrationOrder, Error> {
+        match s {
+            "cap-first" => Ok(RegistrationOrder::CapFirst),
+            "blind" => Ok(RegistrationOrder::RequestBlindly),
+            _ => Err(failure::format_err!(
+                "Expected one of \"cap-first\" or \"blind\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// A command to turn off in a particular channel, written as
+/// `channel:command` (e.g. `#serious:8ball`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisabledCommand {
+    pub channel: String,
+    /// The command's name, without the leading `!`.
+    pub command: String,
+}
+
+impl FromStr for DisabledCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DisabledCommand, Error> {
+        // channel names can't contain a colon
+        let mut parts = s.splitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some(channel), Some(command))
+                if !channel.is_empty() && !command.is_empty() =>
+            {
+                Ok(DisabledCommand {
+                    channel: channel.to_string(),
+                    command: command.trim_start_matches('!').to_string(),
+                })
+            }
+            _ => Err(failure::format_err!(
+                "Expected \"channel:command\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// How many messages we can send to a channel in a given time, written as
+/// `channel:messages/seconds` (e.g. `#busy:3/60`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelRateLimit {
+    pub channel: String,
+    pub messages: u32,
+    pub per: Duration,
+}
+
+impl FromStr for ChannelRateLimit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ChannelRateLimit, Error> {
+        // channel names can't contain a colon
+        let mut parts = s.rsplitn(2, ':');
+        let (rate, channel) = (parts.next(), parts.next());
+        let mut rate = rate.unwrap_or_default().splitn(2, '/');
+        let messages = rate.next().and_then(|n| n.parse().ok());
+        let seconds = rate.next().and_then(|n| n.parse().ok());
+
+        match (channel, messages, seconds) {
+            (Some(channel), Some(messages), Some(seconds))
+                if utils::is_channel(channel)
+                    && messages > 0
+                    && seconds > 0 =>
+            {
+                Ok(ChannelRateLimit {
+                    channel: channel.to_string(),
+                    messages,
+                    per: Duration::from_secs(seconds),
+                })
+            }
+            _ => Err(failure::format_err!(
+                "Expected \"channel:messages/seconds\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// The message which asks services to disconnect a ghost holding our nick,
+/// written as `service:template` (e.g. `NickServ:REGAIN {nick}`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct GhostCommand {
+    pub service: String,
+    pub template: String,
+}
+
+impl GhostCommand {
+    pub fn new<S, T>(service: S, template: T) -> GhostCommand
+    where
+        S: Into<String>,
+        T: Into<String>,
+    {
+        GhostCommand {
+            service: service.into(),
+            template: template.into(),
+        }
+    }
+
+    pub fn message(&self, nick: &str) -> String {
+        utils::expand_template(&self.template, &[("nick", nick)])
+    }
+}
+
+/// How hard to try reconnecting when the server closes the connection
+/// unexpectedly.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    /// How many attempts in a row to make before giving up (`0` never gives
+    /// up).
+    pub max_retries: usize,
+    /// How long to wait before the first attempt, doubling after each one
+    /// which fails.
+    pub base_delay: Duration,
+}
+
+impl ReconnectPolicy {
+    /// How long to wait before a particular attempt, starting from `1`.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let doublings = cmp::min(attempt.saturating_sub(1), 16) as u32;
+
+        self.base_delay
+            .checked_mul(1 << doublings)
+            .map_or(MAX_RECONNECT_DELAY, |delay| {
+                cmp::min(delay, MAX_RECONNECT_DELAY)
+            })
+    }
+
+    /// Have we used up all our attempts?
+    pub fn gives_up_after(&self, attempts: usize) -> bool {
+        self.max_retries != 0 && attempts > self.max_retries
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl FromStr for GhostCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<GhostCommand, Error> {
+        let mut parts = s.splitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some(service), Some(template))
+                if !service.trim().is_empty()
+                    && !template.trim().is_empty() =>
+            {
+                Ok(GhostCommand::new(service.trim(), template.trim()))
+            }
+            _ => Err(failure::format_err!(
+                "Expected \"service:template\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Operator-supplied wording for the bot's replies, keyed by name (e.g.
+/// `unknown-command`).
+///
+/// Each reply has a built-in default, so only the ones being customised (or
+/// translated) need to be provided.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ReplyTemplates {
+    templates: BTreeMap<String, String>,
+}
+
+impl ReplyTemplates {
+    pub fn new() -> ReplyTemplates {
+        ReplyTemplates::default()
+    }
+
+    /// Use a different template for a particular reply.
+    pub fn insert(&mut self, template: ReplyTemplate) {
+        self.templates.insert(template.name, template.template);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.templates.get(name).map(String::as_str)
+    }
+
+    /// Word a reply using its template, falling back to `default` if the
+    /// operator hasn't provided one.
+    pub fn render(
+        &self,
+        name: &str,
+        default: &str,
+        values: &[(&str, &str)],
+    ) -> String {
+        utils::expand_template(self.get(name).unwrap_or(default), values)
+    }
+}
+
+impl Extend<ReplyTemplate> for ReplyTemplates {
+    fn extend<I: IntoIterator<Item = ReplyTemplate>>(&mut self, iter: I) {
+        for template in iter {
+            self.insert(template);
+        }
+    }
+}
+
+/// A custom template for one of the bot's replies, written as
+/// `name=template` (e.g. `unknown-command=Sorry {nick}, try {prefix}help`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplyTemplate {
+    pub name: String,
+    pub template: String,
+}
+
+impl FromStr for ReplyTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ReplyTemplate, Error> {
+        let mut parts = s.splitn(2, '=');
+
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(template)) if !name.trim().is_empty() => {
+                Ok(ReplyTemplate {
+                    name: name.trim().to_string(),
+                    template: template.to_string(),
+                })
+            }
+            _ => Err(failure::format_err!(
+                "Expected \"name=template\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_delays_back_off() {
+        let policy = ReconnectPolicy {
+            max_retries: 3,
+            base_delay: Duration::from_secs(5),
+        };
+
+        let delays: Vec<_> =
+            (1..=4).map(|attempt| policy.delay(attempt)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_secs(5),
+                Duration::from_secs(10),
+                Duration::from_secs(20),
+                Duration::from_secs(40),
+            ]
+        );
+        assert_eq!(policy.delay(100), MAX_RECONNECT_DELAY);
+
+        assert!(!policy.gives_up_after(3));
+        assert!(policy.gives_up_after(4));
+        assert!(!ReconnectPolicy {
+            max_retries: 0,
+            ..policy
+        }
+        .gives_up_after(1000));
+    }
+}
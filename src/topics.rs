@@ -0,0 +1,220 @@
+use actix::fut::WrapFuture;
+use actix::{Actor, ActorFuture, Addr, Arbiter, AsyncContext, Context, Handler};
+use crate::bot::lift_err;
+use crate::messages::{
+    ChannelSummaries, PrivateMessage, Registration, TopicReply, UserJoined,
+};
+use crate::utils;
+use crate::World;
+use failure::Error;
+use futures::future::Future;
+use irc::client::Client;
+use slog::Logger;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How long to wait for the server to tell us a channel's topic.
+const TOPIC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Posts a channel's topic whenever we (or, optionally, anyone) join it.
+///
+/// When we join a channel the server sends its topic shortly after the
+/// `JOIN`, so we wait for the `RPL_TOPIC` instead of reading whatever the
+/// [`crate::channel::Channel`] has cached.
+pub struct TopicAnnouncer<C: Client + 'static> {
+    logger: Logger,
+    world: Addr<World<C>>,
+    target: TopicTarget,
+    /// Announce the topic when anyone joins, not just us.
+    everyone: bool,
+    /// Joins waiting to hear a channel's topic, keyed by the lowercased
+    /// channel name.
+    waiting: HashMap<String, Vec<UserJoined>>,
+}
+
+impl<C: Client + 'static> TopicAnnouncer<C> {
+    /// Spawn a [`TopicAnnouncer`] actor in the background.
+    pub fn spawn(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        target: TopicTarget,
+        everyone: bool,
+    ) -> Addr<TopicAnnouncer<C>> {
+        let announcer = TopicAnnouncer {
+            logger,
+            world: world.clone(),
+            target,
+            everyone,
+            waiting: HashMap::new(),
+        };
+        let announcer = utils::start_isolated("topics", announcer);
+
+        world.do_send(
+            Registration::<UserJoined>::register(announcer.clone().recipient())
+                .from_plugin("topics"),
+        );
+        world.do_send(
+            Registration::<TopicReply>::register(announcer.clone().recipient())
+                .from_plugin("topics"),
+        );
+
+        announcer
+    }
+
+    /// Stop waiting for a topic, returning `false` if we'd already stopped.
+    fn stop_waiting(&mut self, joined: &UserJoined) -> bool {
+        let key = utils::irc_lowercase(&joined.channel);
+
+        let found = match self.waiting.get_mut(&key) {
+            Some(waiting) => {
+                let before = waiting.len();
+                waiting.retain(|j| j != joined);
+                waiting.len() != before
+            }
+            None => false,
+        };
+
+        if self.waiting.get(&key).map_or(false, Vec::is_empty) {
+            self.waiting.remove(&key);
+        }
+
+        found
+    }
+
+    /// Check whether the [`crate::channel::Channel`] already knows its
+    /// topic.
+    fn check_cached_topic(
+        &mut self,
+        joined: UserJoined,
+        ctx: &mut Context<Self>,
+    ) {
+        let fut = self.world.send(ChannelSummaries).into_actor(self).then(
+            move |got, announcer, _ctx| {
+                let topic = match got {
+                    Ok(Ok(summaries)) => summaries
+                        .into_iter()
+                        .find(|s| utils::same_nick(&s.name, &joined.channel))
+                        .and_then(|s| s.topic),
+                    _ => None,
+                };
+
+                if let Some(topic) = topic {
+                    if announcer.stop_waiting(&joined) {
+                        announcer.announce(&joined, &topic);
+                    }
+                }
+
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    fn announce(&self, joined: &UserJoined, topic: &str) {
+        let content = format!("Topic for {}: {}", joined.channel, topic);
+
+        let to = match self.target {
+            TopicTarget::Channel => joined.channel.clone(),
+            TopicTarget::DirectMessage if !joined.is_us => joined.nick.clone(),
+            // there's nobody to DM when we're the ones joining
+            TopicTarget::DirectMessage | TopicTarget::Log => {
+                info!(self.logger, "Channel topic";
+                    "channel" => &joined.channel,
+                    "topic" => topic);
+                return;
+            }
+        };
+
+        let logger = self.logger.clone();
+        let fut = lift_err(self.world.send(PrivateMessage { to, content }));
+
+        Arbiter::spawn(fut.map_err(move |e: Error| {
+            warn!(logger, "Unable to announce a channel's topic";
+                "error" => e.to_string());
+        }));
+    }
+}
+
+impl<C: Client + 'static> Actor for TopicAnnouncer<C> {
+    type Context = Context<TopicAnnouncer<C>>;
+}
+
+impl<C: Client + 'static> Handler<UserJoined> for TopicAnnouncer<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: UserJoined, ctx: &mut Self::Context) {
+        if !msg.is_us && !self.everyone {
+            return;
+        }
+
+        self.waiting
+            .entry(utils::irc_lowercase(&msg.channel))
+            .or_insert_with(Vec::new)
+            .push(msg.clone());
+
+        let joined = msg.clone();
+        ctx.run_later(TOPIC_TIMEOUT, move |announcer, _ctx| {
+            if announcer.stop_waiting(&joined) {
+                debug!(announcer.logger, "Never heard the channel's topic";
+                    "channel" => &joined.channel);
+            }
+        });
+
+        // we've probably already got the topic if someone else is joining,
+        // but the server only sends it to us after our own JOIN
+        if !msg.is_us {
+            self.check_cached_topic(msg, ctx);
+        }
+    }
+}
+
+impl<C: Client + 'static> Handler<TopicReply> for TopicAnnouncer<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: TopicReply, _ctx: &mut Self::Context) {
+        let waiting = self
+            .waiting
+            .remove(&utils::irc_lowercase(&msg.channel))
+            .unwrap_or_default();
+
+        match msg.topic {
+            Some(ref topic) => {
+                for joined in &waiting {
+                    self.announce(joined, topic);
+                }
+            }
+            None => debug!(self.logger, "The channel has no topic";
+                "channel" => &msg.channel),
+        }
+    }
+}
+
+/// Where a [`TopicAnnouncer`] should post a channel's topic.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TopicTarget {
+    /// Send it to whoever joined (or just log it when we're the ones
+    /// joining).
+    DirectMessage,
+    /// Post it in the channel itself.
+    Channel,
+    /// Only write it to the log.
+    Log,
+}
+
+impl FromStr for TopicTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TopicTarget, Error> {
+        match s {
+            "dm" => Ok(TopicTarget::DirectMessage),
+            "channel" => Ok(TopicTarget::Channel),
+            "log" => Ok(TopicTarget::Log),
+            _ => Err(failure::format_err!(
+                "Expected one of \"dm\", \"channel\", or \"log\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
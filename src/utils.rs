@@ -1,25 +1,70 @@
 use actix::dev::ToEnvelope;
-use actix::{Actor, Addr, Handler, Message, Recipient};
+use actix::{
+    Actor, Addr, Arbiter, Context, Handler, Message, Recipient, SendError,
+};
 use anymap::Map;
-use crate::messages::Panic;
+use crate::messages::{MessageWiring, Panic, PluginSummary, Wiring};
+use futures::future::Future;
 use futures::stream::{self, Stream};
+use irc::proto::message::Message as IrcMessage;
+use std::any::{self, Any, TypeId};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Debug, Formatter};
+use std::mem;
 use std::panic::{self, PanicInfo};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type PanicHandler = Box<dyn Fn(&PanicInfo) + 'static + Sync + Send>;
+
+/// The threads started by [`start_isolated()`], and the plugin running on
+/// each of them.
+static ISOLATED_THREADS: Mutex<BTreeMap<String, String>> =
+    Mutex::new(BTreeMap::new());
 
 /// A RAII guard which will forward any panics to some actor which can accept
 /// the [`Panic`] message.
+///
+/// Nothing in the library installs one for you, so applications which embed
+/// the bot can keep their own panic handling. Guards should be dropped in the
+/// reverse order they were created, so each one puts back the hook it
+/// replaced.
 pub struct PanicHook {
-    previous_handler: Option<Box<dyn Fn(&PanicInfo) + 'static + Sync + Send>>,
+    previous_handler: Option<Arc<PanicHandler>>,
 }
 
 impl PanicHook {
+    /// Replace the current panic hook with one which forwards to an actor.
     pub fn new<A: Handler<Panic>>(logger: Addr<A>) -> PanicHook
     where
         <A as Actor>::Context: ToEnvelope<A, Panic>,
     {
-        let previous_handler = panic::take_hook();
+        PanicHook::install(logger, false)
+    }
+
+    /// Forward panics to an actor, then hand them to whichever hook was
+    /// already installed (e.g. an embedding application's).
+    pub fn chained<A: Handler<Panic>>(logger: Addr<A>) -> PanicHook
+    where
+        <A as Actor>::Context: ToEnvelope<A, Panic>,
+    {
+        PanicHook::install(logger, true)
+    }
+
+    fn install<A: Handler<Panic>>(logger: Addr<A>, chain: bool) -> PanicHook
+    where
+        <A as Actor>::Context: ToEnvelope<A, Panic>,
+    {
+        let previous_handler = Arc::new(panic::take_hook());
+        let previous = Arc::clone(&previous_handler);
 
         panic::set_hook(Box::new(move |panic_info| {
             logger.do_send(Panic::from(panic_info));
+
+            if chain {
+                previous(panic_info);
+            }
         }));
 
         PanicHook {
@@ -31,19 +76,410 @@ impl PanicHook {
 impl Drop for PanicHook {
     fn drop(&mut self) {
         let previous_handler = self.previous_handler.take().unwrap();
+        // dropping our hook also drops its copy of the previous one
         let _ = panic::take_hook();
-        panic::set_hook(previous_handler);
+
+        match Arc::try_unwrap(previous_handler) {
+            Ok(handler) => panic::set_hook(handler),
+            Err(shared) => panic::set_hook(Box::new(move |info| shared(info))),
+        }
+    }
+}
+
+/// Start an actor on its own thread, so if it panics (and the
+/// [`crate::settings::PanicPolicy`] allows it) the rest of the bot can carry
+/// on without it.
+///
+/// The `name` should be the one the plugin passes to
+/// [`crate::messages::Registration::from_plugin()`], so a panic unsubscribes
+/// it from everything it registered for.
+pub fn start_isolated<A>(name: &str, actor: A) -> Addr<A>
+where
+    A: Actor<Context = Context<A>> + Send,
+{
+    let arbiter = Arbiter::new(name.to_string());
+    let plugin = name.to_string();
+
+    A::start_in_arbiter(&arbiter, move |_ctx| {
+        // this runs on the new thread before the actor handles anything, so
+        // it's recorded before the plugin gets a chance to panic
+        if let Some(thread) = thread::current().name() {
+            ISOLATED_THREADS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(thread.to_string(), plugin);
+        }
+
+        actor
+    })
+}
+
+/// Which plugin (if any) was started on this thread by [`start_isolated()`]?
+pub fn isolated_plugin(thread: &str) -> Option<String> {
+    ISOLATED_THREADS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(thread)
+        .cloned()
+}
+
+/// Does this message target look like a channel (as opposed to a nick)?
+pub fn is_channel(target: &str) -> bool {
+    target.starts_with(|c: char| c == '#' || c == '&' || c == '+' || c == '!')
+}
+
+/// Lowercase a nick or channel name using the `rfc1459` casemapping, where
+/// `[]\~` are the uppercase versions of `{}|^`.
+pub fn irc_lowercase(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '[' => '{',
+            ']' => '}',
+            '\\' => '|',
+            '~' => '^',
+            other => other.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// Was a message sent by a server (e.g. `irc.example.com`) rather than a
+/// user (e.g. `Michael!mike@example.com`)?
+pub fn is_server_prefix(prefix: &str) -> bool {
+    !prefix.contains('!') && !prefix.contains('@')
+}
+
+/// Compare two nicks (or channel names), ignoring case.
+pub fn same_nick(left: &str, right: &str) -> bool {
+    irc_lowercase(left) == irc_lowercase(right)
+}
+
+/// Does a hostmask (e.g. `Michael!mike@example.com`) match a mask like
+/// `*!*@example.com`, where `*` matches anything and `?` matches any single
+/// character? Case is ignored.
+pub fn matches_mask(mask: &str, hostmask: &str) -> bool {
+    let mask: Vec<char> = irc_lowercase(mask).chars().collect();
+    let hostmask: Vec<char> = irc_lowercase(hostmask).chars().collect();
+    let (mut m, mut h) = (0, 0);
+    // where the last `*` was, and how much of the hostmask it has swallowed
+    let mut backtrack = None;
+
+    while h < hostmask.len() {
+        match mask.get(m) {
+            Some(&'*') => {
+                backtrack = Some((m, h));
+                m += 1;
+            }
+            Some(&c) if c == '?' || c == hostmask[h] => {
+                m += 1;
+                h += 1;
+            }
+            _ => match backtrack {
+                Some((star, swallowed)) => {
+                    m = star + 1;
+                    h = swallowed + 1;
+                    backtrack = Some((star, swallowed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    mask[m..].iter().all(|&c| c == '*')
+}
+
+/// Does this text mention a particular nick (e.g. to highlight them)?
+pub fn mentions(text: &str, nick: &str) -> bool {
+    let nick = irc_lowercase(nick);
+
+    text.split(|c: char| !is_nick_char(c))
+        .any(|word| irc_lowercase(word) == nick)
+}
+
+fn is_nick_char(c: char) -> bool {
+    c.is_alphanumeric() || "[]\\`_^{|}-".contains(c)
+}
+
+/// Strip out anything a server wouldn't accept in a nick, returning `None`
+/// if there's nothing usable left.
+pub fn sanitize_nick(nick: &str) -> Option<String> {
+    let cleaned: String = nick
+        .chars()
+        .filter(|&c| c.is_ascii() && is_nick_char(c))
+        .collect();
+    // nicks can't start with a digit or hyphen
+    let cleaned =
+        cleaned.trim_start_matches(|c: char| c.is_ascii_digit() || c == '-');
+
+    if cleaned.is_empty() {
+        None
+    } else {
+        Some(cleaned.to_string())
+    }
+}
+
+/// Format a duration the way a human would write it (e.g. `"2d 3h 15m"`).
+pub fn human_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let units = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+    ];
+
+    let formatted: Vec<String> = units
+        .iter()
+        .skip_while(|(value, _)| *value == 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+
+    if formatted.is_empty() {
+        format!("{}s", secs)
+    } else {
+        formatted.join(" ")
+    }
+}
+
+/// Fill in the `{placeholder}`s in a template (e.g. `"Hello, {nick}"`).
+///
+/// Placeholders we don't have a value for are left as they are, so a typo in
+/// an operator's template shows up in the reply instead of silently
+/// disappearing.
+pub fn expand_template(template: &str, values: &[(&str, &str)]) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let end = match rest.find('}') {
+            Some(end) => end,
+            None => break,
+        };
+        let name = &rest[1..end];
+
+        match values.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) => expanded.push_str(value),
+            None => expanded.push_str(&rest[..=end]),
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+/// The number of single-character insertions, deletions, or substitutions
+/// needed to turn one string into another.
+pub fn levenshtein(left: &str, right: &str) -> usize {
+    let right: Vec<char> = right.chars().collect();
+    // the distances from the part of `left` we've seen to each prefix of
+    // `right`
+    let mut previous: Vec<usize> = (0..=right.len()).collect();
+
+    for (i, l) in left.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, &r) in right.iter().enumerate() {
+            let substitution = previous[j] + if l == r { 0 } else { 1 };
+            let deletion = previous[j + 1] + 1;
+            let insertion = current[j] + 1;
+            current.push(substitution.min(deletion).min(insertion));
+        }
+
+        previous = current;
+    }
+
+    previous[right.len()]
+}
+
+/// Get the value of one of a message's IRCv3 tags, if present.
+pub fn tag<'a>(msg: &'a IrcMessage, name: &str) -> Option<&'a str> {
+    msg.tags
+        .as_ref()?
+        .iter()
+        .find(|tag| tag.0 == name)
+        .and_then(|tag| tag.1.as_ref())
+        .map(String::as_str)
+}
+
+/// Parse the timestamp from a `server-time` tag (e.g.
+/// `"2019-01-15T12:34:56.789Z"`).
+pub fn parse_server_time(timestamp: &str) -> Option<SystemTime> {
+    if !timestamp.ends_with('Z') {
+        return None;
+    }
+    let timestamp = &timestamp[..timestamp.len() - 1];
+
+    let mut parts = timestamp.splitn(2, 'T');
+    let (date, time) = (parts.next()?, parts.next()?);
+
+    let date: Vec<u64> = date
+        .split('-')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let (year, month, day) = match date.as_slice() {
+        &[year, month, day] => (year, month, day),
+        _ => return None,
+    };
+
+    let mut parts = time.splitn(2, '.');
+    let (time, fraction) = (parts.next()?, parts.next().unwrap_or("0"));
+    let time: Vec<u64> = time
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let (hours, minutes, seconds) = match time.as_slice() {
+        &[hours, minutes, seconds] => (hours, minutes, seconds),
+        _ => return None,
+    };
+
+    // only keep the milliseconds
+    let millis: String =
+        fraction.chars().chain("000".chars()).take(3).collect();
+    let millis: u64 = millis.parse().ok()?;
+
+    let valid = year >= 1970
+        && (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && hours < 24
+        && minutes < 60
+        && seconds <= 60;
+    if !valid {
+        return None;
+    }
+
+    let secs = days_since_epoch(year, month, day) * 86400
+        + hours * 3600
+        + minutes * 60
+        + seconds;
+
+    Some(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis))
+}
+
+/// The number of days between 1970-01-01 and a date in the proleptic
+/// Gregorian calendar.
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    // treat March as the first month, so leap days fall at the end of a year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month = (month + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era =
+        year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Split a message like `"!nick new-nick"` into the command's name and its
+/// arguments, returning `None` if it doesn't start with `prefix`.
+///
+/// Arguments are separated by whitespace, except inside double quotes, so
+/// `!say "hello world"` has a single argument.
+pub fn parse_command(
+    content: &str,
+    prefix: char,
+) -> Option<(String, Vec<String>)> {
+    if !content.starts_with(prefix) {
+        return None;
+    }
+
+    let rest = &content[prefix.len_utf8()..];
+    if rest.starts_with(char::is_whitespace) {
+        return None;
     }
+
+    let mut parts = rest.splitn(2, char::is_whitespace);
+    let name = parts.next().filter(|name| !name.is_empty())?;
+
+    Some((
+        name.to_string(),
+        split_args(parts.next().unwrap_or_default()),
+    ))
+}
+
+/// Split a command's arguments on whitespace, keeping anything in double
+/// quotes together. Inside quotes, a backslash escapes the next character.
+fn split_args(text: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    // lets `""` count as an (empty) argument
+    let mut in_arg = false;
+    let mut quoted = false;
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            '\\' if quoted => current.extend(chars.next()),
+            c if c.is_whitespace() && !quoted => {
+                if in_arg {
+                    args.push(mem::replace(&mut current, String::new()));
+                    in_arg = false;
+                }
+            }
+            c => {
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(current);
+    }
+
+    args
+}
+
+/// Unwrap a CTCP message (e.g. `"\x01VERSION\x01"`) into its command and
+/// arguments.
+pub fn parse_ctcp(text: &str) -> Option<(&str, Option<&str>)> {
+    if text.len() < 2 || !text.starts_with('\u{1}') {
+        return None;
+    }
+
+    // the closing delimiter is sometimes left off
+    let inner = text[1..].trim_end_matches('\u{1}');
+    let mut parts = inner.splitn(2, ' ');
+    let command = parts.next().filter(|cmd| !cmd.is_empty())?;
+
+    Some((command, parts.next()))
 }
 
 #[derive(Debug)]
 pub struct MessageBox {
     map: Map<anymap::any::Any + Send>,
+    /// The message types each named plugin is subscribed to.
+    plugins: BTreeMap<String, HashSet<TypeId>>,
+    /// The names of each message type we've come across.
+    types: HashMap<TypeId, MessageType>,
+    /// How many recipients are registered for each message type.
+    subscribers: HashMap<TypeId, usize>,
+    /// Each named plugin's recipients, so they can all be unregistered at
+    /// once without knowing their message types.
+    recipients_by_plugin: HashMap<String, Vec<Box<dyn Subscription>>>,
+    /// Recipients we've found out have stopped, waiting to be removed by
+    /// [`MessageBox::prune()`]. It's shared with the streams returned by
+    /// [`MessageBox::do_send()`], which only find out once they're polled.
+    closed: Arc<Mutex<Vec<Box<dyn Subscription>>>>,
 }
 
 impl MessageBox {
     pub fn new() -> MessageBox {
-        MessageBox { map: Map::new() }
+        MessageBox {
+            map: Map::new(),
+            plugins: BTreeMap::new(),
+            types: HashMap::new(),
+            subscribers: HashMap::new(),
+            recipients_by_plugin: HashMap::new(),
+            closed: Arc::new(Mutex::new(Vec::new())),
+        }
     }
 
     pub fn register<M>(&mut self, recipient: Recipient<M>)
@@ -51,12 +487,16 @@ impl MessageBox {
         M: Message + Clone + Send + 'static,
         M::Result: Send,
     {
+        self.remember_type::<M>();
+
         let recipients = self
             .map
             .entry::<Vec<Recipient<M>>>()
             .or_insert_with(Default::default);
 
         recipients.push(recipient);
+        let count = recipients.len();
+        self.subscribers.insert(TypeId::of::<M>(), count);
     }
 
     pub fn unregister<M>(&mut self, recipient: &Recipient<M>)
@@ -68,27 +508,134 @@ impl MessageBox {
             if let Some(ix) = recipients.iter().position(|x| *x == *recipient) {
                 recipients.remove(ix);
             }
+
+            if recipients.is_empty() {
+                self.subscribers.remove(&TypeId::of::<M>());
+            } else {
+                self.subscribers.insert(TypeId::of::<M>(), recipients.len());
+            }
         }
     }
 
-    pub fn send<M>(&self, msg: M)
+    /// A snapshot of everyone currently registered for a message type, in the
+    /// order they registered.
+    pub fn recipients<M>(&self) -> Vec<Recipient<M>>
+    where
+        M: Message + Clone + Send + 'static,
+        M::Result: Send,
+    {
+        self.map
+            .get::<Vec<Recipient<M>>>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Send a copy of the message to each registered recipient.
+    ///
+    /// Messages are queued in the order the recipients were registered, so
+    /// actors on the same arbiter will receive them in that order too. Any
+    /// recipient whose actor has stopped is pruned along the way.
+    pub fn send<M>(&mut self, msg: M)
     where
         M: Message + Clone + Send + 'static,
         M::Result: Send,
     {
         if let Some(recipients) = self.map.get::<Vec<Recipient<M>>>() {
             for recipient in recipients {
-                let _ = recipient.do_send(msg.clone());
+                if let Err(SendError::Closed(_)) =
+                    recipient.do_send(msg.clone())
+                {
+                    self.closed
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .push(Box::new(recipient.clone()));
+                }
+            }
+        }
+
+        self.prune();
+    }
+
+    /// Remove every recipient which has been found to have stopped, returning
+    /// how many were removed.
+    ///
+    /// A recipient can only be found out when something is sent to it, so
+    /// [`MessageBox::send()`] does this for you. The streams returned by
+    /// [`MessageBox::do_send()`] report a stopped recipient as a
+    /// [`actix::MailboxError::Closed`], and it gets removed the next time
+    /// anything is sent.
+    pub fn prune(&mut self) -> usize {
+        let closed = mem::replace(
+            &mut *self.closed.lock().unwrap_or_else(|e| e.into_inner()),
+            Vec::new(),
+        );
+
+        for subscription in &closed {
+            subscription.forget(self);
+        }
+
+        closed.len()
+    }
+
+    /// Unregister a recipient and stop tracking it for whichever plugins it
+    /// belonged to.
+    fn forget<M>(&mut self, recipient: &Recipient<M>)
+    where
+        M: Message + Clone + Send + 'static,
+        M::Result: Send,
+    {
+        self.unregister(recipient);
+
+        let plugins: Vec<String> = self
+            .recipients_by_plugin
+            .iter()
+            .filter(|(_, tracked)| {
+                tracked.iter().any(|sub| {
+                    sub.as_any().downcast_ref::<Recipient<M>>()
+                        == Some(recipient)
+                })
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        for plugin in plugins {
+            self.untrack(&plugin, recipient);
+
+            let still_subscribed = self.recipients_by_plugin[&plugin]
+                .iter()
+                .any(|sub| sub.as_any().is::<Recipient<M>>());
+            if !still_subscribed {
+                self.unsubscribe::<M>(&plugin);
             }
         }
     }
 
     /// Send a copy of the message to each registered recipient, returning a
     /// stream of responses which will be resolved as they come in.
+    ///
+    /// The responses arrive in whatever order the recipients get around to
+    /// replying, use [`MessageBox::do_send_ordered()`] if that matters. A
+    /// recipient whose actor has stopped shows up as an error, and is pruned
+    /// before anything else gets sent.
     pub fn do_send<M>(
-        &self,
+        &mut self,
         msg: M,
     ) -> impl Stream<Item = M::Result, Error = actix::MailboxError>
+    where
+        M: Message + Clone + Send + 'static,
+        M::Result: Send,
+    {
+        self.prune();
+
+        stream::futures_unordered(self.requests(msg))
+    }
+
+    /// Send a copy of the message to each registered recipient, remembering
+    /// any which turn out to have stopped.
+    fn requests<M>(
+        &self,
+        msg: M,
+    ) -> Vec<impl Future<Item = M::Result, Error = actix::MailboxError>>
     where
         M: Message + Clone + Send + 'static,
         M::Result: Send,
@@ -98,11 +645,170 @@ impl MessageBox {
             None => &[],
         };
 
-        let futures = recipients
+        recipients
+            .iter()
+            .map(|recipient| {
+                let closed = Arc::clone(&self.closed);
+                let stopped = recipient.clone();
+
+                recipient.send(msg.clone()).map_err(move |e| {
+                    if let actix::MailboxError::Closed = e {
+                        closed
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .push(Box::new(stopped));
+                    }
+                    e
+                })
+            })
+            .collect()
+    }
+
+    /// Like [`MessageBox::do_send()`], except the responses are yielded in
+    /// the order the recipients were registered.
+    ///
+    /// A slow recipient will hold up every response after it.
+    pub fn do_send_ordered<M>(
+        &mut self,
+        msg: M,
+    ) -> impl Stream<Item = M::Result, Error = actix::MailboxError>
+    where
+        M: Message + Clone + Send + 'static,
+        M::Result: Send,
+    {
+        self.prune();
+
+        stream::futures_ordered(self.requests(msg))
+    }
+
+    /// Remember that a plugin is subscribed to `M`.
+    pub fn subscribe<M: 'static>(&mut self, plugin: &str) {
+        self.remember_type::<M>();
+        self.plugins
+            .entry(plugin.to_string())
+            .or_insert_with(HashSet::new)
+            .insert(TypeId::of::<M>());
+    }
+
+    pub fn unsubscribe<M: 'static>(&mut self, plugin: &str) {
+        let now_empty = match self.plugins.get_mut(plugin) {
+            Some(subscriptions) => {
+                subscriptions.remove(&TypeId::of::<M>());
+                subscriptions.is_empty()
+            }
+            None => false,
+        };
+
+        if now_empty {
+            self.plugins.remove(plugin);
+        }
+    }
+
+    /// Remember a named plugin's recipient, so it can be removed by
+    /// [`MessageBox::unregister_all()`].
+    pub fn track<M>(&mut self, plugin: &str, recipient: Recipient<M>)
+    where
+        M: Message + Clone + Send + 'static,
+        M::Result: Send,
+    {
+        self.recipients_by_plugin
+            .entry(plugin.to_string())
+            .or_insert_with(Vec::new)
+            .push(Box::new(recipient));
+    }
+
+    /// Forget a recipient passed to [`MessageBox::track()`].
+    pub fn untrack<M>(&mut self, plugin: &str, recipient: &Recipient<M>)
+    where
+        M: Message + Clone + Send + 'static,
+        M::Result: Send,
+    {
+        if let Some(tracked) = self.recipients_by_plugin.get_mut(plugin) {
+            tracked.retain(|sub| {
+                sub.as_any()
+                    .downcast_ref::<Recipient<M>>()
+                    .map_or(true, |r| r != recipient)
+            });
+        }
+    }
+
+    /// Unregister every recipient a named plugin has registered, whatever
+    /// message types they're for, returning how many were removed.
+    pub fn unregister_all(&mut self, plugin: &str) -> usize {
+        let tracked =
+            self.recipients_by_plugin.remove(plugin).unwrap_or_default();
+
+        for subscription in &tracked {
+            subscription.unregister_from(self);
+        }
+        self.plugins.remove(plugin);
+
+        tracked.len()
+    }
+
+    /// Every named plugin, sorted by name.
+    pub fn plugins(&self) -> Vec<PluginSummary> {
+        self.plugins
             .iter()
-            .map(move |recipient| recipient.send(msg.clone()));
+            .map(|(name, subscriptions)| {
+                let mut subscriptions: Vec<&'static str> =
+                    subscriptions.iter().map(|id| self.type_name(id)).collect();
+                subscriptions.sort();
 
-        stream::futures_unordered(futures)
+                PluginSummary {
+                    name: name.clone(),
+                    subscriptions,
+                    commands: Vec::new(),
+                }
+            })
+            .collect()
+    }
+
+    /// Which message types have subscribers, how many, and which of them are
+    /// named plugins.
+    pub fn wiring(&self) -> Wiring {
+        let mut messages: Vec<MessageWiring> = self
+            .subscribers
+            .iter()
+            .map(|(id, &subscribers)| MessageWiring {
+                message: self.type_name(id).to_string(),
+                subscribers,
+                plugins: self
+                    .plugins
+                    .iter()
+                    .filter(|(_, subscriptions)| subscriptions.contains(id))
+                    .map(|(name, _)| name.clone())
+                    .collect(),
+            })
+            .collect();
+        messages.sort_by(|left, right| left.message.cmp(&right.message));
+
+        Wiring { messages }
+    }
+
+    fn remember_type<M: 'static>(&mut self) {
+        self.types
+            .entry(TypeId::of::<M>())
+            .or_insert_with(MessageType::of::<M>);
+    }
+
+    /// The name to show for a message type, which is its full path if
+    /// another message type has the same name.
+    fn type_name(&self, id: &TypeId) -> &'static str {
+        let ty = match self.types.get(id) {
+            Some(ty) => ty,
+            None => return "?",
+        };
+        let ambiguous = self
+            .types
+            .iter()
+            .any(|(other_id, other)| other_id != id && other.name == ty.name);
+
+        if ambiguous {
+            ty.path
+        } else {
+            ty.name
+        }
     }
 
     pub fn len(&self) -> usize {
@@ -114,16 +820,154 @@ impl MessageBox {
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+struct MessageType {
+    /// The bare name (e.g. `UserJoined`).
+    name: &'static str,
+    /// The full path (e.g. `irc_bot::messages::UserJoined`).
+    path: &'static str,
+}
+
+impl MessageType {
+    fn of<M: 'static>() -> MessageType {
+        MessageType {
+            name: message_name::<M>(),
+            path: any::type_name::<M>(),
+        }
+    }
+}
+
+/// A recipient whose message type has been erased, so recipients for
+/// different messages can be kept together.
+trait Subscription: Send {
+    fn unregister_from(&self, message_box: &mut MessageBox);
+    fn forget(&self, message_box: &mut MessageBox);
+    fn message(&self) -> &'static str;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<M> Subscription for Recipient<M>
+where
+    M: Message + Clone + Send + 'static,
+    M::Result: Send,
+{
+    fn unregister_from(&self, message_box: &mut MessageBox) {
+        message_box.unregister(self);
+    }
+
+    fn forget(&self, message_box: &mut MessageBox) {
+        message_box.forget(self);
+    }
+
+    fn message(&self) -> &'static str {
+        message_name::<M>()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Debug for dyn Subscription {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("Subscription")
+            .field(&self.message())
+            .finish()
+    }
+}
+
+/// The bare name of a type (e.g. `UserJoined` instead of
+/// `irc_bot::messages::UserJoined`).
+fn message_name<M: 'static>() -> &'static str {
+    let name = any::type_name::<M>();
+    let name = name.split('<').next().unwrap_or(name);
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Join items into as few lines as possible without any line going over
+/// `max_length` bytes. Items which are too long by themselves get a line of
+/// their own.
+pub fn pack_lines<I>(
+    items: I,
+    separator: &str,
+    max_length: usize,
+) -> Vec<String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut lines = Vec::new();
+    let mut current = String::new();
+
+    for item in items {
+        if !current.is_empty()
+            && current.len() + separator.len() + item.len() > max_length
+        {
+            lines.push(current);
+            current = String::new();
+        }
+
+        if !current.is_empty() {
+            current.push_str(separator);
+        }
+        current.push_str(&item);
+    }
+
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
 impl Default for MessageBox {
     fn default() -> MessageBox {
         MessageBox::new()
     }
 }
 
+/// A fake [`Client`] which remembers everything it was asked to send.
+#[cfg(test)]
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MockClient {
+    pub config: irc::client::data::Config,
+    pub sent: std::sync::Arc<std::sync::Mutex<Vec<IrcMessage>>>,
+}
+
+#[cfg(test)]
+impl irc::client::Client for MockClient {
+    fn config(&self) -> &irc::client::data::Config {
+        &self.config
+    }
+
+    fn send<M: Into<IrcMessage>>(&self, message: M) -> irc::error::Result<()>
+    where
+        Self: Sized,
+    {
+        self.sent.lock().unwrap().push(message.into());
+        Ok(())
+    }
+
+    fn stream(&self) -> irc::client::ClientStream {
+        unimplemented!("The MockClient can't receive messages")
+    }
+
+    fn list_channels(&self) -> Option<Vec<String>> {
+        None
+    }
+
+    fn list_users(
+        &self,
+        _channel: &str,
+    ) -> Option<Vec<irc::client::data::User>> {
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use actix::{Context, System};
+    use actix::{ActorContext, Context, MessageResult, System};
+    use std::sync::{Arc, Mutex};
 
     #[derive(Debug, Clone, Copy, Message)]
     struct Ping;
@@ -164,6 +1008,227 @@ mod tests {
         }
     }
 
+    #[derive(Debug, Copy, Clone, Message)]
+    struct Stop;
+
+    /// A message type which happens to share a name with [`Ping`].
+    mod other {
+        #[derive(Debug, Clone, Copy, Message)]
+        pub struct Ping;
+    }
+
+    impl Handler<other::Ping> for PingReceiver {
+        type Result = ();
+
+        fn handle(&mut self, _msg: other::Ping, _ctx: &mut Self::Context) {}
+    }
+
+    impl Handler<Stop> for PingReceiver {
+        type Result = ();
+
+        fn handle(&mut self, _msg: Stop, ctx: &mut Self::Context) {
+            ctx.stop();
+        }
+    }
+
+    #[test]
+    fn rfc1459_casemapping() {
+        assert_eq!(irc_lowercase("#Rust[Offtopic]"), "#rust{offtopic}");
+        assert_eq!(irc_lowercase("Michael\\~"), "michael|^");
+    }
+
+    #[test]
+    fn match_hostmasks() {
+        let inputs = vec![
+            ("*!*@example.com", "Michael!mike@example.com", true),
+            ("michael!*@*", "Michael!mike@example.com", true),
+            ("*!mi?e@*.com", "Michael!mike@example.com", true),
+            ("*!*@*", "Michael!mike@example.com", true),
+            ("*!*@example.com", "Michael!mike@example.com.evil", false),
+            ("bob!*@*", "Michael!mike@example.com", false),
+            ("*!*@rust/*", "Michael!mike@rust/contributor/michael", true),
+        ];
+
+        for (mask, hostmask, should_be) in inputs {
+            assert_eq!(matches_mask(mask, hostmask), should_be, "{}", mask);
+        }
+    }
+
+    #[test]
+    fn edit_distances() {
+        let inputs = vec![
+            ("", "", 0),
+            ("nick", "nick", 0),
+            ("", "quit", 4),
+            ("stat", "stats", 1),
+            ("nikc", "nick", 2),
+            ("kitten", "sitting", 3),
+        ];
+
+        for (left, right, should_be) in inputs {
+            assert_eq!(levenshtein(left, right), should_be);
+            assert_eq!(levenshtein(right, left), should_be);
+        }
+    }
+
+    #[test]
+    fn detect_highlights() {
+        assert!(mentions("Michael: ping", "michael"));
+        assert!(mentions("hey [bot], how are you?", "[Bot]"));
+        assert!(!mentions("Michaelangelo is a painter", "Michael"));
+    }
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(
+            parse_command("!nick new-nick", '!'),
+            Some((String::from("nick"), vec![String::from("new-nick")]))
+        );
+        assert_eq!(
+            parse_command("!quit", '!'),
+            Some((String::from("quit"), vec![]))
+        );
+        assert_eq!(parse_command("! nick", '!'), None);
+        assert_eq!(parse_command("!", '!'), None);
+        assert_eq!(parse_command("hello !nick", '!'), None);
+    }
+
+    #[test]
+    fn parse_commands_with_quoted_arguments() {
+        let inputs = vec![
+            (r#"!say "hello world""#, vec!["hello world"]),
+            (
+                r#"!say  "hello world"  again"#,
+                vec!["hello world", "again"],
+            ),
+            (r#"!say "" empty"#, vec!["", "empty"]),
+            (r#"!say "she said \"hi\"""#, vec![r#"she said "hi""#]),
+            (r#"!say "unterminated quote"#, vec!["unterminated quote"]),
+            (r#"!say a"b c"d"#, vec!["ab cd"]),
+        ];
+
+        for (src, should_be) in inputs {
+            let (name, args) = parse_command(src, '!').unwrap();
+            assert_eq!(name, "say");
+            assert_eq!(args, should_be);
+        }
+    }
+
+    #[test]
+    fn commands_can_use_a_different_prefix() {
+        assert_eq!(
+            parse_command(".nick new-nick", '.'),
+            Some((String::from("nick"), vec![String::from("new-nick")]))
+        );
+        assert_eq!(parse_command("!nick new-nick", '.'), None);
+        assert_eq!(
+            parse_command("λping", 'λ'),
+            Some((String::from("ping"), vec![]))
+        );
+    }
+
+    #[test]
+    fn sanitize_invalid_nicks() {
+        let inputs = vec![
+            ("bot", Some("bot")),
+            ("my bot", Some("mybot")),
+            ("b.o.t", Some("bot")),
+            ("42bot", Some("bot")),
+            ("-[bot]", Some("[bot]")),
+            ("ünïcode", Some("ncode")),
+            ("1234", None),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(sanitize_nick(src), should_be.map(String::from));
+        }
+    }
+
+    #[test]
+    fn expand_templates() {
+        let values = [("nick", "Michael"), ("channel", "#rust")];
+
+        assert_eq!(
+            expand_template("Welcome to {channel}, {nick}!", &values),
+            "Welcome to #rust, Michael!"
+        );
+        assert_eq!(expand_template("{nick}{nick}", &values), "MichaelMichael");
+        assert_eq!(
+            expand_template("No placeholders", &values),
+            "No placeholders"
+        );
+    }
+
+    #[test]
+    fn leave_unknown_placeholders_alone() {
+        let values = [("nick", "Michael")];
+
+        assert_eq!(
+            expand_template("{nick} ran {command}", &values),
+            "Michael ran {command}"
+        );
+        assert_eq!(expand_template("{} and {nick", &values), "{} and {nick");
+        assert_eq!(expand_template("{{nick}}", &values), "{{nick}}");
+    }
+
+    #[test]
+    fn format_durations_for_humans() {
+        let inputs = vec![
+            (0, "0s"),
+            (42, "42s"),
+            (15 * 60 + 3, "15m"),
+            (3 * 3600 + 15 * 60, "3h 15m"),
+            (2 * 86400 + 5 * 60, "2d 0h 5m"),
+        ];
+
+        for (secs, should_be) in inputs {
+            assert_eq!(human_duration(Duration::from_secs(secs)), should_be);
+        }
+    }
+
+    #[test]
+    fn parse_server_timestamps() {
+        let inputs = vec![
+            ("1970-01-01T00:00:00.000Z", Some(0)),
+            ("2019-01-15T12:34:56.789Z", Some(1_547_555_696_789)),
+            ("2020-02-29T00:00:00Z", Some(1_582_934_400_000)),
+            ("2019-01-15T12:34:56.7Z", Some(1_547_555_696_700)),
+            ("2019-01-15T12:34:56.789", None),
+            ("2019-13-15T12:34:56.789Z", None),
+            ("yesterday", None),
+        ];
+
+        for (src, should_be) in inputs {
+            let got = parse_server_time(src).map(|time| {
+                let since = time.duration_since(UNIX_EPOCH).unwrap();
+                since.as_secs() * 1000 + u64::from(since.subsec_millis())
+            });
+            assert_eq!(got, should_be, "{}", src);
+        }
+    }
+
+    #[test]
+    fn detect_messages_from_servers() {
+        assert!(is_server_prefix("irc.example.com"));
+        assert!(!is_server_prefix("Michael!mike@example.com"));
+        assert!(!is_server_prefix("Michael@example.com"));
+    }
+
+    #[test]
+    fn parse_ctcp_messages() {
+        let inputs = vec![
+            ("\u{1}VERSION\u{1}", Some(("VERSION", None))),
+            ("\u{1}PING 1234\u{1}", Some(("PING", Some("1234")))),
+            ("\u{1}TIME", Some(("TIME", None))),
+            ("\u{1}\u{1}", None),
+            ("VERSION", None),
+        ];
+
+        for (src, should_be) in inputs {
+            assert_eq!(parse_ctcp(src), should_be);
+        }
+    }
+
     #[test]
     fn receive_a_message() {
         let mut sys = System::new("test");
@@ -181,4 +1246,368 @@ mod tests {
         let count = sys.block_on(addr.send(PingCount)).unwrap();
         assert_eq!(count, 1);
     }
+
+    #[test]
+    fn messages_only_reach_recipients_of_that_type() {
+        let mut sys = System::new("test");
+        let addr = PingReceiver::default().start();
+        let mut map = MessageBox::new();
+        map.register::<Ping>(addr.clone().recipient());
+
+        // the receiver can handle a PingCount, but it only registered for Ping
+        map.send(PingCount);
+        let responses = sys.block_on(map.do_send(PingCount).collect()).unwrap();
+        assert!(responses.is_empty());
+
+        let (mut numbered, asked) = numbered(1);
+        numbered.send(Ping);
+        let count = sys.block_on(addr.send(PingCount)).unwrap();
+        assert_eq!(count, 0);
+        assert!(asked.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn snapshot_the_recipients_for_a_message_type() {
+        let _sys = System::new("test");
+        let addr = PingReceiver::default().start();
+        let recipient: Recipient<Ping> = addr.recipient();
+        let mut map = MessageBox::new();
+        map.register(recipient.clone());
+
+        assert!(map.recipients::<Ping>() == vec![recipient]);
+        assert!(map.recipients::<PingCount>().is_empty());
+    }
+
+    #[derive(Debug, Copy, Clone)]
+    struct WhoAreYou;
+
+    impl Message for WhoAreYou {
+        type Result = usize;
+    }
+
+    /// Replies with its ID, after recording that it was asked.
+    struct Numbered {
+        id: usize,
+        asked: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl Actor for Numbered {
+        type Context = Context<Numbered>;
+    }
+
+    impl Handler<WhoAreYou> for Numbered {
+        type Result = usize;
+
+        fn handle(
+            &mut self,
+            _msg: WhoAreYou,
+            _ctx: &mut Self::Context,
+        ) -> usize {
+            self.asked.lock().unwrap().push(self.id);
+            self.id
+        }
+    }
+
+    fn numbered(count: usize) -> (MessageBox, Arc<Mutex<Vec<usize>>>) {
+        let asked = Arc::new(Mutex::new(Vec::new()));
+        let mut map = MessageBox::new();
+
+        for id in 0..count {
+            let actor = Numbered {
+                id,
+                asked: Arc::clone(&asked),
+            };
+            map.register::<WhoAreYou>(actor.start().recipient());
+        }
+
+        (map, asked)
+    }
+
+    #[test]
+    fn send_in_registration_order() {
+        let mut sys = System::new("test");
+        let (mut map, asked) = numbered(5);
+
+        map.send(WhoAreYou);
+        // everything sent earlier is handled before these replies come back
+        let _ = sys.block_on(map.do_send(WhoAreYou).collect()).unwrap();
+
+        let asked = asked.lock().unwrap();
+        assert_eq!(&asked[..5], &[0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn ordered_responses_follow_registration_order() {
+        let mut sys = System::new("test");
+        let (mut map, _) = numbered(5);
+
+        let got = sys.block_on(map.do_send_ordered(WhoAreYou).collect());
+
+        assert_eq!(got.unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn keep_track_of_plugin_subscriptions() {
+        let mut map = MessageBox::new();
+
+        map.subscribe::<Ping>("pinger");
+        map.subscribe::<PingCount>("pinger");
+        map.subscribe::<Ping>("another");
+        map.unsubscribe::<Ping>("another");
+
+        assert_eq!(
+            map.plugins(),
+            vec![PluginSummary {
+                name: String::from("pinger"),
+                subscriptions: vec!["Ping", "PingCount"],
+                commands: Vec::new(),
+            }]
+        );
+    }
+
+    #[test]
+    fn describe_the_wiring() {
+        let _sys = System::new("test");
+        let first = PingReceiver::default().start();
+        let second = PingReceiver::default().start();
+        let mut map = MessageBox::new();
+
+        map.register::<Ping>(first.clone().recipient());
+        map.subscribe::<Ping>("pinger");
+        map.register::<Ping>(second.clone().recipient());
+        map.register::<PingCount>(second.clone().recipient());
+        map.unregister::<PingCount>(&second.recipient());
+
+        assert_eq!(
+            map.wiring(),
+            Wiring {
+                messages: vec![MessageWiring {
+                    message: String::from("Ping"),
+                    subscribers: 2,
+                    plugins: vec![String::from("pinger")],
+                }],
+            }
+        );
+        assert_eq!(
+            map.wiring().to_string(),
+            "Ping: 2 subscribers (pinger, 1 unnamed)"
+        );
+    }
+
+    #[test]
+    fn message_types_with_the_same_name_are_kept_apart() {
+        let _sys = System::new("test");
+        let addr = PingReceiver::default().start();
+        let mut map = MessageBox::new();
+
+        map.register::<Ping>(addr.clone().recipient());
+        map.subscribe::<Ping>("pinger");
+        map.register::<other::Ping>(addr.clone().recipient());
+        map.register::<other::Ping>(addr.clone().recipient());
+        map.subscribe::<other::Ping>("other");
+        map.unsubscribe::<other::Ping>("pinger");
+
+        let wiring = map.wiring();
+        assert_eq!(wiring.messages.len(), 2);
+        let subscribers: Vec<(usize, Vec<String>)> = wiring
+            .messages
+            .into_iter()
+            .map(|message| (message.subscribers, message.plugins))
+            .collect();
+        assert!(subscribers.contains(&(1, vec![String::from("pinger")])));
+        assert!(subscribers.contains(&(2, vec![String::from("other")])));
+    }
+
+    #[test]
+    fn show_the_full_path_of_ambiguous_message_types() {
+        let _sys = System::new("test");
+        let addr = PingReceiver::default().start();
+        let mut map = MessageBox::new();
+
+        map.register::<PingCount>(addr.clone().recipient());
+        map.register::<Ping>(addr.clone().recipient());
+        map.subscribe::<other::Ping>("other");
+
+        let names: Vec<String> = map
+            .wiring()
+            .messages
+            .into_iter()
+            .map(|message| message.message)
+            .collect();
+        // sorted by name, so the lowercase path comes last
+        assert_eq!(names, vec!["PingCount", any::type_name::<Ping>()]);
+        assert_eq!(
+            map.plugins()[0].subscriptions,
+            vec![any::type_name::<other::Ping>()]
+        );
+    }
+
+    #[test]
+    fn unregister_everything_a_plugin_registered() {
+        let _sys = System::new("test");
+        let pinger = PingReceiver::default().start();
+        let other = PingReceiver::default().start();
+        let mut map = MessageBox::new();
+
+        for (addr, plugin) in vec![(&pinger, "pinger"), (&other, "other")] {
+            map.register::<Ping>(addr.clone().recipient());
+            map.track::<Ping>(plugin, addr.clone().recipient());
+            map.subscribe::<Ping>(plugin);
+        }
+        map.register::<PingCount>(pinger.clone().recipient());
+        map.track::<PingCount>("pinger", pinger.clone().recipient());
+        map.subscribe::<PingCount>("pinger");
+
+        assert_eq!(map.unregister_all("pinger"), 2);
+
+        assert!(map.recipients::<PingCount>().is_empty());
+        assert!(map.recipients::<Ping>() == vec![other.recipient()]);
+        let plugins: Vec<String> =
+            map.plugins().into_iter().map(|p| p.name).collect();
+        assert_eq!(plugins, vec!["other"]);
+        assert_eq!(map.unregister_all("pinger"), 0);
+    }
+
+    #[test]
+    fn stopped_recipients_are_pruned() {
+        let mut sys = System::new("test");
+        let alive = PingReceiver::default().start();
+        let stopped = PingReceiver::default().start();
+        let mut map = MessageBox::new();
+
+        for addr in vec![&alive, &stopped] {
+            map.register::<Ping>(addr.clone().recipient());
+            map.track::<Ping>("pinger", addr.clone().recipient());
+            map.subscribe::<Ping>("pinger");
+        }
+        assert_eq!(map.recipients::<Ping>().len(), 2);
+
+        sys.block_on(stopped.send(Stop)).unwrap();
+        assert!(!stopped.connected());
+        map.send(Ping);
+
+        assert!(map.recipients::<Ping>() == vec![alive.clone().recipient()]);
+        assert_eq!(map.wiring().messages[0].subscribers, 1);
+        // the live recipient is still tracked for its plugin
+        assert_eq!(map.unregister_all("pinger"), 1);
+        assert_eq!(map.prune(), 0);
+
+        let count = sys.block_on(alive.send(PingCount)).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn requests_to_stopped_recipients_are_pruned() {
+        let mut sys = System::new("test");
+        let alive = PingReceiver::default().start();
+        let stopped = PingReceiver::default().start();
+        let mut map = MessageBox::new();
+
+        for addr in vec![&alive, &stopped] {
+            map.register::<PingCount>(addr.clone().recipient());
+            map.track::<PingCount>("pinger", addr.clone().recipient());
+            map.subscribe::<PingCount>("pinger");
+        }
+        sys.block_on(stopped.send(Stop)).unwrap();
+
+        let got: Vec<Result<usize, actix::MailboxError>> = sys
+            .block_on(
+                map.do_send_ordered(PingCount).then(Ok::<_, ()>).collect(),
+            )
+            .unwrap();
+        assert!(got[0].is_ok());
+        assert!(got[1].is_err());
+
+        // the stopped recipient is gone before the next request goes out
+        let got = sys.block_on(map.do_send_ordered(PingCount).collect());
+        assert_eq!(got.unwrap(), vec![0]);
+        assert!(map.recipients::<PingCount>() == vec![alive.recipient()]);
+        assert_eq!(map.unregister_all("pinger"), 1);
+    }
+
+    #[test]
+    fn pack_items_into_lines() {
+        let items = vec!["first", "second", "third", "a-very-long-item"];
+        let got = pack_lines(items.into_iter().map(String::from), ", ", 15);
+
+        assert_eq!(got, vec!["first, second", "third", "a-very-long-item"]);
+    }
+
+    #[derive(Debug, Default)]
+    struct PanicRecorder {
+        panics: Vec<(String, String, u32)>,
+    }
+
+    impl Actor for PanicRecorder {
+        type Context = Context<PanicRecorder>;
+    }
+
+    impl Handler<Panic> for PanicRecorder {
+        type Result = ();
+
+        fn handle(&mut self, msg: Panic, _ctx: &mut Self::Context) {
+            self.panics.push((msg.message, msg.file, msg.line));
+        }
+    }
+
+    #[derive(Debug, Copy, Clone)]
+    struct GetPanics;
+
+    impl Message for GetPanics {
+        type Result = Vec<(String, String, u32)>;
+    }
+
+    impl Handler<GetPanics> for PanicRecorder {
+        type Result = MessageResult<GetPanics>;
+
+        fn handle(
+            &mut self,
+            _msg: GetPanics,
+            _ctx: &mut Self::Context,
+        ) -> Self::Result {
+            MessageResult(self.panics.clone())
+        }
+    }
+
+    #[test]
+    fn panic_hook_forwards_panics_to_an_actor() {
+        let mut sys = System::new("test");
+        let recorder = PanicRecorder::default().start();
+
+        let hook = PanicHook::new(recorder.clone());
+        let line = line!() + 1;
+        let got = panic::catch_unwind(|| panic!("Oops"));
+        drop(hook);
+
+        assert!(got.is_err());
+
+        let panics = sys.block_on(recorder.send(GetPanics)).unwrap();
+        assert_eq!(
+            panics,
+            vec![(String::from("Oops"), String::from(file!()), line)]
+        );
+    }
+
+    #[test]
+    fn chained_panic_hook_still_calls_the_previous_hook() {
+        let mut sys = System::new("test");
+        let recorder = PanicRecorder::default().start();
+        let calls = Arc::new(Mutex::new(0));
+        let calls_2 = Arc::clone(&calls);
+
+        let original = panic::take_hook();
+        panic::set_hook(Box::new(move |_| *calls_2.lock().unwrap() += 1));
+        let hook = PanicHook::chained(recorder.clone());
+        let got = panic::catch_unwind(|| panic!("Oops"));
+        drop(hook);
+
+        // the embedder's hook is back in place once we're done
+        let _ = panic::catch_unwind(|| panic!("Again"));
+        panic::set_hook(original);
+
+        assert!(got.is_err());
+        assert_eq!(*calls.lock().unwrap(), 2);
+        let panics = sys.block_on(recorder.send(GetPanics)).unwrap();
+        assert_eq!(panics.len(), 1);
+    }
 }
@@ -0,0 +1,37 @@
+//! Which build of the bot is running.
+
+/// The bot's version number (e.g. `"0.1.0"`).
+pub fn version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+/// The commit the bot was built from, when it was built from a git checkout.
+pub fn git_hash() -> Option<&'static str> {
+    option_env!("IRC_BOT_GIT_HASH")
+}
+
+/// The version of the `irc` crate we were built against.
+pub fn irc_version() -> Option<&'static str> {
+    option_env!("IRC_BOT_IRC_VERSION")
+}
+
+/// Everything we know about this build, as used for the CTCP `VERSION`
+/// reply (e.g. `"irc_bot v0.1.0 (linux-x86_64) 1a2b3c4, irc v0.13.6"`).
+pub fn describe_version() -> String {
+    let mut description = format!(
+        "irc_bot v{} ({}-{})",
+        version(),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+
+    if let Some(hash) = git_hash() {
+        description.push(' ');
+        description.push_str(hash);
+    }
+    if let Some(irc) = irc_version() {
+        description.push_str(&format!(", irc v{}", irc));
+    }
+
+    description
+}
@@ -0,0 +1,567 @@
+//! Forwarding bot events to an external system (e.g. a Slack bridge or
+//! alerting) by `POST`ing them as JSON to a webhook.
+//!
+//! Events are queued and sent in batches, each batch being a JSON array of
+//! objects like `{"event": "join", "channel": "#rust", "nick": "Michael"}`.
+//! When a batch can't be delivered it goes back on the queue and we back off
+//! before trying again, dropping the oldest events if the queue fills up.
+
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
+use crate::messages::{
+    JoinFailed, JoinFailure, PrivateMessageReceived, Registration, ServerError,
+    ServerNotice, UserJoined, UserKicked, UserParted, UserQuit,
+};
+use crate::utils;
+use crate::World;
+use failure::Fail;
+use irc::client::Client;
+use reqwest::header::USER_AGENT;
+use reqwest::{StatusCode, Url};
+use serde::Serialize;
+use serde_json::Value;
+use slog::Logger;
+use std::cmp;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How often to send whatever events have been queued.
+const BATCH_INTERVAL: Duration = Duration::from_secs(5);
+/// The most events sent in a single request.
+const MAX_BATCH_SIZE: usize = 50;
+/// The most events we'll hold onto while the webhook is unreachable.
+const MAX_QUEUED_EVENTS: usize = 1000;
+/// The longest we'll wait between failed deliveries.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+/// How long to wait on the webhook before giving up on a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where to send events, parsed from a URL like
+/// `https://hooks.example.com/irc`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    pub url: Url,
+}
+
+impl Webhook {
+    /// `POST` a batch of events to the webhook as a JSON array, failing
+    /// unless it responds with a `2xx` status.
+    pub fn post(
+        &self,
+        client: &reqwest::Client,
+        events: &[Value],
+    ) -> Result<(), WebhookError> {
+        let response = client
+            .post(self.url.clone())
+            .header(USER_AGENT, format!("irc_bot/{}", crate::version()))
+            .json(events)
+            .send()
+            .map_err(WebhookError::Unreachable)?;
+
+        let status = response.status();
+
+        if status.is_success() {
+            Ok(())
+        } else {
+            Err(WebhookError::Rejected(status))
+        }
+    }
+}
+
+impl FromStr for Webhook {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Webhook, failure::Error> {
+        let url = Url::parse(s).map_err(|e| {
+            failure::format_err!(
+                "Expected a URL like \"https://hooks.example.com/irc\", found \"{}\" ({})",
+                s,
+                e
+            )
+        })?;
+
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(failure::format_err!(
+                "The webhook URL should start with \"http://\" or \"https://\", found \"{}\"",
+                s
+            ));
+        }
+
+        if url.host_str().map_or(true, str::is_empty) {
+            return Err(failure::err_msg("The webhook URL needs a host"));
+        }
+
+        Ok(Webhook { url })
+    }
+}
+
+/// Something went wrong while delivering events to a [`Webhook`].
+#[derive(Debug, Fail)]
+pub enum WebhookError {
+    #[fail(display = "Unable to set up the HTTP client: {}", _0)]
+    Client(#[cause] reqwest::Error),
+    #[fail(display = "Unable to reach the webhook: {}", _0)]
+    Unreachable(#[cause] reqwest::Error),
+    #[fail(display = "The webhook rejected the events with \"{}\"", _0)]
+    Rejected(StatusCode),
+}
+
+/// The kinds of event a [`WebhookSink`] (or an `EventSocket`) can forward.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WebhookEvent {
+    /// A [`PrivateMessageReceived`], in a channel or sent directly to us.
+    Message,
+    ServerNotice,
+    Join,
+    Part,
+    Quit,
+    Kick,
+    JoinFailed,
+    /// A [`ServerError`], usually sent just before the server disconnects us.
+    ServerError,
+}
+
+impl WebhookEvent {
+    /// Every kind of event, for when nothing more specific was requested.
+    pub const ALL: &'static [WebhookEvent] = &[
+        WebhookEvent::Message,
+        WebhookEvent::ServerNotice,
+        WebhookEvent::Join,
+        WebhookEvent::Part,
+        WebhookEvent::Quit,
+        WebhookEvent::Kick,
+        WebhookEvent::JoinFailed,
+        WebhookEvent::ServerError,
+    ];
+}
+
+impl FromStr for WebhookEvent {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<WebhookEvent, failure::Error> {
+        match s {
+            "message" => Ok(WebhookEvent::Message),
+            "server-notice" => Ok(WebhookEvent::ServerNotice),
+            "join" => Ok(WebhookEvent::Join),
+            "part" => Ok(WebhookEvent::Part),
+            "quit" => Ok(WebhookEvent::Quit),
+            "kick" => Ok(WebhookEvent::Kick),
+            "join-failed" => Ok(WebhookEvent::JoinFailed),
+            "server-error" => Ok(WebhookEvent::ServerError),
+            _ => Err(failure::format_err!(
+                "Expected one of \"message\", \"server-notice\", \"join\", \"part\", \"quit\", \"kick\", \"join-failed\", or \"server-error\", found \"{}\"",
+                s
+            )),
+        }
+    }
+}
+
+/// Subscribes to some of the bot's events and forwards them to a
+/// [`Webhook`].
+pub struct WebhookSink {
+    logger: Logger,
+    webhook: Webhook,
+    client: reqwest::Client,
+    /// Events waiting to be sent, already encoded as JSON.
+    queue: VecDeque<Value>,
+    /// How long to wait after the last failed delivery, if it failed.
+    retry_delay: Option<Duration>,
+    last_failure: Option<Instant>,
+}
+
+impl WebhookSink {
+    /// Spawn a [`WebhookSink`] on its own thread (delivering events means
+    /// waiting on the network) and subscribe it to the requested events.
+    pub fn spawn<C: Client + 'static>(
+        logger: Logger,
+        world: &Addr<World<C>>,
+        webhook: Webhook,
+        events: &[WebhookEvent],
+    ) -> Result<Addr<WebhookSink>, WebhookError> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(WebhookError::Client)?;
+
+        let sink = WebhookSink {
+            logger,
+            webhook,
+            client,
+            queue: VecDeque::new(),
+            retry_delay: None,
+            last_failure: None,
+        };
+        let sink = utils::start_isolated("webhook", sink);
+        subscribe_to_events(world, &sink, "webhook", events);
+
+        Ok(sink)
+    }
+
+    fn enqueue(&mut self, event: Value) {
+        if self.queue.len() >= MAX_QUEUED_EVENTS {
+            warn!(self.logger, "The webhook queue is full, dropping the oldest event";
+                "queued" => self.queue.len());
+            self.queue.pop_front();
+        }
+
+        self.queue.push_back(event);
+    }
+
+    /// Send the next batch of events, if there are any and we aren't backing
+    /// off.
+    fn flush(&mut self) {
+        if self.queue.is_empty() {
+            return;
+        }
+
+        if let (Some(delay), Some(failed)) =
+            (self.retry_delay, self.last_failure)
+        {
+            if failed.elapsed() < delay {
+                return;
+            }
+        }
+
+        let count = cmp::min(self.queue.len(), MAX_BATCH_SIZE);
+        let batch: Vec<Value> = self.queue.drain(..count).collect();
+
+        match self.webhook.post(&self.client, &batch) {
+            Ok(_) => {
+                debug!(self.logger, "Sent events to the webhook";
+                    "events" => batch.len());
+                self.retry_delay = None;
+                self.last_failure = None;
+            }
+            Err(e) => {
+                let delay =
+                    self.retry_delay.map_or(BATCH_INTERVAL, |delay| delay * 2);
+                let delay = cmp::min(delay, MAX_RETRY_DELAY);
+
+                warn!(self.logger, "Unable to send events to the webhook";
+                    "events" => batch.len(),
+                    "retry-in" => format_args!("{:?}", delay),
+                    "error" => e.to_string());
+
+                // put them back so they go out first next time
+                for event in batch.into_iter().rev() {
+                    self.queue.push_front(event);
+                }
+                self.queue.truncate(MAX_QUEUED_EVENTS);
+
+                self.retry_delay = Some(delay);
+                self.last_failure = Some(Instant::now());
+            }
+        }
+    }
+}
+
+impl Actor for WebhookSink {
+    type Context = Context<WebhookSink>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(BATCH_INTERVAL, |sink, _ctx| sink.flush());
+    }
+
+    fn stopping(&mut self, _ctx: &mut Self::Context) -> actix::Running {
+        // one last try, so events from just before shutdown aren't lost
+        self.retry_delay = None;
+        self.flush();
+        actix::Running::Stop
+    }
+}
+
+/// Subscribe an actor to each of the requested kinds of event.
+pub(crate) fn subscribe_to_events<A, C>(
+    world: &Addr<World<C>>,
+    addr: &Addr<A>,
+    plugin: &str,
+    events: &[WebhookEvent],
+) where
+    A: Actor<Context = Context<A>>
+        + Handler<PrivateMessageReceived>
+        + Handler<ServerNotice>
+        + Handler<UserJoined>
+        + Handler<UserParted>
+        + Handler<UserQuit>
+        + Handler<UserKicked>
+        + Handler<JoinFailed>
+        + Handler<ServerError>,
+    C: Client + 'static,
+{
+    for event in events {
+        match *event {
+            WebhookEvent::Message => {
+                subscribe::<PrivateMessageReceived, _, _>(world, addr, plugin)
+            }
+            WebhookEvent::ServerNotice => {
+                subscribe::<ServerNotice, _, _>(world, addr, plugin)
+            }
+            WebhookEvent::Join => {
+                subscribe::<UserJoined, _, _>(world, addr, plugin)
+            }
+            WebhookEvent::Part => {
+                subscribe::<UserParted, _, _>(world, addr, plugin)
+            }
+            WebhookEvent::Quit => {
+                subscribe::<UserQuit, _, _>(world, addr, plugin)
+            }
+            WebhookEvent::Kick => {
+                subscribe::<UserKicked, _, _>(world, addr, plugin)
+            }
+            WebhookEvent::JoinFailed => {
+                subscribe::<JoinFailed, _, _>(world, addr, plugin)
+            }
+            WebhookEvent::ServerError => {
+                subscribe::<ServerError, _, _>(world, addr, plugin)
+            }
+        }
+    }
+}
+
+fn subscribe<M, A, C>(world: &Addr<World<C>>, addr: &Addr<A>, plugin: &str)
+where
+    M: Message<Result = ()> + Clone + Send + 'static,
+    A: Actor<Context = Context<A>> + Handler<M>,
+    C: Client + 'static,
+    World<C>: Handler<Registration<M>>,
+{
+    world.do_send(
+        Registration::<M>::register(addr.clone().recipient())
+            .from_plugin(plugin),
+    );
+}
+
+/// An event as it's forwarded to other systems, with its name under the
+/// `"event"` key.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub(crate) enum Event<'a> {
+    Message {
+        target: &'a str,
+        sender: Option<&'a str>,
+        content: &'a str,
+    },
+    ServerNotice {
+        from: &'a str,
+        content: &'a str,
+    },
+    Join {
+        channel: &'a str,
+        nick: &'a str,
+    },
+    Part {
+        channel: &'a str,
+        nick: &'a str,
+        reason: Option<&'a str>,
+    },
+    Quit {
+        nick: &'a str,
+        reason: Option<&'a str>,
+    },
+    Kick {
+        channel: &'a str,
+        nick: &'a str,
+        by: Option<&'a str>,
+        reason: Option<&'a str>,
+    },
+    JoinFailed {
+        channel: &'a str,
+        reason: JoinFailure,
+        message: Option<&'a str>,
+    },
+    ServerError {
+        message: &'a str,
+    },
+}
+
+/// An event which can be forwarded to other systems as a JSON object.
+pub(crate) trait JsonEvent {
+    fn event(&self) -> Event<'_>;
+
+    fn to_json(&self) -> Value {
+        serde_json::to_value(self.event())
+            .expect("Events are plain data, so they always serialize")
+    }
+}
+
+impl JsonEvent for PrivateMessageReceived {
+    fn event(&self) -> Event<'_> {
+        Event::Message {
+            target: &self.msg_target,
+            sender: self.raw.source_nickname(),
+            content: &self.content,
+        }
+    }
+}
+
+impl JsonEvent for ServerNotice {
+    fn event(&self) -> Event<'_> {
+        Event::ServerNotice {
+            from: &self.from,
+            content: &self.content,
+        }
+    }
+}
+
+impl JsonEvent for UserJoined {
+    fn event(&self) -> Event<'_> {
+        Event::Join {
+            channel: &self.channel,
+            nick: &self.nick,
+        }
+    }
+}
+
+impl JsonEvent for UserParted {
+    fn event(&self) -> Event<'_> {
+        Event::Part {
+            channel: &self.channel,
+            nick: &self.nick,
+            reason: self.reason.as_ref().map(String::as_str),
+        }
+    }
+}
+
+impl JsonEvent for UserQuit {
+    fn event(&self) -> Event<'_> {
+        Event::Quit {
+            nick: &self.nick,
+            reason: self.reason.as_ref().map(String::as_str),
+        }
+    }
+}
+
+impl JsonEvent for UserKicked {
+    fn event(&self) -> Event<'_> {
+        Event::Kick {
+            channel: &self.channel,
+            nick: &self.nick,
+            by: self.by.as_ref().map(String::as_str),
+            reason: self.reason.as_ref().map(String::as_str),
+        }
+    }
+}
+
+impl JsonEvent for JoinFailed {
+    fn event(&self) -> Event<'_> {
+        Event::JoinFailed {
+            channel: &self.channel,
+            reason: self.reason,
+            message: self.message.as_ref().map(String::as_str),
+        }
+    }
+}
+
+impl JsonEvent for ServerError {
+    fn event(&self) -> Event<'_> {
+        Event::ServerError {
+            message: &self.message,
+        }
+    }
+}
+
+impl<M> Handler<M> for WebhookSink
+where
+    M: JsonEvent + Message<Result = ()>,
+{
+    type Result = ();
+
+    fn handle(&mut self, msg: M, _ctx: &mut Self::Context) {
+        self.enqueue(msg.to_json());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_webhook_urls() {
+        let got: Webhook =
+            "https://hooks.example.com/services/irc".parse().unwrap();
+        assert_eq!(got.url.scheme(), "https");
+        assert_eq!(got.url.host_str(), Some("hooks.example.com"));
+        assert_eq!(got.url.port_or_known_default(), Some(443));
+        assert_eq!(got.url.path(), "/services/irc");
+
+        let got: Webhook = "http://localhost:8080".parse().unwrap();
+        assert_eq!(got.url.port_or_known_default(), Some(8080));
+        assert_eq!(got.url.path(), "/");
+
+        let got: Webhook = "http://[::1]:8080/irc".parse().unwrap();
+        assert_eq!(got.url.host_str(), Some("[::1]"));
+        assert_eq!(got.url.port(), Some(8080));
+        assert_eq!(got.url.path(), "/irc");
+
+        assert!("ftp://example.com".parse::<Webhook>().is_err());
+        assert!("hooks.example.com/irc".parse::<Webhook>().is_err());
+    }
+
+    #[test]
+    fn events_are_encoded_as_json() {
+        let parted = UserParted {
+            channel: String::from("#rust"),
+            nick: String::from("Michael"),
+            reason: Some(String::from("Said \"bye\"\n")),
+            is_us: false,
+        };
+        assert_eq!(
+            parted.to_json(),
+            json!({
+                "event": "part",
+                "channel": "#rust",
+                "nick": "Michael",
+                "reason": "Said \"bye\"\n",
+            })
+        );
+
+        let kicked = UserKicked {
+            channel: String::from("#rust"),
+            nick: String::from("spammer"),
+            by: Some(String::from("Michael")),
+            reason: None,
+            is_us: false,
+        };
+        assert_eq!(
+            kicked.to_json(),
+            json!({
+                "event": "kick",
+                "channel": "#rust",
+                "nick": "spammer",
+                "by": "Michael",
+                "reason": null,
+            })
+        );
+
+        let error = ServerError {
+            message: String::from("Closing Link: bot (Ping timeout)"),
+        };
+        assert_eq!(
+            error.to_json(),
+            json!({
+                "event": "server-error",
+                "message": "Closing Link: bot (Ping timeout)",
+            })
+        );
+    }
+
+    #[test]
+    fn every_event_can_be_requested_by_name() {
+        let names = [
+            "message",
+            "server-notice",
+            "join",
+            "part",
+            "quit",
+            "kick",
+            "join-failed",
+            "server-error",
+        ];
+
+        let got: Vec<WebhookEvent> =
+            names.iter().map(|name| name.parse().unwrap()).collect();
+
+        assert_eq!(got, WebhookEvent::ALL);
+    }
+}
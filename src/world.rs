@@ -1,22 +1,104 @@
 use actix::actors::signal::Signal;
-use actix::msgs::StopArbiter;
+use actix::fut::WrapFuture;
 use actix::{
-    Actor, Addr, Arbiter, AsyncContext, Context, Handler, Message,
-    StreamHandler, System,
+    Actor, ActorFuture, Addr, AsyncContext, Context, Handler, Message,
+    ActorContext, MessageResult, ResponseActFuture, ResponseFuture, Running,
+    SpawnHandle, StreamHandler, System,
 };
-use crate::channel::Channel;
+use crate::accounts::AccountCache;
+use crate::channel::{Channel, MemberStatus};
+use crate::commands::{CommandContext, CommandInfo, CommandRegistry};
 use crate::messages::{
-    Connected, Identify, Join, NotRegistered, Panic, PrivateMessage,
-    PrivateMessageReceived, Quit, RawMessage, Registration, StartListening,
+    ActionReceived, AddInterceptor, AddMiddleware, AnnounceAll, CancelSchedule,
+    ChangeModes, ChangeNick, ChannelLeft, ChannelSummaries, ChannelSummary,
+    Channels, ChatEvent, CircuitClosed, CircuitOpened, ClockSkew,
+    CommandCompleted, CommandReceived, ConfirmedPrivateMessage, Connected,
+    CtcpRequest, DescribeWiring, EndOfNames, GetClockSkew, GetNetwork,
+    GetStats, GetTopic, HostChanged, HostmaskOf, Identified, Identify,
+    IsMember, Join, JoinDeferred, JoinFailed, JoinFailure, Joined, Latency,
+    LeaveAndStop, ListChannels, ListCommands, ListPlugins, ListSchedules,
+    LookupAccount, LookupHostmask, MailboxProbe, MailboxStats, MeasureLatency,
+    MemberCount, MemberJoined, MemberLeft, MemberStatusOf, Members,
+    ModAnnounce, Monitor, NamesReply, NickChanged, NickCollision, NickInUse,
+    NickInvalid, NickOffline, NickOnline, NotRegistered, Notice,
+    NoticeReceived, OurStatusIn, Panic, Part, PrivateMessage,
+    PrivateMessageReceived, Quit, RawMessage, Ready, RealNameChanged,
+    Reconnected, RegisterCommand, Registration, ReplyLines, ResyncNick,
+    ScheduleSummary, ServerError, ServerNotice, SetCommandEnabled, SetRealName,
+    SetTopic, SharedChannels, ShutdownReason, StartListening, Stats, Summarize,
+    TopicChanged, TopicReply, UnregisterAll, UserJoined, UserKicked,
+    UserParted, UserQuit, Wallops, WhenReady, WhoIsReply, WhoReply,
 };
-use crate::utils::MessageBox;
+use crate::middleware::{
+    self, Decision, InboundDecision, Interceptor, Middleware, OutgoingMessage,
+};
+use crate::modes::ServerModes;
+use crate::raw_log::{Logged, RawLog};
+use crate::settings::{
+    ChannelRateLimit, LongReplyPolicy, PanicPolicy, ReconnectPolicy,
+    RegistrationOrder, Settings, UnjoinedChannelPolicy, UnknownCommandPolicy,
+};
+use crate::utils::{self, MessageBox};
 use irc::client::prelude::{Client, ClientExt};
+use futures::future::{self, Future};
+use futures::sync::oneshot;
 use irc::error::IrcError;
-use irc::proto::message::Message as IrcMessage;
-use irc::proto::{Command, Response};
+use irc::proto::message::{Message as IrcMessage, Tag};
+use irc::proto::{CapSubCommand, Command, Mode, Response, UserMode};
 use slog::{Discard, Logger};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt::{self, Debug, Formatter};
+use std::io;
+use std::mem;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long to wait for a [`Channel`] to summarize itself.
+const SUMMARY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long to wait for the server to confirm a [`ChangeNick`].
+const NICK_CHANGE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait for the server to finish replying to a [`ListChannels`].
+const LIST_TIMEOUT: Duration = Duration::from_secs(120);
+/// The most channels we'll remember from a single `LIST`.
+const MAX_LISTED_CHANNELS: usize = 5000;
+/// How long to wait for the server to echo a [`ConfirmedPrivateMessage`].
+const ECHO_TIMEOUT: Duration = Duration::from_secs(30);
+/// The most lines we'll send in a single [`ReplyLines`], no matter where
+/// they're going.
+const MAX_REPLY_LINES: usize = 50;
+/// The most channels a single [`AnnounceAll`] or [`ModAnnounce`] will be
+/// sent to.
+const MAX_ANNOUNCE_CHANNELS: usize = 200;
+/// How long to wait for someone to reply to a [`CtcpRequest`].
+const CTCP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait for the server to answer a [`MeasureLatency`] ping.
+const LATENCY_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long to wait for the server to answer a [`ResyncNick`] `WHOIS`.
+const NICK_RESYNC_TIMEOUT: Duration = Duration::from_secs(30);
+/// How many times we'll append an underscore to a nick which is taken
+/// before giving up on registering.
+const MAX_NICK_FALLBACKS: usize = 3;
+/// How often to check whether registration is taking too long.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(5);
+/// How long to wait between re-syncing each channel.
+const RESYNC_SPACING: Duration = Duration::from_secs(2);
+/// How many channels to put in each `JOIN` when rejoining after a reconnect.
+const REJOIN_BATCH: usize = 4;
+/// The longest list of targets to put in a single `MONITOR` command.
+const MAX_MONITOR_LENGTH: usize = 400;
+/// The capabilities we ask the server for, where each group is requested
+/// separately so a server which refuses one still gives us the others.
+const REQUESTED_CAPS: &[&str] = &[
+    "echo-message labeled-response",
+    "setname",
+    "server-time",
+    "chghost",
+];
+/// How many of the most recent `server-time` tags to work out the clock skew
+/// from.
+const CLOCK_SKEW_SAMPLES: usize = 16;
+/// How many `server-time` tags we need before we trust the clock skew enough
+/// to warn about it.
+const MIN_CLOCK_SKEW_SAMPLES: usize = 5;
 
 /// The entire state of the world.
 pub struct World<C> {
@@ -25,6 +107,210 @@ pub struct World<C> {
     client: C,
     logger: Logger,
     message_count: usize,
+    /// How many messages we've read from the server and queued up for
+    /// ourselves.
+    messages_queued: usize,
+    /// How many [`RawMessage`]s we've handled.
+    messages_handled: usize,
+    /// How long the most recent [`MailboxProbe`] waited in our mailbox.
+    mailbox_delay: Option<Duration>,
+    outgoing: Vec<Middleware>,
+    /// Everything messages from the server go through before we act on them.
+    incoming: Vec<Interceptor>,
+    settings: Settings,
+    /// Our nick, as far as the server is concerned.
+    current_nick: Option<String>,
+    /// The nick we're waiting to get back once its ghost has gone.
+    reclaiming: Option<String>,
+    accounts: AccountCache,
+    /// People waiting to hear back about a `WHOIS`, keyed by the lowercased
+    /// nick.
+    pending_whois: HashMap<String, Vec<oneshot::Sender<Option<String>>>>,
+    /// `WHOIS` replies we're in the middle of receiving, keyed by the
+    /// lowercased nick.
+    whois_replies: HashMap<String, WhoIsReply>,
+    raw_log: Option<RawLog>,
+    /// How to get a new connection if the server goes away.
+    reconnect: Option<Reconnect<C>>,
+    /// A [`ChangeNick`] waiting for the server to confirm (or reject) it.
+    pending_nick: Option<PendingNick>,
+    /// The `LIST` reply we're in the middle of receiving.
+    pending_list: Option<PendingList>,
+    /// The capabilities the server has acknowledged.
+    caps: HashSet<String>,
+    /// The label attached to the most recently labeled message.
+    last_label: usize,
+    /// Messages waiting to be echoed back to us, keyed by label.
+    pending_echoes: HashMap<String, oneshot::Sender<Result<(), IrcError>>>,
+    state: ConnectionState,
+    /// When we entered the current [`ConnectionState`].
+    state_changed: Instant,
+    /// People waiting for a CTCP reply, keyed by the (lowercased) nick and
+    /// CTCP command.
+    pending_ctcp: HashMap<(String, String), Vec<PendingCtcp>>,
+    /// How many fallback nicks we've tried while registering.
+    nick_fallbacks: usize,
+    /// When the [`World`] was created, for working out our uptime.
+    started: Instant,
+    /// The channel modes the server supports.
+    modes: ServerModes,
+    /// Has the server made us an oper?
+    is_oper: bool,
+    /// Things to do once we've registered with the server.
+    when_ready: Vec<Box<dyn FnOnce() + Send>>,
+    /// How many times each isolated plugin has panicked.
+    plugin_panics: BTreeMap<String, usize>,
+    /// Why we're shutting down, once we've started.
+    shutdown_reason: Option<ShutdownReason>,
+    /// Are we reading messages from the server?
+    listening: bool,
+    /// The stream of messages from the server, so a connection which gets
+    /// stuck can be torn down.
+    connection: Option<SpawnHandle>,
+    /// Have we identified with services?
+    identified: bool,
+    /// Registered-only channels to join once we've identified.
+    deferred_joins: Vec<String>,
+    /// The channels we still need to join before we're [`Ready`], while
+    /// we're waiting for them.
+    awaiting_joins: Option<Vec<String>>,
+    /// Does the server support `MONITOR`?
+    supports_monitor: bool,
+    /// The most nicks the server will let us monitor (`None` if there's no
+    /// limit).
+    monitor_limit: Option<usize>,
+    /// The network's name, from the `NETWORK` token in `RPL_ISUPPORT`.
+    network: Option<String>,
+    /// The nicks we're monitoring, lowercased.
+    monitoring: HashSet<String>,
+    /// The commands our plugins handle.
+    commands: CommandRegistry,
+    /// How many [`MeasureLatency`] pings we've sent.
+    last_ping: usize,
+    /// [`MeasureLatency`] pings waiting for a `PONG`, keyed by token.
+    pending_pings: HashMap<String, PendingPing>,
+    /// [`ResyncNick`] requests waiting for the server to answer their
+    /// `WHOIS`.
+    pending_nick_resyncs: Vec<PendingNickResync>,
+    /// How far through negotiating capabilities we are, while registering
+    /// with [`RegistrationOrder::CapFirst`].
+    cap_negotiation: Option<CapNegotiation>,
+    /// When the server recently said we weren't registered
+    /// (`ERR_NOTREGISTERED`) while we were still registering.
+    not_registered: VecDeque<Instant>,
+    /// The timers we're waiting on, keyed by the id handed out in
+    /// [`ListSchedules`].
+    schedules: BTreeMap<usize, Scheduled>,
+    last_schedule: usize,
+    /// How far ahead of our clock (in milliseconds) the server's was on the
+    /// most recent timestamped messages.
+    clock_skew: VecDeque<i64>,
+    /// Have we already warned that the server's clock is too far from ours?
+    warned_clock_skew: bool,
+    /// The tokens left for channels with their own rate limit, keyed by the
+    /// lowercased channel name.
+    rate_limits: HashMap<String, TokenBucket>,
+}
+
+/// Opens a fresh connection after the server closes ours, following a
+/// [`ReconnectPolicy`].
+struct Reconnect<C> {
+    policy: ReconnectPolicy,
+    connect: Box<dyn FnMut() -> Result<C, failure::Error>>,
+    /// How many attempts we've made since we were last registered.
+    attempts: usize,
+}
+
+struct PendingCtcp {
+    sent: Instant,
+    tx: oneshot::Sender<Option<String>>,
+}
+
+struct PendingPing {
+    sent: Instant,
+    tx: oneshot::Sender<Duration>,
+}
+
+struct Scheduled {
+    name: String,
+    interval: Option<Duration>,
+    due: Instant,
+    handle: SpawnHandle,
+}
+
+/// Tokens for sending messages to a channel with its own
+/// [`ChannelRateLimit`], which refill steadily over time.
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: &ChannelRateLimit, now: Instant) -> TokenBucket {
+        TokenBucket {
+            tokens: f64::from(limit.messages),
+            updated: now,
+        }
+    }
+
+    /// Try to take a token, returning `false` if there aren't any left.
+    fn take(&mut self, limit: &ChannelRateLimit, now: Instant) -> bool {
+        let capacity = f64::from(limit.messages);
+        let per = limit.per.as_secs() as f64
+            + f64::from(limit.per.subsec_millis()) / 1000.0;
+        let elapsed = now.duration_since(self.updated);
+        let elapsed = elapsed.as_secs() as f64
+            + f64::from(elapsed.subsec_millis()) / 1000.0;
+
+        self.tokens = (self.tokens + elapsed * capacity / per).min(capacity);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct PendingNickResync {
+    sent: Instant,
+    /// The nick we sent a `WHOIS` for.
+    whois: String,
+    tx: oneshot::Sender<String>,
+}
+
+#[derive(Debug, Default)]
+struct CapNegotiation {
+    /// The capabilities listed so far by a (possibly multi-line) `CAP LS`.
+    offered: HashSet<String>,
+    /// How many `CAP REQ`s are still waiting for an `ACK` or `NAK`.
+    outstanding: usize,
+}
+
+/// How far through connecting to the server we are.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ConnectionState {
+    /// Waiting for the server to say anything.
+    Connecting,
+    /// The server is talking to us, but hasn't welcomed us yet.
+    Registering,
+    /// We've received `RPL_WELCOME`.
+    Registered,
+}
+
+struct PendingNick {
+    nick: String,
+    tx: oneshot::Sender<Result<(), IrcError>>,
+}
+
+type ChannelListing = Vec<(String, usize, String)>;
+
+struct PendingList {
+    started: Instant,
+    channels: ChannelListing,
+    waiting: Vec<oneshot::Sender<Result<ChannelListing, IrcError>>>,
 }
 
 impl<C> World<C> {
@@ -33,12 +319,115 @@ impl<C> World<C> {
     }
 
     pub fn new_with_logger(client: C, logger: Logger) -> World<C> {
+        World::new_with_settings(client, logger, Settings::default())
+    }
+
+    pub fn new_with_settings(
+        client: C,
+        logger: Logger,
+        settings: Settings,
+    ) -> World<C> {
+        let accounts = AccountCache::new(
+            settings.account_cache_size,
+            settings.account_cache_ttl,
+        );
+        let mut commands = CommandRegistry::new();
+        for disabled in &settings.disabled_commands {
+            commands.set_enabled_in(
+                &disabled.channel,
+                &disabled.command,
+                false,
+            );
+        }
+
         World {
             client,
             logger,
+            settings,
+            accounts,
             hooks: MessageBox::new(),
             channels: HashMap::new(),
             message_count: 0,
+            messages_queued: 0,
+            messages_handled: 0,
+            mailbox_delay: None,
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+            current_nick: None,
+            reclaiming: None,
+            pending_whois: HashMap::new(),
+            whois_replies: HashMap::new(),
+            raw_log: None,
+            reconnect: None,
+            pending_nick: None,
+            pending_list: None,
+            caps: HashSet::new(),
+            last_label: 0,
+            pending_echoes: HashMap::new(),
+            state: ConnectionState::Connecting,
+            state_changed: Instant::now(),
+            pending_ctcp: HashMap::new(),
+            nick_fallbacks: 0,
+            started: Instant::now(),
+            modes: ServerModes::default(),
+            is_oper: false,
+            when_ready: Vec::new(),
+            plugin_panics: BTreeMap::new(),
+            shutdown_reason: None,
+            listening: false,
+            connection: None,
+            identified: false,
+            deferred_joins: Vec::new(),
+            awaiting_joins: None,
+            supports_monitor: false,
+            monitor_limit: None,
+            network: None,
+            monitoring: HashSet::new(),
+            commands,
+            last_ping: 0,
+            pending_pings: HashMap::new(),
+            pending_nick_resyncs: Vec::new(),
+            cap_negotiation: None,
+            not_registered: VecDeque::new(),
+            schedules: BTreeMap::new(),
+            last_schedule: 0,
+            clock_skew: VecDeque::new(),
+            warned_clock_skew: false,
+            rate_limits: HashMap::new(),
+        }
+    }
+
+    /// Record all raw traffic to and from the server in a [`RawLog`].
+    pub fn with_raw_log(mut self, raw_log: RawLog) -> World<C> {
+        self.raw_log = Some(raw_log);
+        self
+    }
+
+    /// Use `connect` to open a new connection whenever the server closes
+    /// ours without us asking it to.
+    pub fn with_reconnect<F>(
+        mut self,
+        policy: ReconnectPolicy,
+        connect: F,
+    ) -> World<C>
+    where
+        F: FnMut() -> Result<C, failure::Error> + 'static,
+    {
+        self.reconnect = Some(Reconnect {
+            policy,
+            connect: Box::new(connect),
+            attempts: 0,
+        });
+        self
+    }
+
+    /// The last step of a [`World::shutdown()`], stopping the system once
+    /// we're done.
+    fn finish_shutdown(&self) {
+        if let Some(ref reason) = self.shutdown_reason {
+            info!(self.logger, "Finished shutting down";
+                "reason" => reason.to_string());
+            System::current().stop_with_code(reason.exit_code());
         }
     }
 
@@ -49,329 +438,6483 @@ impl<C> World<C> {
     {
         self.hooks.send(msg)
     }
-}
 
-impl<C: 'static> Actor for World<C> {
-    type Context = Context<World<C>>;
-}
+    /// Update what we know about the server from the tokens in a
+    /// `RPL_ISUPPORT` (e.g. `["CHANMODES=b,k,l,imnpst", "MONITOR=100"]`).
+    fn update_isupport(&mut self, tokens: &[String]) {
+        self.modes.update(tokens.iter().map(String::as_str));
 
-impl<C: Debug> Debug for World<C> {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        let World {
-            ref client,
-            ref channels,
-            ref logger,
-            ref hooks,
-            message_count,
-        } = *self;
+        for token in tokens {
+            let mut parts = token.splitn(2, '=');
 
-        f.debug_struct("World")
-            .field("client", client)
-            .field("hooks", &format_args!("({} listeners)", hooks.len()))
-            .field("channels", channels)
-            .field("logger", logger)
-            .field("message_count", &message_count)
-            .finish()
+            match parts.next() {
+                Some("MONITOR") => {
+                    self.supports_monitor = true;
+                    self.monitor_limit =
+                        parts.next().and_then(|n| n.parse().ok());
+                }
+                Some("NETWORK") => {
+                    let network = parts.next().filter(|n| !n.is_empty());
+                    if network != self.network.as_ref().map(String::as_str) {
+                        info!(self.logger, "Connected to a network";
+                            "network" => network);
+                        self.network = network.map(String::from);
+                    }
+                }
+                _ => {}
+            }
+        }
     }
-}
 
-impl<C: Client + 'static> Handler<StartListening> for World<C> {
-    type Result = ();
+    /// How far behind we are on the messages the server has sent us.
+    fn mailbox_stats(&self) -> MailboxStats {
+        MailboxStats {
+            received: self.messages_queued,
+            handled: self.messages_handled,
+            backlog: self.messages_queued.saturating_sub(self.messages_handled),
+            last_delay: self.mailbox_delay,
+        }
+    }
 
-    fn handle(&mut self, _msg: StartListening, ctx: &mut Self::Context) {
-        ctx.add_stream(self.client.stream());
+    /// Which plugin can we carry on without after a panic on this thread?
+    /// `None` means the whole bot should go down with it.
+    fn panicked_plugin(&self, thread: Option<&String>) -> Option<String> {
+        if self.settings.plugin_panics != PanicPolicy::Isolate {
+            return None;
+        }
+
+        thread.and_then(|name| utils::isolated_plugin(name))
     }
-}
 
-impl<C: 'static> StreamHandler<IrcMessage, IrcError> for World<C> {
-    fn handle(&mut self, item: IrcMessage, ctx: &mut Self::Context) {
-        ctx.notify(RawMessage(item));
+    /// We've found out which account a nick is logged in as, so update the
+    /// cache and let anyone waiting on a `WHOIS` know.
+    fn resolve_account(&mut self, nick: &str, account: Option<String>) {
+        self.accounts.insert(nick, account.clone());
+
+        let key = utils::irc_lowercase(nick);
+        if let Some(waiting) = self.pending_whois.remove(&key) {
+            for tx in waiting {
+                let _ = tx.send(account.clone());
+            }
+        }
     }
-}
 
-impl<C: 'static> Handler<RawMessage> for World<C> {
-    type Result = ();
+    /// Keep the [`AccountCache`] up to date with whatever the server tells
+    /// us.
+    fn track_accounts(&mut self, msg: &IrcMessage) {
+        let source = msg.source_nickname();
 
-    fn handle(&mut self, msg: RawMessage, _ctx: &mut Self::Context) {
-        debug!(self.logger, "Received a message";
-            "prefix" => msg.0.prefix.as_ref(),
-            "source-nick" => msg.0.source_nickname(),
-            "command" => format_args!("{:?}", msg.0.command));
+        if let (Some(nick), Some(account)) =
+            (source, utils::tag(msg, "account"))
+        {
+            self.accounts.insert(nick, Some(account.to_string()));
+        }
 
-        if self.message_count == 0 {
-            debug!(self.logger, "Notifying listeners that we've connected");
-            self.publish(Connected);
+        match msg.command {
+            // account-notify and extended-join
+            Command::ACCOUNT(ref account)
+            | Command::JOIN(_, Some(ref account), _) => {
+                if let Some(nick) = source {
+                    let account = if account == "*" {
+                        None
+                    } else {
+                        Some(account.clone())
+                    };
+                    self.accounts.insert(nick, account);
+                }
+            }
+            Command::NICK(ref new_nick) => {
+                if let Some(old_nick) = source {
+                    self.accounts.rename(old_nick, new_nick);
+                }
+            }
+            Command::QUIT(_) => {
+                if let Some(nick) = source {
+                    self.accounts.remove(nick);
+                }
+            }
+            // RPL_WHOISACCOUNT isn't known by the irc crate
+            Command::Raw(ref code, ref args, _) if code == "330" => {
+                if let (Some(nick), Some(account)) = (args.get(1), args.get(2))
+                {
+                    self.resolve_account(nick, Some(account.clone()));
+                }
+            }
+            Command::Response(Response::RPL_ENDOFWHOIS, ref args, _)
+            | Command::Response(Response::ERR_NOSUCHNICK, ref args, _) => {
+                // if we haven't already seen a RPL_WHOISACCOUNT then they
+                // aren't logged in
+                if let Some(nick) = args.get(1) {
+                    if self
+                        .pending_whois
+                        .contains_key(&utils::irc_lowercase(nick))
+                    {
+                        self.resolve_account(nick, None);
+                    }
+                }
+            }
+            _ => {}
         }
-        self.message_count += 1;
+    }
 
-        match msg.0.command {
+    /// Piece together the numerics answering a `WHOIS`, publishing a
+    /// [`WhoIsReply`] once the server's done.
+    fn track_whois(&mut self, msg: &IrcMessage) {
+        let (code, args) = match msg.command {
             Command::Response(
-                Response::ERR_NOTREGISTERED,
+                Response::RPL_WHOISUSER,
                 ref args,
                 ref suffix,
             ) => {
-                self.publish(NotRegistered {
-                    args: args.clone(),
-                    suffix: suffix.clone(),
-                });
+                // e.g. ":server 311 bot Michael mike example.com * :Michael"
+                if let Some(reply) = self.whois_reply(args.get(1)) {
+                    reply.user = args.get(2).cloned();
+                    reply.host = args.get(3).cloned();
+                    reply.real_name = suffix.clone();
+                }
+                return;
             }
-            Command::PRIVMSG(ref target, ref message) => {
-                self.publish(PrivateMessageReceived {
-                    msg_target: target.clone(),
-                    content: message.clone(),
-                    raw: msg.0.clone(),
-                })
+            Command::Response(Response::RPL_ENDOFWHOIS, ref args, _) => {
+                if let Some(nick) = args.get(1) {
+                    let key = utils::irc_lowercase(nick);
+                    if let Some(reply) = self.whois_replies.remove(&key) {
+                        self.publish(reply);
+                    }
+                }
+                return;
+            }
+            Command::Response(Response::ERR_NOSUCHNICK, ref args, _) => {
+                if let Some(nick) = args.get(1) {
+                    self.whois_replies.remove(&utils::irc_lowercase(nick));
+                }
+                return;
+            }
+            // the irc crate doesn't know about the extended WHOIS numerics
+            Command::Raw(ref code, ref args, ref suffix) => {
+                let mut args = args.clone();
+                args.extend(suffix.clone());
+                (code.as_str(), args)
+            }
+            _ => return,
+        };
+
+        match code {
+            // e.g. ":server 330 bot Michael michael :is logged in as"
+            "330" => {
+                if let Some(reply) = self.whois_reply(args.get(1)) {
+                    reply.account = args.get(2).cloned();
+                }
+            }
+            // e.g. ":server 671 bot Michael :is using a secure connection"
+            "671" => {
+                if let Some(reply) = self.whois_reply(args.get(1)) {
+                    reply.secure_connection = Some(true);
+                }
+            }
+            // e.g. ":server 378 bot Michael :is connecting from
+            // *@203.0.113.7.example.com 203.0.113.7"
+            "378" => {
+                let host = args.get(2).and_then(|text| actual_host(text));
+                if let Some(reply) = self.whois_reply(args.get(1)) {
+                    reply.actual_host = host;
+                }
             }
             _ => {}
         }
-
-        self.publish(msg);
     }
-}
 
-impl<C: Client + 'static> Handler<Quit> for World<C> {
-    type Result = ();
+    /// The [`WhoIsReply`] we're filling in for a nick, starting a new one if
+    /// this is the first we've heard of it.
+    fn whois_reply(
+        &mut self,
+        nick: Option<&String>,
+    ) -> Option<&mut WhoIsReply> {
+        let nick = nick?;
 
-    fn handle(&mut self, msg: Quit, _ctx: &mut Self::Context) {
-        info!(self.logger, "Received a request to exit");
+        Some(
+            self.whois_replies
+                .entry(utils::irc_lowercase(nick))
+                .or_insert_with(|| WhoIsReply {
+                    nick: nick.clone(),
+                    ..Default::default()
+                }),
+        )
+    }
 
-        if let Err(e) = self.client.send_quit(msg.msg) {
-            error!(self.logger, "Unable to quit"; "error" => e.to_string());
+    /// Compare the server's `server-time` tag with our own clock, warning if
+    /// they've drifted too far apart.
+    fn track_clock_skew(&mut self, msg: &IrcMessage, now: SystemTime) {
+        // messages in a batch (e.g. history playback) can be hours old
+        if utils::tag(msg, "batch").is_some() {
+            return;
         }
+        let server_time =
+            match utils::tag(msg, "time").and_then(utils::parse_server_time) {
+                Some(time) => time,
+                None => return,
+            };
 
-        System::current().stop();
-    }
-}
-
-impl<C: Client + 'static> Handler<PrivateMessage> for World<C> {
-    type Result = Result<(), IrcError>;
+        let skew = match server_time.duration_since(now) {
+            Ok(ahead) => as_millis(ahead),
+            Err(behind) => -as_millis(behind.duration()),
+        };
+        self.clock_skew.push_back(skew);
+        if self.clock_skew.len() > CLOCK_SKEW_SAMPLES {
+            self.clock_skew.pop_front();
+        }
 
-    fn handle(
-        &mut self,
-        msg: PrivateMessage,
-        _ctx: &mut Self::Context,
-    ) -> Self::Result {
-        debug!(self.logger, "Sending a private message";
-            "recipient" => &msg.to,
-            "content" => &msg.content);
+        let skew = match self.estimate_clock_skew() {
+            Some(ref skew) if skew.samples >= MIN_CLOCK_SKEW_SAMPLES => {
+                skew.millis
+            }
+            _ => return,
+        };
+        let threshold = as_millis(self.settings.clock_skew_threshold);
+        let too_far = skew.abs() > threshold;
 
-        let got = self.client.send_privmsg(msg.to, msg.content);
+        if too_far && !self.warned_clock_skew {
+            warn!(self.logger, "The server's clock doesn't match ours";
+                "skew-ms" => skew,
+                "threshold" => format_args!("{:?}", self.settings.clock_skew_threshold));
+        } else if !too_far && self.warned_clock_skew {
+            info!(self.logger, "The server's clock is back in sync with ours";
+                "skew-ms" => skew);
+        }
+        self.warned_clock_skew = too_far;
+    }
 
-        if let Err(ref e) = got {
-            error!(self.logger, "Unable to send a private message";
-                "error" => e.to_string());
+    /// The median of the recent clock skew samples, so one slow message
+    /// doesn't throw the estimate off.
+    fn estimate_clock_skew(&self) -> Option<ClockSkew> {
+        if self.clock_skew.is_empty() {
+            return None;
         }
 
-        got
+        let mut samples: Vec<i64> = self.clock_skew.iter().cloned().collect();
+        samples.sort();
+
+        Some(ClockSkew {
+            millis: samples[samples.len() / 2],
+            samples: samples.len(),
+        })
     }
-}
 
-impl<C: Client + 'static> Handler<Join> for World<C> {
-    type Result = Result<(), IrcError>;
+    /// Let whoever sent a [`ChangeNick`] know how it went.
+    fn resolve_nick_change(
+        &mut self,
+        nick: &str,
+        outcome: Result<(), IrcError>,
+    ) {
+        let matches = self
+            .pending_nick
+            .as_ref()
+            .map_or(false, |pending| utils::same_nick(&pending.nick, nick));
 
-    fn handle(&mut self, msg: Join, _ctx: &mut Self::Context) -> Self::Result {
-        self.client.send_join(&msg.channels)
+        if matches {
+            if let Some(pending) = self.pending_nick.take() {
+                let _ = pending.tx.send(outcome);
+            }
+        }
     }
-}
 
-impl<C: Client + 'static> Handler<Identify> for World<C> {
-    type Result = Result<(), IrcError>;
-
-    fn handle(
+    /// Let everyone know exactly why the server refused a nick.
+    fn publish_nick_error(
         &mut self,
-        _msg: Identify,
-        _ctx: &mut Self::Context,
-    ) -> Self::Result {
-        info!(self.logger, "Sending identification");
+        code: Response,
+        nick: &str,
+        reason: Option<&String>,
+    ) {
+        let nick = nick.to_string();
 
-        let got = self.client.identify();
+        match code {
+            Response::ERR_ERRONEOUSNICKNAME => self.publish(NickInvalid {
+                nick,
+                reason: reason.cloned(),
+            }),
+            Response::ERR_NICKNAMEINUSE => self.publish(NickInUse { nick }),
+            Response::ERR_NICKCOLLISION => self.publish(NickCollision { nick }),
+            _ => {}
+        }
+    }
 
-        if let Err(ref e) = got {
-            error!(self.logger, "Unable to identify";
-                "error" => e.to_string());
+    /// Tell a [`Channel`] (and anyone else who is interested) about its
+    /// topic.
+    fn update_topic(&mut self, name: &str, topic: Option<String>) {
+        let reply = TopicReply {
+            channel: name.to_string(),
+            topic,
+        };
+
+        if let Some(channel) = self.channels.get(&utils::irc_lowercase(name)) {
+            channel.do_send(reply.clone());
         }
+        self.publish(reply);
+    }
 
-        got
+    /// Ask one of our [`Channel`]s what it knows, falling back to `default`
+    /// if we aren't in the channel or it doesn't answer in time.
+    fn ask_channel<M, T>(
+        &self,
+        name: &str,
+        msg: M,
+        default: T,
+    ) -> ResponseFuture<T, ()>
+    where
+        M: Message<Result = Result<T, ()>> + Send + 'static,
+        T: Send + 'static,
+        Channel: Handler<M>,
+    {
+        let channel = match self.channels.get(&utils::irc_lowercase(name)) {
+            Some(channel) => channel,
+            None => return Box::new(future::ok(default)),
+        };
+
+        let logger = self.logger.clone();
+        let name = name.to_string();
+        let got =
+            channel.send(msg).timeout(SUMMARY_TIMEOUT).then(
+                move |got| match got {
+                    Ok(got) => got,
+                    Err(e) => {
+                        warn!(logger, "Unable to ask a channel what it knows";
+                        "channel" => name,
+                        "error" => e.to_string());
+                        Ok(default)
+                    }
+                },
+            );
+
+        Box::new(got)
     }
-}
 
-impl<C: 'static> Handler<Panic> for World<C> {
-    type Result = ();
+    /// Hand a CTCP reply to whoever was waiting for it.
+    fn resolve_ctcp(&mut self, msg: &IrcMessage) {
+        let text = match msg.command {
+            Command::NOTICE(_, ref text) => text,
+            _ => return,
+        };
+        let nick = match msg.source_nickname() {
+            Some(nick) => nick,
+            None => return,
+        };
+        let (command, args) = match utils::parse_ctcp(text) {
+            Some(ctcp) => ctcp,
+            None => return,
+        };
 
-    fn handle(&mut self, msg: Panic, _ctx: &mut Self::Context) {
-        let Panic {
-            message,
-            file,
-            line,
-            column,
-            thread,
-            backtrace,
-        } = msg;
+        let key = (utils::irc_lowercase(nick), command.to_uppercase());
+        if let Some(waiting) = self.pending_ctcp.remove(&key) {
+            for pending in waiting {
+                let _ = pending.tx.send(args.map(String::from));
+            }
+        }
+    }
 
-        let bt = backtrace.to_string();
-        let bt = if bt.is_empty() { None } else { Some(bt) };
+    /// Work out the round trip for one of our [`MeasureLatency`] pings.
+    fn resolve_ping(&mut self, msg: &IrcMessage) {
+        let pending = match msg.command {
+            // servers disagree on which argument the token goes in
+            Command::PONG(ref first, ref second) => {
+                match self.pending_pings.remove(first) {
+                    Some(pending) => pending,
+                    None => match second
+                        .as_ref()
+                        .and_then(|token| self.pending_pings.remove(token))
+                    {
+                        Some(pending) => pending,
+                        None => return,
+                    },
+                }
+            }
+            _ => return,
+        };
 
-        error!(self.logger, "A thread panicked";
-            "message" => message,
-            "file" => file,
-            "line" => line,
-            "column" => column,
-            "thread" => thread,
-            "backtrace" => bt);
-        Arbiter::current().do_send(StopArbiter(1));
+        let rtt = pending.sent.elapsed();
+        debug!(self.logger, "Measured the latency";
+            "rtt" => format_args!("{:?}", rtt));
+
+        let _ = pending.tx.send(rtt);
+        self.publish(Latency { rtt });
     }
-}
 
-impl<C: Client + 'static> Handler<Signal> for World<C> {
-    type Result = ();
+    /// Check whether this is the end of a [`ResyncNick`] `WHOIS` and, if so,
+    /// believe whichever nick the server sent it to.
+    fn resolve_nick_resync(&mut self, msg: &IrcMessage) {
+        // e.g. ":server 318 our-nick whois-nick :End of /WHOIS list."
+        let (ours, whois) = match msg.command {
+            Command::Response(Response::RPL_ENDOFWHOIS, ref args, _)
+            | Command::Response(Response::ERR_NOSUCHNICK, ref args, _) => {
+                match (args.get(0), args.get(1)) {
+                    (Some(ours), Some(whois)) => (ours.clone(), whois.clone()),
+                    _ => return,
+                }
+            }
+            _ => return,
+        };
 
-    fn handle(&mut self, msg: Signal, _ctx: &mut Self::Context) {
-        info!(self.logger, "Received a signal"; 
-            "signal" => format_args!("{:?}", msg.0));
+        let (resolved, pending): (Vec<_>, Vec<_>) = self
+            .pending_nick_resyncs
+            .drain(..)
+            .partition(|p| utils::same_nick(&p.whois, &whois));
+        self.pending_nick_resyncs = pending;
 
-        if let Err(e) = self.client.send_quit("Leaving...") {
-            error!(self.logger, "Encountered an error while trying to quit gracefully";
-                "error" => e.to_string());
+        if resolved.is_empty() {
+            return;
         }
 
-        System::current().stop();
+        if self.current_nick.as_ref() != Some(&ours) {
+            warn!(self.logger, "Our nick had drifted, re-synced it with the server";
+                "was" => self.current_nick.as_ref(),
+                "now" => &ours);
+            self.current_nick = Some(ours.clone());
+        }
+
+        for pending in resolved {
+            let _ = pending.tx.send(ours.clone());
+        }
     }
-}
 
-macro_rules! allow_registration {
-    ($message_type:ty) => {
-        impl<C: 'static> Handler<Registration<$message_type>> for World<C> {
-            type Result = ();
+    fn set_state(&mut self, state: ConnectionState) {
+        if self.state != state {
+            debug!(self.logger, "Connection state changed";
+                "from" => format_args!("{:?}", self.state),
+                "to" => format_args!("{:?}", state));
+            self.state = state;
+            self.state_changed = Instant::now();
+        }
 
-            fn handle(
-                &mut self,
-                msg: Registration<$message_type>,
-                _ctx: &mut Self::Context,
-            ) {
-                msg.apply(&mut self.hooks);
+        if state == ConnectionState::Registered {
+            for callback in self.when_ready.drain(..) {
+                callback();
             }
         }
-    };
-}
+    }
 
-allow_registration!(RawMessage);
-allow_registration!(Connected);
+    /// Fill in the placeholders in a quit message, falling back to the one
+    /// from our [`Settings`].
+    fn quit_message(&self, msg: Option<String>) -> String {
+        let template =
+            msg.unwrap_or_else(|| self.settings.quit_message.clone());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use actix::actors::mocker::Mocker;
-    use actix::{Arbiter, System};
-    use futures::future::{self, Future};
-    use futures::Stream;
-    use irc::proto::Command;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::sync::{Arc, Mutex};
+        template
+            .replace("{uptime}", &utils::human_duration(self.started.elapsed()))
+            .replace("{version}", crate::version())
+    }
 
-    #[derive(Debug, Clone, Message)]
-    struct DummyMessage;
+    /// Keep track of the server's reply to a `LIST`.
+    fn track_list(&mut self, msg: &IrcMessage) {
+        let pending = match self.pending_list {
+            Some(ref mut pending) => pending,
+            None => return,
+        };
 
-    impl<C: 'static> Handler<DummyMessage> for World<C> {
-        type Result = ();
+        match msg.command {
+            // e.g. ":server 322 our-nick #rust 1234 :The Rust Programming Language"
+            Command::Response(Response::RPL_LIST, ref args, ref topic) => {
+                if pending.channels.len() == MAX_LISTED_CHANNELS {
+                    return;
+                }
 
-        fn handle(&mut self, msg: DummyMessage, _ctx: &mut Self::Context) {
-            Arbiter::spawn(
-                self.hooks
-                    .do_send(msg)
-                    .for_each(|_| future::ok(()))
-                    .map_err(|e| panic!("{}", e)),
-            );
+                if let Some(name) = args.get(1) {
+                    let users =
+                        args.get(2).and_then(|n| n.parse().ok()).unwrap_or(0);
+                    let topic = topic.clone().unwrap_or_default();
+                    pending.channels.push((name.clone(), users, topic));
+
+                    if pending.channels.len() == MAX_LISTED_CHANNELS {
+                        warn!(self.logger, "Too many channels, ignoring the rest of the LIST";
+                            "max" => MAX_LISTED_CHANNELS);
+                    }
+                }
+            }
+            Command::Response(Response::RPL_LISTEND, ..) => {
+                self.finish_list(Ok(()));
+            }
+            Command::Response(Response::RPL_TRYAGAIN, ref args, ref suffix)
+                if args.get(1).map(String::as_str) == Some("LIST") =>
+            {
+                let reason = suffix.clone().unwrap_or_default();
+                self.finish_list(Err(reason));
+            }
+            _ => {}
         }
     }
 
-    impl<C: 'static> Handler<Registration<DummyMessage>> for World<C> {
-        type Result = ();
+    fn finish_list(&mut self, outcome: Result<(), String>) {
+        let pending = match self.pending_list.take() {
+            Some(pending) => pending,
+            None => return,
+        };
 
-        fn handle(
-            &mut self,
-            msg: Registration<DummyMessage>,
-            _ctx: &mut Self::Context,
+        debug!(self.logger, "Finished listing channels";
+            "channels" => pending.channels.len(),
+            "error" => outcome.as_ref().err());
+
+        for tx in pending.waiting {
+            let result = match outcome {
+                Ok(_) => Ok(pending.channels.clone()),
+                Err(ref reason) => Err(IrcError::Custom {
+                    inner: failure::format_err!(
+                        "The server refused to list its channels: {}",
+                        reason
+                    ),
+                }),
+            };
+            let _ = tx.send(result);
+        }
+    }
+
+    /// Can we use labels to find out when the server echoes our messages?
+    fn can_confirm_delivery(&self) -> bool {
+        self.caps.contains("echo-message")
+            && self.caps.contains("labeled-response")
+    }
+
+    /// If this is the response to a labeled message, let the sender know.
+    fn resolve_echo(&mut self, msg: &IrcMessage) {
+        let tx = match utils::tag(msg, "label") {
+            Some(label) => self.pending_echoes.remove(label),
+            None => None,
+        };
+
+        if let Some(tx) = tx {
+            let outcome = match msg.command {
+                Command::Response(code, _, ref suffix) if code.is_error() => {
+                    Err(IrcError::Custom {
+                        inner: failure::format_err!(
+                            "The server rejected our message: {}",
+                            suffix.as_ref().map_or("", String::as_str)
+                        ),
+                    })
+                }
+                _ => Ok(()),
+            };
+
+            let _ = tx.send(outcome);
+        }
+    }
+
+    /// If the server refused to let us join a channel, forget about it and
+    /// let everyone know why.
+    fn check_join_failure(&mut self, msg: &IrcMessage) {
+        let (reason, args, suffix) = match msg.command {
+            Command::Response(code, ref args, ref suffix) => {
+                match JoinFailure::from_response(code) {
+                    Some(reason) => (reason, args, suffix),
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+
+        // e.g. ":server 471 our-nick #channel :Cannot join channel (+l)"
+        let channel = match args.get(1) {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        // we optimistically created the channel when sending the JOIN
+        self.channels.remove(&utils::irc_lowercase(channel));
+
+        let defer = reason == JoinFailure::RegisteredOnly
+            && !self.identified
+            && self.settings.defer_joins_until_identified;
+
+        if defer {
+            info!(self.logger, "Joining once we've identified"; "channel" => channel);
+
+            if !self
+                .deferred_joins
+                .iter()
+                .any(|c| utils::same_nick(c, channel))
+            {
+                self.deferred_joins.push(channel.clone());
+            }
+
+            self.publish(JoinDeferred {
+                channel: channel.clone(),
+                reason: suffix.clone(),
+            });
+            return;
+        }
+
+        warn!(self.logger, "Unable to join a channel";
+            "channel" => channel,
+            "reason" => format_args!("{:?}", reason),
+            "message" => suffix.as_ref());
+
+        self.publish(JoinFailed {
+            channel: channel.clone(),
+            reason,
+            message: suffix.clone(),
+        });
+    }
+
+    /// Run an outgoing message through the [`Middleware`] chain and make sure
+    /// we're allowed to send it.
+    fn prepare_outgoing(
+        &mut self,
+        msg: PrivateMessage,
+    ) -> Result<OutgoingMessage, IrcError> {
+        let mut msg = OutgoingMessage {
+            to: msg.to,
+            content: msg.content,
+        };
+
+        match middleware::apply(&self.outgoing, &mut msg) {
+            Decision::Allow => {}
+            Decision::Modify => {
+                debug!(self.logger, "Middleware rewrote an outgoing message";
+                    "recipient" => &msg.to);
+            }
+            Decision::Block => {
+                info!(self.logger, "Middleware blocked an outgoing message";
+                    "recipient" => &msg.to,
+                    "content" => &msg.content);
+                return Err(middleware::blocked(&msg));
+            }
+        }
+
+        self.check_joined(&msg.to)?;
+        self.check_rate_limit(&msg.to, Instant::now())?;
+
+        Ok(msg)
+    }
+
+    /// Make sure sending another message to `target` won't go over its
+    /// [`ChannelRateLimit`], if it has one.
+    fn check_rate_limit(
+        &mut self,
+        target: &str,
+        now: Instant,
+    ) -> Result<(), IrcError> {
+        let limit = match self.settings.rate_limit(target) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let allowed = self
+            .rate_limits
+            .entry(utils::irc_lowercase(target))
+            .or_insert_with(|| TokenBucket::full(limit, now))
+            .take(limit, now);
+
+        if allowed {
+            Ok(())
+        } else {
+            warn!(self.logger, "Sending to a channel too quickly, dropping a message";
+                "channel" => target,
+                "messages" => limit.messages,
+                "per" => format_args!("{:?}", limit.per));
+            Err(IrcError::Custom {
+                inner: failure::format_err!(
+                    "Refusing to send to \"{}\" more than {} times every {:?}",
+                    target,
+                    limit.messages,
+                    limit.per
+                ),
+            })
+        }
+    }
+
+    /// Figure out where a multi-line reply should go, according to the
+    /// [`LongReplyPolicy`].
+    fn reply_destination(&self, msg: &ReplyLines) -> Result<String, IrcError> {
+        let lines = msg.lines.iter().filter(|line| !line.is_empty()).count();
+
+        if lines > MAX_REPLY_LINES {
+            return Err(IrcError::Custom {
+                inner: failure::format_err!(
+                    "Refusing to send a {} line reply, the most we'll send is {}",
+                    lines,
+                    MAX_REPLY_LINES
+                ),
+            });
+        }
+
+        if !utils::is_channel(&msg.to) || lines <= self.settings.max_reply_lines
+        {
+            return Ok(msg.to.clone());
+        }
+
+        match (self.settings.long_replies, msg.requester.as_ref()) {
+            (LongReplyPolicy::DirectMessage, Some(requester)) => {
+                debug!(self.logger, "Sending a long reply via direct message";
+                    "channel" => &msg.to,
+                    "requester" => requester,
+                    "lines" => lines);
+                Ok(requester.clone())
+            }
+            _ => Err(IrcError::Custom {
+                inner: failure::format_err!(
+                    "Refusing to send a {} line reply to \"{}\", the limit is {}",
+                    lines,
+                    msg.to,
+                    self.settings.max_reply_lines
+                ),
+            }),
+        }
+    }
+
+    /// Check whether we're allowed to send a message to `target`, according
+    /// to the [`UnjoinedChannelPolicy`].
+    fn check_joined(&self, target: &str) -> Result<(), IrcError> {
+        if !utils::is_channel(target)
+            || self.channels.contains_key(&utils::irc_lowercase(target))
+        {
+            return Ok(());
+        }
+
+        match self.settings.unjoined_channels {
+            UnjoinedChannelPolicy::Ignore => Ok(()),
+            UnjoinedChannelPolicy::Warn => {
+                warn!(self.logger, "Sending a message to a channel we haven't joined";
+                    "channel" => target);
+                Ok(())
+            }
+            UnjoinedChannelPolicy::Error => Err(IrcError::Custom {
+                inner: failure::format_err!(
+                    "Refusing to send a message to \"{}\" because we haven't joined it",
+                    target
+                ),
+            }),
+        }
+    }
+}
+
+impl<C: Client> World<C> {
+    /// The client to use when sending anything to the server, so it gets
+    /// recorded in the [`RawLog`].
+    fn outbound(&self) -> Logged<C> {
+        Logged::new(&self.client, self.raw_log.as_ref())
+    }
+
+    /// Send an announcement as a `NOTICE` to each of these channels,
+    /// returning how many were notified.
+    fn announce_to(
+        &mut self,
+        mut channels: Vec<String>,
+        content: &str,
+    ) -> Result<usize, IrcError> {
+        channels.sort();
+
+        if channels.len() > MAX_ANNOUNCE_CHANNELS {
+            warn!(self.logger, "Only announcing to some of the channels";
+                "channels" => channels.len(),
+                "max" => MAX_ANNOUNCE_CHANNELS);
+            channels.truncate(MAX_ANNOUNCE_CHANNELS);
+        }
+
+        info!(self.logger, "Making an announcement";
+            "channels" => channels.len(),
+            "content" => content);
+
+        let mut notified = 0;
+
+        // the client throttles these so we don't trip the flood protection
+        for channel in channels {
+            let notice = match self.prepare_outgoing(PrivateMessage {
+                to: channel,
+                content: content.to_string(),
+            }) {
+                Ok(notice) => notice,
+                // the reason has already been logged
+                Err(_) => continue,
+            };
+
+            self.outbound().send_notice(notice.to, notice.content)?;
+            notified += 1;
+        }
+
+        Ok(notified)
+    }
+
+    /// Keep track of which capabilities the server has enabled.
+    fn track_caps(&mut self, msg: &IrcMessage) {
+        match msg.command {
+            Command::CAP(_, CapSubCommand::LS, ref more, Some(ref caps)) => {
+                self.caps_offered(caps, more.is_some());
+            }
+            Command::CAP(_, CapSubCommand::ACK, _, Some(ref caps)) => {
+                for cap in caps.split_whitespace() {
+                    info!(self.logger, "Capability enabled"; "cap" => cap);
+                    self.caps.insert(cap.to_string());
+                }
+                self.cap_request_answered();
+            }
+            Command::CAP(_, CapSubCommand::NAK, _, ref caps) => {
+                warn!(self.logger, "The server refused some capabilities";
+                    "caps" => caps.as_ref());
+                self.cap_request_answered();
+            }
+            Command::CAP(_, CapSubCommand::DEL, _, Some(ref caps)) => {
+                for cap in caps.split_whitespace() {
+                    info!(self.logger, "Capability removed"; "cap" => cap);
+                    self.caps.remove(cap);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The server told us (some of) the capabilities it supports, so once
+    /// it's finished, request the ones we want.
+    fn caps_offered(&mut self, caps: &str, more_to_come: bool) {
+        let negotiation = match self.cap_negotiation {
+            Some(ref mut negotiation) => negotiation,
+            None => return,
+        };
+
+        // with CAP LS 302, caps may have a value (e.g. "sasl=PLAIN")
+        negotiation.offered.extend(
+            caps.split_whitespace()
+                .map(|cap| cap.splitn(2, '=').next().unwrap_or(cap))
+                .map(String::from),
+        );
+
+        if more_to_come {
+            return;
+        }
+
+        let wanted: Vec<&str> = REQUESTED_CAPS
+            .iter()
+            .cloned()
+            .filter(|group| {
+                group
+                    .split_whitespace()
+                    .all(|cap| negotiation.offered.contains(cap))
+            })
+            .collect();
+        negotiation.outstanding = wanted.len();
+
+        for group in wanted {
+            debug!(self.logger, "Requesting capabilities"; "caps" => group);
+            let req = Command::CAP(
+                None,
+                CapSubCommand::REQ,
+                None,
+                Some(group.to_string()),
+            );
+
+            if let Err(e) = self.outbound().send(req) {
+                warn!(self.logger, "Unable to request capabilities";
+                    "caps" => group,
+                    "error" => e.to_string());
+            }
+        }
+
+        self.finish_cap_negotiation();
+    }
+
+    fn cap_request_answered(&mut self) {
+        if let Some(ref mut negotiation) = self.cap_negotiation {
+            negotiation.outstanding = negotiation.outstanding.saturating_sub(1);
+        }
+
+        self.finish_cap_negotiation();
+    }
+
+    /// Let the server carry on registering us once it has answered all our
+    /// `CAP REQ`s.
+    fn finish_cap_negotiation(&mut self) {
+        let done = match self.cap_negotiation {
+            Some(ref negotiation) => negotiation.outstanding == 0,
+            None => false,
+        };
+
+        if done {
+            debug!(self.logger, "Finished negotiating capabilities");
+            self.cap_negotiation = None;
+
+            let end = Command::CAP(None, CapSubCommand::END, None, None);
+            if let Err(e) = self.outbound().send(end) {
+                warn!(self.logger, "Unable to finish negotiating capabilities";
+                    "error" => e.to_string());
+            }
+        }
+    }
+
+    /// Send `PASS`, `NICK`, and `USER`, without the `CAP END` that
+    /// [`ClientExt::identify()`] starts with.
+    fn send_registration(&self) -> Result<(), IrcError> {
+        let config = self.client.config();
+        let client = self.outbound();
+
+        if !config.password().is_empty() {
+            client.send(Command::PASS(config.password().to_string()))?;
+        }
+        client.send(Command::NICK(config.nickname()?.to_string()))?;
+        client.send(Command::USER(
+            config.username().to_string(),
+            String::from("0"),
+            config.real_name().to_string(),
+        ))
+    }
+
+    /// Request everything we want and carry on registering, without waiting
+    /// to hear what the server supports.
+    fn request_caps_blindly(&self) -> Result<(), IrcError> {
+        for caps in REQUESTED_CAPS {
+            self.outbound().send(Command::CAP(
+                None,
+                CapSubCommand::REQ,
+                None,
+                Some(caps.to_string()),
+            ))?;
+        }
+
+        // identify() sends CAP END, so the server will reply to these before
+        // finishing registration
+        self.outbound().identify()
+    }
+
+    /// Let someone know we don't have the command they asked for, if our
+    /// [`Settings`] say to.
+    fn unknown_command(&mut self, name: &str, sender: &str) {
+        let content = match self.settings.unknown_commands {
+            UnknownCommandPolicy::Ignore => return,
+            UnknownCommandPolicy::Reply => None,
+            UnknownCommandPolicy::Suggest => self.commands.closest(name),
+        };
+        let templates = &self.settings.reply_templates;
+        let prefix = self.settings.command_prefix.to_string();
+        let content = match content {
+            Some(closest) => templates.render(
+                "unknown-command-suggestion",
+                "Unknown command, did you mean {prefix}{suggestion}?",
+                &[
+                    ("nick", sender),
+                    ("command", name),
+                    ("suggestion", closest),
+                    ("prefix", &prefix),
+                ],
+            ),
+            None => templates.render(
+                "unknown-command",
+                "Unknown command, try {prefix}help",
+                &[("nick", sender), ("command", name), ("prefix", &prefix)],
+            ),
+        };
+
+        debug!(self.logger, "Someone used an unknown command";
+            "command" => name,
+            "sender" => sender);
+
+        let got = self
+            .prepare_outgoing(PrivateMessage {
+                to: sender.to_string(),
+                content,
+            })
+            .and_then(|msg| self.outbound().send_notice(msg.to, msg.content));
+
+        if let Err(e) = got {
+            warn!(self.logger, "Unable to tell someone their command is unknown";
+                "sender" => sender,
+                "error" => e.to_string());
+        }
+    }
+
+    /// Let someone know the command they asked for has been switched off
+    /// because it keeps failing.
+    fn command_unavailable(&mut self, name: &str, sender: &str) {
+        debug!(self.logger, "Someone used a command which is switched off";
+            "command" => name,
+            "sender" => sender);
+
+        let prefix = self.settings.command_prefix.to_string();
+        let content = self.settings.reply_templates.render(
+            "command-unavailable",
+            "{prefix}{command} is temporarily unavailable, try again later",
+            &[("nick", sender), ("command", name), ("prefix", &prefix)],
+        );
+
+        let got = self
+            .prepare_outgoing(PrivateMessage {
+                to: sender.to_string(),
+                content,
+            })
+            .and_then(|msg| self.outbound().send_notice(msg.to, msg.content));
+
+        if let Err(e) = got {
+            warn!(self.logger, "Unable to tell someone their command is unavailable";
+                "sender" => sender,
+                "error" => e.to_string());
+        }
+    }
+
+    /// Let someone know the command they asked for can't be used here (e.g.
+    /// `!identify` in a channel).
+    fn command_wrong_context(
+        &mut self,
+        name: &str,
+        sender: &str,
+        context: CommandContext,
+    ) {
+        debug!(self.logger, "Someone used a command in the wrong place";
+            "command" => name,
+            "sender" => sender,
+            "context" => format!("{:?}", context));
+
+        let templates = &self.settings.reply_templates;
+        let prefix = self.settings.command_prefix.to_string();
+        let vars = [("nick", sender), ("command", name), ("prefix", &prefix)];
+        let content = match context {
+            CommandContext::ChannelOnly => templates.render(
+                "command-channel-only",
+                "{prefix}{command} can only be used in a channel",
+                &vars,
+            ),
+            CommandContext::DmOnly => templates.render(
+                "command-dm-only",
+                "{prefix}{command} can only be used in a private message",
+                &vars,
+            ),
+            CommandContext::Anywhere => return,
+        };
+
+        let got = self
+            .prepare_outgoing(PrivateMessage {
+                to: sender.to_string(),
+                content,
+            })
+            .and_then(|msg| self.outbound().send_notice(msg.to, msg.content));
+
+        if let Err(e) = got {
+            warn!(self.logger, "Unable to tell someone their command can't be used there";
+                "sender" => sender,
+                "error" => e.to_string());
+        }
+    }
+
+    fn send_private_message(
+        &mut self,
+        msg: PrivateMessage,
+    ) -> Result<(), IrcError> {
+        let msg = self.prepare_outgoing(msg)?;
+
+        debug!(self.logger, "Sending a private message";
+            "recipient" => &msg.to,
+            "content" => &msg.content);
+
+        let got = self.outbound().send_privmsg(msg.to, msg.content);
+
+        if let Err(ref e) = got {
+            error!(self.logger, "Unable to send a private message";
+                "error" => e.to_string());
+        }
+
+        got
+    }
+}
+
+impl<C: Client + 'static> World<C> {
+    /// Wait a while before trying to connect again, shutting down once we've
+    /// run out of attempts.
+    fn schedule_reconnect(&mut self, ctx: &mut Context<Self>) {
+        let (attempts, policy) = match self.reconnect {
+            Some(ref mut reconnect) => {
+                reconnect.attempts += 1;
+                (reconnect.attempts, reconnect.policy)
+            }
+            None => return,
+        };
+
+        if policy.gives_up_after(attempts) {
+            let reason = ShutdownReason::Fatal(format!(
+                "Unable to reconnect after {} attempts",
+                policy.max_retries
+            ));
+            self.shutdown(reason, None, ctx);
+            return;
+        }
+
+        let delay = policy.delay(attempts);
+        info!(self.logger, "Reconnecting to the server";
+            "attempt" => attempts,
+            "max-retries" => policy.max_retries,
+            "delay" => format_args!("{:?}", delay));
+
+        self.schedule_once("reconnect", delay, ctx, |world, ctx| {
+            world.reconnect(ctx)
+        });
+    }
+
+    fn reconnect(&mut self, ctx: &mut Context<Self>) {
+        let got = match self.reconnect {
+            Some(ref mut reconnect) => (reconnect.connect)(),
+            None => return,
+        };
+
+        match got {
+            Ok(client) => {
+                self.client = client;
+                self.start_new_connection(ctx);
+            }
+            Err(e) => {
+                warn!(self.logger, "Unable to reconnect";
+                    "error" => e.to_string());
+                self.schedule_reconnect(ctx);
+            }
+        }
+    }
+
+    /// Forget everything we knew about a connection which has gone away,
+    /// failing anyone still waiting to hear back from the server.
+    ///
+    /// The channels we were in are kept, so they can be rejoined.
+    fn reset_connection_state(&mut self) {
+        if let Some(pending) = self.pending_list.take() {
+            for tx in pending.waiting {
+                let _ = tx.send(Err(connection_lost()));
+            }
+        }
+        if let Some(pending) = self.pending_nick.take() {
+            let _ = pending.tx.send(Err(connection_lost()));
+        }
+        for (_, tx) in self.pending_echoes.drain() {
+            let _ = tx.send(Err(connection_lost()));
+        }
+        // dropping the rest cancels their requests
+        self.pending_whois.clear();
+        self.pending_ctcp.clear();
+        self.pending_pings.clear();
+        self.pending_nick_resyncs.clear();
+        self.whois_replies.clear();
+
+        self.message_count = 0;
+        self.current_nick = None;
+        self.reclaiming = None;
+        self.nick_fallbacks = 0;
+        self.caps.clear();
+        self.cap_negotiation = None;
+        self.is_oper = false;
+        self.identified = false;
+        self.deferred_joins.clear();
+        self.awaiting_joins = None;
+        self.not_registered.clear();
+
+        // a different server can support different things
+        self.modes = ServerModes::default();
+        self.supports_monitor = false;
+        self.monitor_limit = None;
+        self.network = None;
+        self.monitoring.clear();
+        self.clock_skew.clear();
+        self.warned_clock_skew = false;
+        self.rate_limits.clear();
+    }
+
+    /// Start listening to a new connection, rejoining our channels once
+    /// we've registered.
+    fn start_new_connection(&mut self, ctx: &mut Context<Self>) {
+        let mut channels: Vec<String> = self.channels.keys().cloned().collect();
+        channels.sort();
+
+        // the channel actors stop once we drop their addresses
+        self.channels.clear();
+
+        self.connection = Some(ctx.add_stream(self.client.stream()));
+        self.listening = true;
+        self.set_state(ConnectionState::Connecting);
+
+        if !channels.is_empty() {
+            let world = ctx.address();
+            self.when_ready.push(Box::new(move || {
+                for batch in channels.chunks(REJOIN_BATCH) {
+                    world.do_send(Join::new(batch.join(",")));
+                }
+            }));
+        }
+
+        let attempts = self.reconnect.as_ref().map_or(0, |r| r.attempts);
+        info!(self.logger, "Reconnected to the server";
+            "attempts" => attempts);
+        self.publish(Reconnected { attempts });
+    }
+
+    fn join(
+        &mut self,
+        msg: &Join,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), IrcError> {
+        let keys: Vec<&str> = msg
+            .keys
+            .as_ref()
+            .map(|keys| keys.split(',').collect())
+            .unwrap_or_default();
+        let requested = msg
+            .channels
+            .split(',')
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, name)| !name.is_empty())
+            .map(|(i, name)| (name, keys.get(i).cloned()));
+
+        let mut channels = Vec::new();
+        let mut keys = Vec::new();
+        let mut tracked = self.tracked_channels();
+
+        for (name, key) in requested {
+            let is_new =
+                !self.channels.contains_key(&utils::irc_lowercase(name))
+                    && !self.settings.is_sticky(name);
+
+            if is_new {
+                if self
+                    .settings
+                    .max_channels
+                    .map_or(false, |max| tracked >= max)
+                {
+                    warn!(self.logger, "Refusing to join a channel, we're already in too many";
+                        "channel" => name,
+                        "max-channels" => self.settings.max_channels);
+                    continue;
+                }
+                tracked += 1;
+            }
+
+            channels.push(name);
+            // keyed channels always come first, so the keys stay lined up
+            keys.extend(key);
+        }
+
+        if channels.is_empty() {
+            return Err(IrcError::Custom {
+                inner: failure::format_err!(
+                    "Unable to join \"{}\", we're already in too many channels",
+                    msg.channels
+                ),
+            });
+        }
+
+        let channels = channels.join(",");
+        if keys.is_empty() {
+            self.outbound().send_join(&channels)?;
+        } else {
+            self.outbound().send_join_with_keys::<&str, &str>(
+                &channels,
+                &keys.join(","),
+            )?;
+        }
+
+        // optimistically assume the join will succeed
+        for name in channels.split(',') {
+            self.channel(name, ctx);
+        }
+
+        Ok(())
+    }
+
+    /// Run something once after a delay, keeping track of it so it shows up
+    /// in [`ListSchedules`] and can be cancelled.
+    fn schedule_once<S, F>(
+        &mut self,
+        name: S,
+        delay: Duration,
+        ctx: &mut Context<Self>,
+        f: F,
+    ) where
+        S: Into<String>,
+        F: FnOnce(&mut Self, &mut Context<Self>) + 'static,
+    {
+        self.last_schedule += 1;
+        let id = self.last_schedule;
+
+        let handle = ctx.run_later(delay, move |world, ctx| {
+            world.schedules.remove(&id);
+            f(world, ctx);
+        });
+
+        self.schedules.insert(
+            id,
+            Scheduled {
+                name: name.into(),
+                interval: None,
+                due: Instant::now() + delay,
+                handle,
+            },
+        );
+    }
+
+    /// Run something repeatedly, keeping track of it so it shows up in
+    /// [`ListSchedules`] and can be cancelled.
+    fn schedule_every<S, F>(
+        &mut self,
+        name: S,
+        interval: Duration,
+        ctx: &mut Context<Self>,
+        mut f: F,
+    ) where
+        S: Into<String>,
+        F: FnMut(&mut Self, &mut Context<Self>) + 'static,
+    {
+        self.last_schedule += 1;
+        let id = self.last_schedule;
+
+        let handle = ctx.run_interval(interval, move |world, ctx| {
+            if let Some(scheduled) = world.schedules.get_mut(&id) {
+                scheduled.due = Instant::now() + interval;
+            }
+            f(world, ctx);
+        });
+
+        self.schedules.insert(
+            id,
+            Scheduled {
+                name: name.into(),
+                interval: Some(interval),
+                due: Instant::now() + interval,
+                handle,
+            },
+        );
+    }
+
+    /// How many channels count towards [`Settings::max_channels`], which
+    /// leaves out sticky channels so they can't be crowded out.
+    fn tracked_channels(&self) -> usize {
+        self.channels
+            .keys()
+            .filter(|name| !self.settings.is_sticky(name))
+            .count()
+    }
+
+    /// The one place we shut down from, so every exit is logged and
+    /// accounted for the same way.
+    ///
+    /// Once we've started shutting down we stop handing out commands, then
+    /// send a `QUIT`. It goes out after anything already queued, so when the
+    /// server closes the connection we know everything else was sent too.
+    /// The system is stopped when that happens, or after
+    /// [`Settings::shutdown_timeout`], whichever comes first.
+    fn shutdown(
+        &mut self,
+        reason: ShutdownReason,
+        quit_message: Option<String>,
+        ctx: &mut Context<Self>,
+    ) {
+        if let Some(ref previous) = self.shutdown_reason {
+            debug!(self.logger, "Already shutting down";
+                "reason" => previous.to_string());
+            return;
+        }
+
+        if reason.is_intentional() {
+            info!(self.logger, "Shutting down";
+                "reason" => reason.to_string());
+        } else {
+            // this also writes out the messages leading up to it
+            crit!(self.logger, "Shutting down after a fatal error";
+                "reason" => reason.to_string());
+        }
+        self.shutdown_reason = Some(reason);
+
+        let msg = self.quit_message(quit_message);
+        let quit_sent = match self.outbound().send_quit(msg) {
+            Ok(_) => true,
+            Err(e) => {
+                error!(self.logger, "Unable to quit gracefully";
+                    "error" => e.to_string());
+                false
+            }
+        };
+
+        let timeout = self.settings.shutdown_timeout;
+
+        if quit_sent && self.listening && timeout > Duration::from_secs(0) {
+            // the stream finishing stops us early
+            self.schedule_once("shutdown-timeout", timeout, ctx, |world, ctx| {
+                warn!(world.logger, "The server didn't close the connection in time";
+                    "timeout" => format_args!("{:?}", world.settings.shutdown_timeout));
+                ctx.stop();
+            });
+        } else {
+            // there's no connection to wait on
+            ctx.stop();
+        }
+    }
+
+    /// Give up on the connection if registration is taking too long (e.g.
+    /// the server is waiting on an ident response which never comes),
+    /// reconnecting if we can.
+    fn check_registration(&mut self, ctx: &mut Context<Self>) {
+        let timeout = match self.settings.registration_timeout {
+            Some(timeout) => timeout,
+            None => return,
+        };
+
+        // there's nothing to time out while we're waiting to reconnect
+        let stuck = self.listening
+            && self.state != ConnectionState::Registered
+            && self.state_changed.elapsed() >= timeout;
+
+        if !stuck {
+            return;
+        }
+
+        error!(self.logger, "Timed out waiting to register with the server";
+            "state" => format_args!("{:?}", self.state),
+            "timeout" => format_args!("{:?}", timeout));
+
+        if self.reconnect.is_some() {
+            self.stop_listening(ctx);
+            self.schedule_reconnect(ctx);
+        } else {
+            self.shutdown(
+                ShutdownReason::Fatal(String::from("Registration timed out")),
+                None,
+                ctx,
+            );
+        }
+    }
+
+    /// Tear down the current connection without waiting for the server to
+    /// close it.
+    fn stop_listening(&mut self, ctx: &mut Context<Self>) {
+        if let Some(connection) = self.connection.take() {
+            ctx.cancel_future(connection);
+        }
+        self.listening = false;
+        self.reset_connection_state();
+    }
+
+    /// The server says we aren't registered.
+    ///
+    /// This can happen by accident while we're still registering (e.g. a
+    /// `PING` crossing our `USER` on the wire), so only give up on the
+    /// connection if it keeps happening or the server has forgotten we
+    /// registered.
+    fn check_not_registered(&mut self, ctx: &mut Context<Self>) {
+        if self.state == ConnectionState::Registered {
+            error!(
+                self.logger,
+                "The server says we aren't registered any more"
+            );
+            self.shutdown(
+                ShutdownReason::Fatal(String::from(
+                    "The server forgot we were registered",
+                )),
+                None,
+                ctx,
+            );
+            return;
+        }
+
+        let now = Instant::now();
+        let window = self.settings.not_registered_window;
+        self.not_registered.push_back(now);
+        while self
+            .not_registered
+            .front()
+            .map_or(false, |&at| now.duration_since(at) > window)
+        {
+            self.not_registered.pop_front();
+        }
+
+        if self.not_registered.len() >= self.settings.not_registered_limit {
+            error!(self.logger, "The server keeps saying we aren't registered";
+                "times" => self.not_registered.len(),
+                "window" => format_args!("{:?}", window));
+            self.shutdown(
+                ShutdownReason::Fatal(String::from(
+                    "The server kept saying we weren't registered",
+                )),
+                None,
+                ctx,
+            );
+        } else {
+            warn!(self.logger, "The server says we aren't registered yet, carrying on";
+                "times" => self.not_registered.len());
+        }
+    }
+
+    /// The server refused our nick before we finished registering, so try
+    /// another one.
+    ///
+    /// Appending an underscore only helps when someone else has the nick. An
+    /// invalid nick stays invalid no matter how many underscores it gets, so
+    /// strip out the bad characters and give up if that doesn't help.
+    fn retry_registration_nick(
+        &mut self,
+        code: Response,
+        nick: &str,
+        ctx: &mut Context<Self>,
+    ) {
+        // the irc crate works through the configured alternate nicks for us
+        let handled_by_client = (code == Response::ERR_ERRONEOUSNICKNAME
+            || code == Response::ERR_NICKNAMEINUSE)
+            && !self.client.config().alternate_nicknames().is_empty();
+        if handled_by_client {
+            return;
+        }
+
+        let fallback = match code {
+            Response::ERR_ERRONEOUSNICKNAME => {
+                utils::sanitize_nick(nick).filter(|sanitized| sanitized != nick)
+            }
+            _ if self.nick_fallbacks < MAX_NICK_FALLBACKS => {
+                self.nick_fallbacks += 1;
+                Some(format!("{}_", nick))
+            }
+            _ => None,
+        };
+
+        match fallback {
+            Some(fallback) => {
+                warn!(self.logger, "The server rejected our nick, trying another";
+                    "nick" => nick,
+                    "fallback" => &fallback,
+                    "code" => format_args!("{:?}", code));
+
+                if let Err(e) = self.outbound().send(Command::NICK(fallback)) {
+                    error!(self.logger, "Unable to send a fallback nick";
+                        "error" => e.to_string());
+                }
+            }
+            None => {
+                error!(self.logger, "Unable to find a nick the server will accept";
+                    "nick" => nick,
+                    "code" => format_args!("{:?}", code));
+                self.shutdown(
+                    ShutdownReason::Fatal(String::from("No usable nick")),
+                    None,
+                    ctx,
+                );
+            }
+        }
+    }
+
+    /// We registered with a fallback nick (e.g. because a ghost of our last
+    /// connection was still holding ours), so now that we've identified ask
+    /// services to disconnect it and take our nick back.
+    fn start_reclaiming_nick(&mut self, ctx: &mut Context<Self>) {
+        let preferred = match self.client.config().nickname() {
+            Ok(nick) => nick.to_string(),
+            Err(_) => return,
+        };
+
+        if !self.settings.reclaim_nick
+            || self.reclaiming.is_some()
+            || self.current_nick.is_none()
+            || self.is_us(&preferred)
+        {
+            return;
+        }
+
+        info!(self.logger, "Someone else has our nick, trying to reclaim it";
+            "nick" => &preferred,
+            "current-nick" => self.current_nick.as_ref());
+
+        if let Some(ghost) = self.settings.ghost_command.clone() {
+            debug!(self.logger, "Asking services to disconnect the ghost";
+                "service" => &ghost.service,
+                "nick" => &preferred);
+
+            let got = self.send_private_message(PrivateMessage {
+                to: ghost.service.clone(),
+                content: ghost.message(&preferred),
+            });
+
+            if let Err(e) = got {
+                warn!(self.logger, "Unable to ask services to disconnect the ghost";
+                    "service" => &ghost.service,
+                    "error" => e.to_string());
+            }
+        }
+
+        self.reclaiming = Some(preferred);
+
+        // services don't always tell us when the ghost is gone
+        let timeout = self.settings.reclaim_timeout;
+        self.schedule_once("reclaim-nick", timeout, ctx, |world, _ctx| {
+            if world.reclaiming.is_some() {
+                debug!(world.logger, "Gave up waiting for the ghost to leave");
+                world.reclaim_nick();
+            }
+        });
+    }
+
+    /// Someone stopped using a nick, which might be the one we're waiting to
+    /// reclaim.
+    fn ghost_left(&mut self, nick: &str) {
+        let is_ghost = self
+            .reclaiming
+            .as_ref()
+            .map_or(false, |reclaiming| utils::same_nick(reclaiming, nick));
+
+        if is_ghost {
+            info!(self.logger, "The ghost holding our nick has gone";
+                "nick" => nick);
+            self.reclaim_nick();
+        }
+    }
+
+    fn reclaim_nick(&mut self) {
+        let nick = match self.reclaiming.take() {
+            Some(nick) => nick,
+            None => return,
+        };
+
+        info!(self.logger, "Reclaiming our nick"; "nick" => &nick);
+
+        if let Err(e) = self.outbound().send(Command::NICK(nick)) {
+            warn!(self.logger, "Unable to reclaim our nick";
+                "error" => e.to_string());
+        }
+    }
+
+    /// The [`Channel`] actor for a channel, starting one if we aren't
+    /// tracking it yet.
+    fn channel(
+        &mut self,
+        name: &str,
+        ctx: &mut Context<Self>,
+    ) -> Addr<Channel> {
+        let world = ctx.address().recipient();
+
+        self.channels
+            .entry(utils::irc_lowercase(name))
+            .or_insert_with(|| Channel::new(name).with_world(world).start())
+            .clone()
+    }
+
+    /// Stop tracking a channel we're no longer in (e.g. because we were
+    /// kicked), so it isn't counted or rejoined.
+    fn forget_channel(&mut self, name: &str) {
+        // the channel's actor stops once we drop its address
+        if self.channels.remove(&utils::irc_lowercase(name)).is_some() {
+            info!(self.logger, "We're no longer in a channel"; "channel" => name);
+        }
+    }
+
+    /// Someone else left a channel, so leave too if we're the only one left
+    /// and our [`Settings`] say to.
+    fn member_left(&mut self, name: &str, nick: &str, ctx: &mut Context<Self>) {
+        let channel = match self.channels.get(&utils::irc_lowercase(name)) {
+            Some(channel) => channel.clone(),
+            None => return,
+        };
+        let msg = MemberLeft {
+            nick: nick.to_string(),
+        };
+
+        if !self.settings.leaves_when_empty(name) {
+            channel.do_send(msg);
+            return;
+        }
+
+        let name = name.to_string();
+        let fut = channel.send(msg).into_actor(self).then(
+            move |remaining, world, _ctx| {
+                // the only member left is us
+                if let Ok(Some(1)) = remaining {
+                    if world.settings.is_sticky(&name) {
+                        warn!(world.logger, "Staying in an empty channel because it's sticky";
+                            "channel" => &name);
+                        return actix::fut::ok(());
+                    }
+
+                    info!(world.logger, "Everyone else has left the channel";
+                        "channel" => &name);
+                    channel.do_send(LeaveAndStop {
+                        reason: Some(String::from("Nobody else is here")),
+                    });
+                }
+
+                actix::fut::ok(())
+            },
+        );
+
+        ctx.spawn(fut);
+    }
+
+    /// The server has confirmed we joined a channel, so mark it as joined
+    /// and ask who's there.
+    fn confirm_join(&mut self, name: &str, ctx: &mut Context<Self>) {
+        info!(self.logger, "Joined a channel"; "channel" => name);
+
+        self.channel(name, ctx).do_send(Joined);
+        self.stop_awaiting(name);
+
+        let who = Command::WHO(Some(name.to_string()), None);
+        if let Err(e) = self.outbound().send(who) {
+            warn!(self.logger, "Unable to ask who is in a channel";
+                "channel" => name,
+                "error" => e.to_string());
+        }
+    }
+
+    /// Hold off on being [`Ready`] until we've joined the channels in our
+    /// [`Settings`], giving up on the stragglers after `ready_timeout`.
+    fn await_joins(&mut self, ctx: &mut Context<Self>) {
+        if self.settings.wait_for_channels.is_empty() {
+            self.become_ready();
+            return;
+        }
+
+        info!(self.logger, "Waiting to join channels before taking commands";
+            "channels" => format_args!("{:?}", self.settings.wait_for_channels),
+            "timeout" => format_args!("{:?}", self.settings.ready_timeout));
+
+        self.awaiting_joins = Some(self.settings.wait_for_channels.clone());
+        let timeout = self.settings.ready_timeout;
+        self.schedule_once("ready-timeout", timeout, ctx, |world, _ctx| {
+            if world.awaiting_joins.is_some() {
+                world.become_ready();
+            }
+        });
+    }
+
+    /// We've joined a channel, which might be the last one we were waiting
+    /// for.
+    fn stop_awaiting(&mut self, name: &str) {
+        let done = match self.awaiting_joins {
+            Some(ref mut awaiting) => {
+                awaiting.retain(|channel| !utils::same_nick(channel, name));
+                awaiting.is_empty()
+            }
+            None => false,
+        };
+
+        if done {
+            self.become_ready();
+        }
+    }
+
+    fn become_ready(&mut self) {
+        let missing = self.awaiting_joins.take().unwrap_or_default();
+
+        for channel in &missing {
+            warn!(self.logger, "Gave up waiting to join a channel";
+                "channel" => channel);
+        }
+
+        info!(self.logger, "Ready to take commands";
+            "missing-channels" => missing.len());
+        self.publish(Ready { missing });
+    }
+
+    /// Ask the server for the names (and optionally topic) of every
+    /// channel, in case our idea of them has drifted.
+    ///
+    /// The requests are spread out so we don't send them all in one burst.
+    fn resync_channels(&mut self, ctx: &mut Context<Self>) {
+        let mut channels: Vec<String> = self.channels.keys().cloned().collect();
+        channels.sort();
+
+        debug!(self.logger, "Re-syncing channels";
+            "channels" => channels.len());
+
+        for (i, name) in channels.into_iter().enumerate() {
+            let task = format!("resync-{}", name);
+            self.schedule_once(
+                task,
+                RESYNC_SPACING * i as u32,
+                ctx,
+                move |world, _ctx| world.resync_channel(&name),
+            );
+        }
+    }
+
+    fn resync_channel(&mut self, name: &str) {
+        let mut commands = vec![Command::NAMES(Some(name.to_string()), None)];
+        if self.settings.resync_topics {
+            commands.push(Command::TOPIC(name.to_string(), None));
+        }
+
+        for cmd in commands {
+            if let Err(e) = self.outbound().send(cmd) {
+                warn!(self.logger, "Unable to re-sync a channel";
+                    "channel" => name,
+                    "error" => e.to_string());
+            }
+        }
+    }
+}
+
+impl<C: 'static> World<C> {
+    /// Is this nick ours?
+    fn is_us(&self, nick: &str) -> bool {
+        self.current_nick
+            .as_ref()
+            .map_or(false, |current| utils::same_nick(current, nick))
+    }
+
+    /// Publish a [`ChatEvent`], asking the relevant [`Channel`] for the
+    /// sender's status first.
+    fn publish_chat_event(
+        &mut self,
+        target: &str,
+        content: &str,
+        raw: &IrcMessage,
+        ctx: &mut Context<Self>,
+    ) {
+        let channel = if utils::is_channel(target) {
+            Some(target.to_string())
+        } else {
+            None
+        };
+        let (content, is_action) = match utils::parse_ctcp(content) {
+            Some(("ACTION", action)) => (action.unwrap_or_default(), true),
+            _ => (content, false),
+        };
+        let highlighted = self
+            .current_nick
+            .as_ref()
+            .map(|nick| utils::mentions(content, nick))
+            .unwrap_or(false);
+
+        let event = ChatEvent {
+            sender: raw.source_nickname().map(String::from),
+            hostmask: raw.prefix.clone(),
+            account: utils::tag(raw, "account").map(String::from),
+            channel,
+            highlighted,
+            status: None,
+            timestamp: utils::tag(raw, "time").map(String::from),
+            is_action,
+            content: content.to_string(),
+            raw: raw.clone(),
+        };
+
+        let channel = event
+            .channel
+            .as_ref()
+            .and_then(|name| self.channels.get(&utils::irc_lowercase(name)))
+            .cloned();
+
+        match (channel, event.sender.clone()) {
+            (Some(channel), Some(nick)) => {
+                let fut = channel
+                    .send(MemberStatusOf { nick })
+                    .into_actor(self)
+                    .then(move |status, world, _ctx| {
+                        let mut event = event;
+                        event.status = status.unwrap_or(None);
+                        world.publish(event);
+                        actix::fut::ok(())
+                    });
+
+                ctx.spawn(fut);
+            }
+            _ => self.publish(event),
+        }
+    }
+}
+
+impl<C: 'static> Actor for World<C> {
+    type Context = Context<World<C>>;
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.finish_shutdown();
+    }
+}
+
+impl<C: Debug> Debug for World<C> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let World {
+            ref client,
+            ref channels,
+            ref logger,
+            ref hooks,
+            message_count,
+            messages_queued,
+            messages_handled,
+            mailbox_delay,
+            ref outgoing,
+            ref incoming,
+            ref settings,
+            ref current_nick,
+            ref reclaiming,
+            ref accounts,
+            ref pending_whois,
+            ref whois_replies,
+            ref raw_log,
+            ref reconnect,
+            ref pending_nick,
+            ref pending_list,
+            ref caps,
+            last_label,
+            ref pending_echoes,
+            state,
+            state_changed,
+            ref pending_ctcp,
+            nick_fallbacks,
+            started,
+            ref modes,
+            is_oper,
+            ref when_ready,
+            ref plugin_panics,
+            ref shutdown_reason,
+            listening,
+            connection,
+            identified,
+            ref deferred_joins,
+            ref awaiting_joins,
+            supports_monitor,
+            monitor_limit,
+            ref network,
+            ref monitoring,
+            ref commands,
+            last_ping,
+            ref pending_pings,
+            ref pending_nick_resyncs,
+            ref cap_negotiation,
+            ref not_registered,
+            ref schedules,
+            last_schedule,
+            ref clock_skew,
+            warned_clock_skew,
+            ref rate_limits,
+        } = *self;
+
+        f.debug_struct("World")
+            .field("client", client)
+            .field("hooks", &format_args!("({} listeners)", hooks.len()))
+            .field("channels", channels)
+            .field("logger", logger)
+            .field("message_count", &message_count)
+            .field("messages_queued", &messages_queued)
+            .field("messages_handled", &messages_handled)
+            .field("mailbox_delay", &mailbox_delay)
+            .field("outgoing", &format_args!("({} middleware)", outgoing.len()))
+            .field(
+                "incoming",
+                &format_args!("({} interceptors)", incoming.len()),
+            )
+            .field("settings", settings)
+            .field("current_nick", current_nick)
+            .field("reclaiming", reclaiming)
+            .field("accounts", accounts)
+            .field(
+                "pending_whois",
+                &format_args!("({} lookups)", pending_whois.len()),
+            )
+            .field(
+                "whois_replies",
+                &format_args!("({} in progress)", whois_replies.len()),
+            )
+            .field("raw_log", &raw_log.is_some())
+            .field("reconnect", &reconnect.as_ref().map(|r| r.policy))
+            .field("pending_nick", &pending_nick.as_ref().map(|p| &p.nick))
+            .field(
+                "pending_list",
+                &pending_list.as_ref().map(|p| p.channels.len()),
+            )
+            .field("caps", caps)
+            .field("last_label", &last_label)
+            .field(
+                "pending_echoes",
+                &format_args!("({} messages)", pending_echoes.len()),
+            )
+            .field("state", &state)
+            .field("state_changed", &state_changed)
+            .field(
+                "pending_ctcp",
+                &format_args!("({} queries)", pending_ctcp.len()),
+            )
+            .field("nick_fallbacks", &nick_fallbacks)
+            .field("started", &started)
+            .field("modes", modes)
+            .field("is_oper", &is_oper)
+            .field(
+                "when_ready",
+                &format_args!("({} callbacks)", when_ready.len()),
+            )
+            .field("plugin_panics", plugin_panics)
+            .field("shutdown_reason", shutdown_reason)
+            .field("listening", &listening)
+            .field("connection", &connection.is_some())
+            .field("identified", &identified)
+            .field("deferred_joins", deferred_joins)
+            .field("awaiting_joins", awaiting_joins)
+            .field("supports_monitor", &supports_monitor)
+            .field("monitor_limit", &monitor_limit)
+            .field("network", network)
+            .field("monitoring", monitoring)
+            .field("commands", commands)
+            .field("last_ping", &last_ping)
+            .field(
+                "pending_pings",
+                &format_args!("({} pings)", pending_pings.len()),
+            )
+            .field(
+                "pending_nick_resyncs",
+                &format_args!("({} resyncs)", pending_nick_resyncs.len()),
+            )
+            .field("cap_negotiation", cap_negotiation)
+            .field("not_registered", &not_registered.len())
+            .field(
+                "schedules",
+                &schedules.values().map(|s| &s.name).collect::<Vec<_>>(),
+            )
+            .field("last_schedule", &last_schedule)
+            .field("clock_skew", clock_skew)
+            .field("warned_clock_skew", &warned_clock_skew)
+            .field(
+                "rate_limits",
+                &format_args!("({} channels)", rate_limits.len()),
+            )
+            .finish()
+    }
+}
+
+impl<C: Client + 'static> Handler<StartListening> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, _msg: StartListening, ctx: &mut Self::Context) {
+        self.connection = Some(ctx.add_stream(self.client.stream()));
+        self.listening = true;
+
+        self.set_state(ConnectionState::Connecting);
+        self.schedule_every(
+            "registration-watchdog",
+            WATCHDOG_INTERVAL,
+            ctx,
+            |world, ctx| world.check_registration(ctx),
+        );
+
+        if let Some(interval) = self.settings.resync_interval {
+            self.schedule_every(
+                "resync-channels",
+                interval,
+                ctx,
+                |world, ctx| world.resync_channels(ctx),
+            );
+        }
+    }
+}
+
+impl<C: Client + 'static> StreamHandler<IrcMessage, IrcError> for World<C> {
+    fn handle(&mut self, item: IrcMessage, ctx: &mut Self::Context) {
+        self.messages_queued += 1;
+        ctx.notify(RawMessage(item));
+    }
+
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        self.listening = false;
+        self.connection = None;
+        self.reset_connection_state();
+
+        if self.shutdown_reason.is_some() {
+            debug!(self.logger, "The server closed the connection");
+        } else {
+            warn!(self.logger, "The server closed the connection");
+
+            if self.reconnect.is_some() {
+                self.schedule_reconnect(ctx);
+                return;
+            }
+        }
+
+        ctx.stop();
+    }
+
+    fn error(&mut self, err: IrcError, _ctx: &mut Self::Context) -> Running {
+        if is_malformed_line(&err) {
+            // one garbled line isn't worth dropping the connection over
+            warn!(self.logger, "Skipping a line we couldn't understand";
+                "error" => err.to_string());
+            Running::Continue
+        } else {
+            error!(self.logger, "The connection failed";
+                "error" => err.to_string());
+            Running::Stop
+        }
+    }
+}
+
+impl<C: Client + 'static> Handler<RawMessage> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, mut msg: RawMessage, ctx: &mut Self::Context) {
+        self.messages_handled += 1;
+
+        debug!(self.logger, "Received a message";
+            "prefix" => msg.0.prefix.as_ref(),
+            "source-nick" => msg.0.source_nickname(),
+            "command" => format_args!("{:?}", msg.0.command));
+
+        if let Some(ref raw_log) = self.raw_log {
+            raw_log.inbound(&msg.0);
+        }
+
+        if self.message_count == 0 {
+            debug!(self.logger, "Notifying listeners that we've connected");
+            self.set_state(ConnectionState::Registering);
+            self.publish(Connected);
+        }
+        self.message_count += 1;
+
+        match middleware::intercept(&self.incoming, &msg.0) {
+            InboundDecision::Process => {}
+            InboundDecision::ProcessTagged(tags) => {
+                msg.0.tags.get_or_insert_with(Vec::new).extend(tags);
+            }
+            InboundDecision::Drop => {
+                debug!(self.logger, "An interceptor dropped a message";
+                    "command" => format_args!("{:?}", msg.0.command));
+                return;
+            }
+        }
+
+        self.track_accounts(&msg.0);
+        self.track_whois(&msg.0);
+        self.track_clock_skew(&msg.0, SystemTime::now());
+        self.check_join_failure(&msg.0);
+        self.track_list(&msg.0);
+        self.track_caps(&msg.0);
+        self.resolve_echo(&msg.0);
+        self.resolve_ctcp(&msg.0);
+        self.resolve_ping(&msg.0);
+        self.resolve_nick_resync(&msg.0);
+
+        let from_us = msg.0.source_nickname().map_or(false, |n| self.is_us(n));
+        let from_server = msg
+            .0
+            .prefix
+            .as_ref()
+            .map_or(false, |p| utils::is_server_prefix(p));
+
+        match msg.0.command {
+            Command::Response(
+                Response::ERR_NOTREGISTERED,
+                ref args,
+                ref suffix,
+            ) => {
+                self.publish(NotRegistered {
+                    args: args.clone(),
+                    suffix: suffix.clone(),
+                });
+                self.check_not_registered(ctx);
+            }
+            // e.g. ":server 005 our-nick CHANMODES=b,k,l,imnpst :are supported by this server"
+            Command::Response(Response::RPL_ISUPPORT, ref args, _) => {
+                self.update_isupport(args.get(1..).unwrap_or(&[]));
+            }
+            // e.g. ":server 730 our-nick :Michael!mike@example.com,Bob"
+            Command::Response(
+                Response::RPL_MONONLINE,
+                _,
+                Some(ref targets),
+            ) => {
+                for target in targets.split(',').filter(|t| !t.is_empty()) {
+                    let nick = target.split('!').next().unwrap_or(target);
+                    self.publish(NickOnline {
+                        nick: nick.to_string(),
+                    });
+                }
+            }
+            Command::Response(
+                Response::RPL_MONOFFLINE,
+                _,
+                Some(ref targets),
+            ) => {
+                for nick in targets.split(',').filter(|t| !t.is_empty()) {
+                    self.publish(NickOffline {
+                        nick: nick.to_string(),
+                    });
+                }
+            }
+            // e.g. ":Michael!mike@example.com CHGHOST mike rust/contributor/michael"
+            Command::CHGHOST(ref user, ref host) => {
+                if let Some(nick) = msg.0.source_nickname() {
+                    let changed = HostChanged {
+                        nick: nick.to_string(),
+                        new_user: user.clone(),
+                        new_host: host.clone(),
+                    };
+
+                    if from_us {
+                        info!(self.logger, "Our host changed";
+                            "hostmask" => changed.hostmask());
+                    }
+
+                    for channel in self.channels.values() {
+                        channel.do_send(changed.clone());
+                    }
+                    self.publish(changed);
+                }
+            }
+            Command::Raw(ref cmd, _, Some(ref name)) if cmd == "SETNAME" => {
+                if let Some(nick) = msg.0.source_nickname() {
+                    self.publish(RealNameChanged {
+                        nick: nick.to_string(),
+                        name: name.clone(),
+                    });
+                }
+            }
+            // ERR_MONLISTFULL, which the irc crate doesn't know about
+            Command::Raw(ref code, ref args, _) if code == "734" => {
+                let targets = args.get(2).map(String::as_str).unwrap_or("");
+                warn!(self.logger, "The server's MONITOR list is full";
+                    "targets" => targets);
+
+                for nick in targets.split(',') {
+                    self.monitoring.remove(&utils::irc_lowercase(nick));
+                }
+            }
+            Command::Response(Response::RPL_YOUREOPER, ..) => {
+                info!(self.logger, "The server made us an oper");
+                self.is_oper = true;
+            }
+            Command::UserMODE(ref target, ref modes) if self.is_us(target) => {
+                for mode in modes {
+                    match *mode {
+                        Mode::Plus(UserMode::Oper, _) => self.is_oper = true,
+                        Mode::Minus(UserMode::Oper, _) => self.is_oper = false,
+                        _ => {}
+                    }
+                }
+            }
+            Command::ERROR(ref message) => {
+                warn!(self.logger, "The server sent an error";
+                    "message" => message);
+                self.publish(ServerError {
+                    message: message.clone(),
+                });
+            }
+            Command::WALLOPS(ref content) => {
+                self.publish(ServerNotice {
+                    from: msg.0.prefix.clone().unwrap_or_default(),
+                    content: content.clone(),
+                });
+            }
+            Command::NOTICE(_, ref content) if from_server => {
+                self.publish(ServerNotice {
+                    from: msg.0.prefix.clone().unwrap_or_default(),
+                    content: content.clone(),
+                });
+            }
+            // with echo-message the server sends our own notices back
+            Command::NOTICE(ref target, ref content) if !from_us => {
+                self.publish(NoticeReceived {
+                    msg_target: target.clone(),
+                    content: content.clone(),
+                    raw: msg.0.clone(),
+                });
+            }
+            Command::Response(Response::RPL_WELCOME, ref args, _) => {
+                if let Some(nick) = args.first() {
+                    info!(self.logger, "Registered with the server";
+                        "nick" => nick);
+                    self.current_nick = Some(nick.clone());
+                }
+                // servers without CAP support never answer our CAP LS
+                self.cap_negotiation = None;
+                if let Some(ref mut reconnect) = self.reconnect {
+                    reconnect.attempts = 0;
+                }
+                self.set_state(ConnectionState::Registered);
+                self.await_joins(ctx);
+            }
+            Command::Response(
+                Response::RPL_NAMREPLY,
+                ref args,
+                Some(ref names),
+            ) => {
+                let channel = args.last().and_then(|name| {
+                    self.channels.get(&utils::irc_lowercase(name))
+                });
+
+                if let Some(channel) = channel {
+                    channel.do_send(NamesReply {
+                        names: names
+                            .split_whitespace()
+                            .map(String::from)
+                            .collect(),
+                    });
+                }
+            }
+            // e.g. ":server 366 our-nick #rust :End of /NAMES list."
+            Command::Response(Response::RPL_ENDOFNAMES, ref args, _) => {
+                let channel = args.get(1).and_then(|name| {
+                    self.channels.get(&utils::irc_lowercase(name))
+                });
+
+                if let Some(channel) = channel {
+                    channel.do_send(EndOfNames);
+                }
+            }
+            Command::JOIN(ref channels, ..) => {
+                let nick = msg.0.source_nickname().unwrap_or_default();
+
+                for name in channels.split(',') {
+                    if from_us {
+                        self.confirm_join(name, ctx);
+                    } else if let Some(channel) =
+                        self.channels.get(&utils::irc_lowercase(name))
+                    {
+                        channel.do_send(MemberJoined {
+                            nick: nick.to_string(),
+                            hostmask: msg.0.prefix.clone(),
+                        });
+                    }
+
+                    self.publish(UserJoined {
+                        channel: name.to_string(),
+                        nick: nick.to_string(),
+                        hostmask: msg.0.prefix.clone(),
+                        is_us: from_us,
+                    });
+                }
+            }
+            Command::PART(ref channels, ref reason) => {
+                let nick = msg.0.source_nickname().unwrap_or_default();
+
+                for name in channels.split(',') {
+                    if from_us {
+                        self.forget_channel(name);
+                    } else {
+                        self.member_left(name, nick, ctx);
+                    }
+
+                    self.publish(UserParted {
+                        channel: name.to_string(),
+                        nick: nick.to_string(),
+                        reason: reason.clone(),
+                        is_us: from_us,
+                    });
+                }
+            }
+            Command::KICK(ref channels, ref nicks, ref reason) => {
+                let by = msg.0.source_nickname().map(String::from);
+                let channels: Vec<&str> = channels.split(',').collect();
+                let nicks: Vec<&str> = nicks.split(',').collect();
+
+                // either a nick for each channel, or one channel and a list
+                // of nicks to kick from it
+                let kicked: Vec<_> = if channels.len() == nicks.len() {
+                    channels.into_iter().zip(nicks).collect()
+                } else if channels.len() == 1 {
+                    nicks.into_iter().map(|nick| (channels[0], nick)).collect()
+                } else {
+                    warn!(self.logger, "Ignoring a KICK with a different number of channels and nicks";
+                        "channels" => channels.join(","),
+                        "nicks" => nicks.join(","));
+                    Vec::new()
+                };
+
+                for (name, nick) in kicked {
+                    let is_us = self.is_us(nick);
+                    if is_us {
+                        self.forget_channel(name);
+                    } else {
+                        self.member_left(name, nick, ctx);
+                    }
+
+                    self.publish(UserKicked {
+                        channel: name.to_string(),
+                        nick: nick.to_string(),
+                        by: by.clone(),
+                        reason: reason.clone(),
+                        is_us,
+                    });
+                }
+            }
+            Command::QUIT(ref reason) => {
+                let nick = msg.0.source_nickname().unwrap_or_default();
+                let names: Vec<String> =
+                    self.channels.keys().cloned().collect();
+
+                for name in &names {
+                    self.member_left(name, nick, ctx);
+                }
+
+                self.publish(UserQuit {
+                    nick: nick.to_string(),
+                    reason: reason.clone(),
+                });
+                self.ghost_left(nick);
+            }
+            // e.g. ":server 332 our-nick #rust :The Rust Programming Language"
+            Command::Response(Response::RPL_TOPIC, ref args, ref topic) => {
+                if let Some(name) = args.get(1) {
+                    self.update_topic(name, topic.clone());
+                    self.publish(TopicChanged {
+                        channel: name.clone(),
+                        topic: topic.clone().unwrap_or_default(),
+                        set_by: None,
+                    });
+                }
+            }
+            // e.g. ":Michael!mike@example.com TOPIC #rust :Rust 1.31 is out!"
+            Command::TOPIC(ref name, Some(ref topic)) => {
+                let new_topic = if topic.is_empty() {
+                    None
+                } else {
+                    Some(topic.clone())
+                };
+                self.update_topic(name, new_topic);
+                self.publish(TopicChanged {
+                    channel: name.clone(),
+                    topic: topic.clone(),
+                    set_by: msg.0.source_nickname().map(String::from),
+                });
+            }
+            Command::Response(Response::RPL_NOTOPIC, ref args, _) => {
+                if let Some(name) = args.get(1) {
+                    self.update_topic(name, None);
+                }
+            }
+            // e.g. ":server 352 our-nick #rust user host server Michael H@ :0 Michael"
+            Command::Response(Response::RPL_WHOREPLY, ref args, _) => {
+                let channel = args.get(1).and_then(|name| {
+                    self.channels.get(&utils::irc_lowercase(name))
+                });
+
+                if let (Some(channel), Some(nick), Some(flags)) =
+                    (channel, args.get(5), args.get(6))
+                {
+                    let hostmask = match (args.get(2), args.get(3)) {
+                        (Some(user), Some(host)) => {
+                            Some(format!("{}!{}@{}", nick, user, host))
+                        }
+                        _ => None,
+                    };
+
+                    channel.do_send(WhoReply {
+                        nick: nick.clone(),
+                        flags: flags.clone(),
+                        hostmask,
+                    });
+                }
+            }
+            Command::NICK(ref new_nick) => {
+                if from_us {
+                    info!(self.logger, "Our nick was changed";
+                        "nick" => new_nick);
+                    self.current_nick = Some(new_nick.clone());
+                    self.resolve_nick_change(new_nick, Ok(()));
+                } else if let Some(old_nick) = msg.0.source_nickname() {
+                    self.ghost_left(old_nick);
+                }
+
+                if let Some(old_nick) = msg.0.source_nickname() {
+                    let changed = NickChanged {
+                        old: old_nick.to_string(),
+                        new: new_nick.clone(),
+                    };
+
+                    for channel in self.channels.values() {
+                        channel.do_send(changed.clone());
+                    }
+                    self.publish(changed);
+                }
+            }
+            Command::Response(code, ref args, ref suffix)
+                if is_nick_rejection(code) =>
+            {
+                // e.g. ":server 433 our-nick new-nick :Nickname is already in use"
+                if let Some(nick) = args.get(1) {
+                    let reason = suffix.as_ref().map_or("", String::as_str);
+                    let err = IrcError::Custom {
+                        inner: failure::format_err!(
+                            "Unable to change nick to \"{}\": {}",
+                            nick,
+                            reason
+                        ),
+                    };
+                    self.resolve_nick_change(nick, Err(err));
+                    self.publish_nick_error(code, nick, suffix.as_ref());
+
+                    if self.state != ConnectionState::Registered {
+                        self.retry_registration_nick(code, nick, ctx);
+                    }
+                }
+            }
+            // with echo-message the server sends our own messages back
+            Command::PRIVMSG(..) if from_us => {}
+            Command::PRIVMSG(ref target, ref message) => {
+                self.publish(PrivateMessageReceived {
+                    msg_target: target.clone(),
+                    content: message.clone(),
+                    raw: msg.0.clone(),
+                });
+                self.publish_chat_event(target, message, &msg.0, ctx);
+
+                if let Some(("ACTION", action)) = utils::parse_ctcp(message) {
+                    let sender = msg.0.source_nickname().map(String::from);
+                    let channel = if utils::is_channel(target) {
+                        Some(target.clone())
+                    } else {
+                        None
+                    };
+                    let reply_to = channel.clone().or_else(|| sender.clone());
+
+                    if let Some(reply_to) = reply_to {
+                        self.publish(ActionReceived {
+                            action: action.unwrap_or_default().to_string(),
+                            channel,
+                            reply_to,
+                            sender,
+                            raw: msg.0.clone(),
+                        });
+                    }
+                }
+
+                // we stop taking commands once we've started shutting down,
+                // and don't start until we've joined our channels
+                let prefix = self.settings.command_prefix;
+                let command =
+                    utils::parse_command(message, prefix).filter(|_| {
+                        self.shutdown_reason.is_none()
+                            && self.awaiting_joins.is_none()
+                    });
+
+                let disabled = command.as_ref().map_or(false, |(name, _)| {
+                    utils::is_channel(target)
+                        && !self.commands.is_enabled_in(target, name)
+                });
+
+                if disabled {
+                    debug!(self.logger, "Ignoring a command which is disabled in this channel";
+                        "channel" => target,
+                        "command" => command.as_ref().map(|(name, _)| name));
+                } else if let Some((name, args)) = command {
+                    let sender = msg.0.source_nickname().map(String::from);
+                    let reply_to = if utils::is_channel(target) {
+                        Some(target.clone())
+                    } else {
+                        sender.clone()
+                    };
+
+                    // a plugin acting on a command we've just said doesn't
+                    // exist would contradict us
+                    let unknown = !self.commands.contains(&name)
+                        && self.settings.unknown_commands
+                            != UnknownCommandPolicy::Ignore;
+
+                    let context = self
+                        .commands
+                        .get(&name)
+                        .map_or(CommandContext::Anywhere, |info| info.context);
+                    let available = self.commands.is_available(
+                        &name,
+                        Instant::now(),
+                        self.settings.command_cooldown,
+                    );
+
+                    if unknown {
+                        if let Some(ref sender) = sender {
+                            self.unknown_command(&name, sender);
+                        }
+                    } else if !context.allows(utils::is_channel(target)) {
+                        if let Some(ref sender) = sender {
+                            self.command_wrong_context(&name, sender, context);
+                        }
+                    } else if !available {
+                        if let Some(ref sender) = sender {
+                            self.command_unavailable(&name, sender);
+                        }
+                    } else if let Some(reply_to) = reply_to {
+                        self.publish(CommandReceived {
+                            prefix,
+                            name,
+                            args,
+                            reply_to,
+                            sender,
+                            raw: msg.0.clone(),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        self.publish(msg);
+    }
+}
+
+impl<C: Client + 'static> Handler<Quit> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Quit, ctx: &mut Self::Context) {
+        self.shutdown(msg.reason, msg.msg, ctx);
+    }
+}
+
+impl<C: Client + 'static> Handler<PrivateMessage> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: PrivateMessage,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        self.send_private_message(msg)
+    }
+}
+
+impl<C: Client + 'static> Handler<Notice> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: Notice,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let msg = self.prepare_outgoing(PrivateMessage {
+            to: msg.to,
+            content: msg.content,
+        })?;
+
+        debug!(self.logger, "Sending a notice";
+            "recipient" => &msg.to,
+            "content" => &msg.content);
+
+        let got = self.outbound().send_notice(msg.to, msg.content);
+
+        if let Err(ref e) = got {
+            error!(self.logger, "Unable to send a notice";
+                "error" => e.to_string());
+        }
+
+        got
+    }
+}
+
+impl<C: Client + 'static> Handler<ReplyLines> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: ReplyLines,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let to = self.reply_destination(&msg)?;
+
+        // the client queues messages in order and throttles them for us
+        for line in msg.lines.into_iter().filter(|line| !line.is_empty()) {
+            self.send_private_message(PrivateMessage {
+                to: to.clone(),
+                content: line,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<C: Client + 'static> Handler<AnnounceAll> for World<C> {
+    type Result = Result<usize, IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: AnnounceAll,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        check_announcement(&msg.content)?;
+
+        let channels = self.channels.keys().cloned().collect();
+        self.announce_to(channels, &msg.content)
+    }
+}
+
+impl<C: Client + 'static> Handler<ModAnnounce> for World<C> {
+    type Result = ResponseActFuture<Self, usize, IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: ModAnnounce,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if let Err(e) = check_announcement(&msg.content) {
+            return Box::new(actix::fut::err(e));
+        }
+
+        let nick = match self.current_nick {
+            Some(ref nick) => nick.clone(),
+            // we can't be an op anywhere before registering
+            None => return Box::new(actix::fut::ok(0)),
+        };
+
+        let pending = self.channels.iter().map(|(name, channel)| {
+            let name = name.clone();
+
+            channel
+                .send(MemberStatusOf { nick: nick.clone() })
+                .timeout(SUMMARY_TIMEOUT)
+                .then(move |got| Ok::<_, IrcError>((name, got)))
+        });
+
+        let fut = future::join_all(pending).into_actor(self).and_then(
+            move |results, world, _ctx| {
+                let mut moderated = Vec::new();
+
+                for (name, result) in results {
+                    match result {
+                        Ok(Some(status)) if status >= MemberStatus::Op => {
+                            moderated.push(name)
+                        }
+                        Ok(_) => {}
+                        Err(e) => {
+                            warn!(world.logger, "Unable to check our status in a channel";
+                                "channel" => name,
+                                "error" => e.to_string());
+                        }
+                    }
+                }
+
+                actix::fut::result(world.announce_to(moderated, &msg.content))
+            },
+        );
+
+        Box::new(fut)
+    }
+}
+
+impl<C: Client + 'static> Handler<ConfirmedPrivateMessage> for World<C> {
+    type Result = ResponseFuture<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: ConfirmedPrivateMessage,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let msg = match self.prepare_outgoing(msg.0) {
+            Ok(msg) => msg,
+            Err(e) => return Box::new(future::err(e)),
+        };
+
+        // labels only work when the message fits on a single line
+        let single_line = !msg.content.contains(|c| c == '\r' || c == '\n');
+
+        if !self.can_confirm_delivery() || !single_line {
+            let got = self.outbound().send_privmsg(msg.to, msg.content);
+            return Box::new(future::result(got));
+        }
+
+        self.last_label += 1;
+        let label = self.last_label.to_string();
+
+        debug!(self.logger, "Sending a labeled private message";
+            "recipient" => &msg.to,
+            "content" => &msg.content,
+            "label" => &label);
+
+        let labeled = IrcMessage {
+            tags: Some(vec![Tag(String::from("label"), Some(label.clone()))]),
+            prefix: None,
+            command: Command::PRIVMSG(msg.to, msg.content),
+        };
+
+        if let Err(e) = self.outbound().send(labeled) {
+            return Box::new(future::err(e));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.pending_echoes.insert(label.clone(), tx);
+
+        // dropping the sender means the caller sees a cancellation error
+        ctx.run_later(ECHO_TIMEOUT, move |world, _ctx| {
+            if world.pending_echoes.remove(&label).is_some() {
+                warn!(world.logger, "The server never echoed a message back";
+                    "label" => label);
+            }
+        });
+
+        Box::new(rx.map_err(IrcError::from).and_then(|outcome| outcome))
+    }
+}
+
+impl<C: Client + 'static> Handler<CtcpRequest> for World<C> {
+    type Result = ResponseFuture<Option<String>, IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: CtcpRequest,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let command = msg.command.to_uppercase();
+        let content = match msg.args {
+            Some(ref args) => format!("\u{1}{} {}\u{1}", command, args),
+            None => format!("\u{1}{}\u{1}", command),
+        };
+
+        debug!(self.logger, "Sending a CTCP query";
+            "to" => &msg.to,
+            "command" => &command);
+
+        if let Err(e) = self.outbound().send_privmsg(&msg.to, content) {
+            return Box::new(future::err(e));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let key = (utils::irc_lowercase(&msg.to), command);
+        self.pending_ctcp
+            .entry(key.clone())
+            .or_insert_with(Vec::new)
+            .push(PendingCtcp {
+                sent: Instant::now(),
+                tx,
+            });
+
+        // dropping the sender means the caller sees a cancellation error
+        ctx.run_later(CTCP_TIMEOUT, move |world, _ctx| {
+            if let Some(waiting) = world.pending_ctcp.get_mut(&key) {
+                waiting.retain(|pending| pending.sent.elapsed() < CTCP_TIMEOUT);
+            }
+            if world.pending_ctcp.get(&key).map_or(false, Vec::is_empty) {
+                world.pending_ctcp.remove(&key);
+            }
+        });
+
+        Box::new(rx.map_err(IrcError::from))
+    }
+}
+
+impl<C: Client + 'static> Handler<MeasureLatency> for World<C> {
+    type Result = ResponseFuture<Duration, IrcError>;
+
+    fn handle(
+        &mut self,
+        _msg: MeasureLatency,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        self.last_ping += 1;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let millis = now.as_secs() * 1000 + u64::from(now.subsec_millis());
+        let token = format!("irc_bot-{}-{}", self.last_ping, millis);
+
+        if let Err(e) = self.outbound().send(Command::PING(token.clone(), None))
+        {
+            return Box::new(future::err(e));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.pending_pings.insert(
+            token.clone(),
+            PendingPing {
+                sent: Instant::now(),
+                tx,
+            },
+        );
+
+        // dropping the sender means the caller sees a cancellation error
+        ctx.run_later(LATENCY_TIMEOUT, move |world, _ctx| {
+            world.pending_pings.remove(&token);
+        });
+
+        Box::new(rx.map_err(IrcError::from))
+    }
+}
+
+impl<C: 'static> Handler<CommandCompleted> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: CommandCompleted, _ctx: &mut Self::Context) {
+        match msg.error {
+            Some(error) => {
+                debug!(self.logger, "A command failed";
+                    "command" => &msg.command,
+                    "error" => &error);
+
+                let opened = self.commands.record_failure(
+                    &msg.command,
+                    Instant::now(),
+                    self.settings.command_failure_threshold,
+                    self.settings.command_cooldown,
+                );
+
+                if opened {
+                    warn!(self.logger, "A command keeps failing, switching it off for a while";
+                        "command" => &msg.command,
+                        "cooldown" => format_args!("{:?}", self.settings.command_cooldown),
+                        "error" => &error);
+                    self.publish(CircuitOpened {
+                        command: msg.command,
+                        error: Some(error),
+                    });
+                }
+            }
+            None => {
+                if self.commands.record_success(&msg.command) {
+                    info!(self.logger, "A command is working again";
+                        "command" => &msg.command);
+                    self.publish(CircuitClosed {
+                        command: msg.command,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl<C: Client + 'static> Handler<ResyncNick> for World<C> {
+    type Result = ResponseFuture<String, IrcError>;
+
+    fn handle(
+        &mut self,
+        _msg: ResyncNick,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let whois = match self.current_nick {
+            Some(ref nick) => nick.clone(),
+            None => match self.client.config().nickname() {
+                Ok(nick) => nick.to_string(),
+                Err(e) => return Box::new(future::err(e)),
+            },
+        };
+
+        debug!(self.logger, "Re-syncing our nick with the server";
+            "nick" => &whois);
+
+        if let Err(e) =
+            self.outbound().send(Command::WHOIS(None, whois.clone()))
+        {
+            return Box::new(future::err(e));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.pending_nick_resyncs.push(PendingNickResync {
+            sent: Instant::now(),
+            whois,
+            tx,
+        });
+
+        // dropping the sender means the caller sees a cancellation error
+        ctx.run_later(NICK_RESYNC_TIMEOUT, |world, _ctx| {
+            world
+                .pending_nick_resyncs
+                .retain(|p| p.sent.elapsed() < NICK_RESYNC_TIMEOUT);
+        });
+
+        Box::new(rx.map_err(IrcError::from))
+    }
+}
+
+impl<C: Client + 'static> Handler<ChangeNick> for World<C> {
+    type Result = ResponseFuture<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: ChangeNick,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if self.current_nick.as_ref() == Some(&msg.nick) {
+            return Box::new(future::ok(()));
+        }
+
+        info!(self.logger, "Changing nick"; "nick" => &msg.nick);
+
+        if let Err(e) = self.outbound().send(Command::NICK(msg.nick.clone())) {
+            return Box::new(future::err(e));
+        }
+
+        // only the most recent request gets to hear back
+        let (tx, rx) = oneshot::channel();
+        self.pending_nick = Some(PendingNick {
+            nick: msg.nick.clone(),
+            tx,
+        });
+
+        let nick = msg.nick;
+        ctx.run_later(NICK_CHANGE_TIMEOUT, move |world, _ctx| {
+            let err = IrcError::Custom {
+                inner: failure::format_err!(
+                    "Timed out waiting for the server to confirm our new nick, \"{}\"",
+                    nick
+                ),
+            };
+            world.resolve_nick_change(&nick, Err(err));
+        });
+
+        Box::new(rx.map_err(IrcError::from).and_then(|outcome| outcome))
+    }
+}
+
+impl<C: Client + 'static> Handler<ListChannels> for World<C> {
+    type Result = ResponseFuture<ChannelListing, IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: ListChannels,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let (tx, rx) = oneshot::channel();
+
+        // piggyback on a LIST that's already in progress
+        if let Some(ref mut pending) = self.pending_list {
+            pending.waiting.push(tx);
+            return Box::new(rx.map_err(IrcError::from).and_then(|got| got));
+        }
+
+        info!(self.logger, "Listing channels";
+            "filter" => msg.filter.as_ref());
+
+        if let Err(e) = self.outbound().send(Command::LIST(msg.filter, None)) {
+            return Box::new(future::err(e));
+        }
+
+        self.pending_list = Some(PendingList {
+            started: Instant::now(),
+            channels: Vec::new(),
+            waiting: vec![tx],
+        });
+
+        ctx.run_later(LIST_TIMEOUT, |world, _ctx| {
+            let timed_out =
+                world.pending_list.as_ref().map_or(false, |pending| {
+                    pending.started.elapsed() >= LIST_TIMEOUT
+                });
+
+            if timed_out {
+                warn!(
+                    world.logger,
+                    "Timed out waiting for the server to list its channels"
+                );
+                world.finish_list(Err(String::from("timed out")));
+            }
+        });
+
+        Box::new(rx.map_err(IrcError::from).and_then(|got| got))
+    }
+}
+
+impl<C: Client + 'static> Handler<LookupAccount> for World<C> {
+    type Result = ResponseFuture<Option<String>, IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: LookupAccount,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if let Some(account) = self.accounts.get(&msg.nick) {
+            return Box::new(future::ok(account));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let waiting = self
+            .pending_whois
+            .entry(utils::irc_lowercase(&msg.nick))
+            .or_insert_with(Vec::new);
+
+        // there's no need to WHOIS if someone else is already waiting
+        if waiting.is_empty() {
+            debug!(self.logger, "Looking up a user's account";
+                "nick" => &msg.nick);
+
+            if let Err(e) = self.outbound().send(Command::WHOIS(None, msg.nick))
+            {
+                return Box::new(future::err(e));
+            }
+        }
+
+        waiting.push(tx);
+        Box::new(rx.map_err(IrcError::from))
+    }
+}
+
+impl<C: 'static> Handler<GetClockSkew> for World<C> {
+    type Result = MessageResult<GetClockSkew>;
+
+    fn handle(
+        &mut self,
+        _msg: GetClockSkew,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        MessageResult(self.estimate_clock_skew())
+    }
+}
+
+impl<C: Client + 'static> Handler<ListSchedules> for World<C> {
+    type Result = MessageResult<ListSchedules>;
+
+    fn handle(
+        &mut self,
+        _msg: ListSchedules,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let now = Instant::now();
+        let mut schedules: Vec<ScheduleSummary> = self
+            .schedules
+            .iter()
+            .map(|(&id, scheduled)| ScheduleSummary {
+                id,
+                name: scheduled.name.clone(),
+                interval: scheduled.interval,
+                due_in: if scheduled.due > now {
+                    scheduled.due - now
+                } else {
+                    Duration::from_secs(0)
+                },
+            })
+            .collect();
+        schedules.sort_by_key(|s| (s.due_in, s.id));
+
+        MessageResult(schedules)
+    }
+}
+
+impl<C: Client + 'static> Handler<CancelSchedule> for World<C> {
+    type Result = MessageResult<CancelSchedule>;
+
+    fn handle(
+        &mut self,
+        msg: CancelSchedule,
+        ctx: &mut Self::Context,
+    ) -> Self::Result {
+        match self.schedules.remove(&msg.id) {
+            Some(scheduled) => {
+                info!(self.logger, "Cancelling a scheduled task";
+                    "id" => msg.id,
+                    "name" => &scheduled.name);
+                MessageResult(ctx.cancel_future(scheduled.handle))
+            }
+            None => MessageResult(false),
+        }
+    }
+}
+
+impl<C: Client + 'static> Handler<GetNetwork> for World<C> {
+    type Result = MessageResult<GetNetwork>;
+
+    fn handle(
+        &mut self,
+        _msg: GetNetwork,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let network = self
+            .network
+            .clone()
+            .or_else(|| self.client.config().server().ok().map(String::from));
+
+        MessageResult(network)
+    }
+}
+
+impl<C: 'static> Handler<DescribeWiring> for World<C> {
+    type Result = MessageResult<DescribeWiring>;
+
+    fn handle(
+        &mut self,
+        _msg: DescribeWiring,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        MessageResult(self.hooks.wiring())
+    }
+}
+
+impl<C: 'static> Handler<ListPlugins> for World<C> {
+    type Result = MessageResult<ListPlugins>;
+
+    fn handle(
+        &mut self,
+        _msg: ListPlugins,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let mut plugins = self.hooks.plugins();
+
+        for plugin in &mut plugins {
+            plugin.commands = self.commands.handled_by(&plugin.name);
+        }
+
+        MessageResult(plugins)
+    }
+}
+
+impl<C: 'static> Handler<UnregisterAll> for World<C> {
+    type Result = usize;
+
+    fn handle(
+        &mut self,
+        msg: UnregisterAll,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let removed = self.hooks.unregister_all(&msg.plugin);
+
+        debug!(self.logger, "Unregistered a plugin from everything";
+            "plugin" => &msg.plugin,
+            "registrations" => removed);
+
+        removed
+    }
+}
+
+impl<C: 'static> Handler<ChannelSummaries> for World<C> {
+    type Result = ResponseFuture<Vec<ChannelSummary>, ()>;
+
+    fn handle(
+        &mut self,
+        _msg: ChannelSummaries,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        // a stuck channel shouldn't be able to hang the entire query
+        let pending = self.channels.values().map(|channel| {
+            channel
+                .send(Summarize)
+                .timeout(SUMMARY_TIMEOUT)
+                .then(|got| Ok::<_, ()>(got))
+        });
+
+        let logger = self.logger.clone();
+        let summaries = future::join_all(pending).map(move |results| {
+            let mut summaries = Vec::new();
+
+            for result in results {
+                match result {
+                    Ok(summary) => summaries.push(summary),
+                    Err(e) => {
+                        warn!(logger, "Unable to summarize a channel";
+                            "error" => e.to_string());
+                    }
+                }
+            }
+
+            summaries.sort_by(|left, right| left.name.cmp(&right.name));
+            summaries
+        });
+
+        Box::new(summaries)
+    }
+}
+
+impl<C: 'static> Handler<MailboxProbe> for World<C> {
+    type Result = MessageResult<MailboxProbe>;
+
+    fn handle(
+        &mut self,
+        msg: MailboxProbe,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        self.mailbox_delay = Some(msg.sent.elapsed());
+        MessageResult(self.mailbox_stats())
+    }
+}
+
+impl<C: 'static> Handler<Channels> for World<C> {
+    type Result = MessageResult<Channels>;
+
+    fn handle(
+        &mut self,
+        _msg: Channels,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        MessageResult(self.channels.clone())
+    }
+}
+
+impl<C: 'static> Handler<SharedChannels> for World<C> {
+    type Result = ResponseFuture<Vec<String>, ()>;
+
+    fn handle(
+        &mut self,
+        msg: SharedChannels,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let pending = self.channels.iter().map(|(name, channel)| {
+            let name = name.clone();
+
+            channel
+                .send(MemberStatusOf {
+                    nick: msg.nick.clone(),
+                })
+                .timeout(SUMMARY_TIMEOUT)
+                .then(move |got| Ok::<_, ()>((name, got)))
+        });
+
+        let logger = self.logger.clone();
+        let shared = future::join_all(pending).map(move |results| {
+            let mut shared = Vec::new();
+
+            for (name, result) in results {
+                match result {
+                    Ok(Some(_)) => shared.push(name),
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!(logger, "Unable to check a channel's members";
+                            "channel" => name,
+                            "error" => e.to_string());
+                    }
+                }
+            }
+
+            shared.sort();
+            shared
+        });
+
+        Box::new(shared)
+    }
+}
+
+impl<C: 'static> Handler<GetStats> for World<C> {
+    type Result = MessageResult<GetStats>;
+
+    fn handle(
+        &mut self,
+        _msg: GetStats,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        MessageResult(Stats {
+            uptime: self.started.elapsed(),
+            messages_received: self.message_count,
+            channels: self.channels.len(),
+            nick: self.current_nick.clone(),
+            mailbox: self.mailbox_stats(),
+        })
+    }
+}
+
+impl<C: 'static> Handler<IsMember> for World<C> {
+    type Result = ResponseFuture<bool, ()>;
+
+    fn handle(
+        &mut self,
+        msg: IsMember,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let IsMember {
+            channel: name,
+            nick,
+        } = msg;
+        let channel = match self.channels.get(&utils::irc_lowercase(&name)) {
+            Some(channel) => channel,
+            None => return Box::new(future::ok(false)),
+        };
+
+        let logger = self.logger.clone();
+        let is_member = channel
+            .send(MemberStatusOf { nick })
+            .timeout(SUMMARY_TIMEOUT)
+            .then(move |got| match got {
+                Ok(status) => Ok(status.is_some()),
+                Err(e) => {
+                    warn!(logger, "Unable to check a channel's members";
+                        "channel" => name,
+                        "error" => e.to_string());
+                    Ok(false)
+                }
+            });
+
+        Box::new(is_member)
+    }
+}
+
+impl<C: 'static> Handler<LookupHostmask> for World<C> {
+    type Result = ResponseFuture<Option<String>, ()>;
+
+    fn handle(
+        &mut self,
+        msg: LookupHostmask,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let logger = self.logger.clone();
+        let pending = self.channels.iter().map(move |(name, channel)| {
+            let name = name.clone();
+            let logger = logger.clone();
+
+            channel
+                .send(HostmaskOf {
+                    nick: msg.nick.clone(),
+                })
+                .timeout(SUMMARY_TIMEOUT)
+                .then(move |got| match got {
+                    Ok(hostmask) => Ok(hostmask),
+                    Err(e) => {
+                        warn!(logger, "Unable to check a channel's members";
+                            "channel" => name,
+                            "error" => e.to_string());
+                        Ok(None)
+                    }
+                })
+        });
+
+        let hostmask = future::join_all(pending)
+            .map(|hostmasks| hostmasks.into_iter().filter_map(|h| h).next());
+
+        Box::new(hostmask)
+    }
+}
+
+impl<C: 'static> Handler<GetTopic> for World<C> {
+    type Result = ResponseFuture<Option<String>, ()>;
+
+    fn handle(
+        &mut self,
+        msg: GetTopic,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let name = msg.channel.clone();
+        self.ask_channel(&name, msg, None)
+    }
+}
+
+impl<C: 'static> Handler<Members> for World<C> {
+    type Result = ResponseFuture<HashSet<String>, ()>;
+
+    fn handle(
+        &mut self,
+        msg: Members,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let name = msg.channel.clone();
+        self.ask_channel(&name, msg, HashSet::new())
+    }
+}
+
+impl<C: 'static> Handler<MemberCount> for World<C> {
+    type Result = ResponseFuture<usize, ()>;
+
+    fn handle(
+        &mut self,
+        msg: MemberCount,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let name = msg.channel.clone();
+        self.ask_channel(&name, msg, 0)
+    }
+}
+
+impl<C: 'static> Handler<OurStatusIn> for World<C> {
+    type Result = ResponseFuture<Option<MemberStatus>, ()>;
+
+    fn handle(
+        &mut self,
+        msg: OurStatusIn,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let channel = self.channels.get(&utils::irc_lowercase(&msg.channel));
+        let (channel, nick) = match (channel, self.current_nick.clone()) {
+            (Some(channel), Some(nick)) => (channel, nick),
+            _ => return Box::new(future::ok(None)),
+        };
+
+        let logger = self.logger.clone();
+        let name = msg.channel;
+        let status = channel
+            .send(MemberStatusOf { nick })
+            .timeout(SUMMARY_TIMEOUT)
+            .then(move |got| match got {
+                Ok(status) => Ok(status),
+                Err(e) => {
+                    warn!(logger, "Unable to check our status in a channel";
+                        "channel" => name,
+                        "error" => e.to_string());
+                    Ok(None)
+                }
+            });
+
+        Box::new(status)
+    }
+}
+
+impl<C: 'static> Handler<SetCommandEnabled> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetCommandEnabled, _ctx: &mut Self::Context) {
+        info!(self.logger, "Changing whether a command can be used in a channel";
+            "channel" => &msg.channel,
+            "command" => &msg.command,
+            "enabled" => msg.enabled);
+
+        self.commands
+            .set_enabled_in(&msg.channel, &msg.command, msg.enabled);
+    }
+}
+
+impl<C: 'static> Handler<RegisterCommand> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegisterCommand, _ctx: &mut Self::Context) {
+        debug!(self.logger, "Registering a command";
+            "command" => &msg.name,
+            "plugin" => msg.plugin.as_ref());
+
+        self.commands.register(CommandInfo {
+            name: msg.name,
+            plugin: msg.plugin,
+            access: msg.access,
+            context: msg.context,
+            description: msg.description,
+            usage: msg.usage,
+        });
+    }
+}
+
+impl<C: 'static> Handler<ListCommands> for World<C> {
+    type Result = MessageResult<ListCommands>;
+
+    fn handle(
+        &mut self,
+        _msg: ListCommands,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        MessageResult(self.commands.iter().cloned().collect())
+    }
+}
+
+impl<C: 'static> Handler<AddMiddleware> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: AddMiddleware, _ctx: &mut Self::Context) {
+        self.outgoing.push(msg.0);
+    }
+}
+
+impl<C: 'static> Handler<AddInterceptor> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: AddInterceptor, _ctx: &mut Self::Context) {
+        self.incoming.push(msg.0);
+    }
+}
+
+impl<C: Client + 'static> Handler<Join> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(&mut self, msg: Join, ctx: &mut Self::Context) -> Self::Result {
+        self.join(&msg, ctx)
+    }
+}
+
+impl<C: Client + 'static> Handler<Identified> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Identified, ctx: &mut Self::Context) {
+        debug!(self.logger, "We've identified with services";
+            "service" => &msg.service,
+            "deferred-joins" => self.deferred_joins.len());
+        self.identified = true;
+        self.start_reclaiming_nick(ctx);
+
+        for channel in mem::replace(&mut self.deferred_joins, Vec::new()) {
+            if let Err(e) = self.join(&Join::new(channel.clone()), ctx) {
+                warn!(self.logger, "Unable to retry a deferred join";
+                    "channel" => channel,
+                    "error" => e.to_string());
+            }
+        }
+    }
+}
+
+impl<C: Client + 'static> Handler<Part> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(&mut self, msg: Part, _ctx: &mut Self::Context) -> Self::Result {
+        let names: Vec<&str> = msg
+            .channels
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect();
+
+        if names.is_empty() {
+            return Err(IrcError::Custom {
+                inner: failure::format_err!(
+                    "There were no channels to leave in \"{}\"",
+                    msg.channels
+                ),
+            });
+        }
+
+        if !msg.force {
+            if let Some(sticky) =
+                names.iter().find(|name| self.settings.is_sticky(name))
+            {
+                warn!(self.logger, "Refusing to leave a sticky channel";
+                    "channel" => sticky);
+                return Err(IrcError::Custom {
+                    inner: failure::format_err!(
+                        "{} is sticky, use --force to leave it anyway",
+                        sticky
+                    ),
+                });
+            }
+        }
+
+        // send the PARTs ourselves, so the caller finds out if they fail
+        for name in names {
+            self.outbound()
+                .send(Command::PART(name.to_string(), msg.reason.clone()))?;
+
+            if let Some(channel) =
+                self.channels.remove(&utils::irc_lowercase(name))
+            {
+                info!(self.logger, "Leaving a channel";
+                    "channel" => name,
+                    "reason" => msg.reason.as_ref());
+                channel.do_send(LeaveAndStop {
+                    reason: msg.reason.clone(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<C: Client + 'static> Handler<ChangeModes> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: ChangeModes,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        let cmd = msg.0.build(&self.modes).map_err(|e| {
+            warn!(self.logger, "Refusing to send an invalid mode change";
+                "channel" => &msg.0.channel,
+                "error" => e.to_string());
+            IrcError::Custom { inner: e }
+        })?;
+
+        self.outbound().send(cmd)
+    }
+}
+
+impl<C: Client + 'static> Handler<SetTopic> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: SetTopic,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if !utils::is_channel(&msg.channel) {
+            return Err(IrcError::Custom {
+                inner: failure::format_err!(
+                    "Can't set the topic of \"{}\", it isn't a channel",
+                    msg.channel
+                ),
+            });
+        }
+
+        self.outbound()
+            .send(Command::TOPIC(msg.channel, Some(msg.topic)))
+    }
+}
+
+impl<C: Client + 'static> Handler<Wallops> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: Wallops,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if !self.is_oper {
+            return Err(IrcError::Custom {
+                inner: failure::err_msg("Only opers can send WALLOPS"),
+            });
+        }
+
+        self.outbound().send(Command::WALLOPS(msg.content))
+    }
+}
+
+impl<C: Client + 'static> Handler<SetRealName> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: SetRealName,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if !self.caps.contains("setname") {
+            return Err(IrcError::Custom {
+                inner: failure::err_msg("The server doesn't support SETNAME"),
+            });
+        }
+        if msg.name.trim().is_empty() || msg.name.contains(&['\r', '\n'][..]) {
+            return Err(IrcError::Custom {
+                inner: failure::err_msg(
+                    "A realname must be a single, non-empty line",
+                ),
+            });
+        }
+
+        info!(self.logger, "Changing our realname"; "name" => &msg.name);
+
+        self.outbound().send(Command::Raw(
+            String::from("SETNAME"),
+            Vec::new(),
+            Some(msg.name),
+        ))
+    }
+}
+
+impl<C: Client + 'static> Handler<Monitor> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        msg: Monitor,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        if !self.supports_monitor {
+            return Err(IrcError::Custom {
+                inner: failure::err_msg("The server doesn't support MONITOR"),
+            });
+        }
+
+        let mut monitoring = self.monitoring.clone();
+        for nick in &msg.remove {
+            monitoring.remove(&utils::irc_lowercase(nick));
+        }
+        monitoring.extend(msg.add.iter().map(|n| utils::irc_lowercase(n)));
+
+        if let Some(limit) = self.monitor_limit {
+            if monitoring.len() > limit {
+                return Err(IrcError::Custom {
+                    inner: failure::format_err!(
+                        "The server only lets us monitor {} nicks",
+                        limit
+                    ),
+                });
+            }
+        }
+
+        let changes = vec![("-", msg.remove), ("+", msg.add)];
+
+        for (sign, nicks) in changes {
+            for targets in utils::pack_lines(nicks, ",", MAX_MONITOR_LENGTH) {
+                self.outbound()
+                    .send(Command::MONITOR(sign.to_string(), Some(targets)))?;
+            }
+        }
+
+        self.monitoring = monitoring;
+        Ok(())
+    }
+}
+
+impl<C: 'static> Handler<WhenReady> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: WhenReady, _ctx: &mut Self::Context) {
+        if self.state == ConnectionState::Registered {
+            (msg.0)();
+        } else {
+            self.when_ready.push(msg.0);
+        }
+    }
+}
+
+impl<C: Client + 'static> Handler<ChannelLeft> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChannelLeft, _ctx: &mut Self::Context) {
+        // a Part has already sent the PART and forgotten the channel
+        if self
+            .channels
+            .remove(&utils::irc_lowercase(&msg.channel))
+            .is_none()
+        {
+            return;
+        }
+
+        info!(self.logger, "Leaving a channel";
+            "channel" => &msg.channel,
+            "reason" => msg.reason.as_ref());
+
+        let part = Command::PART(msg.channel.clone(), msg.reason);
+        if let Err(e) = self.outbound().send(part) {
+            warn!(self.logger, "Unable to leave a channel";
+                "channel" => &msg.channel,
+                "error" => e.to_string());
+        }
+    }
+}
+
+impl<C: Client + 'static> Handler<Identify> for World<C> {
+    type Result = Result<(), IrcError>;
+
+    fn handle(
+        &mut self,
+        _msg: Identify,
+        _ctx: &mut Self::Context,
+    ) -> Self::Result {
+        info!(self.logger, "Sending identification";
+            "order" => format_args!("{:?}", self.settings.registration_order));
+
+        let got = match self.settings.registration_order {
+            RegistrationOrder::CapFirst => {
+                self.cap_negotiation = Some(CapNegotiation::default());
+
+                // the rest of the negotiation happens as the server replies
+                let ls = Command::CAP(
+                    None,
+                    CapSubCommand::LS,
+                    Some(String::from("302")),
+                    None,
+                );
+                self.outbound()
+                    .send(ls)
+                    .and_then(|_| self.send_registration())
+            }
+            RegistrationOrder::RequestBlindly => self.request_caps_blindly(),
+        };
+
+        if let Err(ref e) = got {
+            error!(self.logger, "Unable to identify";
+                "error" => e.to_string());
+        }
+
+        got
+    }
+}
+
+impl<C: Client + 'static> Handler<Panic> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Panic, ctx: &mut Self::Context) {
+        let Panic {
+            message,
+            file,
+            line,
+            column,
+            thread,
+            backtrace,
+        } = msg;
+
+        let bt = backtrace.to_string();
+        let bt = if bt.is_empty() { None } else { Some(bt) };
+
+        let plugin = self.panicked_plugin(thread.as_ref());
+
+        error!(self.logger, "A thread panicked";
+            "message" => &message,
+            "file" => file,
+            "line" => line,
+            "column" => column,
+            "thread" => &thread,
+            "backtrace" => bt);
+
+        if let Some(plugin) = plugin {
+            // the plugin's arbiter died with it, so make sure we stop
+            // sending it messages and forget what it was subscribed to
+            let removed = self.hooks.unregister_all(&plugin);
+            let panics = self.plugin_panics.entry(plugin.clone()).or_insert(0);
+            *panics += 1;
+
+            warn!(self.logger, "Carrying on without the plugin";
+                "plugin" => &plugin,
+                "thread" => thread,
+                "registrations" => removed,
+                "panics" => *panics);
+        } else {
+            let reason = format!("A thread panicked, {}", message);
+            self.shutdown(ShutdownReason::Fatal(reason), None, ctx);
+        }
+    }
+}
+
+impl<C: Client + 'static> Handler<Signal> for World<C> {
+    type Result = ();
+
+    fn handle(&mut self, msg: Signal, ctx: &mut Self::Context) {
+        self.shutdown(ShutdownReason::Signal(msg.0), None, ctx);
+    }
+}
+
+/// Pull the host out of a `RPL_WHOISHOST`'s text (e.g. `"is connecting from
+/// *@example.com 203.0.113.7"`).
+fn actual_host(text: &str) -> Option<String> {
+    let from = text.find("from ")? + "from ".len();
+    let mask = text[from..].split_whitespace().next()?;
+    let host = mask.rsplit('@').next()?;
+
+    if host.is_empty() {
+        None
+    } else {
+        Some(host.to_string())
+    }
+}
+
+fn as_millis(duration: Duration) -> i64 {
+    duration.as_secs() as i64 * 1000 + i64::from(duration.subsec_millis())
+}
+
+/// The numerics a server may use to reject a `NICK`.
+fn is_nick_rejection(code: Response) -> bool {
+    match code {
+        Response::ERR_ERRONEOUSNICKNAME
+        | Response::ERR_NICKNAMEINUSE
+        | Response::ERR_NICKCOLLISION
+        | Response::ERR_UNAVAILRESOURCE => true,
+        _ => false,
+    }
+}
+
+/// The error for a request the server never answered because we lost our
+/// connection.
+fn connection_lost() -> IrcError {
+    IrcError::Custom {
+        inner: failure::err_msg("The connection to the server was lost"),
+    }
+}
+
+/// Was this error caused by a single line the server sent us, rather than
+/// the connection itself?
+fn is_malformed_line(err: &IrcError) -> bool {
+    match *err {
+        IrcError::InvalidMessage { .. } | IrcError::CodecFailed { .. } => true,
+        // the line codec reports undecodable bytes as an I/O error
+        IrcError::Io(ref e) => match e.kind() {
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// Announcements must be a single line, so nobody can sneak in extra
+/// commands.
+fn check_announcement(content: &str) -> Result<(), IrcError> {
+    if content.trim().is_empty() || content.contains(&['\r', '\n'][..]) {
+        Err(IrcError::Custom {
+            inner: failure::err_msg(
+                "Announcements must be a single, non-empty line",
+            ),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+macro_rules! allow_registration {
+    ($message_type:ty) => {
+        impl<C: 'static> Handler<Registration<$message_type>> for World<C> {
+            type Result = ();
+
+            fn handle(
+                &mut self,
+                msg: Registration<$message_type>,
+                _ctx: &mut Self::Context,
+            ) {
+                msg.apply(&mut self.hooks);
+            }
+        }
+    };
+}
+
+allow_registration!(RawMessage);
+allow_registration!(Connected);
+allow_registration!(Reconnected);
+allow_registration!(Ready);
+allow_registration!(PrivateMessageReceived);
+allow_registration!(ChatEvent);
+allow_registration!(CommandReceived);
+allow_registration!(ActionReceived);
+allow_registration!(CircuitOpened);
+allow_registration!(CircuitClosed);
+allow_registration!(JoinFailed);
+allow_registration!(JoinDeferred);
+allow_registration!(NickInvalid);
+allow_registration!(NickInUse);
+allow_registration!(NickCollision);
+allow_registration!(UserJoined);
+allow_registration!(UserParted);
+allow_registration!(UserKicked);
+allow_registration!(UserQuit);
+allow_registration!(TopicReply);
+allow_registration!(TopicChanged);
+allow_registration!(ServerNotice);
+allow_registration!(ServerError);
+allow_registration!(NoticeReceived);
+allow_registration!(NickOnline);
+allow_registration!(NickOffline);
+allow_registration!(Latency);
+allow_registration!(RealNameChanged);
+allow_registration!(HostChanged);
+allow_registration!(NickChanged);
+allow_registration!(WhoIsReply);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix::actors::mocker::Mocker;
+    use actix::{Arbiter, MessageResult, System};
+    use crate::messages::LeaveAndStop;
+    use crate::settings::ReplyTemplates;
+    use futures::future::{self, Future};
+    use futures::Stream;
+    use irc::proto::message::Tag;
+    use irc::proto::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::utils::MockClient;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use failure::Backtrace;
+
+    #[derive(Debug, Clone, Message)]
+    struct DummyMessage;
+
+    impl<C: 'static> Handler<DummyMessage> for World<C> {
+        type Result = ();
+
+        fn handle(&mut self, msg: DummyMessage, _ctx: &mut Self::Context) {
+            Arbiter::spawn(
+                self.hooks
+                    .do_send(msg)
+                    .for_each(|_| future::ok(()))
+                    .map_err(|e| panic!("{}", e)),
+            );
+        }
+    }
+
+    impl<C: 'static> Handler<Registration<DummyMessage>> for World<C> {
+        type Result = ();
+
+        fn handle(
+            &mut self,
+            msg: Registration<DummyMessage>,
+            _ctx: &mut Self::Context,
         ) {
             msg.apply(&mut self.hooks);
         }
     }
 
-    struct Sub<M> {
-        received: Arc<Mutex<Vec<M>>>,
+    struct GetState;
+
+    impl Message for GetState {
+        type Result = ConnectionState;
+    }
+
+    impl<C: 'static> Handler<GetState> for World<C> {
+        type Result = MessageResult<GetState>;
+
+        fn handle(
+            &mut self,
+            _msg: GetState,
+            _ctx: &mut Self::Context,
+        ) -> Self::Result {
+            MessageResult(self.state)
+        }
+    }
+
+    struct GetChannel(&'static str);
+
+    impl Message for GetChannel {
+        type Result = Option<Addr<Channel>>;
+    }
+
+    impl<C: 'static> Handler<GetChannel> for World<C> {
+        type Result = MessageResult<GetChannel>;
+
+        fn handle(
+            &mut self,
+            msg: GetChannel,
+            _ctx: &mut Self::Context,
+        ) -> Self::Result {
+            MessageResult(self.channels.get(msg.0).cloned())
+        }
+    }
+
+    struct Sub<M> {
+        received: Arc<Mutex<Vec<M>>>,
+    }
+
+    impl<M: 'static> Sub<M> {
+        pub fn new() -> (Addr<Sub<M>>, Arc<Mutex<Vec<M>>>) {
+            let received = Arc::new(Mutex::new(Vec::new()));
+            let sub = Sub {
+                received: Arc::clone(&received),
+            };
+            (sub.start(), received)
+        }
+    }
+
+    impl<M: 'static> Actor for Sub<M> {
+        type Context = Context<Sub<M>>;
+    }
+
+    impl<M> Handler<M> for Sub<M>
+    where
+        M: Message<Result = ()> + 'static,
+    {
+        type Result = ();
+
+        fn handle(&mut self, msg: M, _ctx: &mut Self::Context) {
+            self.received.lock().unwrap().push(msg);
+
+            System::current().stop();
+        }
+    }
+
+    #[test]
+    fn register_and_receive_messages() {
+        let mut sys = System::new("test");
+        let world = World::new("this-is-a-client").start();
+        let calls = Arc::new(AtomicUsize::default());
+        let calls_2 = Arc::clone(&calls);
+
+        let mock: Addr<Mocker<DummyMessage>> =
+            Mocker::mock(Box::new(move |msg, _ctx| {
+                assert!(msg.downcast_ref::<DummyMessage>().is_some());
+                calls_2.fetch_add(1, Ordering::SeqCst);
+                System::current().stop();
+                Box::new(Some(<DummyMessage as Message>::Result::default()))
+            }))
+            .start();
+
+        // tell the world we want to register for DummyMessages
+        let msg: Registration<DummyMessage> =
+            Registration::register(mock.clone().recipient());
+        sys.block_on(world.send(msg)).unwrap();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        // then send a message and wait for it to arrive
+        world.do_send(DummyMessage);
+        assert_eq!(sys.run(), 0);
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn raw_messages_are_forwarded_to_subscribers() {
+        let mut sys = System::new("test");
+        let world = World::new(MockClient::default()).start();
+        let (sub, got) = Sub::<RawMessage>::new();
+
+        sys.block_on(world.send(Registration::for_actor(sub.clone(), true)))
+            .unwrap();
+
+        let msg = RawMessage(IrcMessage::from(Command::INFO(None)));
+        world.do_send(msg.clone());
+        assert_eq!(sys.run(), 0);
+
+        let got = got.lock().unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0], msg);
+    }
+
+    #[test]
+    fn interceptors_filter_and_tag_raw_messages() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<RawMessage>::new();
+        world.hooks.register(sub.recipient());
+        world.incoming.push(Box::new(|msg: &IrcMessage| {
+            match msg.source_nickname() {
+                Some("spammer") => InboundDecision::Drop,
+                _ => InboundDecision::ProcessTagged(vec![Tag(
+                    String::from("checked"),
+                    None,
+                )]),
+            }
+        }));
+        let world = world.start();
+
+        for msg in &[
+            ":spammer!spam@example.com PRIVMSG #rust :buy now",
+            ":Michael!mike@example.com PRIVMSG #rust :hi",
+        ] {
+            world.do_send(RawMessage(msg.parse().unwrap()));
+        }
+        assert_eq!(sys.run(), 0);
+
+        let got = got.lock().unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].0.source_nickname(), Some("Michael"));
+        assert_eq!(
+            got[0].0.tags,
+            Some(vec![Tag(String::from("checked"), None)])
+        );
+    }
+
+    #[test]
+    fn direct_messages_become_chat_events() {
+        let mut sys = System::new("test");
+        let world = World::new(MockClient::default()).start();
+        let (sub, got) = Sub::<ChatEvent>::new();
+
+        sys.block_on(world.send(Registration::for_actor(sub.clone(), true)))
+            .unwrap();
+
+        let welcome: IrcMessage =
+            ":irc.example.com 001 bot :Welcome".parse().unwrap();
+        world.do_send(RawMessage(welcome));
+        let privmsg: IrcMessage = "@account=michael;time=2018-10-16T01:02:03.000Z \
+                                   :Michael!mike@example.com PRIVMSG bot :hi bot"
+            .parse()
+            .unwrap();
+        world.do_send(RawMessage(privmsg.clone()));
+        assert_eq!(sys.run(), 0);
+
+        let got = got.lock().unwrap();
+        assert_eq!(got.len(), 1);
+        let event = &got[0];
+        assert_eq!(event.sender, Some(String::from("Michael")));
+        assert_eq!(
+            event.hostmask,
+            Some(String::from("Michael!mike@example.com"))
+        );
+        assert_eq!(event.account, Some(String::from("michael")));
+        assert!(event.is_private());
+        assert!(event.highlighted);
+        assert_eq!(event.status, None);
+        assert_eq!(
+            event.timestamp,
+            Some(String::from("2018-10-16T01:02:03.000Z"))
+        );
+        assert_eq!(event.content, "hi bot");
+        assert!(!event.is_action);
+        assert_eq!(event.raw, privmsg);
+    }
+
+    #[test]
+    fn actions_are_published() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<ActionReceived>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let action: IrcMessage =
+            ":Michael!mike@example.com PRIVMSG #rust :\u{1}ACTION waves\u{1}"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(action.clone()));
+        assert_eq!(sys.run(), 0);
+
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![ActionReceived {
+                action: String::from("waves"),
+                channel: Some(String::from("#rust")),
+                reply_to: String::from("#rust"),
+                sender: Some(String::from("Michael")),
+                raw: action,
+            }]
+        );
+    }
+
+    #[test]
+    fn actions_become_chat_events() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<ChatEvent>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let action: IrcMessage =
+            ":Michael!mike@example.com PRIVMSG bot :\u{1}ACTION waves\u{1}"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(action));
+        assert_eq!(sys.run(), 0);
+
+        let got = got.lock().unwrap();
+        assert_eq!(got.len(), 1);
+        assert!(got[0].is_action);
+        assert!(got[0].is_private());
+        assert_eq!(got[0].sender, Some(String::from("Michael")));
+        assert_eq!(got[0].content, "waves");
+    }
+
+    #[test]
+    fn summarize_each_channel() {
+        let mut sys = System::new("test");
+        let mut world = World::new("asd");
+        for name in &["#rust", "#actix"] {
+            world
+                .channels
+                .insert(name.to_string(), Channel::new(*name).start());
+        }
+        let world = world.start();
+
+        let got = sys.block_on(world.send(ChannelSummaries)).unwrap().unwrap();
+
+        let names: Vec<_> = got.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["#actix", "#rust"]);
+        assert!(got.iter().all(|s| s.member_count == 0));
+    }
+
+    #[test]
+    fn refuse_to_message_unjoined_channels_when_configured() {
+        let _sys = System::new("test");
+        let settings = Settings {
+            unjoined_channels: UnjoinedChannelPolicy::Error,
+            ..Default::default()
+        };
+        let mut world = World::new_with_settings(
+            "asd",
+            Logger::root(Discard, o!()),
+            settings,
+        );
+        world
+            .channels
+            .insert(String::from("#rust"), Channel::new("#Rust").start());
+
+        assert!(world.check_joined("#rust").is_ok());
+        assert!(world.check_joined("#RUST").is_ok());
+        assert!(world.check_joined("Michael-F-Bryan").is_ok());
+        assert!(world.check_joined("#not-joined").is_err());
+    }
+
+    #[test]
+    fn join_failures_are_published() {
+        let inputs = vec![
+            ("471", JoinFailure::ChannelIsFull),
+            ("473", JoinFailure::InviteOnly),
+            ("474", JoinFailure::Banned),
+            ("475", JoinFailure::BadChannelKey),
+        ];
+
+        for (numeric, reason) in inputs {
+            let sys = System::new("test");
+            let mut world = World::new("asd");
+            world
+                .channels
+                .insert(String::from("#rust"), Channel::new("#Rust").start());
+            let (sub, got) = Sub::<JoinFailed>::new();
+            world.hooks.register(sub.recipient());
+
+            let raw = format!(
+                ":irc.example.com {} bot #Rust :Cannot join channel",
+                numeric
+            );
+            world.check_join_failure(&raw.parse().unwrap());
+
+            assert!(world.channels.is_empty(), "{}", numeric);
+            assert_eq!(sys.run(), 0);
+            let got = got.lock().unwrap();
+            assert_eq!(
+                *got,
+                vec![JoinFailed {
+                    channel: String::from("#Rust"),
+                    reason,
+                    message: Some(String::from("Cannot join channel")),
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn retry_registered_only_channels_once_identified() {
+        let sys = System::new("test");
+        let client = MockClient::default();
+        let mut world = World::new(client.clone());
+        world
+            .channels
+            .insert(String::from("#rust"), Channel::new("#rust").start());
+        let (sub, got) = Sub::<JoinDeferred>::new();
+        world.hooks.register(sub.recipient());
+
+        let refused: IrcMessage =
+            ":irc.example.com 477 bot #rust :You need to be identified"
+                .parse()
+                .unwrap();
+        world.check_join_failure(&refused);
+
+        assert!(world.channels.is_empty());
+        assert_eq!(sys.run(), 0);
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![JoinDeferred {
+                channel: String::from("#rust"),
+                reason: Some(String::from("You need to be identified")),
+            }]
+        );
+
+        // then identify, and we should try again
+        let mut sys = System::new("test");
+        let world = world.start();
+        sys.block_on(world.send(Identified {
+            service: String::from("NickServ"),
+        }))
+        .unwrap();
+        sys.block_on(world.send(GetStats)).unwrap();
+
+        assert_eq!(
+            *client.sent.lock().unwrap(),
+            vec![IrcMessage::from(Command::JOIN(
+                String::from("#rust"),
+                None,
+                None
+            ))]
+        );
+    }
+
+    #[test]
+    fn nick_changes_are_confirmed_by_the_server() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        world.current_nick = Some(String::from("bot"));
+        let (tx, rx) = oneshot::channel();
+        world.pending_nick = Some(PendingNick {
+            nick: String::from("new-bot"),
+            tx,
+        });
+        let world = world.start();
+
+        let rejected: IrcMessage =
+            ":irc.example.com 433 bot other-bot :Nickname is already in use"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(rejected));
+        let confirmed: IrcMessage =
+            ":bot!bot@example.com NICK new-bot".parse().unwrap();
+        world.do_send(RawMessage(confirmed));
+
+        let got = sys.block_on(rx).unwrap();
+        assert!(got.is_ok());
+    }
+
+    #[test]
+    fn rejected_nick_changes_are_errors() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        world.current_nick = Some(String::from("bot"));
+        let (tx, rx) = oneshot::channel();
+        world.pending_nick = Some(PendingNick {
+            nick: String::from("new-bot"),
+            tx,
+        });
+        let world = world.start();
+
+        let rejected: IrcMessage =
+            ":irc.example.com 433 bot new-bot :Nickname is already in use"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(rejected));
+
+        let got = sys.block_on(rx).unwrap();
+        assert!(got.is_err());
+    }
+
+    #[test]
+    fn reclaim_our_nick_from_a_ghost() {
+        let mut sys = System::new("test");
+        let client = MockClient {
+            config: irc::client::data::Config {
+                nickname: Some(String::from("bot")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let sent = Arc::clone(&client.sent);
+        let mut world = World::new(client);
+        world.current_nick = Some(String::from("bot_"));
+        let world = world.start();
+
+        world.do_send(Identified {
+            service: String::from("NickServ"),
+        });
+        let quit: IrcMessage =
+            ":bot!bot@example.com QUIT :Disconnected by services"
+                .parse()
+                .unwrap();
+        sys.block_on(world.send(RawMessage(quit))).unwrap();
+
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![
+                IrcMessage::from(Command::PRIVMSG(
+                    String::from("NickServ"),
+                    String::from("GHOST bot")
+                )),
+                IrcMessage::from(Command::NICK(String::from("bot"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_nicks_are_sanitized_while_registering() {
+        let sys = System::new("test");
+        let client = MockClient::default();
+        let mut world = World::new(client.clone());
+        let (sub, got) = Sub::<NickInvalid>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let rejected: IrcMessage =
+            ":irc.example.com 432 * my.bot :Erroneous Nickname"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(rejected));
+
+        assert_eq!(sys.run(), 0);
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![NickInvalid {
+                nick: String::from("my.bot"),
+                reason: Some(String::from("Erroneous Nickname")),
+            }]
+        );
+        assert_eq!(
+            *client.sent.lock().unwrap(),
+            vec![IrcMessage::from(Command::NICK(String::from("mybot")))]
+        );
+    }
+
+    #[test]
+    fn taken_nicks_get_a_fallback_while_registering() {
+        let sys = System::new("test");
+        let client = MockClient::default();
+        let mut world = World::new(client.clone());
+        let (sub, got) = Sub::<NickInUse>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let rejected: IrcMessage =
+            ":irc.example.com 433 * bot :Nickname is already in use"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(rejected));
+
+        assert_eq!(sys.run(), 0);
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![NickInUse {
+                nick: String::from("bot"),
+            }]
+        );
+        assert_eq!(
+            *client.sent.lock().unwrap(),
+            vec![IrcMessage::from(Command::NICK(String::from("bot_")))]
+        );
+    }
+
+    #[test]
+    fn collect_the_channel_list() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (tx, rx) = oneshot::channel();
+        world.pending_list = Some(PendingList {
+            started: Instant::now(),
+            channels: Vec::new(),
+            waiting: vec![tx],
+        });
+        let world = world.start();
+
+        let replies = vec![
+            ":irc.example.com 321 bot Channel :Users  Name",
+            ":irc.example.com 322 bot #rust 1234 :The Rust Programming Language",
+            ":irc.example.com 322 bot #actix 56 :",
+            ":irc.example.com 323 bot :End of /LIST",
+        ];
+        for reply in replies {
+            world.do_send(reply.parse::<RawMessage>().unwrap());
+        }
+
+        let got = sys.block_on(rx).unwrap().unwrap();
+        assert_eq!(
+            got,
+            vec![
+                (
+                    String::from("#rust"),
+                    1234,
+                    String::from("The Rust Programming Language")
+                ),
+                (String::from("#actix"), 56, String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn labeled_echoes_confirm_delivery() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        world.current_nick = Some(String::from("bot"));
+        let (tx, rx) = oneshot::channel();
+        world.pending_echoes.insert(String::from("42"), tx);
+        let world = world.start();
+
+        let echo: IrcMessage =
+            "@label=42 :bot!bot@example.com PRIVMSG #rust :hi"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(echo));
+
+        let got = sys.block_on(rx).unwrap();
+        assert!(got.is_ok());
+    }
+
+    #[test]
+    fn long_replies_are_capped() {
+        let _sys = System::new("test");
+        let settings = Settings {
+            max_reply_lines: 2,
+            long_replies: LongReplyPolicy::DirectMessage,
+            ..Default::default()
+        };
+        let world = World::new_with_settings(
+            "asd",
+            Logger::root(Discard, o!()),
+            settings,
+        );
+        let reply = |to: &str, lines: usize, requester: Option<&str>| {
+            world.reply_destination(&ReplyLines {
+                to: to.to_string(),
+                lines: vec![String::from("line"); lines],
+                requester: requester.map(String::from),
+            })
+        };
+
+        assert_eq!(reply("#rust", 2, Some("Michael")).unwrap(), "#rust");
+        assert_eq!(reply("#rust", 3, Some("Michael")).unwrap(), "Michael");
+        assert!(reply("#rust", 3, None).is_err());
+        assert_eq!(reply("Michael", 3, None).unwrap(), "Michael");
+        assert!(reply("Michael", MAX_REPLY_LINES + 1, None).is_err());
+    }
+
+    #[test]
+    fn refuse_long_replies_by_default() {
+        let _sys = System::new("test");
+        let world = World::new("asd");
+        let msg = ReplyLines {
+            to: String::from("#rust"),
+            lines: vec![String::from("line"); 10],
+            requester: Some(String::from("Michael")),
+        };
+
+        assert!(world.reply_destination(&msg).is_err());
+    }
+
+    #[test]
+    fn our_own_join_confirms_the_channel() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let mut world = World::new(client.clone());
+        world.current_nick = Some(String::from("bot"));
+        world
+            .channels
+            .insert(String::from("#rust"), Channel::new("#rust").start());
+        let world = world.start();
+
+        let join: IrcMessage =
+            ":bot!bot@example.com JOIN #rust".parse().unwrap();
+        world.do_send(RawMessage(join));
+        let got = sys.block_on(world.send(ChannelSummaries)).unwrap().unwrap();
+
+        assert_eq!(got.len(), 1);
+        assert!(got[0].joined);
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(
+            *sent,
+            vec![IrcMessage::from(Command::WHO(
+                Some(String::from("#rust")),
+                None
+            ))]
+        );
+    }
+
+    #[test]
+    fn announce_to_every_channel() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let mut world = World::new(client.clone());
+        for name in &["#rust", "#irc"] {
+            world
+                .channels
+                .insert(name.to_string(), Channel::new(*name).start());
+        }
+        let world = world.start();
+
+        let got = sys
+            .block_on(world.send(AnnounceAll {
+                content: String::from("Restarting in 5 minutes"),
+            }))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(got, 2);
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(
+            *sent,
+            vec![
+                IrcMessage::from(Command::NOTICE(
+                    String::from("#irc"),
+                    String::from("Restarting in 5 minutes")
+                )),
+                IrcMessage::from(Command::NOTICE(
+                    String::from("#rust"),
+                    String::from("Restarting in 5 minutes")
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_announce_to_channels_we_moderate() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let mut world = World::new(client.clone());
+        world.current_nick = Some(String::from("bot"));
+        for (name, us) in &[("#rust", "@bot"), ("#irc", "+bot")] {
+            let channel = Channel::new(*name).start();
+            channel.do_send(NamesReply {
+                names: vec![us.to_string(), String::from("Michael")],
+            });
+            world.channels.insert(name.to_string(), channel);
+        }
+        let world = world.start();
+
+        let got = sys
+            .block_on(world.send(ModAnnounce {
+                content: String::from("Please keep it civil"),
+            }))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(got, 1);
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(
+            *sent,
+            vec![IrcMessage::from(Command::NOTICE(
+                String::from("#rust"),
+                String::from("Please keep it civil")
+            ))]
+        );
+    }
+
+    #[test]
+    fn leaving_a_channel_stops_its_actor() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let world = World::new(client.clone()).start();
+
+        sys.block_on(world.send(Join::new("#rust")))
+            .unwrap()
+            .unwrap();
+        let channel = sys.block_on(world.send(GetChannel("#rust"))).unwrap();
+        let channel = channel.expect("The channel should be tracked");
+
+        sys.block_on(channel.send(LeaveAndStop {
+            reason: Some(String::from("Bye")),
+        }))
+        .unwrap();
+
+        assert!(sys.block_on(channel.send(Summarize)).is_err());
+        let got = sys.block_on(world.send(GetChannel("#rust"))).unwrap();
+        assert!(got.is_none());
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(
+            sent.last(),
+            Some(&IrcMessage::from(Command::PART(
+                String::from("#rust"),
+                Some(String::from("Bye"))
+            )))
+        );
+    }
+
+    #[test]
+    fn leave_channels_once_everyone_else_has_gone() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let settings = Settings {
+            leave_when_empty: vec![String::from("#rust")],
+            ..Default::default()
+        };
+        let mut world = World::new_with_settings(
+            client.clone(),
+            Logger::root(Discard, o!()),
+            settings,
+        );
+        world.current_nick = Some(String::from("bot"));
+        let world = world.start();
+
+        sys.block_on(world.send(Join::new("#rust")))
+            .unwrap()
+            .unwrap();
+        let names: IrcMessage =
+            ":irc.example.com 353 bot = #rust :bot @Michael"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(names));
+        let part: IrcMessage =
+            ":Michael!mike@example.com PART #rust".parse().unwrap();
+        world.do_send(RawMessage(part));
+
+        // leaving takes a couple round trips between the world and channel
+        let mut still_there = true;
+        for _ in 0..10 {
+            let got = sys.block_on(world.send(GetChannel("#rust"))).unwrap();
+            still_there = got.is_some();
+            if !still_there {
+                break;
+            }
+        }
+
+        assert!(!still_there);
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(
+            sent.last(),
+            Some(&IrcMessage::from(Command::PART(
+                String::from("#rust"),
+                Some(String::from("Nobody else is here"))
+            )))
+        );
+    }
+
+    #[test]
+    fn quit_messages_fill_in_placeholders() {
+        let sys = System::new("test");
+        let client = MockClient::default();
+        let settings = Settings {
+            quit_message: String::from("irc_bot v{version}, up {uptime}"),
+            ..Default::default()
+        };
+        let world = World::new_with_settings(
+            client.clone(),
+            Logger::root(Discard, o!()),
+            settings,
+        )
+        .start();
+
+        world.do_send(Quit::default());
+
+        assert_eq!(sys.run(), 0);
+        let should_be =
+            format!("irc_bot v{}, up 0s", env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            *client.sent.lock().unwrap(),
+            vec![IrcMessage::from(Command::QUIT(Some(should_be)))]
+        );
+    }
+
+    #[test]
+    fn fatal_errors_exit_with_a_non_zero_code() {
+        let sys = System::new("test");
+        let client = MockClient::default();
+        let world = World::new(client.clone()).start();
+
+        world.do_send(
+            Quit::new("Oops")
+                .because(ShutdownReason::Fatal(String::from("Broken"))),
+        );
+        // only the first shutdown counts
+        world.do_send(Quit::default());
+
+        assert_eq!(sys.run(), 1);
+        assert_eq!(
+            *client.sent.lock().unwrap(),
+            vec![IrcMessage::from(Command::QUIT(Some(String::from("Oops"))))]
+        );
+    }
+
+    #[test]
+    fn give_up_waiting_for_the_server_to_hang_up() {
+        let sys = System::new("test");
+        let client = MockClient::default();
+        let settings = Settings {
+            shutdown_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let mut world = World::new_with_settings(
+            client.clone(),
+            Logger::root(Discard, o!()),
+            settings,
+        );
+        // pretend we're connected, the server never closes the connection
+        world.listening = true;
+        let world = world.start();
+
+        world.do_send(Quit::new("Bye"));
+        let started = Instant::now();
+
+        assert_eq!(sys.run(), 0);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            *client.sent.lock().unwrap(),
+            vec![IrcMessage::from(Command::QUIT(Some(String::from("Bye"))))]
+        );
+    }
+
+    #[test]
+    fn server_notices_are_distinct_from_user_notices() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<ServerNotice>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let from_user: IrcMessage =
+            ":Michael!mike@example.com NOTICE bot :hi".parse().unwrap();
+        world.do_send(RawMessage(from_user));
+        let from_server: IrcMessage =
+            ":irc.example.com NOTICE * :*** Looking up your hostname"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(from_server));
+
+        assert_eq!(sys.run(), 0);
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![ServerNotice {
+                from: String::from("irc.example.com"),
+                content: String::from("*** Looking up your hostname"),
+            }]
+        );
+    }
+
+    #[test]
+    fn publish_errors_from_the_server() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<ServerError>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let error: IrcMessage =
+            "ERROR :Closing Link: bot[example.com] (Ping timeout)"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(error));
+
+        assert_eq!(sys.run(), 0);
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![ServerError {
+                message: String::from(
+                    "Closing Link: bot[example.com] (Ping timeout)"
+                ),
+            }]
+        );
+    }
+
+    #[test]
+    fn give_up_reconnecting_after_too_many_attempts() {
+        let sys = System::new("test");
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let policy = ReconnectPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(0),
+        };
+        let counter = Arc::clone(&attempts);
+        let world = World::new(MockClient::default()).with_reconnect(
+            policy,
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err(failure::err_msg("The server is down"))
+            },
+        );
+        let _world = World::create(move |ctx| {
+            // a connection which the server closes straight away
+            ctx.add_stream(futures::stream::empty::<IrcMessage, IrcError>());
+            world
+        });
+
+        assert_eq!(sys.run(), 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn stuck_registrations_reconnect() {
+        let sys = System::new("test");
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let policy = ReconnectPolicy {
+            max_retries: 1,
+            base_delay: Duration::from_millis(0),
+        };
+        let counter = Arc::clone(&attempts);
+        let mut world = World::new(MockClient::default()).with_reconnect(
+            policy,
+            move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Err(failure::err_msg("The server is down"))
+            },
+        );
+        world.settings.registration_timeout = Some(Duration::from_millis(0));
+
+        let _world = World::create(move |ctx| {
+            // a connection which never says anything
+            let silent = futures::stream::poll_fn(
+                || -> futures::Poll<Option<IrcMessage>, IrcError> {
+                    Ok(futures::Async::NotReady)
+                },
+            );
+            world.connection = Some(ctx.add_stream(silent));
+            world.listening = true;
+
+            world.check_registration(ctx);
+            assert!(!world.listening);
+            assert!(world.shutdown_reason.is_none());
+            world
+        });
+
+        // we only give up once reconnecting fails too
+        assert_eq!(sys.run(), 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn losing_the_connection_fails_pending_requests() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let policy = ReconnectPolicy {
+            max_retries: 1,
+            base_delay: Duration::from_secs(60),
+        };
+        let mut world = World::new(client)
+            .with_reconnect(policy, || Err(failure::err_msg("Not yet")));
+        world.current_nick = Some(String::from("bot"));
+        let (tx, rx) = futures::sync::mpsc::unbounded::<IrcMessage>();
+        let world = World::create(move |ctx| {
+            ctx.add_stream(rx.map_err(|_| -> IrcError { unreachable!() }));
+            world
+        });
+
+        let lookup = world.send(LookupAccount {
+            nick: String::from("Michael"),
+        });
+        let list = world.send(ListChannels { filter: None });
+        sys.block_on(world.send(GetClockSkew)).unwrap();
+        assert_eq!(sent.lock().unwrap().len(), 2);
+
+        // the server hangs up before answering
+        drop(tx);
+
+        assert!(sys.block_on(lookup).unwrap().is_err());
+        assert!(sys.block_on(list).unwrap().is_err());
+    }
+
+    #[test]
+    fn count_the_messages_waiting_to_be_handled() {
+        let mut sys = System::new("test");
+        let world = World::new(MockClient::default());
+        let (tx, rx) = futures::sync::mpsc::unbounded::<IrcMessage>();
+        let world = World::create(move |ctx| {
+            ctx.add_stream(rx.map_err(|_| -> IrcError { unreachable!() }));
+            world
+        });
+
+        for _ in 0..3 {
+            let notice = ":irc.example.com NOTICE * :hi".parse().unwrap();
+            tx.unbounded_send(notice).unwrap();
+        }
+
+        // the stream is only polled after the mailbox, so it may take a
+        // couple of round trips before everything has been handled
+        let mut stats = sys.block_on(world.send(GetStats)).unwrap();
+        for _ in 0..10 {
+            if stats.mailbox.handled == 3 {
+                break;
+            }
+            stats = sys.block_on(world.send(GetStats)).unwrap();
+        }
+        assert_eq!(stats.mailbox.received, 3);
+        assert_eq!(stats.mailbox.handled, 3);
+        assert_eq!(stats.mailbox.backlog, 0);
+        assert_eq!(stats.mailbox.last_delay, None);
+
+        let probe = MailboxProbe {
+            sent: Instant::now(),
+        };
+        let got = sys.block_on(world.send(probe)).unwrap();
+        assert_eq!(got.received, 3);
+        assert!(got.last_delay.is_some());
+
+        let stats = sys.block_on(world.send(GetStats)).unwrap();
+        assert_eq!(stats.mailbox, got);
+    }
+
+    #[test]
+    fn notices_from_users_are_published() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        world.current_nick = Some(String::from("bot"));
+        let (sub, got) = Sub::<NoticeReceived>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let messages = vec![
+            ":irc.example.com NOTICE * :*** Looking up your hostname",
+            ":bot!bot@example.com NOTICE #rust :echoed back to us",
+            ":Michael!mike@example.com NOTICE bot :hi",
+        ];
+        for msg in messages {
+            world.do_send(RawMessage(msg.parse().unwrap()));
+        }
+        assert_eq!(sys.run(), 0);
+
+        let got = got.lock().unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].msg_target, "bot");
+        assert_eq!(got[0].content, "hi");
+        assert_eq!(got[0].raw.source_nickname(), Some("Michael"));
+    }
+
+    #[test]
+    fn only_opers_can_send_wallops() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let mut world = World::new(client.clone());
+        world.current_nick = Some(String::from("bot"));
+        let world = world.start();
+        let wallops = || Wallops {
+            content: String::from("Restarting services"),
+        };
+
+        let got = sys.block_on(world.send(wallops())).unwrap();
+        assert!(got.is_err());
+
+        let oper: IrcMessage =
+            ":irc.example.com 381 bot :You are now an IRC operator"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(oper));
+        let got = sys.block_on(world.send(wallops())).unwrap();
+
+        assert!(got.is_ok());
+        assert_eq!(
+            client.sent.lock().unwrap().last(),
+            Some(&IrcMessage::from(Command::WALLOPS(String::from(
+                "Restarting services"
+            ))))
+        );
+    }
+
+    #[test]
+    fn channels_remember_their_topic() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        world
+            .channels
+            .insert(String::from("#rust"), Channel::new("#rust").start());
+        let world = world.start();
+
+        let topic: IrcMessage =
+            ":irc.example.com 332 bot #Rust :The Rust Programming Language"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(topic));
+        let got = sys.block_on(world.send(ChannelSummaries)).unwrap().unwrap();
+
+        assert_eq!(
+            got[0].topic,
+            Some(String::from("The Rust Programming Language"))
+        );
+    }
+
+    #[test]
+    fn track_the_connection_state() {
+        let mut sys = System::new("test");
+        let world = World::new(MockClient::default());
+        assert_eq!(world.state, ConnectionState::Connecting);
+        let world = world.start();
+
+        let notice: IrcMessage =
+            ":irc.example.com NOTICE * :*** Looking up your hostname"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(notice));
+        let welcome: IrcMessage =
+            ":irc.example.com 001 bot :Welcome".parse().unwrap();
+        world.do_send(RawMessage(welcome));
+
+        let got = sys.block_on(world.send(GetState)).unwrap();
+        assert_eq!(got, ConnectionState::Registered);
+    }
+
+    #[test]
+    fn deferred_actions_run_once_registered() {
+        let mut sys = System::new("test");
+        let world = World::new(MockClient::default()).start();
+        let count = Arc::new(AtomicUsize::new(0));
+        let increment = || {
+            let count = Arc::clone(&count);
+            WhenReady::new(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+
+        world.do_send(increment());
+        sys.block_on(world.send(GetState)).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+
+        let welcome: IrcMessage =
+            ":irc.example.com 001 bot :Welcome".parse().unwrap();
+        world.do_send(RawMessage(welcome));
+        sys.block_on(world.send(GetState)).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // and straight away if we're already registered
+        world.do_send(increment());
+        sys.block_on(world.send(GetState)).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn ctcp_replies_are_matched_to_requests() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (tx, rx) = oneshot::channel();
+        world.pending_ctcp.insert(
+            (String::from("michael"), String::from("VERSION")),
+            vec![PendingCtcp {
+                sent: Instant::now(),
+                tx,
+            }],
+        );
+        let world = world.start();
+
+        let reply: IrcMessage =
+            ":Michael!mike@example.com NOTICE bot :\u{1}VERSION irssi 1.1\u{1}"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(reply));
+
+        let got = sys.block_on(rx).unwrap();
+        assert_eq!(got, Some(String::from("irssi 1.1")));
+    }
+
+    #[test]
+    fn only_survive_panics_from_isolated_plugins() {
+        let mut world = World::new(MockClient::default());
+        let home = thread::current().name().map(String::from);
+        let unknown = Some(String::from("arbiter:1234:admin"));
+
+        assert!(world.panicked_plugin(home.as_ref()).is_none());
+        assert!(world.panicked_plugin(unknown.as_ref()).is_none());
+        assert!(world.panicked_plugin(None).is_none());
+
+        world.settings.plugin_panics = PanicPolicy::Isolate;
+
+        assert!(world.panicked_plugin(home.as_ref()).is_none());
+        assert!(world.panicked_plugin(unknown.as_ref()).is_none());
+        assert!(world.panicked_plugin(None).is_none());
+    }
+
+    struct WhereAmI;
+
+    impl Message for WhereAmI {
+        type Result = Option<String>;
+    }
+
+    struct Isolated;
+
+    impl Actor for Isolated {
+        type Context = Context<Isolated>;
+    }
+
+    impl Handler<WhereAmI> for Isolated {
+        type Result = Option<String>;
+
+        fn handle(
+            &mut self,
+            _: WhereAmI,
+            _: &mut Self::Context,
+        ) -> Self::Result {
+            thread::current().name().map(String::from)
+        }
+    }
+
+    impl Handler<DummyMessage> for Isolated {
+        type Result = ();
+
+        fn handle(&mut self, _: DummyMessage, _: &mut Self::Context) {}
+    }
+
+    #[test]
+    fn forget_isolated_plugins_which_panic() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        world.settings.plugin_panics = PanicPolicy::Isolate;
+        let world = world.start();
+
+        let plugin = utils::start_isolated("pinger", Isolated);
+        world.do_send(
+            Registration::<DummyMessage>::register(plugin.clone().recipient())
+                .from_plugin("pinger"),
+        );
+        let thread = sys.block_on(plugin.send(WhereAmI)).unwrap();
+        assert_eq!(
+            utils::isolated_plugin(thread.as_ref().unwrap()),
+            Some(String::from("pinger"))
+        );
+        let plugins = sys.block_on(world.send(ListPlugins)).unwrap();
+        assert_eq!(plugins.len(), 1);
+
+        world.do_send(Panic {
+            message: String::from("Oops"),
+            file: String::from(file!()),
+            line: line!(),
+            column: column!(),
+            thread,
+            backtrace: Backtrace::new(),
+        });
+
+        let plugins = sys.block_on(world.send(ListPlugins)).unwrap();
+        assert!(plugins.is_empty());
+        assert!(world.connected());
+    }
+
+    #[test]
+    fn monitor_nicks_within_the_servers_limit() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let world = World::new(client.clone()).start();
+        let monitor = |nicks: &[&str]| Monitor {
+            add: nicks.iter().map(|n| n.to_string()).collect(),
+            remove: Vec::new(),
+        };
+
+        let got = sys.block_on(world.send(monitor(&["Michael"]))).unwrap();
+        assert!(got.is_err(), "MONITOR hasn't been advertised yet");
+
+        let isupport: IrcMessage =
+            ":irc.example.com 005 bot MONITOR=2 :are supported by this server"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(isupport));
+
+        let got = sys.block_on(world.send(monitor(&["Michael", "Bob"])));
+        assert!(got.unwrap().is_ok());
+        assert_eq!(
+            client.sent.lock().unwrap().last(),
+            Some(&IrcMessage::from(Command::MONITOR(
+                String::from("+"),
+                Some(String::from("Michael,Bob"))
+            )))
+        );
+
+        let got = sys.block_on(world.send(monitor(&["Alice"]))).unwrap();
+        assert!(got.is_err(), "We'd go over the limit");
+    }
+
+    #[test]
+    fn change_our_realname_once_setname_is_enabled() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let world = World::new(client.clone()).start();
+        let set_name = || SetRealName {
+            name: String::from("Status: busy"),
+        };
+
+        let got = sys.block_on(world.send(set_name())).unwrap();
+        assert!(got.is_err(), "setname hasn't been negotiated yet");
+
+        let ack: IrcMessage =
+            ":irc.example.com CAP bot ACK :setname".parse().unwrap();
+        world.do_send(RawMessage(ack));
+
+        sys.block_on(world.send(set_name())).unwrap().unwrap();
+        assert_eq!(
+            client.sent.lock().unwrap().last(),
+            Some(&IrcMessage::from(Command::Raw(
+                String::from("SETNAME"),
+                Vec::new(),
+                Some(String::from("Status: busy"))
+            )))
+        );
+    }
+
+    #[test]
+    fn host_changes_are_published() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<HostChanged>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let chghost: IrcMessage =
+            ":Michael!mike@example.com CHGHOST mike rust/contributor/michael"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(chghost));
+        sys.run();
+
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![HostChanged {
+                nick: String::from("Michael"),
+                new_user: String::from("mike"),
+                new_host: String::from("rust/contributor/michael"),
+            }]
+        );
+    }
+
+    #[test]
+    fn host_changes_update_the_cached_hostmask() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let channel = Channel::new("#rust").start();
+        channel.do_send(MemberJoined {
+            nick: String::from("Michael"),
+            hostmask: Some(String::from("Michael!mike@example.com")),
+        });
+        world.channels.insert(String::from("#rust"), channel);
+        let world = world.start();
+
+        let lookup = || LookupHostmask {
+            nick: String::from("michael"),
+        };
+        let before = sys.block_on(world.send(lookup())).unwrap();
+        let chghost: IrcMessage =
+            ":Michael!mike@example.com CHGHOST mike rust/contributor/michael"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(chghost));
+        let after = sys.block_on(world.send(lookup())).unwrap();
+
+        assert_eq!(before, Ok(Some(String::from("Michael!mike@example.com"))));
+        assert_eq!(
+            after,
+            Ok(Some(String::from("Michael!mike@rust/contributor/michael")))
+        );
+    }
+
+    #[test]
+    fn topic_changes_are_published() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<TopicChanged>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let topic: IrcMessage =
+            ":Michael!mike@example.com TOPIC #rust :Rust 1.31 is out!"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(topic));
+        assert_eq!(sys.run(), 0);
+
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![TopicChanged {
+                channel: String::from("#rust"),
+                topic: String::from("Rust 1.31 is out!"),
+                set_by: Some(String::from("Michael")),
+            }]
+        );
+    }
+
+    #[test]
+    fn channels_remember_their_latest_topic() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let channel = Channel::new("#rust").start();
+        world.channels.insert(String::from("#rust"), channel);
+        let world = world.start();
+
+        let get_topic = |channel: &str| GetTopic {
+            channel: channel.to_string(),
+        };
+        let messages = vec![
+            ":irc.example.com 332 bot #rust :The Rust Programming Language",
+            ":Michael!mike@example.com TOPIC #Rust :Rust 1.31 is out!",
+        ];
+        for msg in messages {
+            world.do_send(RawMessage(msg.parse().unwrap()));
+        }
+
+        let got = sys.block_on(world.send(get_topic("#RUST"))).unwrap();
+        assert_eq!(got, Ok(Some(String::from("Rust 1.31 is out!"))));
+        let got = sys.block_on(world.send(get_topic("#elsewhere"))).unwrap();
+        assert_eq!(got, Ok(None));
+    }
+
+    #[test]
+    fn quitting_leaves_every_channel() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        for name in &["#rust", "#home"] {
+            let channel = Channel::new(*name).start();
+            channel.do_send(NamesReply {
+                names: vec![String::from("@bot"), String::from("Michael")],
+            });
+            world.channels.insert(name.to_string(), channel);
+        }
+        let world = world.start();
+
+        let quit: IrcMessage =
+            ":Michael!mike@example.com QUIT :Bye".parse().unwrap();
+        world.do_send(RawMessage(quit));
+
+        for name in &["#rust", "#home"] {
+            let members = Members {
+                channel: name.to_string(),
+            };
+            let got = sys.block_on(world.send(members)).unwrap().unwrap();
+            assert_eq!(got.into_iter().collect::<Vec<_>>(), vec!["bot"]);
+        }
+        let count = MemberCount {
+            channel: String::from("#elsewhere"),
+        };
+        assert_eq!(sys.block_on(world.send(count)).unwrap(), Ok(0));
+    }
+
+    #[test]
+    fn nick_changes_are_published() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<NickChanged>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let nick: IrcMessage = ":Michael!mike@example.com NICK Michael|away"
+            .parse()
+            .unwrap();
+        world.do_send(RawMessage(nick));
+        assert_eq!(sys.run(), 0);
+
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![NickChanged {
+                old: String::from("Michael"),
+                new: String::from("Michael|away"),
+            }]
+        );
     }
 
-    impl<M: 'static> Sub<M> {
-        pub fn new() -> (Addr<Sub<M>>, Arc<Mutex<Vec<M>>>) {
-            let received = Arc::new(Mutex::new(Vec::new()));
-            let sub = Sub {
-                received: Arc::clone(&received),
+    #[test]
+    fn nick_changes_rename_channel_members() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let channel = Channel::new("#rust").start();
+        channel.do_send(NamesReply {
+            names: vec![String::from("@bot"), String::from("+Michael")],
+        });
+        world
+            .channels
+            .insert(String::from("#rust"), channel.clone());
+        let world = world.start();
+
+        let nick: IrcMessage = ":Michael!mike@example.com NICK Michael|away"
+            .parse()
+            .unwrap();
+        world.do_send(RawMessage(nick));
+
+        let members = Members {
+            channel: String::from("#rust"),
+        };
+        let mut got: Vec<String> = sys
+            .block_on(world.send(members))
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .collect();
+        got.sort();
+        assert_eq!(got, vec!["Michael|away", "bot"]);
+
+        let status = MemberStatusOf {
+            nick: String::from("michael|AWAY"),
+        };
+        let status = sys.block_on(channel.send(status)).unwrap();
+        assert_eq!(status, Some(MemberStatus::Voice));
+    }
+
+    #[test]
+    fn learn_the_networks_name() {
+        let mut sys = System::new("test");
+        let client = MockClient {
+            config: irc::client::data::Config {
+                server: Some(String::from("irc.example.com")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let world = World::new(client).start();
+
+        let got = sys.block_on(world.send(GetNetwork)).unwrap();
+        assert_eq!(got, Some(String::from("irc.example.com")));
+
+        let isupport: IrcMessage =
+            ":irc.example.com 005 bot NETWORK=ExampleNet MONITOR=100 :are supported by this server"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(isupport));
+        let got = sys.block_on(world.send(GetNetwork)).unwrap();
+        assert_eq!(got, Some(String::from("ExampleNet")));
+    }
+
+    #[test]
+    fn negotiate_caps_before_finishing_registration() {
+        let mut sys = System::new("test");
+        let client = MockClient {
+            config: irc::client::data::Config {
+                nickname: Some(String::from("bot")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let world = World::new(client.clone()).start();
+        let sent = || -> Vec<String> {
+            client
+                .sent
+                .lock()
+                .unwrap()
+                .drain(..)
+                .map(|msg| msg.to_string().trim_end().to_string())
+                .collect()
+        };
+
+        sys.block_on(world.send(Identify)).unwrap().unwrap();
+        assert_eq!(
+            sent(),
+            vec!["CAP LS 302", "NICK :bot", "USER bot 0 * :bot"]
+        );
+
+        let ls: IrcMessage =
+            ":irc.example.com CAP * LS * :sasl=PLAIN echo-message"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(ls));
+        let ls: IrcMessage = ":irc.example.com CAP * LS :labeled-response"
+            .parse()
+            .unwrap();
+        sys.block_on(world.send(RawMessage(ls))).unwrap();
+        assert_eq!(sent(), vec!["CAP REQ :echo-message labeled-response"]);
+
+        let ack: IrcMessage =
+            ":irc.example.com CAP bot ACK :echo-message labeled-response"
+                .parse()
+                .unwrap();
+        sys.block_on(world.send(RawMessage(ack))).unwrap();
+        assert_eq!(sent(), vec!["CAP END"]);
+    }
+
+    #[test]
+    fn hear_when_monitored_nicks_come_online() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<NickOnline>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let online: IrcMessage =
+            ":irc.example.com 730 bot :Michael!mike@example.com"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(online));
+
+        assert_eq!(sys.run(), 0);
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![NickOnline {
+                nick: String::from("Michael"),
+            }]
+        );
+    }
+
+    #[test]
+    fn refuse_to_leave_sticky_channels() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let settings = Settings {
+            sticky_channels: vec![String::from("#Home")],
+            ..Default::default()
+        };
+        let world = World::new_with_settings(
+            client,
+            Logger::root(Discard, o!()),
+            settings,
+        )
+        .start();
+
+        let got = sys.block_on(world.send(Part::new("#rust,#home"))).unwrap();
+        assert!(got.is_err());
+        assert!(sent.lock().unwrap().is_empty());
+
+        let forced = Part {
+            force: true,
+            ..Part::new("#home")
+        };
+        sys.block_on(world.send(forced)).unwrap().unwrap();
+        assert_eq!(
+            sent.lock().unwrap().last(),
+            Some(&IrcMessage::from(Command::PART(
+                String::from("#home"),
+                None
+            )))
+        );
+    }
+
+    #[test]
+    fn being_kicked_forgets_the_channel() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        world.current_nick = Some(String::from("bot"));
+        let world = world.start();
+
+        sys.block_on(world.send(Join::new("#rust,#offtopic")))
+            .unwrap()
+            .unwrap();
+        let kick: IrcMessage = ":Michael!mike@example.com KICK #Rust bot :Out"
+            .parse()
+            .unwrap();
+        sys.block_on(world.send(RawMessage(kick))).unwrap();
+
+        let got = sys.block_on(world.send(GetChannel("#rust"))).unwrap();
+        assert!(got.is_none());
+        let got = sys.block_on(world.send(GetChannel("#offtopic"))).unwrap();
+        assert!(got.is_some());
+    }
+
+    #[test]
+    fn kicks_pair_each_channel_with_a_nick() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        world.current_nick = Some(String::from("bot"));
+        let world = world.start();
+
+        sys.block_on(world.send(Join::new("#rust,#offtopic,#help")))
+            .unwrap()
+            .unwrap();
+        // we're only kicked from #rust, Michael is kicked from #offtopic
+        let kick: IrcMessage =
+            ":op!op@example.com KICK #rust,#offtopic bot,Michael :Out"
+                .parse()
+                .unwrap();
+        sys.block_on(world.send(RawMessage(kick))).unwrap();
+
+        let got = sys.block_on(world.send(GetChannel("#rust"))).unwrap();
+        assert!(got.is_none());
+        let got = sys.block_on(world.send(GetChannel("#offtopic"))).unwrap();
+        assert!(got.is_some());
+
+        // a single channel applies to every nick
+        let kick: IrcMessage =
+            ":op!op@example.com KICK #offtopic Michael,bot :Out"
+                .parse()
+                .unwrap();
+        sys.block_on(world.send(RawMessage(kick))).unwrap();
+
+        let got = sys.block_on(world.send(GetChannel("#offtopic"))).unwrap();
+        assert!(got.is_none());
+        let got = sys.block_on(world.send(GetChannel("#help"))).unwrap();
+        assert!(got.is_some());
+    }
+
+    #[test]
+    fn parting_sends_the_part_before_replying() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let world = World::new(client.clone()).start();
+
+        sys.block_on(world.send(Join::new("#rust")))
+            .unwrap()
+            .unwrap();
+        let channel = sys.block_on(world.send(GetChannel("#rust"))).unwrap();
+        let channel = channel.expect("The channel should be tracked");
+
+        let part = Part {
+            reason: Some(String::from("Bye")),
+            ..Part::new("#rust")
+        };
+        sys.block_on(world.send(part)).unwrap().unwrap();
+
+        let part = IrcMessage::from(Command::PART(
+            String::from("#rust"),
+            Some(String::from("Bye")),
+        ));
+        assert_eq!(client.sent.lock().unwrap().last(), Some(&part));
+        let got = sys.block_on(world.send(GetChannel("#rust"))).unwrap();
+        assert!(got.is_none());
+
+        // the channel stops without asking us to send a second PART
+        assert!(sys.block_on(channel.send(Summarize)).is_err());
+        sys.block_on(world.send(GetChannel("#rust"))).unwrap();
+        let sent = client.sent.lock().unwrap();
+        assert_eq!(sent.iter().filter(|msg| **msg == part).count(), 1);
+    }
+
+    #[test]
+    fn parting_needs_at_least_one_channel() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let world = World::new(client).start();
+
+        for channels in &["", "  ", " , "] {
+            let got = sys.block_on(world.send(Part::new(*channels))).unwrap();
+            assert!(got.is_err());
+        }
+
+        assert!(sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rate_limit_channels_separately() {
+        let mut sys = System::new("test");
+        let settings = Settings {
+            channel_rate_limits: vec![
+                "#busy:1/60".parse().unwrap(),
+                "#quiet:3/60".parse().unwrap(),
+            ],
+            ..Default::default()
+        };
+        let world = World::new_with_settings(
+            MockClient::default(),
+            Logger::root(Discard, o!()),
+            settings,
+        )
+        .start();
+        let mut send = |to: &str| {
+            let msg = PrivateMessage {
+                to: to.to_string(),
+                content: String::from("hi"),
             };
-            (sub.start(), received)
+            sys.block_on(world.send(msg)).unwrap().is_ok()
+        };
+
+        let busy: Vec<bool> = (0..3).map(|_| send("#busy")).collect();
+        assert_eq!(busy, vec![true, false, false]);
+        let quiet: Vec<bool> = (0..4).map(|_| send("#Quiet")).collect();
+        assert_eq!(quiet, vec![true, true, true, false]);
+        // everywhere else is only limited by the client's flood protection
+        assert!((0..5).all(|_| send("#rust")));
+    }
+
+    #[test]
+    fn refuse_to_join_too_many_channels() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let settings = Settings {
+            max_channels: Some(1),
+            sticky_channels: vec![String::from("#home")],
+            ..Default::default()
+        };
+        let world = World::new_with_settings(
+            client,
+            Logger::root(Discard, o!()),
+            settings,
+        )
+        .start();
+
+        sys.block_on(world.send(Join::new("#rust,#offtopic,#home")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            sent.lock().unwrap().last(),
+            Some(&IrcMessage::from(Command::JOIN(
+                String::from("#rust,#home"),
+                None,
+                None
+            )))
+        );
+
+        let got = sys.block_on(world.send(Join::new("#offtopic"))).unwrap();
+        assert!(got.is_err());
+        assert_eq!(sent.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn ignore_commands_disabled_in_a_channel() {
+        let sys = System::new("test");
+        let settings = Settings {
+            disabled_commands: vec!["#serious:8ball".parse().unwrap()],
+            ..Default::default()
+        };
+        let mut world = World::new_with_settings(
+            MockClient::default(),
+            Logger::root(Discard, o!()),
+            settings,
+        );
+        let (sub, got) = Sub::<CommandReceived>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        for channel in &["#Serious", "#rust"] {
+            let msg: IrcMessage = format!(
+                ":Michael!mike@example.com PRIVMSG {} :!8ball",
+                channel
+            )
+            .parse()
+            .unwrap();
+            world.do_send(RawMessage(msg));
         }
+        sys.run();
+
+        let got = got.lock().unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].reply_to, "#rust");
     }
 
-    impl<M: 'static> Actor for Sub<M> {
-        type Context = Context<Sub<M>>;
+    #[test]
+    fn commands_wait_until_weve_joined_our_channels() {
+        let sys = System::new("test");
+        let settings = Settings {
+            wait_for_channels: vec![String::from("#rust")],
+            ..Default::default()
+        };
+        let mut world = World::new_with_settings(
+            MockClient::default(),
+            Logger::root(Discard, o!()),
+            settings,
+        );
+        let (sub, got) = Sub::<CommandReceived>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let messages = vec![
+            ":irc.example.com 001 bot :Welcome to the network",
+            ":Michael!mike@example.com PRIVMSG bot :!ping too-early",
+            ":bot!bot@example.com JOIN #Rust",
+            ":Michael!mike@example.com PRIVMSG bot :!ping ready",
+        ];
+        for msg in messages {
+            world.do_send(RawMessage(msg.parse().unwrap()));
+        }
+        sys.run();
+
+        let got = got.lock().unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].args, vec![String::from("ready")]);
     }
 
-    impl<M> Handler<M> for Sub<M>
-    where
-        M: Message<Result = ()> + 'static,
-    {
-        type Result = ();
+    #[test]
+    fn suggest_the_closest_known_command() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let settings = Settings {
+            unknown_commands: UnknownCommandPolicy::Suggest,
+            ..Default::default()
+        };
+        let world = World::new_with_settings(
+            client,
+            Logger::root(Discard, o!()),
+            settings,
+        )
+        .start();
 
-        fn handle(&mut self, msg: M, _ctx: &mut Self::Context) {
-            self.received.lock().unwrap().push(msg);
+        world.do_send(RegisterCommand::new("nick"));
+        let typo: IrcMessage =
+            ":Michael!mike@example.com PRIVMSG #rust :!nikc bob"
+                .parse()
+                .unwrap();
+        let known: IrcMessage =
+            ":Michael!mike@example.com PRIVMSG #rust :!nick bob"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(typo));
+        sys.block_on(world.send(RawMessage(known))).unwrap();
 
-            System::current().stop();
+        let sent = sent.lock().unwrap();
+        assert_eq!(
+            *sent,
+            vec![IrcMessage::from(Command::NOTICE(
+                String::from("Michael"),
+                String::from("Unknown command, did you mean !nick?")
+            ))]
+        );
+    }
+
+    #[test]
+    fn unknown_commands_we_reply_to_arent_published() {
+        let sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let settings = Settings {
+            unknown_commands: UnknownCommandPolicy::Reply,
+            ..Default::default()
+        };
+        let mut world = World::new_with_settings(
+            client,
+            Logger::root(Discard, o!()),
+            settings,
+        );
+        let (sub, got) = Sub::<CommandReceived>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        world.do_send(RegisterCommand::new("nick"));
+        let messages = vec![
+            ":Michael!mike@example.com PRIVMSG #rust :!frobnicate",
+            ":Michael!mike@example.com PRIVMSG #rust :!nick bob",
+        ];
+        for msg in messages {
+            world.do_send(RawMessage(msg.parse().unwrap()));
         }
+        sys.run();
+
+        let got = got.lock().unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].name, "nick");
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![IrcMessage::from(Command::NOTICE(
+                String::from("Michael"),
+                String::from("Unknown command, try !help")
+            ))]
+        );
     }
 
     #[test]
-    fn register_and_receive_messages() {
+    fn replies_mention_our_command_prefix() {
         let mut sys = System::new("test");
-        let world = World::new("this-is-a-client").start();
-        let calls = Arc::new(AtomicUsize::default());
-        let calls_2 = Arc::clone(&calls);
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let settings = Settings {
+            unknown_commands: UnknownCommandPolicy::Reply,
+            command_prefix: '.',
+            ..Default::default()
+        };
+        let world = World::new_with_settings(
+            client,
+            Logger::root(Discard, o!()),
+            settings,
+        )
+        .start();
 
-        let mock: Addr<Mocker<DummyMessage>> =
-            Mocker::mock(Box::new(move |msg, _ctx| {
-                assert!(msg.downcast_ref::<DummyMessage>().is_some());
-                calls_2.fetch_add(1, Ordering::SeqCst);
-                System::current().stop();
-                Box::new(Some(<DummyMessage as Message>::Result::default()))
-            }))
-            .start();
+        let unknown: IrcMessage =
+            ":Michael!mike@example.com PRIVMSG #rust :.frobnicate"
+                .parse()
+                .unwrap();
+        sys.block_on(world.send(RawMessage(unknown))).unwrap();
 
-        // tell the world we want to register for DummyMessages
-        let msg: Registration<DummyMessage> =
-            Registration::register(mock.clone().recipient());
-        sys.block_on(world.send(msg)).unwrap();
+        let sent = sent.lock().unwrap();
+        assert_eq!(
+            *sent,
+            vec![IrcMessage::from(Command::NOTICE(
+                String::from("Michael"),
+                String::from("Unknown command, try .help")
+            ))]
+        );
+    }
 
-        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    #[test]
+    fn switch_off_commands_which_keep_failing() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let settings = Settings {
+            command_failure_threshold: 2,
+            ..Default::default()
+        };
+        let mut world = World::new_with_settings(
+            client,
+            Logger::root(Discard, o!()),
+            settings,
+        );
+        let (sub, got) = Sub::<CommandReceived>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
 
-        // then send a message and wait for it to arrive
-        world.do_send(DummyMessage);
-        assert_eq!(sys.run(), 0);
+        world.do_send(RegisterCommand::new("weather"));
+        world.do_send(CommandCompleted::failed("weather", "timed out"));
+        world.do_send(CommandCompleted::failed("weather", "timed out"));
+        let weather: IrcMessage =
+            ":Michael!mike@example.com PRIVMSG #rust :!weather"
+                .parse()
+                .unwrap();
+        sys.block_on(world.send(RawMessage(weather))).unwrap();
 
-        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(got.lock().unwrap().is_empty());
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![IrcMessage::from(Command::NOTICE(
+                String::from("Michael"),
+                String::from(
+                    "!weather is temporarily unavailable, try again later"
+                )
+            ))]
+        );
     }
 
     #[test]
-    fn raw_messages_are_forwarded_to_subscribers() {
+    fn refuse_channel_only_commands_in_private() {
         let mut sys = System::new("test");
-        let world = World::new("asd").start();
-        let (sub, got) = Sub::<RawMessage>::new();
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let mut world = World::new(client);
+        let (sub, got) = Sub::<CommandReceived>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
 
-        sys.block_on(world.send(Registration::for_actor(sub.clone(), true)))
+        world.do_send(
+            RegisterCommand::new("vote").only_in(CommandContext::ChannelOnly),
+        );
+        let vote: IrcMessage = ":Michael!mike@example.com PRIVMSG bot :!vote"
+            .parse()
             .unwrap();
+        sys.block_on(world.send(RawMessage(vote))).unwrap();
 
-        let msg = RawMessage(IrcMessage::from(Command::INFO(None)));
-        world.do_send(msg.clone());
-        assert_eq!(sys.run(), 0);
+        assert!(got.lock().unwrap().is_empty());
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![IrcMessage::from(Command::NOTICE(
+                String::from("Michael"),
+                String::from("!vote can only be used in a channel")
+            ))]
+        );
+    }
+
+    #[test]
+    fn refuse_dm_only_commands_in_channels() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let mut reply_templates = ReplyTemplates::new();
+        reply_templates.insert(
+            "command-dm-only=Psst {nick}, /msg me instead"
+                .parse()
+                .unwrap(),
+        );
+        let settings = Settings {
+            reply_templates,
+            ..Default::default()
+        };
+        let mut world = World::new_with_settings(
+            client,
+            Logger::root(Discard, o!()),
+            settings,
+        );
+        let (sub, got) = Sub::<CommandReceived>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        world.do_send(
+            RegisterCommand::new("identify").only_in(CommandContext::DmOnly),
+        );
+        let identify: IrcMessage =
+            ":Michael!mike@example.com PRIVMSG #rust :!identify hunter2"
+                .parse()
+                .unwrap();
+        sys.block_on(world.send(RawMessage(identify))).unwrap();
+
+        assert!(got.lock().unwrap().is_empty());
+        assert_eq!(
+            *sent.lock().unwrap(),
+            vec![IrcMessage::from(Command::NOTICE(
+                String::from("Michael"),
+                String::from("Psst Michael, /msg me instead")
+            ))]
+        );
+    }
+
+    #[test]
+    fn accept_dm_only_commands_in_private() {
+        let sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let mut world = World::new(client);
+        let (sub, got) = Sub::<CommandReceived>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        world.do_send(
+            RegisterCommand::new("identify").only_in(CommandContext::DmOnly),
+        );
+        let identify: IrcMessage =
+            ":Michael!mike@example.com PRIVMSG bot :!identify hunter2"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(identify));
+        sys.run();
 
         let got = got.lock().unwrap();
         assert_eq!(got.len(), 1);
-        assert_eq!(got[0], msg);
+        assert_eq!(got[0].name, "identify");
+        assert!(sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn commands_work_anywhere_by_default() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<CommandReceived>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        world.do_send(RegisterCommand::new("weather"));
+        let weather: IrcMessage =
+            ":Michael!mike@example.com PRIVMSG #rust :!weather perth"
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(weather));
+        sys.run();
+
+        assert_eq!(got.lock().unwrap()[0].reply_to, "#rust");
+    }
+
+    #[test]
+    fn measure_the_round_trip_to_the_server() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let world = World::new(client).start();
+
+        let rtt = world.send(MeasureLatency);
+        // messages are handled in order, so the ping has gone out by now
+        sys.block_on(world.send(GetStats)).unwrap();
+        let token = match sent.lock().unwrap().last() {
+            Some(IrcMessage {
+                command: Command::PING(token, None),
+                ..
+            }) => token.clone(),
+            other => panic!("Expected a PING, found {:?}", other),
+        };
+        let pong = IrcMessage::from(Command::PONG(
+            String::from("irc.example.com"),
+            Some(token),
+        ));
+        world.do_send(RawMessage(pong));
+
+        assert!(sys.block_on(rtt).unwrap().is_ok());
+    }
+
+    #[test]
+    fn resync_our_nick_from_the_servers_whois_reply() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let world = World::new(client).start();
+        let welcome: IrcMessage =
+            ":irc.example.com 001 bot :Welcome".parse().unwrap();
+        world.do_send(RawMessage(welcome));
+
+        let resynced = world.send(ResyncNick);
+        sys.block_on(world.send(GetStats)).unwrap();
+        assert_eq!(
+            sent.lock().unwrap().last(),
+            Some(&IrcMessage::from(Command::WHOIS(None, String::from("bot"))))
+        );
+        // we missed being renamed during a netsplit
+        let end_of_whois: IrcMessage =
+            ":irc.example.com 318 bot_ bot :End of /WHOIS list."
+                .parse()
+                .unwrap();
+        world.do_send(RawMessage(end_of_whois));
+
+        assert_eq!(sys.block_on(resynced).unwrap().unwrap(), "bot_");
+        let stats = sys.block_on(world.send(GetStats)).unwrap();
+        assert_eq!(stats.nick, Some(String::from("bot_")));
+    }
+
+    #[test]
+    fn estimate_clock_skew_from_server_time() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let msg: IrcMessage =
+            "@time=2019-01-15T12:00:00.000Z :Michael!mike@example.com PRIVMSG #rust :hi"
+                .parse()
+                .unwrap();
+        let server_time = UNIX_EPOCH + Duration::from_secs(1_547_553_600);
+
+        assert_eq!(world.estimate_clock_skew(), None);
+        for (i, skew) in [-2, 30_000, -1, 0, 1].iter().enumerate() {
+            let at = if *skew < 0 {
+                server_time + Duration::from_millis(-skew as u64)
+            } else {
+                server_time - Duration::from_millis(*skew as u64)
+            };
+            world.track_clock_skew(&msg, at);
+            assert_eq!(world.estimate_clock_skew().unwrap().samples, i + 1);
+        }
+
+        // the one slow message doesn't skew the estimate
+        assert_eq!(
+            world.estimate_clock_skew(),
+            Some(ClockSkew {
+                millis: 0,
+                samples: 5
+            })
+        );
+        assert!(!world.warned_clock_skew);
+
+        // history playback is left out
+        let playback: IrcMessage =
+            "@batch=123;time=2019-01-14T12:00:00.000Z :Michael!mike@example.com PRIVMSG #rust :hi"
+                .parse()
+                .unwrap();
+        world.track_clock_skew(&playback, server_time);
+        assert_eq!(world.estimate_clock_skew().unwrap().samples, 5);
+
+        drop(sys);
+    }
+
+    #[test]
+    fn list_and_cancel_scheduled_tasks() {
+        let mut sys = System::new("test");
+        let world = World::create(|ctx| {
+            let mut world = World::new(MockClient::default());
+            world.schedule_once(
+                "later",
+                Duration::from_secs(60),
+                ctx,
+                |_, _| panic!("This should have been cancelled"),
+            );
+            world.schedule_every(
+                "often",
+                Duration::from_secs(30),
+                ctx,
+                |_, _| {},
+            );
+            world
+        });
+
+        let got = sys.block_on(world.send(ListSchedules)).unwrap();
+        let names: Vec<_> = got.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, vec!["often", "later"]);
+        assert_eq!(got[0].interval, Some(Duration::from_secs(30)));
+        assert_eq!(got[1].interval, None);
+
+        let later = CancelSchedule { id: got[1].id };
+        assert!(sys.block_on(world.send(later)).unwrap());
+        assert!(!sys.block_on(world.send(later)).unwrap());
+        let got = sys.block_on(world.send(ListSchedules)).unwrap();
+        assert_eq!(got.len(), 1);
+    }
+
+    #[test]
+    fn tolerate_an_early_not_registered() {
+        let mut sys = System::new("test");
+        let client = MockClient::default();
+        let sent = Arc::clone(&client.sent);
+        let world = World::new(client).start();
+        let is_quit = |msg: &IrcMessage| match msg.command {
+            Command::QUIT(_) => true,
+            _ => false,
+        };
+        let not_registered: IrcMessage =
+            ":irc.example.com 451 * :You have not registered"
+                .parse()
+                .unwrap();
+
+        world.do_send(RawMessage(not_registered.clone()));
+        sys.block_on(world.send(GetStats)).unwrap();
+        assert!(!sent.lock().unwrap().iter().any(is_quit));
+
+        // but it's a problem once we've registered
+        let welcome: IrcMessage =
+            ":irc.example.com 001 bot :Welcome".parse().unwrap();
+        world.do_send(RawMessage(welcome));
+        world.do_send(RawMessage(not_registered));
+        let _ = sys.block_on(world.send(GetStats));
+        assert!(sent.lock().unwrap().iter().any(is_quit));
+    }
+
+    #[test]
+    fn assemble_whois_replies() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<WhoIsReply>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let lines = &[
+            ":irc.example.com 311 bot Michael mike example.com * :Michael B",
+            ":irc.example.com 330 bot Michael michael :is logged in as",
+            ":irc.example.com 671 bot Michael :is using a secure connection",
+            ":irc.example.com 378 bot Michael :is connecting from *@203.0.113.7.example.com 203.0.113.7",
+            ":irc.example.com 318 bot Michael :End of /WHOIS list.",
+        ];
+        for line in lines {
+            world.do_send(RawMessage(line.parse().unwrap()));
+        }
+        sys.run();
+
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![WhoIsReply {
+                nick: String::from("Michael"),
+                user: Some(String::from("mike")),
+                host: Some(String::from("example.com")),
+                real_name: Some(String::from("Michael B")),
+                account: Some(String::from("michael")),
+                secure_connection: Some(true),
+                actual_host: Some(String::from("203.0.113.7.example.com")),
+            }]
+        );
+    }
+
+    #[test]
+    fn whois_replies_leave_out_what_the_server_didnt_send() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<WhoIsReply>::new();
+        world.hooks.register(sub.recipient());
+        let world = world.start();
+
+        let lines = &[
+            ":irc.example.com 311 bot guest guest example.org * :Guest",
+            ":irc.example.com 378 bot guest :is connecting from *@",
+            ":irc.example.com 318 bot guest :End of /WHOIS list.",
+        ];
+        for line in lines {
+            world.do_send(RawMessage(line.parse().unwrap()));
+        }
+        sys.run();
+
+        assert_eq!(
+            *got.lock().unwrap(),
+            vec![WhoIsReply {
+                nick: String::from("guest"),
+                user: Some(String::from("guest")),
+                host: Some(String::from("example.org")),
+                real_name: Some(String::from("Guest")),
+                ..Default::default()
+            }]
+        );
+    }
+
+    #[test]
+    fn skip_lines_which_cant_be_decoded() {
+        let sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let (sub, got) = Sub::<NickOnline>::new();
+        world.hooks.register(sub.recipient());
+
+        let garbled = IrcError::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Failed to decode the line as UTF-8.",
+        ));
+        let online: IrcMessage =
+            ":irc.example.com 730 bot :Michael!mike@example.com"
+                .parse()
+                .unwrap();
+        let _world = World::create(move |ctx| {
+            ctx.add_stream(futures::stream::iter_result(vec![
+                Err(garbled),
+                Ok(online),
+            ]));
+            world
+        });
+
+        assert_eq!(sys.run(), 0);
+        assert_eq!(got.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn find_the_channels_we_share_with_someone() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+
+        for (name, names) in vec![
+            ("#rust", vec!["bot", "@Michael"]),
+            ("#irc", vec!["bot", "Bob"]),
+            ("#actix", vec!["bot", "+michael"]),
+        ] {
+            let channel = Channel::new(name).start();
+            channel.do_send(NamesReply {
+                names: names.into_iter().map(String::from).collect(),
+            });
+            world.channels.insert(String::from(name), channel);
+        }
+        let world = world.start();
+
+        let got = sys
+            .block_on(world.send(SharedChannels {
+                nick: String::from("Michael"),
+            }))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(got, vec!["#actix", "#rust"]);
+    }
+
+    #[test]
+    fn check_whether_someone_is_in_a_channel() {
+        let mut sys = System::new("test");
+        let mut world = World::new(MockClient::default());
+        let channel = Channel::new("#Rust").start();
+        channel.do_send(NamesReply {
+            names: vec![String::from("bot"), String::from("@Mich[ael]")],
+        });
+        world.channels.insert(String::from("#rust"), channel);
+        let world = world.start();
+
+        let mut is_member = |channel: &str, nick: &str| {
+            let msg = IsMember {
+                channel: channel.to_string(),
+                nick: nick.to_string(),
+            };
+            sys.block_on(world.send(msg)).unwrap().unwrap()
+        };
+
+        assert!(is_member("#RUST", "mich{ael}"));
+        assert!(!is_member("#rust", "Bob"));
+        assert!(!is_member("#irc", "Michael"));
     }
 }